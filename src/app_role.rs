@@ -0,0 +1,204 @@
+use crate::state::settings_url;
+use postgresql_embedded::Settings;
+use serde::{Deserialize, Serialize};
+use sqlx::Connection;
+use sqlx::postgres::PgConnection;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Role attributes `start --role` accepts, each also with a `NO` prefix.
+/// `LOGIN` is always given, as the roles exist to connect with.
+const ROLE_ATTRIBUTES: &[&str] = &[
+    "SUPERUSER",
+    "CREATEDB",
+    "CREATEROLE",
+    "INHERIT",
+    "REPLICATION",
+    "BYPASSRLS",
+];
+
+/// Non-superuser role and database created for an application by `--app-user`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppCredentials {
+    pub user: String,
+    pub password: String,
+    pub database: String,
+}
+
+/// Create the role and its database if they are missing, and make sure the
+/// role can log in with `credentials.password`. Safe to run on every start.
+pub async fn ensure(settings: &Settings, credentials: &AppCredentials) -> Result<(), sqlx::Error> {
+    let mut connection = PgConnection::connect(&settings_url(settings, "postgres")).await?;
+    let role = quote_identifier(&credentials.user);
+    let password = quote_literal(&credentials.password);
+
+    let role_exists =
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = $1)")
+            .bind(&credentials.user)
+            .fetch_one(&mut connection)
+            .await?;
+    let statement = if role_exists {
+        format!("ALTER ROLE {role} WITH LOGIN PASSWORD {password}")
+    } else {
+        format!("CREATE ROLE {role} WITH LOGIN PASSWORD {password}")
+    };
+    sqlx::query(&statement).execute(&mut connection).await?;
+
+    let database_exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1)",
+    )
+    .bind(&credentials.database)
+    .fetch_one(&mut connection)
+    .await?;
+    if !database_exists {
+        let database = quote_identifier(&credentials.database);
+        sqlx::query(&format!("CREATE DATABASE {database} OWNER {role}"))
+            .execute(&mut connection)
+            .await?;
+    }
+
+    connection.close().await
+}
+
+/// A role declared with `start --role name:password[:options]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Role {
+    pub name: String,
+    pub password: String,
+    /// Upper-case attributes such as `CREATEDB` or `NOINHERIT`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<String>,
+}
+
+/// Check and upper-case role attributes given as `createdb,noinherit`.
+pub fn parse_attributes(options: &[&str]) -> Result<Vec<String>, String> {
+    options
+        .iter()
+        .map(|option| option.trim().to_ascii_uppercase())
+        .filter(|option| !option.is_empty())
+        .map(|option| {
+            let base = option.strip_prefix("NO").unwrap_or(&option);
+            if ROLE_ATTRIBUTES.contains(&base) {
+                Ok(option)
+            } else {
+                Err(format!(
+                    "unknown role option {option:?}; expected one of {} or NO<option>",
+                    ROLE_ATTRIBUTES.join(", ").to_ascii_lowercase()
+                ))
+            }
+        })
+        .collect()
+}
+
+/// Create `role` or bring an existing one's password and attributes in line.
+/// Safe to run on every start.
+pub async fn ensure_role(settings: &Settings, role: &Role) -> Result<(), sqlx::Error> {
+    let mut connection = PgConnection::connect(&settings_url(settings, "postgres")).await?;
+    let exists =
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = $1)")
+            .bind(&role.name)
+            .fetch_one(&mut connection)
+            .await?;
+    let statement = format!(
+        "{} ROLE {} WITH LOGIN {} PASSWORD {}",
+        if exists { "ALTER" } else { "CREATE" },
+        quote_identifier(&role.name),
+        role.attributes.join(" "),
+        quote_literal(&role.password)
+    );
+    sqlx::query(&statement).execute(&mut connection).await?;
+    connection.close().await
+}
+
+/// Roles declared so far, by name, for `pgx url --role`.
+pub fn read_roles(path: &Path) -> io::Result<BTreeMap<String, Role>> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(error) => return Err(error),
+    };
+    serde_json::from_str(&raw).map_err(io::Error::other)
+}
+
+/// Write the declared roles readable only by the current user.
+pub fn write_roles(path: &Path, roles: &BTreeMap<String, Role>) -> io::Result<()> {
+    let raw = serde_json::to_string_pretty(roles).map_err(io::Error::other)?;
+    fs::write(path, raw)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+pub fn read(path: &Path) -> io::Result<Option<AppCredentials>> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    serde_json::from_str(&raw)
+        .map(Some)
+        .map_err(io::Error::other)
+}
+
+/// Write the credentials readable only by the current user.
+pub fn write(path: &Path, credentials: &AppCredentials) -> io::Result<()> {
+    let raw = serde_json::to_string_pretty(credentials).map_err(io::Error::other)?;
+    fs::write(path, raw)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+pub(crate) fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_and_their_negations_are_accepted() {
+        assert_eq!(
+            parse_attributes(&["createdb", "NOINHERIT", "Replication", "nobypassrls"]).unwrap(),
+            ["CREATEDB", "NOINHERIT", "REPLICATION", "NOBYPASSRLS"]
+        );
+        assert!(parse_attributes(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn whitespace_and_empty_options_are_ignored() {
+        assert_eq!(
+            parse_attributes(&[" createrole ", "", "  ", "\tnosuperuser"]).unwrap(),
+            ["CREATEROLE", "NOSUPERUSER"]
+        );
+    }
+
+    #[test]
+    fn unknown_attributes_are_refused() {
+        for option in [
+            "superuse",
+            "no",
+            "login",
+            "nologin",
+            "password",
+            "create db",
+        ] {
+            let error = parse_attributes(&["createdb", option]).unwrap_err();
+            assert!(error.starts_with("unknown role option"), "{error}");
+            assert!(error.contains("createdb, createrole"), "{error}");
+        }
+    }
+}
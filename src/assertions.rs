@@ -0,0 +1,569 @@
+use crate::state::settings_url;
+use postgresql_embedded::Settings;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+
+type CheckOutcome = Result<(bool, String), Box<dyn std::error::Error + Send + Sync>>;
+
+/// Declarative expectations about a provisioned cluster, usually loaded from
+/// a TOML file passed to `pgx assert --file`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckSpec {
+    pub server: Option<ServerCheck>,
+    #[serde(default, rename = "extension")]
+    pub extensions: Vec<ExtensionCheck>,
+    #[serde(default, rename = "database")]
+    pub databases: Vec<DatabaseCheck>,
+    #[serde(default, rename = "table")]
+    pub tables: Vec<TableCheck>,
+    #[serde(default, rename = "setting")]
+    pub settings: Vec<SettingCheck>,
+    #[serde(default, rename = "role")]
+    pub roles: Vec<RoleCheck>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerCheck {
+    /// Semver requirement such as `>=17`.
+    pub version: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtensionCheck {
+    pub name: String,
+    pub min_version: Option<String>,
+    pub database: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseCheck {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TableCheck {
+    /// Optionally schema-qualified, e.g. `app.users`.
+    pub name: String,
+    pub database: Option<String>,
+    pub rows: Option<i64>,
+    pub min_rows: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingCheck {
+    pub name: String,
+    /// Compared against `SHOW <name>`, so units are written as the server prints them.
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleCheck {
+    pub name: String,
+    pub superuser: Option<bool>,
+    pub login: Option<bool>,
+    pub createdb: Option<bool>,
+    pub createrole: Option<bool>,
+    pub replication: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckResult {
+    pub check: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl CheckSpec {
+    pub fn is_empty(&self) -> bool {
+        self.server.is_none()
+            && self.extensions.is_empty()
+            && self.databases.is_empty()
+            && self.tables.is_empty()
+            && self.settings.is_empty()
+            && self.roles.is_empty()
+    }
+
+    /// Append every check from `other`; a server check in `other` wins.
+    pub fn merge(&mut self, other: CheckSpec) {
+        if other.server.is_some() {
+            self.server = other.server;
+        }
+        self.extensions.extend(other.extensions);
+        self.databases.extend(other.databases);
+        self.tables.extend(other.tables);
+        self.settings.extend(other.settings);
+        self.roles.extend(other.roles);
+    }
+}
+
+/// Tally of evaluated checks, naming the ones that failed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Summary<'a> {
+    pub passed: usize,
+    pub failed: Vec<&'a str>,
+}
+
+impl<'a> Summary<'a> {
+    pub fn of(results: &'a [CheckResult]) -> Self {
+        let failed: Vec<&str> = results
+            .iter()
+            .filter(|result| !result.passed)
+            .map(|result| result.check.as_str())
+            .collect();
+        Summary {
+            passed: results.len() - failed.len(),
+            failed,
+        }
+    }
+
+    /// The error `pgx assert` fails with, or `None` when every check passed.
+    pub fn failure(&self) -> Option<String> {
+        (!self.failed.is_empty()).then(|| {
+            format!(
+                "{} of {} checks failed: {}",
+                self.failed.len(),
+                self.passed + self.failed.len(),
+                self.failed.join(", ")
+            )
+        })
+    }
+}
+
+/// Parse the inline `name` or `name>=version` extension form.
+pub fn parse_extension_check(raw: &str) -> ExtensionCheck {
+    match raw.split_once(">=") {
+        Some((name, min_version)) => ExtensionCheck {
+            name: name.trim().to_string(),
+            min_version: Some(min_version.trim().to_string()),
+            database: None,
+        },
+        None => ExtensionCheck {
+            name: raw.trim().to_string(),
+            min_version: None,
+            database: None,
+        },
+    }
+}
+
+/// Evaluate every check in `spec`. Failures, including SQL errors, are
+/// recorded per check so one broken expectation never hides the others.
+pub async fn evaluate(
+    settings: &Settings,
+    default_database: &str,
+    spec: &CheckSpec,
+) -> Vec<CheckResult> {
+    let mut connections = Connections {
+        settings,
+        pools: HashMap::new(),
+    };
+    let mut results = Vec::new();
+
+    if let Some(server) = &spec.server {
+        let outcome = check_server(&mut connections, default_database, server).await;
+        results.push(record(
+            format!("server version {}", server.version),
+            outcome,
+        ));
+    }
+
+    for extension in &spec.extensions {
+        let database = extension.database.as_deref().unwrap_or(default_database);
+        let outcome = check_extension(&mut connections, database, extension).await;
+        let check = match &extension.min_version {
+            Some(min_version) => format!("extension {} >= {min_version}", extension.name),
+            None => format!("extension {}", extension.name),
+        };
+        results.push(record(check, outcome));
+    }
+
+    for database in &spec.databases {
+        let outcome = check_database(&mut connections, default_database, database).await;
+        results.push(record(format!("database {}", database.name), outcome));
+    }
+
+    for table in &spec.tables {
+        let database = table.database.as_deref().unwrap_or(default_database);
+        let outcome = check_table(&mut connections, database, table).await;
+        let check = match (table.rows, table.min_rows) {
+            (Some(rows), _) => format!("table {} has {rows} rows", table.name),
+            (None, Some(min_rows)) => format!("table {} has at least {min_rows} rows", table.name),
+            (None, None) => format!("table {}", table.name),
+        };
+        results.push(record(check, outcome));
+    }
+
+    for setting in &spec.settings {
+        let outcome = check_setting(&mut connections, default_database, setting).await;
+        results.push(record(
+            format!("setting {} = {}", setting.name, setting.value),
+            outcome,
+        ));
+    }
+
+    for role in &spec.roles {
+        let outcome = check_role(&mut connections, default_database, role).await;
+        results.push(record(format!("role {}", role.name), outcome));
+    }
+
+    connections.close().await;
+    results
+}
+
+struct Connections<'a> {
+    settings: &'a Settings,
+    pools: HashMap<String, PgPool>,
+}
+
+impl Connections<'_> {
+    async fn pool(&mut self, database: &str) -> Result<&PgPool, sqlx::Error> {
+        if !self.pools.contains_key(database) {
+            let pool = PgPool::connect(&settings_url(self.settings, database)).await?;
+            self.pools.insert(database.to_string(), pool);
+        }
+        Ok(&self.pools[database])
+    }
+
+    async fn close(self) {
+        for pool in self.pools.into_values() {
+            pool.close().await;
+        }
+    }
+}
+
+fn record(check: String, outcome: CheckOutcome) -> CheckResult {
+    match outcome {
+        Ok((passed, detail)) => CheckResult {
+            check,
+            passed,
+            detail,
+        },
+        Err(error) => CheckResult {
+            check,
+            passed: false,
+            detail: error.to_string(),
+        },
+    }
+}
+
+async fn check_server(
+    connections: &mut Connections<'_>,
+    database: &str,
+    server: &ServerCheck,
+) -> CheckOutcome {
+    let requirement = VersionReq::parse(&server.version)?;
+    let raw = sqlx::query_scalar::<_, String>("SELECT current_setting('server_version')")
+        .fetch_one(connections.pool(database).await?)
+        .await?;
+    let version = lenient_version(&raw)?;
+    Ok((requirement.matches(&version), format!("server is {raw}")))
+}
+
+async fn check_extension(
+    connections: &mut Connections<'_>,
+    database: &str,
+    extension: &ExtensionCheck,
+) -> CheckOutcome {
+    let installed = sqlx::query_scalar::<_, String>(
+        "SELECT extversion::text FROM pg_extension WHERE extname = $1",
+    )
+    .bind(&extension.name)
+    .fetch_optional(connections.pool(database).await?)
+    .await?;
+
+    let Some(installed) = installed else {
+        return Ok((false, format!("not installed in database {database}")));
+    };
+    let passed = match &extension.min_version {
+        Some(min_version) => lenient_version(&installed)? >= lenient_version(min_version)?,
+        None => true,
+    };
+    Ok((passed, format!("installed {installed}")))
+}
+
+async fn check_database(
+    connections: &mut Connections<'_>,
+    database: &str,
+    expected: &DatabaseCheck,
+) -> CheckOutcome {
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1)",
+    )
+    .bind(&expected.name)
+    .fetch_one(connections.pool(database).await?)
+    .await?;
+
+    let detail = if exists { "exists" } else { "does not exist" };
+    Ok((exists, detail.to_string()))
+}
+
+async fn check_table(
+    connections: &mut Connections<'_>,
+    database: &str,
+    table: &TableCheck,
+) -> CheckOutcome {
+    let pool = connections.pool(database).await?;
+    // Round-tripping through regclass yields a correctly quoted identifier.
+    let relation = sqlx::query_scalar::<_, Option<String>>("SELECT to_regclass($1)::text")
+        .bind(&table.name)
+        .fetch_one(pool)
+        .await?;
+    let Some(relation) = relation else {
+        return Ok((false, format!("does not exist in database {database}")));
+    };
+
+    if table.rows.is_none() && table.min_rows.is_none() {
+        return Ok((true, "exists".to_string()));
+    }
+
+    let count = sqlx::query_scalar::<_, i64>(&format!("SELECT count(*) FROM {relation}"))
+        .fetch_one(pool)
+        .await?;
+    let passed = match (table.rows, table.min_rows) {
+        (Some(rows), _) => count == rows,
+        (None, Some(min_rows)) => count >= min_rows,
+        (None, None) => true,
+    };
+    Ok((passed, format!("{count} rows")))
+}
+
+async fn check_setting(
+    connections: &mut Connections<'_>,
+    database: &str,
+    setting: &SettingCheck,
+) -> CheckOutcome {
+    let value = sqlx::query_scalar::<_, Option<String>>("SELECT current_setting($1, true)")
+        .bind(&setting.name)
+        .fetch_one(connections.pool(database).await?)
+        .await?;
+
+    match value {
+        Some(value) => Ok((value == setting.value, format!("value is {value}"))),
+        None => Ok((false, "unknown setting".to_string())),
+    }
+}
+
+async fn check_role(
+    connections: &mut Connections<'_>,
+    database: &str,
+    role: &RoleCheck,
+) -> CheckOutcome {
+    let attributes = sqlx::query_as::<_, (bool, bool, bool, bool, bool)>(
+        "SELECT rolsuper, rolcanlogin, rolcreatedb, rolcreaterole, rolreplication \
+         FROM pg_roles WHERE rolname = $1",
+    )
+    .bind(&role.name)
+    .fetch_optional(connections.pool(database).await?)
+    .await?;
+
+    let Some((superuser, login, createdb, createrole, replication)) = attributes else {
+        return Ok((false, "does not exist".to_string()));
+    };
+
+    let mismatches: Vec<String> = [
+        ("superuser", role.superuser, superuser),
+        ("login", role.login, login),
+        ("createdb", role.createdb, createdb),
+        ("createrole", role.createrole, createrole),
+        ("replication", role.replication, replication),
+    ]
+    .into_iter()
+    .filter_map(|(attribute, expected, actual)| {
+        expected
+            .filter(|expected| *expected != actual)
+            .map(|expected| format!("{attribute} is {actual}, expected {expected}"))
+    })
+    .collect();
+
+    if mismatches.is_empty() {
+        Ok((true, "exists".to_string()))
+    } else {
+        Ok((false, mismatches.join("; ")))
+    }
+}
+
+/// Parse versions like `17.5`, `0.7.0`, `3.4.2dev` or `17.5 (Debian 17.5-1)`
+/// by taking up to three numeric components from the leading run of digits
+/// and dots, and padding the rest with zeros.
+fn lenient_version(raw: &str) -> Result<Version, Box<dyn std::error::Error + Send + Sync>> {
+    let leading = raw
+        .trim()
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .next()
+        .unwrap_or_default();
+    let mut components = [0u64; 3];
+    let mut parsed = 0;
+    for (index, part) in leading.split('.').take(3).enumerate() {
+        if part.is_empty() {
+            break;
+        }
+        components[index] = part.parse()?;
+        parsed += 1;
+    }
+
+    if parsed == 0 {
+        return Err(format!("cannot parse version '{raw}'").into());
+    }
+    Ok(Version::new(components[0], components[1], components[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<CheckSpec, toml::de::Error> {
+        toml::from_str(raw)
+    }
+
+    fn result(check: &str, passed: bool) -> CheckResult {
+        CheckResult {
+            check: check.to_string(),
+            passed,
+            detail: String::new(),
+        }
+    }
+
+    #[test]
+    fn parses_every_kind_of_check() {
+        let spec = parse(
+            r#"
+            server = { version = ">=17" }
+
+            [[extension]]
+            name = "pg_search"
+            min_version = "0.17"
+
+            [[database]]
+            name = "app"
+
+            [[table]]
+            name = "app.users"
+            database = "app"
+            min_rows = 1
+
+            [[setting]]
+            name = "shared_buffers"
+            value = "128MB"
+
+            [[role]]
+            name = "app"
+            login = true
+            superuser = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(spec.server.unwrap().version, ">=17");
+        assert_eq!(spec.extensions[0].min_version.as_deref(), Some("0.17"));
+        assert_eq!(spec.databases[0].name, "app");
+        assert_eq!(spec.tables[0].database.as_deref(), Some("app"));
+        assert_eq!(spec.tables[0].min_rows, Some(1));
+        assert_eq!(spec.settings[0].value, "128MB");
+        assert_eq!(spec.roles[0].login, Some(true));
+        assert_eq!(spec.roles[0].createdb, None);
+    }
+
+    #[test]
+    fn refuses_unknown_sections() {
+        let error = parse("[[tables]]\nname = \"users\"\n").unwrap_err();
+        assert!(
+            error.to_string().contains("unknown field `tables`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn refuses_unknown_fields_in_a_check() {
+        let error = parse("[[table]]\nname = \"users\"\nmin_row = 1\n").unwrap_err();
+        assert!(
+            error.to_string().contains("unknown field `min_row`"),
+            "{error}"
+        );
+
+        let error = parse("server = { version = \">=17\", edition = \"pro\" }\n").unwrap_err();
+        assert!(
+            error.to_string().contains("unknown field `edition`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn merge_appends_checks_and_prefers_the_later_server() {
+        let mut spec =
+            parse("server = { version = \">=16\" }\n[[database]]\nname = \"a\"\n").unwrap();
+        spec.merge(parse("server = { version = \">=17\" }\n[[database]]\nname = \"b\"\n").unwrap());
+        assert_eq!(spec.server.unwrap().version, ">=17");
+        let names: Vec<_> = spec
+            .databases
+            .iter()
+            .map(|database| database.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn parses_inline_extension_checks() {
+        let check = parse_extension_check("pgvector >= 0.6");
+        assert_eq!(check.name, "pgvector");
+        assert_eq!(check.min_version.as_deref(), Some("0.6"));
+        assert_eq!(parse_extension_check("postgis").min_version, None);
+    }
+
+    #[test]
+    fn reads_versions_leniently() {
+        assert_eq!(lenient_version("17.5").unwrap(), Version::new(17, 5, 0));
+        assert_eq!(lenient_version("3.4.2dev").unwrap(), Version::new(3, 4, 2));
+        assert_eq!(
+            lenient_version("17.5 (Debian 17.5-1)").unwrap(),
+            Version::new(17, 5, 0)
+        );
+        assert!(lenient_version("dev").is_err());
+    }
+
+    #[test]
+    fn a_failed_query_fails_only_its_own_check() {
+        let failed = record("table users".to_string(), Err("relation is locked".into()));
+        assert!(!failed.passed);
+        assert_eq!(failed.detail, "relation is locked");
+
+        let passed = record("database app".to_string(), Ok((true, "exists".to_string())));
+        assert!(passed.passed);
+    }
+
+    #[test]
+    fn summary_passes_when_every_check_passes() {
+        let results = [result("database app", true), result("role app", true)];
+        let summary = Summary::of(&results);
+        assert_eq!(
+            summary,
+            Summary {
+                passed: 2,
+                failed: Vec::new()
+            }
+        );
+        assert_eq!(summary.failure(), None);
+    }
+
+    #[test]
+    fn summary_names_every_failed_check() {
+        let results = [
+            result("database app", false),
+            result("role app", true),
+            result("setting fsync = off", false),
+        ];
+        let summary = Summary::of(&results);
+        assert_eq!(summary.passed, 1);
+        assert_eq!(
+            summary.failure().as_deref(),
+            Some("2 of 3 checks failed: database app, setting fsync = off")
+        );
+    }
+}
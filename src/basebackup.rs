@@ -0,0 +1,79 @@
+//! Physical backups taken with the bundled pg_basebackup, with a small
+//! metadata file recording where and from which version they came.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+/// Written into the backup directory next to what pg_basebackup produced.
+pub const METADATA_FILE: &str = "pgx-basebackup.json";
+/// How much of pg_basebackup's stderr is kept to explain a failure.
+const STDERR_TAIL_BYTES: usize = 4096;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BasebackupMetadata {
+    pub source_data_dir: PathBuf,
+    pub pg_version: Option<String>,
+    /// `plain` or `tar`.
+    pub format: String,
+    pub compress: Option<String>,
+    /// RFC 3339 timestamp.
+    pub created_at: String,
+}
+
+pub fn write_metadata(output: &Path, metadata: &BasebackupMetadata) -> io::Result<()> {
+    let raw = serde_json::to_string_pretty(metadata).map_err(io::Error::other)?;
+    fs::write(output.join(METADATA_FILE), raw)
+}
+
+/// pg_basebackup refuses to write into a non-empty directory; check up front
+/// rather than after connecting.
+pub fn ensure_empty_output(output: &Path) -> io::Result<()> {
+    match fs::read_dir(output).map(|mut entries| entries.next().is_some()) {
+        Ok(true) => Err(io::Error::other(format!(
+            "{} is not empty; pg_basebackup needs a new or empty directory",
+            output.display()
+        ))),
+        Ok(false) => Ok(()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Run `command`, copying its stderr to ours as it arrives so `--progress`
+/// updates show up live, and return the tail of it for error reporting.
+pub async fn run_streaming(mut command: Command) -> io::Result<(ExitStatus, String)> {
+    let mut child = command.stderr(Stdio::piped()).spawn()?;
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+
+    let mut tail = Vec::new();
+    let mut buffer = [0u8; 1024];
+    loop {
+        let read = stderr.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        let mut terminal = io::stderr().lock();
+        terminal.write_all(&buffer[..read])?;
+        terminal.flush()?;
+        tail.extend_from_slice(&buffer[..read]);
+        if tail.len() > STDERR_TAIL_BYTES {
+            tail.drain(..tail.len() - STDERR_TAIL_BYTES);
+        }
+    }
+
+    let status = child.wait().await?;
+    Ok((status, String::from_utf8_lossy(&tail).into_owned()))
+}
+
+/// Whether pg_basebackup failed because the server does not accept
+/// replication connections from us, rather than for any other reason.
+pub fn replication_refused(stderr: &str) -> bool {
+    stderr.contains("replication connection")
+        || stderr.contains("must be superuser or replication role")
+        || stderr.contains("number of requested standby connections exceeds max_wal_senders")
+}
@@ -0,0 +1,5383 @@
+//! The `pgx` command line, kept in the library so it can reach the
+//! building blocks that are not part of the public API.
+
+use crate::cluster::{PG_VERSION_REQ, resolved_version};
+use crate::lifecycle::{
+    StopOptions, before_deadline, clear_stale_pid_file, init_scripts, open_log_file,
+    prepare_data_dir, record_prewarm_relations, run_sql_script, stale_pid_file_message, timed_out,
+    write_port_file,
+};
+use crate::runtime::{
+    PGX_BINARIES_ENV, PGX_RELEASES_URL_ENV, RuntimeContext, UrlRequest, acquire_operation_lock,
+    build_settings, cluster_major_version, configured_version, connection_env, database_env,
+    database_url, ensure_running, env_releases_url, load_runtime_context, metadata_error,
+    profile_defaults, required_major_version, runtime_liveness, stopped_description,
+};
+#[cfg(windows)]
+use crate::service;
+use crate::state::{
+    DEFAULT_DATABASE, DEFAULT_SUPERUSER, PGX_PASSWORD_FILE_ENV, PGX_STATE_FILE_ENV,
+    STATE_SCHEMA_VERSION, StateFile, absolute_data_dir, app_credentials_path,
+    cluster_is_initialized, custom_password_file, init_marker_path, lock_file_path,
+    mark_state_stopped, override_sidecar_paths, password_file_path, prewarm_file_path,
+    read_managed_password_file, read_state_file, requested_sidecar_paths, reset_dir_path,
+    roles_path, set_owner_only_permissions, settings_url, sidecar_file_path, snapshot_root_path,
+    socket_dir_path, state_file_path, state_file_schema_version, templates_path, tls_dir_path,
+    write_state_file,
+};
+use crate::{Cluster, ClusterOptions, ClusterStatus, PgxError, ProvisionLimits};
+use crate::{
+    app_role, assertions, basebackup, clone, doctor, extensions, hba, idle, info, initdb,
+    instances, offline, postmaster, profiles, progress, project, provision, readiness, reload,
+    reset, server_log, shell_env, snapshot, supervise, systemd, templates, tools, top,
+};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use postgresql_embedded::{PostgreSQL, Settings, Status, VersionReq};
+use postmaster::{Liveness, ShutdownMode};
+use shell_env::Shell;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+use tokio::time::{Duration, Instant, interval};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+type AppResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+const PGX_DATA_DIR_ENV: &str = "PGX_DATA_DIR";
+const PGX_PORT_ENV: &str = "PGX_PORT";
+const PGX_HOST_ENV: &str = "PGX_HOST";
+const PGX_LOG_FORMAT_ENV: &str = "PGX_LOG_FORMAT";
+
+/// Set from `--quiet` before any command runs.
+static QUIET: AtomicBool = AtomicBool::new(false);
+static JSON: AtomicBool = AtomicBool::new(false);
+/// Set from `--profile` before any command runs.
+static PROFILE: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+/// The `pgx.toml` found from the working directory, looked up before any
+/// command runs.
+static PROJECT: std::sync::OnceLock<Option<project::Project>> = std::sync::OnceLock::new();
+/// `--state-file` and `--password-file`, or their variables, set before any
+/// command runs and applied to the data directory it resolves.
+static SIDECAR_PATHS: std::sync::OnceLock<(Option<PathBuf>, Option<PathBuf>)> =
+    std::sync::OnceLock::new();
+const STATUS_WAIT_INTERVAL: Duration = Duration::from_millis(200);
+/// Windows terminates a closing console's processes after about five seconds.
+#[cfg(windows)]
+const CONSOLE_CLOSE_GRACE: Duration = Duration::from_secs(4);
+/// How long `pgx status --check` waits for its test connection.
+const STATUS_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Exit codes of `pgx status --check` when the postmaster is up but unusable.
+const STATUS_AUTH_FAILED_EXIT_CODE: i32 = 3;
+const STATUS_UNREACHABLE_EXIT_CODE: i32 = 4;
+/// Exit code for an operation that ran out of its `--timeout`, as with GNU timeout.
+const TIMEOUT_EXIT_CODE: i32 = 124;
+/// Window in which `--max-restarts` applies to a supervised server.
+const RESTART_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Extra time `stop --all` gives each cluster beyond `--timeout`, covering
+/// prewarm capture and `--force` escalation.
+const STOP_ALL_ALLOWANCE: Duration = Duration::from_secs(30);
+/// How often the foreground process re-checks a server whose exit is watched
+/// directly and whose log is not echoed, in case the watch misses it.
+const WATCHED_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long `pgx reset` waits for the server to stop, and with `--start` for
+/// it to accept connections again.
+const RESET_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Parser)]
+#[command(name = "pgx", version)]
+#[command(about = "Run embedded PostgreSQL 17 locally.")]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+    /// Print only results and errors; in foreground mode the server log is
+    /// still written to the `log` directory but not echoed.
+    #[arg(
+        short,
+        long,
+        global = true,
+        default_value_t = false,
+        conflicts_with = "verbose"
+    )]
+    quiet: bool,
+    /// Log more detail: `-v` for debug, `-vv` for trace.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Format of pgx's log output on stderr [env: PGX_LOG_FORMAT].
+    #[arg(long, global = true, value_enum)]
+    log_format: Option<LogFormat>,
+    /// Use the named data directory under the pgx home directory
+    /// (~/.local/share/pgx/profiles/<name>) when no data directory is given.
+    #[arg(short, long, global = true, visible_alias = "name")]
+    profile: Option<String>,
+    /// Keep the state file here instead of next to the data directory; a
+    /// pointer file next to it lets later commands find it [env: PGX_STATE_FILE].
+    #[arg(long, global = true, value_name = "PATH")]
+    state_file: Option<PathBuf>,
+    /// Keep the superuser password here instead of next to the data
+    /// directory; recorded in the state file [env: PGX_PASSWORD_FILE].
+    #[arg(long, global = true, value_name = "PATH")]
+    password_file: Option<PathBuf>,
+    /// Print results as JSON: the same as `--format json` on commands that
+    /// take it, and an object per cluster from start and stop. Informational
+    /// messages move to stderr.
+    #[arg(long, global = true, default_value_t = false)]
+    json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines (default).
+    Pretty,
+    /// Shorter human-readable lines.
+    Compact,
+    /// One JSON object per line with timestamp, level, target and fields.
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    Start(StartArgs),
+    Stop(StopArgs),
+    /// Stop the server and start it again on the same data directory, with
+    /// the same password and, unless overridden, the same port.
+    Restart(RestartArgs),
+    Status(StatusArgs),
+    Url(UrlArgs),
+    /// Re-read configuration files without restarting the server.
+    Reload(DataDirArgs),
+    /// Print the end of the server log, optionally following new lines.
+    Logs(LogsArgs),
+    /// Open the bundled psql, already connected to the cluster.
+    Psql(PsqlArgs),
+    /// Run SQL statements or files with the bundled psql, stopping at the
+    /// first error.
+    Exec(ExecArgs),
+    /// Back up a database with the bundled pg_dump.
+    Dump(DumpArgs),
+    /// Load a dump produced by `pgx dump` or pg_dump.
+    Restore(RestoreArgs),
+    /// Take a physical copy of the running cluster with the bundled pg_basebackup.
+    Basebackup(BasebackupArgs),
+    /// Save, list, restore and delete named copies of a stopped data directory.
+    Snapshot(SnapshotArgs),
+    /// Move a stopped cluster to a newer PostgreSQL major version with pg_upgrade.
+    Upgrade(UpgradeArgs),
+    /// Diagnose common problems with a data directory and its sidecar files.
+    Doctor(DoctorArgs),
+    /// Remove cached PostgreSQL installations that no known cluster uses.
+    Prune(PruneArgs),
+    /// Download PostgreSQL binaries into the cache without touching a data directory.
+    Fetch(FetchArgs),
+    /// Show the pgx version and the PostgreSQL build it resolves to.
+    Version(VersionArgs),
+    /// Describe a cluster: paths, version, state, uptime, databases and size.
+    Info(InfoArgs),
+    /// Install, print or remove a systemd user unit that starts the cluster at login.
+    Systemd(SystemdArgs),
+    /// Print a shell completion script, e.g. `pgx completions zsh > _pgx`.
+    Completions(CompletionsArgs),
+    /// Install and list PostgreSQL extensions such as pgvector.
+    Ext(ExtArgs),
+    /// Manage pgx's own block of pg_hba.conf authentication rules.
+    Hba(HbaArgs),
+    /// Build seeded template databases that `pgx createdb --from-template`
+    /// copies in milliseconds.
+    Template(TemplateArgs),
+    /// Create a database, optionally as a copy of a template, and print its URL.
+    Createdb(CreatedbArgs),
+    /// Run a PostgreSQL client tool bundled with the managed installation.
+    Bin(BinArgs),
+    /// Check a running instance against declared expectations.
+    Assert(AssertArgs),
+    /// Print connection environment variables for `eval "$(pgx env)"`.
+    Env(EnvArgs),
+    /// Run a command with DATABASE_URL and the PG* variables pointing at the instance.
+    Run(RunArgs),
+    /// Show transactions per second, connections, cache hit ratio, the
+    /// longest-running query and database sizes, refreshed periodically.
+    Top(TopArgs),
+    /// Reclaim dead rows and refresh planner statistics with VACUUM (ANALYZE).
+    Vacuum(VacuumArgs),
+    /// Refresh planner statistics with ANALYZE.
+    Analyze(AnalyzeArgs),
+    /// List profiles and every other cluster pgx has started, with status,
+    /// port, PostgreSQL version and data directory.
+    List(ListArgs),
+    /// Stop a cluster if it is running, then delete its data directory,
+    /// sidecar files and profile entry.
+    Destroy(DestroyArgs),
+    /// Delete every database and initialize the cluster again, keeping its
+    /// port, password and configuration files.
+    Reset(ResetArgs),
+    /// Copy a cluster to a new data directory or profile with a password of
+    /// its own. A running source is copied with pg_basebackup.
+    Clone(CloneArgs),
+}
+
+#[derive(Debug, Args)]
+struct StartArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Port to listen on; 0 picks a free one. Without it, the port of the
+    /// previous start is reused when still free [env: PGX_PORT].
+    #[arg(long)]
+    port: Option<u16>,
+    /// Host to listen on [default: localhost, env: PGX_HOST].
+    #[arg(long)]
+    host: Option<String>,
+    #[arg(long, default_value_t = false)]
+    daemon: bool,
+    /// If pgx already started the server and it accepts connections, print
+    /// its URL and exit 0 instead of failing.
+    #[arg(
+        long,
+        alias = "reuse",
+        default_value_t = false,
+        conflicts_with = "ephemeral"
+    )]
+    if_not_running: bool,
+    /// Load relations recorded on the previous stop into shared buffers after startup.
+    #[arg(long, default_value_t = false)]
+    prewarm: bool,
+    /// How long to wait for the server to accept connections, e.g. `30s` or `2m`.
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    ready_timeout: Duration,
+    /// How often the foreground process checks the server and echoes its log,
+    /// in milliseconds. On Linux and macOS a crash is noticed immediately
+    /// regardless; raise this to wake up less often.
+    #[arg(long, default_value_t = 250, value_parser = clap::value_parser!(u64).range(10..))]
+    poll_interval: u64,
+    /// Give up if setup, startup and readiness together take longer than this,
+    /// e.g. `5m`. Unlimited by default.
+    #[arg(long, value_parser = parse_duration)]
+    timeout: Option<Duration>,
+    /// Warn when the data directory's filesystem has less free space than
+    /// this, e.g. `5GiB` or `500MB`.
+    #[arg(long, default_value = "1GiB", value_parser = parse_size)]
+    min_free_space: u64,
+    /// Start even with less than 100 MiB free on the data directory's filesystem.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+    /// Run a throwaway cluster in a temporary directory that is deleted, along
+    /// with its sidecar files, when pgx exits.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["daemon", "data_dir"])]
+    ephemeral: bool,
+    /// Maximum number of pgx processes on this machine that may set up and start a
+    /// server at the same time [default: half the CPUs].
+    #[arg(long)]
+    provision_limit: Option<usize>,
+    /// Use a pre-downloaded PostgreSQL release archive or extracted installation
+    /// directory instead of downloading one [env: PGX_BINARIES].
+    ///
+    /// For air-gapped machines, download the archive for your platform (e.g.
+    /// postgresql-17.5.0-x86_64-unknown-linux-gnu.tar.gz) from
+    /// https://github.com/theseus-rs/postgresql-binaries/releases, or copy
+    /// ~/.theseus/postgresql/<version> from a machine where `pgx start` has
+    /// already run; only the latter includes the pg_search extension.
+    #[arg(long, value_name = "PATH")]
+    binaries: Option<PathBuf>,
+    /// Fail instead of downloading PostgreSQL binaries or extensions.
+    #[arg(long, default_value_t = false)]
+    offline: bool,
+    /// Where `--daemon` writes pgx's own log output and the server log, appending
+    /// across restarts [default: <data_dir>.pgx.log]. `-` keeps pgx's output on
+    /// stderr and leaves the server log in the data directory.
+    #[arg(long, value_name = "PATH", requires = "daemon")]
+    log_file: Option<PathBuf>,
+    /// How many times to retry a PostgreSQL download that failed with a
+    /// transient network error, backing off exponentially.
+    #[arg(long, default_value_t = 3)]
+    download_retries: u32,
+    /// Download PostgreSQL binaries from this mirror of the theseus-rs releases
+    /// instead of GitHub [env: PGX_RELEASES_URL].
+    #[arg(long, value_name = "URL")]
+    releases_url: Option<String>,
+    /// Write the port the server listens on to this file once it is up. A
+    /// foreground server removes the file again when it stops.
+    #[arg(long, value_name = "PATH")]
+    port_file: Option<PathBuf>,
+    /// postgresql.conf setting, e.g. `shared_buffers=256MB`; repeatable.
+    /// Settings are recorded, so later starts and restarts keep them until
+    /// set again; `KEY=` with no value drops one.
+    #[arg(long = "set", short = 'c', value_name = "KEY=VALUE", value_parser = parse_server_setting)]
+    server_settings: Vec<(String, String)>,
+    /// Set the connection URL in this `.env` file once the server is up,
+    /// keeping the file's other lines.
+    #[arg(long, value_name = "PATH")]
+    env_file: Option<PathBuf>,
+    /// Variable `--env-file` sets.
+    #[arg(long, default_value = "DATABASE_URL", value_parser = parse_env_key, requires = "env_file")]
+    env_key: String,
+    /// Stop the server after it has had no client connections for this long,
+    /// e.g. `30m` or `2h`. `0` disables it.
+    #[arg(long, value_parser = parse_duration, conflicts_with = "daemon")]
+    idle_timeout: Option<Duration>,
+    /// Restart the server with exponential backoff if it stops unexpectedly.
+    #[arg(long, default_value_t = false, conflicts_with = "daemon")]
+    supervise: bool,
+    /// Give up supervising after this many restarts within ten minutes.
+    #[arg(long, default_value_t = 5, requires = "supervise")]
+    max_restarts: u32,
+    /// Shell command to run before the cluster is set up and started, e.g. to
+    /// clear a cache. May be repeated; hooks run in order.
+    #[arg(long, value_name = "CMD")]
+    pre_start: Vec<String>,
+    /// Shell command to run once the server accepts connections, with
+    /// DATABASE_URL and the PG* variables set. May be repeated; a failing hook
+    /// stops the server and fails the start.
+    #[arg(long, value_name = "CMD")]
+    post_start: Vec<String>,
+    /// How long each hook may run before it is killed, e.g. `5m`.
+    #[arg(long, default_value = "5m", value_parser = parse_duration)]
+    hook_timeout: Duration,
+    /// Database that URLs, `pgx env` and pgx's own connections use, created
+    /// if it does not exist. Kept across starts [default: postgres]. Names
+    /// that are not plain identifiers must be double-quoted, e.g. `'"My DB"'`.
+    /// Repeatable: databases after the first are created too, but URLs keep
+    /// pointing at the first.
+    #[arg(long, value_parser = parse_database_name)]
+    database: Vec<String>,
+    /// SQL file to run once, after the first start of a new cluster, like
+    /// /docker-entrypoint-initdb.d. Repeatable; runs before --init-dir files.
+    #[arg(long, value_name = "FILE")]
+    init_sql: Vec<PathBuf>,
+    /// Run the `.sql` files in this directory, in file name order, as with
+    /// --init-sql. Other files are ignored.
+    #[arg(long, value_name = "DIR")]
+    init_dir: Vec<PathBuf>,
+    /// PostgreSQL version to run, e.g. `16` or `=16.4.0`; a bare number means
+    /// that major version. An existing cluster keeps the version it was
+    /// started with [default: the recorded version, else 17].
+    #[arg(long, value_name = "VERSION", value_parser = parse_pg_version)]
+    pg_version: Option<VersionReq>,
+    /// Locale for a new cluster, e.g. `en_US.UTF-8`.
+    #[arg(long)]
+    locale: Option<String>,
+    /// Encoding for a new cluster [default: UTF8].
+    #[arg(long)]
+    encoding: Option<String>,
+    /// Initialize a new cluster with data page checksums.
+    #[arg(long, default_value_t = false)]
+    data_checksums: bool,
+    /// Fail instead of warning when --locale, --encoding or --data-checksums
+    /// disagree with an existing cluster.
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+    /// Address for the server to listen on, e.g. `0.0.0.0` to accept LAN
+    /// connections. May be repeated; --host is always included.
+    #[arg(long, value_name = "ADDR")]
+    listen: Vec<String>,
+    /// Network allowed to log in with a password when listening beyond
+    /// loopback, e.g. `192.168.1.0/24` [default: samenet]. May be repeated.
+    #[arg(long, value_name = "CIDR", requires = "listen")]
+    allow: Vec<String>,
+    /// Also listen on a Unix socket in this directory [default when given
+    /// without a value: <data_dir>.pgx-socket]. Recorded, so later starts
+    /// keep it; `pgx url --socket` prints a URL that uses it. Unix only.
+    #[arg(long, value_name = "DIR")]
+    socket_dir: Option<Option<PathBuf>>,
+    /// Let local connections (Unix socket and loopback TCP) in without a
+    /// password. The rules stay in pg_hba.conf; `pgx hba list` and `pgx hba
+    /// remove` take them out again.
+    #[arg(long, default_value_t = false)]
+    trust_local: bool,
+    /// Name of the superuser created when the cluster is initialized
+    /// [default: postgres]. Recorded, so URLs and `pgx env` use it.
+    #[arg(long, value_name = "NAME", value_parser = parse_role_name)]
+    superuser: Option<String>,
+    /// Superuser password for a new cluster instead of a generated one. An
+    /// existing cluster keeps its password; giving a different one is an error.
+    #[arg(long, value_name = "PASSWORD")]
+    password: Option<String>,
+    /// Read the --password from this file, keeping it out of the process list.
+    #[arg(long, value_name = "PATH", conflicts_with = "password")]
+    password_from_file: Option<PathBuf>,
+    /// Create this non-superuser login role if it does not exist.
+    #[arg(long, value_name = "NAME")]
+    app_user: Option<String>,
+    /// Password for --app-user, or `generate` to keep the stored one or make
+    /// up a new one [default: generate].
+    #[arg(long, value_name = "PASSWORD", requires = "app_user")]
+    app_password: Option<String>,
+    /// Database owned by --app-user, created if it does not exist
+    /// [default: the --app-user name].
+    #[arg(long, value_name = "NAME", requires = "app_user")]
+    app_db: Option<String>,
+    /// Create or update a login role after startup, e.g. `app:secret` or
+    /// `migrator:secret:createdb,createrole`. Options are role attributes,
+    /// also as `no<attribute>`; the password cannot contain `:`. Repeatable;
+    /// `pgx url --role` prints a role's URL.
+    #[arg(long = "role", value_name = "NAME:PASSWORD[:OPTIONS]", value_parser = parse_role_spec)]
+    roles: Vec<app_role::Role>,
+    /// Serve TLS with a self-signed certificate, generated on first use and
+    /// kept in <data_dir>.pgx-tls. Later starts keep TLS on until --no-tls.
+    #[arg(long, default_value_t = false)]
+    tls: bool,
+    /// Serve plain connections only, even if an earlier start used --tls.
+    #[arg(long, default_value_t = false, conflicts_with = "tls")]
+    no_tls: bool,
+    /// Run under the Windows service control manager, stopping the server
+    /// when the service is stopped.
+    #[cfg(windows)]
+    #[arg(long, default_value_t = false, conflicts_with_all = ["daemon", "ephemeral"])]
+    windows_service: bool,
+}
+
+#[derive(Debug, Args)]
+struct UrlArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Print the URL of the role and database created with `start --app-user`.
+    #[arg(long, default_value_t = false)]
+    app: bool,
+    /// Print the URL of a role declared with `start --role`.
+    #[arg(long, value_name = "NAME", value_parser = parse_role_name, conflicts_with = "app")]
+    role: Option<String>,
+    /// sslmode to put in the URL [default: require for a server started with --tls].
+    #[arg(long)]
+    sslmode: Option<String>,
+    /// Query parameter to append, e.g. `connect_timeout=5`. Repeatable; the
+    /// last value given for a key wins.
+    #[arg(long = "param", value_name = "KEY=VALUE")]
+    params: Vec<String>,
+    /// Connect through the Unix socket of a server started with --socket-dir.
+    #[arg(long, default_value_t = false)]
+    socket: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct DataDirArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Give up waiting for the server after this long, e.g. `10s` or `2m`;
+    /// `0` waits forever.
+    #[arg(long, default_value = "60s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+#[derive(Debug, Args)]
+struct StopArgs {
+    /// May be repeated to stop several clusters at once.
+    #[arg(long)]
+    data_dir: Vec<PathBuf>,
+    /// Stop every known cluster: each --data-dir, PGX_DATA_DIR, every
+    /// profile and every other cluster pgx has started.
+    #[arg(long, default_value_t = false)]
+    all: bool,
+    /// Remove a stale postmaster.pid left behind by a crash or reboot.
+    #[arg(long, default_value_t = false)]
+    repair: bool,
+    #[arg(long, value_enum, default_value_t = ShutdownMode::Fast)]
+    mode: ShutdownMode,
+    /// If the graceful stop does not finish within --timeout, signal the
+    /// postmaster with SIGINT, then SIGQUIT, then SIGKILL.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+    /// How long to wait for the graceful stop, e.g. `30s` or `2m`; `0`
+    /// waits forever.
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+#[derive(Debug, Args)]
+struct RestartArgs {
+    #[arg(long, value_enum, default_value_t = ShutdownMode::Fast)]
+    mode: ShutdownMode,
+    /// How long to wait for the server to stop, e.g. `30s`; `0` waits forever.
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    stop_timeout: Duration,
+    #[command(flatten)]
+    start: StartArgs,
+}
+
+#[derive(Debug, Args)]
+struct StatusArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Remove a stale postmaster.pid left behind by a crash or reboot.
+    #[arg(long, default_value_t = false)]
+    repair: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// Block until the server is running and accepts connections.
+    #[arg(long, default_value_t = false)]
+    wait: bool,
+    /// How long --wait may block, e.g. `30s` or `2m`.
+    #[arg(long, default_value = "30s", value_parser = parse_duration, requires = "wait")]
+    timeout: Duration,
+    /// Keep running and print a line whenever the server starts or stops.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["wait", "repair"])]
+    watch: bool,
+    /// Polling interval for --watch, in milliseconds.
+    #[arg(long, default_value_t = 500, requires = "watch")]
+    interval: u64,
+    /// Also connect with the stored password and run `SELECT 1`. Exits with
+    /// 3 if authentication fails and 4 if the recorded port does not answer.
+    #[arg(long, default_value_t = false, conflicts_with = "watch")]
+    check: bool,
+    /// List the client connections of a running server from
+    /// `pg_stat_activity`.
+    #[arg(long, default_value_t = false, conflicts_with = "watch")]
+    connections: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Args)]
+struct ExtArgs {
+    #[command(subcommand)]
+    command: ExtCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ExtCommand {
+    /// Download an extension build for the cluster's PostgreSQL version and
+    /// install it into the managed installation.
+    Install(ExtInstallArgs),
+    /// Show installed and available extensions.
+    List(ExtListArgs),
+}
+
+#[derive(Debug, Args)]
+struct ExtInstallArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Extension name as shown by `pgx ext list`, e.g. `pgvector_compiled`;
+    /// `pgvector` is enough when only one build's name starts with it.
+    name: String,
+    /// Repository to install from when several provide the extension.
+    #[arg(long)]
+    namespace: Option<String>,
+    /// Version requirement for the extension build, e.g. `^0.8`.
+    #[arg(long, default_value = "*")]
+    version: String,
+    /// Run CREATE EXTENSION afterwards; the server must be running.
+    #[arg(long, default_value_t = false)]
+    create: bool,
+    /// Database for --create; repeatable [default: the one recorded with
+    /// `start --database`].
+    #[arg(long, requires = "create")]
+    database: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct ExtListArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct HbaArgs {
+    #[command(subcommand)]
+    command: HbaCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum HbaCommand {
+    /// Append a rule to the pgx block and reload a running server.
+    Add(HbaAddArgs),
+    /// Show the rules in the pgx block with their numbers.
+    List(DataDirArgs),
+    /// Show every rule in pg_hba.conf in the order PostgreSQL checks them.
+    Show(DataDirArgs),
+    /// Allow logins over TCP from an address and reload a running server.
+    Allow(HbaAllowArgs),
+    /// Remove a rule by the number shown by `pgx hba list`.
+    Remove(HbaRemoveArgs),
+}
+
+#[derive(Debug, Args)]
+struct HbaAddArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Connection type: local, host, hostssl, hostnossl, hostgssenc or hostnogssenc.
+    #[arg(long = "type", default_value = "host")]
+    kind: String,
+    #[arg(long, default_value = "all")]
+    database: String,
+    #[arg(long, default_value = "all")]
+    user: String,
+    /// CIDR address, host name, `samenet` or `all`; not used by local rules.
+    #[arg(long)]
+    address: Option<String>,
+    #[arg(long, default_value = "scram-sha-256")]
+    method: String,
+}
+
+#[derive(Debug, Args)]
+struct HbaAllowArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// CIDR address, host name, `samenet` or `all`, e.g. `172.17.0.0/16`.
+    address: String,
+    #[arg(long, default_value = "scram-sha-256")]
+    method: String,
+}
+
+#[derive(Debug, Args)]
+struct HbaRemoveArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    index: usize,
+}
+
+#[derive(Debug, Args)]
+struct TemplateArgs {
+    #[command(subcommand)]
+    command: TemplateCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum TemplateCommand {
+    /// Create a database, run SQL files in it and mark it as a template.
+    Create(TemplateCreateArgs),
+    /// Show the templates pgx created.
+    List(TemplateListArgs),
+    /// Drop a template database.
+    Drop(TemplateNameArgs),
+}
+
+#[derive(Debug, Args)]
+struct TemplateCreateArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    #[arg(value_parser = parse_database_name)]
+    name: String,
+    /// SQL file to seed the template with. Repeatable; run in the order given.
+    #[arg(long, value_name = "FILE", required = true)]
+    from_sql: Vec<PathBuf>,
+    /// Drop and rebuild a template of the same name.
+    #[arg(long, default_value_t = false)]
+    replace: bool,
+}
+
+#[derive(Debug, Args)]
+struct TemplateListArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct TemplateNameArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    #[arg(value_parser = parse_database_name)]
+    name: String,
+}
+
+#[derive(Debug, Args)]
+struct CreatedbArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    #[arg(value_parser = parse_database_name)]
+    name: String,
+    /// Copy this template made with `pgx template create` instead of an empty database.
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_database_name)]
+    from_template: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct BinArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// List the available tools.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["path", "tool"])]
+    list: bool,
+    /// Print the absolute path of a tool instead of running it.
+    #[arg(long, value_name = "TOOL", conflicts_with = "tool")]
+    path: Option<String>,
+    #[arg(required_unless_present_any = ["list", "path"])]
+    tool: Option<String>,
+    #[arg(last = true)]
+    args: Vec<OsString>,
+}
+
+#[derive(Debug, Args)]
+struct LogsArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Keep printing lines as the server writes them, until Ctrl-C.
+    #[arg(long, short, default_value_t = false)]
+    follow: bool,
+    /// How many of the most recent lines to print first.
+    #[arg(long, short = 'n', default_value_t = 50)]
+    lines: usize,
+}
+
+#[derive(Debug, Args)]
+struct PsqlArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// [default: the database recorded with `start --database`]
+    #[arg(long)]
+    database: Option<String>,
+    /// Extra arguments passed to psql unchanged, e.g. `-- -c 'select 1'`.
+    #[arg(last = true)]
+    args: Vec<OsString>,
+}
+
+#[derive(Debug, Args)]
+struct ExecArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// [default: the database recorded with `start --database`]
+    #[arg(long)]
+    database: Option<String>,
+    /// SQL to run; may be repeated. Statements run before any --file.
+    #[arg(long, short, required_unless_present = "file")]
+    command: Vec<String>,
+    /// SQL file to run, `-` for stdin; may be repeated.
+    #[arg(long, short)]
+    file: Vec<PathBuf>,
+    /// Run everything in one transaction so a failure changes nothing.
+    #[arg(long, default_value_t = false)]
+    single_transaction: bool,
+    /// Extra arguments passed to psql unchanged, e.g. `-- --tuples-only`.
+    #[arg(last = true)]
+    args: Vec<OsString>,
+}
+
+#[derive(Debug, Args)]
+struct DumpArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// File (or directory, for `--format directory`) to write; stdout if omitted.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// [default: the database recorded with `start --database`]
+    #[arg(long)]
+    database: Option<String>,
+    #[arg(long, value_enum, default_value_t = DumpFormat::Plain)]
+    format: DumpFormat,
+    /// Dump table definitions, functions and the like, but no rows.
+    #[arg(long, default_value_t = false)]
+    schema_only: bool,
+    /// Extra arguments passed to pg_dump unchanged.
+    #[arg(last = true)]
+    args: Vec<OsString>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DumpFormat {
+    Plain,
+    Custom,
+    Directory,
+}
+
+impl DumpFormat {
+    fn pg_dump_flag(self) -> &'static str {
+        match self {
+            DumpFormat::Plain => "--format=plain",
+            DumpFormat::Custom => "--format=custom",
+            DumpFormat::Directory => "--format=directory",
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct BasebackupArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Directory to write the backup to; must be new or empty.
+    #[arg(long, short)]
+    output: PathBuf,
+    #[arg(long, value_enum, default_value_t = BasebackupFormat::Plain)]
+    format: BasebackupFormat,
+    /// Compression passed to pg_basebackup, e.g. `gzip`, `lz4:5` or
+    /// `server-zstd`.
+    #[arg(long)]
+    compress: Option<String>,
+    /// `fast` checkpoints immediately instead of spreading the I/O out.
+    #[arg(long, value_enum, default_value_t = CheckpointMode::Spread)]
+    checkpoint: CheckpointMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BasebackupFormat {
+    /// A copy of the data directory that can be started as is.
+    Plain,
+    /// One tar file per tablespace, plus the WAL.
+    Tar,
+}
+
+impl BasebackupFormat {
+    fn name(self) -> &'static str {
+        match self {
+            BasebackupFormat::Plain => "plain",
+            BasebackupFormat::Tar => "tar",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CheckpointMode {
+    Fast,
+    Spread,
+}
+
+#[derive(Debug, Args)]
+struct RestoreArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Plain SQL file, custom-format archive, or directory-format dump.
+    #[arg(long, short)]
+    input: PathBuf,
+    /// [default: the database recorded with `start --database`]
+    #[arg(long)]
+    database: Option<String>,
+    /// Create the target database before restoring into it.
+    #[arg(long, default_value_t = false)]
+    create: bool,
+    /// Drop existing objects before recreating them (archive formats only).
+    #[arg(long, default_value_t = false)]
+    clean: bool,
+    /// Restore in one transaction so a failure leaves the database untouched.
+    #[arg(long, default_value_t = false)]
+    single_transaction: bool,
+    /// Extra arguments passed to psql or pg_restore unchanged.
+    #[arg(last = true)]
+    args: Vec<OsString>,
+}
+
+/// How `pgx restore` must load a given input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RestoreInput {
+    /// SQL script, executed with psql.
+    Plain,
+    /// pg_dump custom or directory archive, loaded with pg_restore.
+    Archive,
+}
+
+#[derive(Debug, Args)]
+struct SnapshotArgs {
+    #[command(subcommand)]
+    command: SnapshotCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum SnapshotCommand {
+    /// Copy the stopped data directory into <data_dir>.pgx-snapshots/<name>,
+    /// sharing blocks with it on filesystems that support reflinks.
+    Create(SnapshotCreateArgs),
+    /// Show snapshots with their sizes and creation times, oldest first.
+    List(SnapshotListArgs),
+    /// Replace the data directory with a snapshot; the server must be stopped.
+    Restore(SnapshotNameArgs),
+    /// Remove a snapshot.
+    Delete(SnapshotNameArgs),
+}
+
+#[derive(Debug, Args)]
+struct SnapshotCreateArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    name: String,
+    /// Stop the server first instead of refusing while it runs.
+    #[arg(long, default_value_t = false)]
+    stop: bool,
+}
+
+#[derive(Debug, Args)]
+struct SnapshotListArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct SnapshotNameArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    name: String,
+}
+
+#[derive(Debug, Args)]
+struct UpgradeArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Version requirement for the new server, e.g. `=18`.
+    #[arg(long)]
+    to: String,
+    /// Only run pg_upgrade's compatibility checks; leave the cluster untouched.
+    #[arg(long, default_value_t = false)]
+    check: bool,
+}
+
+#[derive(Debug, Args)]
+struct DoctorArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Rewrite a state file from an older pgx in the current schema.
+    #[arg(long, default_value_t = false)]
+    migrate_state: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct PruneArgs {
+    /// Data directories whose recorded PostgreSQL version must be kept; may be
+    /// repeated. PGX_DATA_DIR and every profile are always included.
+    #[arg(long)]
+    data_dir: Vec<PathBuf>,
+    /// Remove every cached installation, including referenced ones.
+    #[arg(long, default_value_t = false, conflicts_with = "keep")]
+    all: bool,
+    /// Only print what would be removed.
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+    /// Also keep installations matching this version requirement; may be repeated.
+    #[arg(long, value_name = "VERSION_REQ")]
+    keep: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct FetchArgs {
+    /// Version requirement to download, e.g. `=17` or `=17.5.0`.
+    #[arg(long, default_value = PG_VERSION_REQ)]
+    pg_version: String,
+    /// Fail instead of downloading when the version is not cached yet.
+    #[arg(long, default_value_t = false)]
+    offline: bool,
+    /// Download from this mirror of the theseus-rs releases [env: PGX_RELEASES_URL].
+    #[arg(long, value_name = "URL")]
+    releases_url: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct InfoArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct SystemdArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Write the unit to the systemd user directory and reload systemd.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["print", "uninstall"],
+        required_unless_present_any = ["print", "uninstall"]
+    )]
+    install: bool,
+    /// Print the unit instead of installing it.
+    #[arg(long, default_value_t = false, conflicts_with = "uninstall")]
+    print: bool,
+    /// Stop, disable and remove the unit.
+    #[arg(long, default_value_t = false)]
+    uninstall: bool,
+    /// Unit name suffix, giving pgx-<name>.service [default: data directory name].
+    #[arg(long)]
+    name: Option<String>,
+    #[arg(long, value_enum, default_value_t = systemd::RestartPolicy::OnFailure)]
+    restart: systemd::RestartPolicy,
+    /// Also enable and start the unit after installing it.
+    #[arg(long, default_value_t = false, requires = "install")]
+    enable: bool,
+    /// Skip `systemctl --user daemon-reload` after installing or removing.
+    #[arg(long, default_value_t = false)]
+    no_reload: bool,
+}
+
+#[derive(Debug, Args)]
+struct TopArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Time between refreshes, e.g. `1`, `5s` or `500ms`.
+    #[arg(long, default_value = "1", value_parser = parse_duration)]
+    interval: Duration,
+}
+
+#[derive(Debug, Args)]
+struct VacuumArgs {
+    #[command(flatten)]
+    target: MaintenanceTarget,
+    /// Rewrite tables completely with VACUUM FULL. Takes an exclusive lock on
+    /// each table while it is rewritten.
+    #[arg(long, default_value_t = false)]
+    full: bool,
+}
+
+#[derive(Debug, Args)]
+struct AnalyzeArgs {
+    #[command(flatten)]
+    target: MaintenanceTarget,
+}
+
+/// Which databases and tables `vacuum` and `analyze` process.
+#[derive(Debug, Args)]
+struct MaintenanceTarget {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Only this database [default: every non-template database].
+    #[arg(long)]
+    database: Option<String>,
+    /// Only this table; may be repeated.
+    #[arg(long)]
+    table: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct ListArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// Forget clusters whose data directory no longer exists.
+    #[arg(long, default_value_t = false)]
+    prune: bool,
+}
+
+#[derive(Debug, Args)]
+struct DestroyArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Confirm that the cluster's data may be deleted without asking.
+    #[arg(long, default_value_t = false)]
+    yes: bool,
+    /// How long to wait for a running server to stop, e.g. `30s`; `0` waits
+    /// forever.
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+#[derive(Debug, Args)]
+struct ResetArgs {
+    /// Options for the start with --start. --timeout also limits the new
+    /// initdb, and --init-sql/--init-dir name the scripts --seed re-applies.
+    #[command(flatten)]
+    server: StartArgs,
+    /// Confirm that every database in the cluster may be deleted without asking.
+    #[arg(long, default_value_t = false)]
+    yes: bool,
+    /// Start the server again afterwards, in the background, and print its URL.
+    #[arg(long, default_value_t = false)]
+    start: bool,
+    /// Run the init scripts (--init-sql, --init-dir and pgx.toml's
+    /// init_scripts) again on the next start, which is right away with --start.
+    #[arg(long, default_value_t = false)]
+    seed: bool,
+}
+
+#[derive(Debug, Args)]
+struct CloneArgs {
+    /// Data directory or profile name to copy. Values containing a path
+    /// separator, or naming an existing directory, are data directories.
+    #[arg(long)]
+    from: String,
+    /// Data directory or profile name to create.
+    #[arg(long)]
+    to: String,
+    /// Replace a target that already has data in it.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+}
+
+#[derive(Debug, Args)]
+struct VersionArgs {
+    /// Also report the PostgreSQL major version of this cluster.
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct CompletionsArgs {
+    shell: clap_complete::Shell,
+}
+
+#[derive(Debug, Args)]
+struct AssertArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// TOML file declaring the checks to run.
+    #[arg(long)]
+    file: Option<PathBuf>,
+    /// Require the server version to match a semver requirement, e.g. `>=17`.
+    #[arg(long)]
+    server_version: Option<String>,
+    /// Require an extension, optionally at a minimum version: `pgvector>=0.6`.
+    #[arg(long)]
+    extension: Vec<String>,
+    /// Require a database to exist.
+    #[arg(long)]
+    database: Vec<String>,
+    /// Require a table to exist, optionally schema-qualified.
+    #[arg(long)]
+    table: Vec<String>,
+    /// Exact row count required of every `--table`.
+    #[arg(long, requires = "table", conflicts_with = "min_rows")]
+    rows: Option<i64>,
+    /// Minimum row count required of every `--table`.
+    #[arg(long, requires = "table")]
+    min_rows: Option<i64>,
+    /// Require a server setting to have a value: `key=value`.
+    #[arg(long, value_name = "KEY=VALUE")]
+    setting: Vec<String>,
+    /// Require a role to exist.
+    #[arg(long)]
+    role: Vec<String>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct EnvArgs {
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// [default: fish when $SHELL is fish, powershell on Windows, otherwise sh]
+    #[arg(long, value_enum)]
+    shell: Option<Shell>,
+    /// Print `KEY=value` lines suitable for a `.env` file.
+    #[arg(long, default_value_t = false, conflicts_with = "shell")]
+    dotenv: bool,
+}
+
+#[derive(Debug, Args)]
+struct RunArgs {
+    #[command(flatten)]
+    server: StartArgs,
+    /// Start the server first if it is not already running. Implied by
+    /// --ephemeral, which also deletes the cluster once the command exits.
+    #[arg(long, default_value_t = false)]
+    start: bool,
+    #[arg(last = true, required = true)]
+    command: Vec<OsString>,
+}
+
+/// Temporary directory holding an ephemeral data directory and its sidecar
+/// files. Everything in it is removed on drop, so it must outlive the server.
+struct EphemeralRoot {
+    path: PathBuf,
+}
+
+enum ShutdownOutcome {
+    Signal,
+    ServerStopped,
+    Idle(Duration),
+}
+
+impl EphemeralRoot {
+    fn create() -> io::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or_default();
+        let path = std::env::temp_dir().join(format!("pgx-ephemeral-{}-{nanos}", process::id()));
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    fn data_dir(&self) -> PathBuf {
+        self.path.join("data")
+    }
+}
+
+impl Drop for EphemeralRoot {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_dir_all(&self.path) {
+            tracing::warn!("failed to remove {}: {error}", self.path.display());
+        }
+    }
+}
+
+/// Parse the command line and run the command; exits the process on failure.
+#[tokio::main]
+pub async fn main() {
+    let mut cli = Cli::parse();
+    if cli.json {
+        JSON.store(true, Ordering::Relaxed);
+        use_json_format(&mut cli.command);
+    }
+    if let Some(profile) = &cli.profile {
+        let _ = PROFILE.set(profile.clone());
+    }
+    let sidecar_paths = requested_sidecar_paths(cli.state_file.clone(), cli.password_file.clone());
+    if sidecar_paths != (None, None) {
+        if let Some(command) = multi_cluster_command(&cli.command) {
+            eprintln!(
+                "error: --state-file and --password-file ({PGX_STATE_FILE_ENV}, {PGX_PASSWORD_FILE_ENV}) name the files of a single cluster and cannot be used with `pgx {command}`"
+            );
+            process::exit(1);
+        }
+        let _ = SIDECAR_PATHS.set(sidecar_paths);
+    }
+    // Only the commands that start a server take options from the file; the
+    // rest merely look up its data_dir, so a broken file must not stop them.
+    let uses_project_defaults = matches!(
+        cli.command,
+        Commands::Start(_) | Commands::Restart(_) | Commands::Run(_) | Commands::Reset(_)
+    );
+    let project_defaults = std::env::current_dir()
+        .and_then(|dir| project::find(&dir))
+        .map_err(Into::into)
+        .and_then(|found| {
+            let _ = PROJECT.set(found);
+            match &mut cli.command {
+                Commands::Start(args) => apply_project_config(args),
+                Commands::Restart(args) => apply_project_config(&mut args.start),
+                Commands::Run(args) => apply_project_config(&mut args.server),
+                Commands::Reset(args) => apply_project_config(&mut args.server),
+                _ => Ok(()),
+            }
+        });
+    let project_error = match project_defaults {
+        Err(error) if uses_project_defaults => {
+            eprintln!("error: {error}");
+            process::exit(1);
+        }
+        result => result.err(),
+    };
+
+    if let Err(error) = init_tracing(&cli) {
+        eprintln!("error: {error}");
+        process::exit(1);
+    }
+    if let Some(error) = project_error {
+        tracing::warn!("ignoring {}: {error}", project::FILE_NAME);
+    }
+    let result = match cli.command {
+        Commands::Start(args) => handle_start(args).await,
+        Commands::Stop(args) => handle_stop(args).await,
+        Commands::Restart(args) => handle_restart(args).await,
+        Commands::Status(args) => handle_status(args).await,
+        Commands::Url(args) => handle_url(args).await,
+        Commands::Reload(args) => handle_reload(args).await,
+        Commands::Logs(args) => handle_logs(args).await,
+        Commands::Psql(args) => handle_psql(args),
+        Commands::Exec(args) => handle_exec(args),
+        Commands::Dump(args) => handle_dump(args).await,
+        Commands::Basebackup(args) => handle_basebackup(args).await,
+        Commands::Restore(args) => handle_restore(args).await,
+        Commands::Snapshot(args) => handle_snapshot(args).await,
+        Commands::Upgrade(args) => handle_upgrade(args).await,
+        Commands::Doctor(args) => handle_doctor(args).await,
+        Commands::Prune(args) => handle_prune(args).await,
+        Commands::Fetch(args) => handle_fetch(args).await,
+        Commands::Version(args) => handle_version(args).await,
+        Commands::Info(args) => handle_info(args).await,
+        Commands::Systemd(args) => handle_systemd(args),
+        Commands::Completions(args) => handle_completions(args),
+        Commands::Ext(args) => handle_ext(args).await,
+        Commands::Hba(args) => handle_hba(args).await,
+        Commands::Template(args) => handle_template(args).await,
+        Commands::Createdb(args) => handle_createdb(args).await,
+        Commands::Bin(args) => handle_bin(args).await,
+        Commands::Assert(args) => handle_assert(args).await,
+        Commands::Env(args) => handle_env(args).await,
+        Commands::Run(args) => handle_run(args).await,
+        Commands::Top(args) => handle_top(args).await,
+        Commands::Vacuum(args) => {
+            let mode = if args.full { "--full" } else { "--analyze" };
+            handle_maintenance(args.target, "VACUUM", mode).await
+        }
+        Commands::Analyze(args) => {
+            handle_maintenance(args.target, "ANALYZE", "--analyze-only").await
+        }
+        Commands::List(args) => handle_list(args),
+        Commands::Destroy(args) => handle_destroy(args).await,
+        Commands::Reset(args) => handle_reset(args).await,
+        Commands::Clone(args) => handle_clone(args).await,
+    };
+
+    if let Err(error) = result {
+        eprintln!("error: {error}");
+        let code = if matches!(error.downcast_ref(), Some(PgxError::TimedOut(_))) {
+            TIMEOUT_EXIT_CODE
+        } else {
+            1
+        };
+        process::exit(code);
+    }
+}
+
+async fn handle_start(args: StartArgs) -> AppResult<()> {
+    #[cfg(windows)]
+    if args.windows_service {
+        return handle_windows_service(args).await;
+    }
+
+    let (_ephemeral_root, data_dir) = start_data_dir(&args)?;
+    if !args.ephemeral && uses_profile(args.data_dir.as_deref()) {
+        profiles::register(&selected_profile(), &data_dir)?;
+    }
+    if args.if_not_running
+        && let Some(url) = reusable_server_url(&data_dir, &args).await?
+    {
+        print_started(&data_dir, &url)?;
+        return Ok(());
+    }
+    let cluster = Cluster::new(&data_dir, cluster_options(&args, &data_dir)?);
+    let server = cluster.start_server(interrupt()).await?;
+    print_started(&data_dir, &server.url)?;
+
+    if args.daemon {
+        std::mem::forget(server.postgresql);
+        return Ok(());
+    }
+
+    let mut postgresql = server.postgresql;
+    let mut log_tail = (!quiet()).then(|| server_log::LogTail::new(&data_dir));
+    let idle_timeout = args.idle_timeout.filter(|limit| !limit.is_zero());
+    let mut restarts = supervise::RestartBudget::new(args.max_restarts, RESTART_WINDOW);
+    let shutdown_outcome = loop {
+        let outcome = wait_for_shutdown_signal_or_server_stop(
+            &postgresql,
+            log_tail.as_mut(),
+            Duration::from_millis(args.poll_interval),
+            idle_timeout,
+            args.supervise,
+        )
+        .await?;
+        if !args.supervise || !matches!(outcome, ShutdownOutcome::ServerStopped) {
+            break outcome;
+        }
+
+        let reason = server_log::last_failure(&data_dir)
+            .unwrap_or_else(|| "no reason found in the server log".to_string());
+        let Some(delay) = restarts.next_delay() else {
+            tracing::error!(
+                "PostgreSQL stopped unexpectedly ({reason}); giving up after {} restarts within {}",
+                args.max_restarts,
+                humantime::format_duration(RESTART_WINDOW)
+            );
+            break outcome;
+        };
+        tracing::warn!(
+            "PostgreSQL stopped unexpectedly ({reason}); restarting in {}",
+            humantime::format_duration(delay)
+        );
+        if !sleep_unless_signal(delay).await? {
+            break ShutdownOutcome::Signal;
+        }
+        if let Err(error) = restart_server(&mut postgresql, &data_dir, args.ready_timeout).await {
+            tracing::warn!("restart failed: {error}");
+        }
+    };
+    if let ShutdownOutcome::Idle(limit) = shutdown_outcome {
+        report(format!(
+            "Stopping PostgreSQL after {} without client connections.",
+            humantime::format_duration(limit)
+        ));
+    }
+    let should_stop = matches!(
+        shutdown_outcome,
+        ShutdownOutcome::Signal | ShutdownOutcome::Idle(_)
+    ) && postgresql.status() == Status::Started;
+
+    if should_stop {
+        if args.prewarm {
+            record_prewarm_relations(&data_dir, postgresql.settings()).await;
+        }
+        stop_after_signal(&postgresql).await?;
+        mark_state_stopped(&data_dir)?;
+        report("PostgreSQL stopped cleanly.");
+    } else {
+        report("PostgreSQL is no longer running.");
+    }
+    if let Some(port_file) = &args.port_file
+        && let Err(error) = fs::remove_file(port_file)
+        && error.kind() != io::ErrorKind::NotFound
+    {
+        tracing::warn!("failed to remove {}: {error}", port_file.display());
+    }
+
+    Ok(())
+}
+
+/// URL of a server pgx already started for `data_dir` that still accepts
+/// connections, or `None` when `start` should go ahead. A server that is up
+/// but rejects the stored credentials is an error rather than a reason to
+/// start a second one.
+async fn reusable_server_url(data_dir: &Path, args: &StartArgs) -> AppResult<Option<String>> {
+    if read_state_file(data_dir)?.is_none() {
+        return Ok(None);
+    }
+    let runtime = load_runtime_context(data_dir.to_path_buf())?;
+    if runtime_liveness(&runtime) != Liveness::Running {
+        return Ok(None);
+    }
+
+    let url = database_url(&runtime);
+    match readiness::connectivity(&url, STATUS_CHECK_TIMEOUT).await {
+        readiness::Connectivity::Ok => {}
+        connectivity => {
+            return Err(io::Error::other(format!(
+                "already running for {}, but {connectivity}",
+                data_dir.display()
+            ))
+            .into());
+        }
+    }
+    if let Some(port_file) = &args.port_file {
+        write_port_file(port_file, runtime.connection.port)?;
+    }
+    tracing::info!("already running for {}; reusing it", data_dir.display());
+    Ok(Some(runtime.connection.url()))
+}
+
+/// Serve `pgx start` as a Windows service until SCM asks it to stop.
+#[cfg(windows)]
+async fn handle_windows_service(args: StartArgs) -> AppResult<()> {
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        service::run(move |stop| {
+            runtime.block_on(async move {
+                let (_ephemeral_root, data_dir) = start_data_dir(&args)?;
+                let cluster = Cluster::new(&data_dir, cluster_options(&args, &data_dir)?);
+                let server = cluster.start_server(interrupt()).await?;
+                let _ = stop.await;
+                stop_after_signal(&server.postgresql).await?;
+                mark_state_stopped(&data_dir)?;
+                if let Some(port_file) = &args.port_file {
+                    let _ = fs::remove_file(port_file);
+                }
+                Ok(())
+            })
+        })
+    })
+    .await??;
+    Ok(())
+}
+
+/// Completes on Ctrl-C, which abandons a start that is still setting up.
+async fn interrupt() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Stop the server after a shutdown signal.
+#[cfg(not(windows))]
+async fn stop_after_signal(postgresql: &PostgreSQL) -> AppResult<()> {
+    postgresql.stop().await?;
+    Ok(())
+}
+
+/// Stop the server after a shutdown signal. A closing console is killed
+/// shortly after it is signalled, so a clean stop that takes too long is
+/// cut short with an immediate shutdown.
+#[cfg(windows)]
+async fn stop_after_signal(postgresql: &PostgreSQL) -> AppResult<()> {
+    if let Ok(result) = tokio::time::timeout(CONSOLE_CLOSE_GRACE, postgresql.stop()).await {
+        return Ok(result?);
+    }
+
+    tracing::warn!("clean shutdown is taking too long; shutting down immediately");
+    // setup() has pointed installation_dir at the version directory.
+    let binary_dir = postgresql.settings().installation_dir.join("bin");
+    let pg_ctl = tools::tool_path(&binary_dir, "pg_ctl")?;
+    postmaster::stop(
+        &pg_ctl,
+        &postgresql.settings().data_dir,
+        ShutdownMode::Immediate,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn handle_stop(args: StopArgs) -> AppResult<()> {
+    if !args.all && args.data_dir.len() <= 1 {
+        let data_dir = resolve_data_dir(args.data_dir.first().cloned())?;
+        let message = stop_cluster(data_dir.clone(), &stop_options(&args)).await?;
+        if json_output() {
+            print_json(&serde_json::json!({
+                "data_dir": data_dir.display().to_string(),
+                "status": "not running",
+                "message": message,
+            }))?;
+        } else {
+            report(message);
+        }
+        return Ok(());
+    }
+
+    let data_dirs = stop_all_data_dirs(&args)?;
+    let limit = args.timeout + STOP_ALL_ALLOWANCE;
+    let options = stop_options(&args);
+    let mut stops = tokio::task::JoinSet::new();
+    for data_dir in data_dirs.iter().cloned() {
+        let options = options.clone();
+        stops.spawn(async move {
+            let label = data_dir.display().to_string();
+            let outcome = if options.timeout.is_zero() {
+                stop_cluster(data_dir, &options).await
+            } else {
+                bounded(
+                    limit,
+                    "the server to stop",
+                    stop_cluster(data_dir, &options),
+                )
+                .await
+            };
+            (label, outcome)
+        });
+    }
+
+    let mut failures = 0;
+    let mut results = Vec::new();
+    while let Some(joined) = stops.join_next().await {
+        match joined {
+            Ok((label, Ok(message))) if json_output() => results.push(serde_json::json!({
+                "data_dir": label,
+                "status": "not running",
+                "message": message,
+            })),
+            Ok((label, Ok(message))) => report(format!("{label}: {message}")),
+            Ok((label, Err(error))) => {
+                failures += 1;
+                if json_output() {
+                    results.push(serde_json::json!({
+                        "data_dir": label,
+                        "status": "error",
+                        "message": error.to_string(),
+                    }));
+                } else {
+                    println!("{label}: error: {error}");
+                }
+            }
+            Err(error) => {
+                failures += 1;
+                eprintln!("error: stop task failed: {error}");
+            }
+        }
+    }
+    if json_output() {
+        print_json(&results)?;
+    }
+
+    if failures > 0 {
+        return Err(io::Error::other(format!(
+            "{failures} of {} clusters failed to stop",
+            data_dirs.len()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// `pgx stop` followed by `pgx start` with the given start options. A
+/// server that is not running is simply started.
+async fn handle_restart(args: RestartArgs) -> AppResult<()> {
+    if args.start.ephemeral {
+        return Err(io::Error::other("an --ephemeral cluster cannot be restarted").into());
+    }
+    let data_dir = resolve_data_dir(args.start.data_dir.clone())?;
+    if read_state_file(&data_dir)?.is_none() {
+        return Err(io::Error::other(format!(
+            "{} has never been started with pgx; use `pgx start`",
+            data_dir.display()
+        ))
+        .into());
+    }
+
+    let stop = StopOptions {
+        mode: args.mode,
+        timeout: args.stop_timeout,
+        ..StopOptions::default()
+    };
+    report(format!("stop: {}", stop_cluster(data_dir, &stop).await?));
+    handle_start(args.start).await
+}
+
+/// Clusters targeted by `stop --all` or repeated `--data-dir` flags: every
+/// `--data-dir` plus `PGX_DATA_DIR`, without duplicates.
+fn stop_all_data_dirs(args: &StopArgs) -> AppResult<Vec<PathBuf>> {
+    let mut data_dirs = args.data_dir.clone();
+    if let Some(env_data_dir) = std::env::var_os(PGX_DATA_DIR_ENV)
+        && !env_data_dir.is_empty()
+    {
+        data_dirs.push(PathBuf::from(env_data_dir));
+    }
+    if args.all {
+        data_dirs.extend(
+            profiles::ProfileIndex::read()?
+                .profiles
+                .into_values()
+                .map(|profile| profile.data_dir)
+                .chain(instances::Registry::read()?.instances.into_keys())
+                .filter(|data_dir| state_file_path(data_dir).exists()),
+        );
+    }
+    // So `./db` and its absolute path count as one cluster.
+    let mut data_dirs = data_dirs
+        .iter()
+        .map(|data_dir| absolute_data_dir(data_dir))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut seen = std::collections::HashSet::new();
+    data_dirs.retain(|data_dir| seen.insert(data_dir.clone()));
+    if data_dirs.is_empty() {
+        return Err(io::Error::other(format!(
+            "no clusters to stop: pass --data-dir (repeatable), set {PGX_DATA_DIR_ENV} or start a profile"
+        ))
+        .into());
+    }
+    Ok(data_dirs)
+}
+
+/// Stop `data_dir` as `pgx stop` does, returning the message it prints.
+async fn stop_cluster(data_dir: PathBuf, options: &StopOptions) -> AppResult<String> {
+    Ok(Cluster::new(data_dir, ClusterOptions::default())
+        .stop_with(options)
+        .await?)
+}
+
+fn stop_options(args: &StopArgs) -> StopOptions {
+    StopOptions {
+        mode: args.mode,
+        timeout: args.timeout,
+        force: args.force,
+        repair: args.repair,
+    }
+}
+
+async fn handle_status(args: StatusArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    if args.watch {
+        return watch_status(&data_dir, Duration::from_millis(args.interval), args.format).await;
+    }
+
+    let cluster = Cluster::new(data_dir.clone(), ClusterOptions::default());
+    if !args.wait && cluster.status()? == ClusterStatus::NeverStarted {
+        return print_unrecorded_status(&data_dir, args.format);
+    }
+
+    let runtime = if args.wait {
+        wait_for_ready_runtime(&data_dir, args.timeout).await?
+    } else {
+        cluster.runtime()?
+    };
+
+    let connectivity = if args.check && runtime_liveness(&runtime) == Liveness::Running {
+        let url = database_url(&runtime);
+        Some(readiness::connectivity(&url, STATUS_CHECK_TIMEOUT).await)
+    } else {
+        None
+    };
+
+    let connections = if args.connections && runtime_liveness(&runtime) == Liveness::Running {
+        Some(list_connections(&runtime).await?)
+    } else {
+        None
+    };
+
+    if args.format == OutputFormat::Json {
+        print_status_json(
+            &runtime,
+            args.repair,
+            connectivity.as_ref(),
+            connections.as_deref(),
+        )?;
+    } else {
+        match runtime_liveness(&runtime) {
+            Liveness::Running => {
+                match &connectivity {
+                    None | Some(readiness::Connectivity::Ok) => println!("running"),
+                    Some(readiness::Connectivity::AuthenticationFailed) => {
+                        println!("running (authentication failed with stored password)")
+                    }
+                    Some(readiness::Connectivity::Refused) => println!(
+                        "running (connection refused on recorded port {})",
+                        runtime.connection.port
+                    ),
+                    Some(readiness::Connectivity::Failed(reason)) => println!(
+                        "running (connection to recorded port {} failed: {reason})",
+                        runtime.connection.port
+                    ),
+                }
+                println!("{}", runtime.connection.url());
+                if !runtime.state.listen_addresses.is_empty() {
+                    println!("listening on {}", runtime.state.listen_addresses.join(", "));
+                }
+                print_disk_usage(&runtime.data_dir);
+                if let Some(connections) = &connections {
+                    info::print_connections(connections);
+                }
+            }
+            Liveness::Stale => report_stale_pid_file(&runtime.data_dir, args.repair)?,
+            Liveness::Stopped => {
+                println!("{}", stopped_description(&runtime));
+                print_disk_usage(&runtime.data_dir);
+            }
+        }
+    }
+
+    match connectivity {
+        Some(readiness::Connectivity::AuthenticationFailed) => {
+            process::exit(STATUS_AUTH_FAILED_EXIT_CODE)
+        }
+        Some(readiness::Connectivity::Refused | readiness::Connectivity::Failed(_)) => {
+            process::exit(STATUS_UNREACHABLE_EXIT_CODE)
+        }
+        None | Some(readiness::Connectivity::Ok) => Ok(()),
+    }
+}
+
+async fn list_connections(runtime: &RuntimeContext) -> AppResult<Vec<info::Backend>> {
+    let url = database_url(runtime);
+    match tokio::time::timeout(STATUS_CHECK_TIMEOUT, info::connections(&url)).await {
+        Ok(Ok(backends)) => Ok(backends),
+        Ok(Err(error)) if readiness::is_authentication_failure(&error) => {
+            Err(io::Error::other(format!(
+                "cannot list connections: the server rejected the stored password in {}",
+                password_file_path(&runtime.data_dir).display()
+            ))
+            .into())
+        }
+        Ok(Err(error)) => Err(io::Error::other(format!("cannot list connections: {error}")).into()),
+        Err(_) => Err(io::Error::other(format!(
+            "cannot list connections: no answer within {}",
+            humantime::format_duration(STATUS_CHECK_TIMEOUT)
+        ))
+        .into()),
+    }
+}
+
+/// Size of the data directory and free space on its filesystem, either of
+/// which is `None` when it cannot be measured.
+fn disk_usage(data_dir: &Path) -> (Option<u64>, Option<u64>) {
+    (
+        snapshot::tree_size(data_dir).ok(),
+        doctor::free_space(data_dir).ok().flatten(),
+    )
+}
+
+fn print_disk_usage(data_dir: &Path) {
+    let (size, free) = disk_usage(data_dir);
+    let or_unknown =
+        |bytes: Option<u64>| bytes.map_or_else(|| "unknown".to_string(), snapshot::format_size);
+    println!(
+        "data dir size {}, {} free",
+        or_unknown(size),
+        or_unknown(free)
+    );
+}
+
+/// Status of a data directory pgx has no state file for.
+fn print_unrecorded_status(data_dir: &Path, format: OutputFormat) -> AppResult<()> {
+    let cluster_exists = cluster_is_initialized(data_dir);
+    let (status, description) = if cluster_exists {
+        (
+            "unknown",
+            "unknown (a cluster exists but pgx has no record of starting it)",
+        )
+    } else {
+        (
+            "never started",
+            "never started (no cluster or pgx state found)",
+        )
+    };
+
+    match format {
+        OutputFormat::Text => println!("{description}"),
+        OutputFormat::Json => print_json(&serde_json::json!({
+            "status": status,
+            "cluster_exists": cluster_exists,
+            "data_dir": data_dir.display().to_string(),
+        }))?,
+    }
+    Ok(())
+}
+
+async fn handle_url(args: UrlArgs) -> AppResult<()> {
+    let cluster = Cluster::new(resolve_data_dir(args.data_dir)?, ClusterOptions::default());
+    let mut params = std::collections::BTreeMap::new();
+    for raw in &args.params {
+        let (key, value) = parse_key_value(raw)?;
+        if value.is_empty() {
+            return Err(io::Error::other(format!("--param {key} needs a value")).into());
+        }
+        params.insert(key, value);
+    }
+    let (url, params) = cluster.url(&UrlRequest {
+        params,
+        socket: args.socket,
+        sslmode: args.sslmode,
+        app: args.app,
+        role: args.role,
+    })?;
+
+    match args.format {
+        OutputFormat::Text => println!("{url}"),
+        OutputFormat::Json => print_json(&serde_json::json!({ "url": url, "params": params }))?,
+    }
+    Ok(())
+}
+
+async fn handle_reload(args: DataDirArgs) -> AppResult<()> {
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+
+    let settings = runtime.postgresql.settings();
+    let pg_ctl = tools::tool_path(&tools::binary_dir(settings)?, "pg_ctl")?;
+    let url = settings_url(settings, DEFAULT_DATABASE);
+    let reloading = reload::reload(&pg_ctl, &runtime.data_dir, &url);
+    let pending = bounded(args.timeout, "the server to reload", reloading).await?;
+
+    report("reloaded");
+    if !pending.is_empty() {
+        println!("restart required to apply: {}", pending.join(", "));
+    }
+    Ok(())
+}
+
+async fn handle_basebackup(args: BasebackupArgs) -> AppResult<()> {
+    basebackup::ensure_empty_output(&args.output)?;
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    match runtime_liveness(&runtime) {
+        Liveness::Running => {}
+        _ => {
+            return Err(io::Error::other(format!(
+                "cannot take a base backup: not running; start it with `pgx start --data-dir {}`",
+                runtime.data_dir.display()
+            ))
+            .into());
+        }
+    }
+    let binary_dir = tools::binary_dir(runtime.postgresql.settings())?;
+
+    let mut command = tokio::process::Command::new(tools::tool_path(&binary_dir, "pg_basebackup")?);
+    command
+        .arg("--pgdata")
+        .arg(&args.output)
+        .arg(format!("--format={}", args.format.name()))
+        .arg(match args.checkpoint {
+            CheckpointMode::Fast => "--checkpoint=fast",
+            CheckpointMode::Spread => "--checkpoint=spread",
+        })
+        .arg("--wal-method=stream")
+        .arg("--progress")
+        .arg("--no-password")
+        .envs(connection_env(&runtime.connection));
+    if let Some(compress) = &args.compress {
+        command.arg(format!("--compress={compress}"));
+    }
+
+    let (status, stderr) = basebackup::run_streaming(command).await?;
+    if !status.success() {
+        return Err(basebackup_failure(&runtime, status, &stderr));
+    }
+
+    basebackup::write_metadata(
+        &args.output,
+        &basebackup::BasebackupMetadata {
+            source_data_dir: fs::canonicalize(&runtime.data_dir)?,
+            pg_version: runtime.state.pg_version.clone(),
+            format: args.format.name().to_string(),
+            compress: args.compress,
+            created_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        },
+    )?;
+    report(format!("base backup written to {}", args.output.display()));
+    Ok(())
+}
+
+/// Explain a failed pg_basebackup, pointing at pg_hba.conf when the server
+/// turned the replication connection away.
+fn basebackup_failure(
+    runtime: &RuntimeContext,
+    status: ExitStatus,
+    stderr: &str,
+) -> Box<dyn Error + Send + Sync> {
+    if basebackup::replication_refused(stderr) {
+        return io::Error::other(format!(
+            "the server does not accept replication connections from {}; allow them with \
+             `pgx hba add --data-dir {} --database replication --address 127.0.0.1/32 --method password` \
+             and check that max_wal_senders is above 0",
+            runtime.state.superuser(),
+            runtime.data_dir.display()
+        ))
+        .into();
+    }
+    io::Error::other(format!("pg_basebackup failed ({status})")).into()
+}
+
+async fn handle_logs(args: LogsArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let recorded = read_state_file(&data_dir)?.and_then(|state| state.log_file);
+    let path = server_log::log_file(&data_dir, recorded.as_deref())?;
+    match &path {
+        Some(path) => {
+            for line in server_log::last_lines(path, args.lines)? {
+                println!("{line}");
+            }
+        }
+        None if !args.follow => {
+            return Err(io::Error::other(format!(
+                "no server log for {} yet; it is written once the server has been started",
+                data_dir.display()
+            ))
+            .into());
+        }
+        None => {}
+    }
+    if !args.follow {
+        return Ok(());
+    }
+
+    // Without a file yet, the tail picks up the first one the server writes.
+    let mut tail = match path {
+        Some(path) => server_log::LogTail::from_end(&data_dir, path)?,
+        None => server_log::LogTail::new(&data_dir),
+    };
+    poll_until_signal(Duration::from_millis(250), || {
+        tail.print_new_lines();
+        None::<()>
+    })
+    .await?;
+    Ok(())
+}
+
+fn handle_psql(args: PsqlArgs) -> AppResult<()> {
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+    let binary_dir = tools::binary_dir(runtime.postgresql.settings())?;
+    let database = args
+        .database
+        .unwrap_or_else(|| runtime.connection.database.clone());
+
+    let mut command = Command::new(tools::tool_path(&binary_dir, "psql")?);
+    command
+        .arg(format!("--dbname={database}"))
+        .args(&args.args)
+        .envs(connection_env(&runtime.connection));
+    Err(tools::exec(command).into())
+}
+
+fn handle_exec(args: ExecArgs) -> AppResult<()> {
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+    let binary_dir = tools::binary_dir(runtime.postgresql.settings())?;
+    let database = args
+        .database
+        .unwrap_or_else(|| runtime.connection.database.clone());
+
+    let mut command = Command::new(tools::tool_path(&binary_dir, "psql")?);
+    command.args(["--no-psqlrc", "--set", "ON_ERROR_STOP=1"]);
+    if args.single_transaction {
+        command.arg("--single-transaction");
+    }
+    for sql in &args.command {
+        command.arg("--command").arg(sql);
+    }
+    for file in &args.file {
+        command.arg("--file").arg(file);
+    }
+    command
+        .arg(format!("--dbname={database}"))
+        .args(&args.args)
+        .envs(connection_env(&runtime.connection));
+    // psql exits 3 when a statement fails under ON_ERROR_STOP.
+    Err(tools::exec(command).into())
+}
+
+async fn handle_dump(args: DumpArgs) -> AppResult<()> {
+    if args.format == DumpFormat::Directory && args.output.is_none() {
+        return Err(io::Error::other("--format directory requires --output").into());
+    }
+
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    match runtime_liveness(&runtime) {
+        Liveness::Running => {}
+        _ => {
+            return Err(io::Error::other(format!(
+                "cannot dump: not running; start it with `pgx start --data-dir {}`",
+                runtime.data_dir.display()
+            ))
+            .into());
+        }
+    }
+    let binary_dir = tools::binary_dir(runtime.postgresql.settings())?;
+    let database = args
+        .database
+        .unwrap_or_else(|| runtime.connection.database.clone());
+
+    let mut command = Command::new(tools::tool_path(&binary_dir, "pg_dump")?);
+    command
+        .arg(args.format.pg_dump_flag())
+        .arg(format!("--dbname={database}"));
+    if let Some(output) = &args.output {
+        command.arg("--file").arg(output);
+    }
+    if args.schema_only {
+        command.arg("--schema-only");
+    }
+    command
+        .args(&args.args)
+        .envs(connection_env(&runtime.connection));
+    // pg_dump inherits stderr, so progress from `--verbose` streams as it runs,
+    // and exec hands its exit code straight back to the caller.
+    Err(tools::exec(command).into())
+}
+
+async fn handle_restore(args: RestoreArgs) -> AppResult<()> {
+    let input_kind = restore_input_kind(&args.input)?;
+    if args.clean && input_kind == RestoreInput::Plain {
+        return Err(io::Error::other(
+            "--clean only applies to archive dumps; for SQL files, dump with `pg_dump --clean` instead",
+        )
+        .into());
+    }
+
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+    let binary_dir = tools::binary_dir(runtime.postgresql.settings())?;
+    let env = connection_env(&runtime.connection);
+    let database = args
+        .database
+        .unwrap_or_else(|| runtime.connection.database.clone());
+
+    if args.create {
+        let status = tokio::process::Command::new(tools::tool_path(&binary_dir, "createdb")?)
+            .arg(&database)
+            .envs(env.clone())
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(io::Error::other(format!("could not create database {database}")).into());
+        }
+    }
+
+    let mut command = match input_kind {
+        RestoreInput::Plain => {
+            // Stop at the first failing statement; psql reports it with the
+            // file name and line number.
+            let mut command = Command::new(tools::tool_path(&binary_dir, "psql")?);
+            command
+                .args(["--no-psqlrc", "--quiet", "--set", "ON_ERROR_STOP=1"])
+                .arg("--file")
+                .arg(&args.input);
+            if args.single_transaction {
+                command.arg("--single-transaction");
+            }
+            command
+        }
+        RestoreInput::Archive => {
+            let mut command = Command::new(tools::tool_path(&binary_dir, "pg_restore")?);
+            if args.clean {
+                command.args(["--clean", "--if-exists"]);
+            }
+            if args.single_transaction {
+                command.arg("--single-transaction");
+            }
+            command.arg(&args.input);
+            command
+        }
+    };
+    command
+        .arg(format!("--dbname={database}"))
+        .args(&args.args)
+        .envs(env);
+
+    // The restore tool's own messages still reach stderr, between the
+    // elapsed-time updates.
+    let size = fs::metadata(&args.input)
+        .ok()
+        .filter(fs::Metadata::is_file)
+        .map(|metadata| format!(" ({})", snapshot::format_size(metadata.len())))
+        .unwrap_or_default();
+    let phase = format!("restoring {}{size} into {database}", args.input.display());
+    let status = progress::track(
+        &phase,
+        || None,
+        tokio::process::Command::from(command).status(),
+    )
+    .await?;
+    if !status.success() {
+        return Err(io::Error::other(format!("restore into {database} failed ({status})")).into());
+    }
+    report(format!("restored {} into {database}", args.input.display()));
+    Ok(())
+}
+
+/// Tell SQL scripts from pg_dump archives: directory dumps contain `toc.dat`
+/// and custom-format files start with the `PGDMP` magic.
+fn restore_input_kind(input: &Path) -> AppResult<RestoreInput> {
+    if input.is_dir() {
+        if input.join("toc.dat").is_file() {
+            return Ok(RestoreInput::Archive);
+        }
+        return Err(io::Error::other(format!(
+            "{} is a directory but not a directory-format dump (no toc.dat)",
+            input.display()
+        ))
+        .into());
+    }
+
+    let mut magic = [0u8; 5];
+    let mut file = fs::File::open(input)
+        .map_err(|error| io::Error::other(format!("{}: {error}", input.display())))?;
+    let is_archive = io::Read::read_exact(&mut file, &mut magic).is_ok() && &magic == b"PGDMP";
+    Ok(if is_archive {
+        RestoreInput::Archive
+    } else {
+        RestoreInput::Plain
+    })
+}
+
+async fn handle_snapshot(args: SnapshotArgs) -> AppResult<()> {
+    match args.command {
+        SnapshotCommand::Create(args) => handle_snapshot_create(args).await,
+        SnapshotCommand::List(args) => handle_snapshot_list(args),
+        SnapshotCommand::Restore(args) => handle_snapshot_restore(args).await,
+        SnapshotCommand::Delete(args) => handle_snapshot_delete(args),
+    }
+}
+
+fn handle_snapshot_list(args: SnapshotListArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let snapshots = snapshot::list(&snapshot_root_path(&data_dir))?;
+    if args.format == OutputFormat::Json {
+        let snapshots: Vec<_> = snapshots
+            .iter()
+            .map(|info| {
+                serde_json::json!({
+                    "name": info.name,
+                    "size_bytes": info.size,
+                    "created_at": info.metadata.as_ref().map(|metadata| &metadata.created_at),
+                    "pg_version": info.metadata.as_ref().and_then(|metadata| metadata.pg_version.as_ref()),
+                })
+            })
+            .collect();
+        return print_json(&snapshots);
+    }
+    for info in snapshots {
+        let created_at = info
+            .metadata
+            .as_ref()
+            .map_or("unknown", |metadata| metadata.created_at.as_str());
+        println!(
+            "{}\t{}\t{created_at}",
+            info.name,
+            snapshot::format_size(info.size)
+        );
+    }
+    Ok(())
+}
+
+fn handle_snapshot_delete(args: SnapshotNameArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let _operation_lock = acquire_operation_lock(&data_dir)?;
+    snapshot::delete(&snapshot_root_path(&data_dir), &args.name)?;
+    report(format!("deleted snapshot {}", args.name));
+    Ok(())
+}
+
+async fn handle_snapshot_create(args: SnapshotCreateArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let root = snapshot_root_path(&data_dir);
+    let name = args.name;
+    let runtime = load_runtime_context(data_dir)?;
+    let _operation_lock = acquire_operation_lock(&runtime.data_dir)?;
+    if runtime_liveness(&runtime) == Liveness::Running {
+        if !args.stop {
+            return Err(io::Error::other(
+                "cannot snapshot a running server; stop it first or pass --stop",
+            )
+            .into());
+        }
+        let pg_ctl =
+            tools::tool_path(&tools::binary_dir(runtime.postgresql.settings())?, "pg_ctl")?;
+        postmaster::stop(&pg_ctl, &runtime.data_dir, ShutdownMode::Fast).await?;
+        mark_state_stopped(&runtime.data_dir)?;
+        report("stopped");
+    }
+
+    let info = snapshot::create(
+        &runtime.data_dir,
+        &password_file_path(&runtime.data_dir),
+        &root,
+        &name,
+        runtime.state.pg_version.clone(),
+    )?;
+    report(format!(
+        "saved snapshot {} ({})",
+        info.name,
+        snapshot::format_size(info.size)
+    ));
+    Ok(())
+}
+
+async fn handle_snapshot_restore(args: SnapshotNameArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let _operation_lock = acquire_operation_lock(&data_dir)?;
+    let state = read_state_file(&data_dir)?;
+    let host = state
+        .as_ref()
+        .map_or("localhost", |state| state.host.as_str());
+    if postmaster::inspect(&data_dir, host) == Liveness::Running {
+        return Err(io::Error::other(
+            "cannot restore a snapshot while the server is running; stop it first",
+        )
+        .into());
+    }
+
+    let metadata = snapshot::restore(
+        &data_dir,
+        &password_file_path(&data_dir),
+        &snapshot_root_path(&data_dir),
+        &args.name,
+        &sidecar_file_path(&data_dir, "pgx-restore-staging"),
+        &sidecar_file_path(&data_dir, "pgx-restore-replaced"),
+    )?;
+
+    // The recorded runtime details describe the server that was replaced.
+    if let Some(mut state) = state {
+        state.running = false;
+        state.pid = None;
+        state.started_at = None;
+        state.pg_version = metadata.pg_version;
+        write_state_file(&data_dir, &state)?;
+    }
+    let _ = fs::remove_file(prewarm_file_path(&data_dir));
+
+    report(format!(
+        "restored snapshot {} (taken {})",
+        args.name, metadata.created_at
+    ));
+    Ok(())
+}
+
+async fn handle_upgrade(args: UpgradeArgs) -> AppResult<()> {
+    let target = VersionReq::parse(&args.to)?;
+    let data_dir = fs::canonicalize(resolve_data_dir(args.data_dir)?)?;
+    let _operation_lock = acquire_operation_lock(&data_dir)?;
+    if !cluster_is_initialized(&data_dir) {
+        return Err(io::Error::other(format!("no cluster found in {}", data_dir.display())).into());
+    }
+
+    let state = read_state_file(&data_dir)?;
+    let host = state
+        .as_ref()
+        .map_or("localhost", |state| state.host.as_str());
+    if postmaster::inspect(&data_dir, host) != Liveness::Stopped {
+        return Err(io::Error::other(
+            "the server must be stopped before upgrading; run `pgx stop` (or `pgx stop --repair`) first",
+        )
+        .into());
+    }
+
+    let old_major = cluster_major_version(&data_dir)?.ok_or_else(|| {
+        io::Error::other(format!("{} has no PG_VERSION file", data_dir.display()))
+    })?;
+    let moved_aside = sidecar_file_path(&data_dir, "old");
+    if !args.check && moved_aside.exists() {
+        return Err(io::Error::other(format!(
+            "{} already exists; remove it before upgrading again",
+            moved_aside.display()
+        ))
+        .into());
+    }
+    let password = read_managed_password_file(&data_dir)?.ok_or_else(metadata_error)?;
+
+    let mut old_settings = build_settings(&data_dir, None, None, Some(password.clone()))?;
+    old_settings.version = VersionReq::parse(&format!("={old_major}"))?;
+    let old_binary_dir = tools::binary_dir(&old_settings).map_err(|_| {
+        io::Error::other(format!(
+            "PostgreSQL {old_major} binaries are not installed in {}; they are needed to read the old cluster",
+            old_settings.installation_dir.display()
+        ))
+    })?;
+
+    // Initialize the new cluster next to the old one with the same superuser
+    // password, so pg_upgrade can authenticate against both.
+    let new_data_dir = sidecar_file_path(&data_dir, "pgx-upgrade");
+    if new_data_dir.exists() {
+        fs::remove_dir_all(&new_data_dir)?;
+    }
+    let mut new_settings = build_settings(&new_data_dir, None, None, Some(password.clone()))?;
+    new_settings.version = target;
+    new_settings.username = old_settings.username.clone();
+    new_settings.password_file = password_file_path(&data_dir);
+    let mut new_postgresql = PostgreSQL::new(new_settings);
+    tracing::info!(
+        "installing PostgreSQL {} and initializing the new cluster",
+        args.to
+    );
+    let upgrade_logs = sidecar_file_path(&data_dir, "pgx-upgrade-logs");
+    let upgraded: AppResult<String> = async {
+        new_postgresql.setup().await?;
+        set_owner_only_permissions(&password_file_path(&data_dir))?;
+
+        let new_version = resolved_version(new_postgresql.settings());
+        let new_major = semver::Version::parse(&new_version)?.major;
+        if new_major <= old_major {
+            let message = if new_major == old_major {
+                format!("cluster is already at PostgreSQL {old_major}; nothing to upgrade")
+            } else {
+                format!("cannot downgrade from PostgreSQL {old_major} to {new_major}")
+            };
+            return Err(io::Error::other(message).into());
+        }
+
+        extensions::initialize()?;
+        extensions::install_pg_search(new_postgresql.settings()).await?;
+        // setup() has pointed installation_dir at the version directory.
+        let new_binary_dir = new_postgresql.settings().installation_dir.join("bin");
+
+        let mut pg_upgrade =
+            tokio::process::Command::new(tools::tool_path(&new_binary_dir, "pg_upgrade")?);
+        pg_upgrade
+            .arg("--old-bindir")
+            .arg(&old_binary_dir)
+            .arg("--new-bindir")
+            .arg(&new_binary_dir)
+            .arg("--old-datadir")
+            .arg(&data_dir)
+            .arg("--new-datadir")
+            .arg(&new_data_dir)
+            .arg("--username")
+            .arg(&old_settings.username)
+            .env("PGPASSWORD", &password)
+            // pg_upgrade writes its socket and scripts to the working directory.
+            .current_dir(data_dir.parent().unwrap_or_else(|| Path::new(".")));
+        if args.check {
+            pg_upgrade.arg("--check");
+        }
+        let status = pg_upgrade.status().await?;
+        if !status.success() {
+            // The new cluster is removed below; keep pg_upgrade's logs.
+            let output = new_data_dir.join("pg_upgrade_output.d");
+            let mut message = format!("pg_upgrade failed ({status}); the old cluster is untouched");
+            if output.exists() {
+                if upgrade_logs.exists() {
+                    fs::remove_dir_all(&upgrade_logs)?;
+                }
+                fs::rename(&output, &upgrade_logs)?;
+                message.push_str(&format!(" and logs are in {}", upgrade_logs.display()));
+            }
+            return Err(io::Error::other(message).into());
+        }
+        Ok(new_version)
+    }
+    .await;
+
+    let new_version = match upgraded {
+        Ok(new_version) if !args.check => new_version,
+        result => {
+            // Whether the upgrade failed or was only checked, the new
+            // cluster is not kept.
+            if let Err(error) = fs::remove_dir_all(&new_data_dir)
+                && error.kind() != io::ErrorKind::NotFound
+            {
+                tracing::warn!("failed to remove {}: {error}", new_data_dir.display());
+            }
+            let new_version = result?;
+            println!("upgrade check from PostgreSQL {old_major} to {new_version} passed");
+            return Ok(());
+        }
+    };
+
+    fs::rename(&data_dir, &moved_aside).inspect_err(|_| {
+        let _ = fs::remove_dir_all(&new_data_dir);
+    })?;
+    if let Err(error) = fs::rename(&new_data_dir, &data_dir) {
+        let _ = fs::rename(&moved_aside, &data_dir);
+        let _ = fs::remove_dir_all(&new_data_dir);
+        return Err(error.into());
+    }
+    if let Some(mut state) = state {
+        state.pid = None;
+        state.started_at = None;
+        state.pg_version = Some(new_version.clone());
+        write_state_file(&data_dir, &state)?;
+    }
+
+    report(format!(
+        "upgraded from PostgreSQL {old_major} to {new_version}"
+    ));
+    report(format!("old cluster kept at {}", moved_aside.display()));
+    report(
+        "after starting, refresh planner statistics with `pgx bin vacuumdb -- --all --analyze-in-stages`",
+    );
+    Ok(())
+}
+
+async fn handle_doctor(args: DoctorArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let mut findings = vec![
+        doctor::data_dir_writable(&data_dir),
+        cluster_version_finding(&data_dir),
+    ];
+    let reset_dir = reset_dir_path(&data_dir);
+    if reset_dir.exists() {
+        findings.push(doctor::Finding::fail(
+            "reset",
+            format!(
+                "a reset did not finish; {} holds the saved configuration",
+                reset_dir.display()
+            ),
+            format!(
+                "run `pgx reset --data-dir {} --yes` to complete it",
+                data_dir.display()
+            ),
+        ));
+    }
+
+    let state_path = state_file_path(&data_dir);
+    let state = match read_state_file(&data_dir) {
+        Ok(Some(state)) => {
+            let written = state_file_schema_version(&data_dir)?.unwrap_or(STATE_SCHEMA_VERSION);
+            findings.push(if written == STATE_SCHEMA_VERSION {
+                doctor::Finding::pass(
+                    "state file",
+                    format!("{} is valid (schema {written})", state_path.display()),
+                )
+            } else if args.migrate_state {
+                write_state_file(&data_dir, &state)?;
+                doctor::Finding::pass(
+                    "state file",
+                    format!(
+                        "{} migrated from schema {written} to {STATE_SCHEMA_VERSION}",
+                        state_path.display()
+                    ),
+                )
+            } else {
+                doctor::Finding::warn(
+                    "state file",
+                    format!(
+                        "{} uses schema {written}; it is read as schema {STATE_SCHEMA_VERSION}",
+                        state_path.display()
+                    ),
+                    "run `pgx doctor --migrate-state` or start the server to rewrite it",
+                )
+            });
+            Some(state)
+        }
+        Ok(None) => {
+            findings.push(doctor::Finding::warn(
+                "state file",
+                format!("{} does not exist", state_path.display()),
+                "run `pgx start` to create it",
+            ));
+            None
+        }
+        Err(error @ PgxError::UnsupportedStateSchema { .. }) => {
+            findings.push(doctor::Finding::fail(
+                "state file",
+                error.to_string(),
+                "upgrade pgx; deleting the file would lose the recorded port and settings",
+            ));
+            None
+        }
+        Err(error) => {
+            findings.push(doctor::Finding::fail(
+                "state file",
+                format!("{} cannot be parsed: {error}", state_path.display()),
+                format!(
+                    "delete {}; the next `pgx start` rewrites it",
+                    state_path.display()
+                ),
+            ));
+            None
+        }
+    };
+
+    let password_path = password_file_path(&data_dir);
+    findings.push(match read_managed_password_file(&data_dir) {
+        Ok(Some(_)) => doctor::Finding::pass("password file", password_path.display().to_string()),
+        Ok(None) if cluster_is_initialized(&data_dir) => doctor::Finding::fail(
+            "password file",
+            format!(
+                "{} is missing or empty for an initialized cluster",
+                password_path.display()
+            ),
+            format!(
+                "reset the postgres password and write it to {}",
+                password_path.display()
+            ),
+        ),
+        Ok(None) => doctor::Finding::pass("password file", "not created yet"),
+        Err(error) => doctor::Finding::fail(
+            "password file",
+            format!("cannot read {}: {error}", password_path.display()),
+            "check the ownership of the password file",
+        ),
+    });
+    findings.push(doctor::owner_only_permissions(
+        "data directory permissions",
+        &data_dir,
+        false,
+    ));
+    findings.push(doctor::owner_only_permissions(
+        "password file permissions",
+        &password_path,
+        true,
+    ));
+    findings.push(doctor::owner_only_permissions(
+        "state file permissions",
+        &state_path,
+        false,
+    ));
+    if let Some(log_file) = state.as_ref().and_then(|state| state.log_file.as_ref()) {
+        findings.push(doctor::owner_only_permissions(
+            "log file permissions",
+            log_file,
+            false,
+        ));
+    }
+
+    let host = state
+        .as_ref()
+        .map_or("localhost", |state| state.host.as_str());
+    findings.push(doctor::pid_file_consistency(
+        &data_dir,
+        host,
+        state.as_ref().and_then(|state| state.pid),
+    ));
+    if let Some(state) = &state {
+        let running = postmaster::inspect(&data_dir, host) == Liveness::Running;
+        findings.push(doctor::port(host, state.port, running));
+    }
+    findings.push(doctor::disk_space(&doctor::free_space, &data_dir));
+
+    let failed = findings
+        .iter()
+        .filter(|finding| finding.verdict == doctor::Verdict::Fail)
+        .count();
+    match args.format {
+        OutputFormat::Text => doctor::print_text(&findings),
+        OutputFormat::Json => {
+            let warned = findings
+                .iter()
+                .filter(|finding| finding.verdict == doctor::Verdict::Warn)
+                .count();
+            let output = serde_json::json!({
+                "failed": failed,
+                "warnings": warned,
+                "checks": &findings,
+            });
+            print_json(&output)?;
+        }
+    }
+
+    if failed > 0 {
+        return Err(
+            io::Error::other(format!("{failed} of {} checks failed", findings.len())).into(),
+        );
+    }
+    Ok(())
+}
+
+fn cluster_version_finding(data_dir: &Path) -> doctor::Finding {
+    const CHECK: &str = "PG_VERSION";
+    let configured = VersionReq::parse(&configured_version(data_dir)).ok();
+    match cluster_major_version(data_dir) {
+        Ok(None) => doctor::Finding::pass(CHECK, "no cluster initialized yet"),
+        Ok(Some(major)) => match configured.as_ref().and_then(required_major_version) {
+            Some(configured) if configured != major => doctor::Finding::fail(
+                CHECK,
+                format!("cluster is PostgreSQL {major} but pgx is configured for {configured}"),
+                format!("run `pgx upgrade --to ={configured}`, or choose --pg-version {major}"),
+            ),
+            _ => doctor::Finding::pass(CHECK, format!("PostgreSQL {major}")),
+        },
+        Err(error) => doctor::Finding::fail(
+            CHECK,
+            error.to_string(),
+            "the data directory may be corrupt; restore it from a backup or snapshot",
+        ),
+    }
+}
+
+async fn handle_prune(args: PruneArgs) -> AppResult<()> {
+    let mut data_dirs = args.data_dir.clone();
+    if let Some(env_data_dir) = std::env::var_os(PGX_DATA_DIR_ENV)
+        && !env_data_dir.is_empty()
+    {
+        data_dirs.push(PathBuf::from(env_data_dir));
+    }
+    data_dirs.extend(
+        profiles::ProfileIndex::read()?
+            .profiles
+            .into_values()
+            .map(|profile| profile.data_dir)
+            .chain(instances::Registry::read()?.instances.into_keys()),
+    );
+
+    let mut keep = Vec::new();
+    for requirement in &args.keep {
+        keep.push(VersionReq::parse(requirement)?);
+    }
+    // Versions recorded by known clusters, plus the configured version so the
+    // next `pgx start` does not download it again.
+    let mut referenced = Vec::new();
+    for data_dir in &data_dirs {
+        let version = match read_state_file(data_dir) {
+            Ok(state) => state.and_then(|state| state.pg_version),
+            Err(error) => {
+                tracing::warn!("skipping {}: {error}", data_dir.display());
+                continue;
+            }
+        };
+        if let Some(version) = version {
+            match semver::Version::parse(&version) {
+                Ok(version) => referenced.push(version),
+                Err(error) => tracing::warn!(
+                    "skipping {}: invalid PostgreSQL version '{version}': {error}",
+                    data_dir.display()
+                ),
+            }
+        }
+    }
+
+    let installation_dir = Settings::default().installation_dir;
+    let mut installations = tools::installations(&installation_dir);
+    installations.sort_by(|(left, _), (right, _)| left.cmp(right));
+    let configured = VersionReq::parse(PG_VERSION_REQ)?;
+    let newest_configured = installations
+        .iter()
+        .rev()
+        .find(|(version, _)| configured.matches(version))
+        .map(|(version, _)| version.clone());
+
+    let mut freed = 0;
+    let mut removed = 0;
+    for (version, path) in &installations {
+        let size = snapshot::tree_size(path)?;
+        let reason = if args.all {
+            None
+        } else if referenced.contains(version) {
+            Some("used by a known cluster")
+        } else if keep.iter().any(|requirement| requirement.matches(version)) {
+            Some("matches --keep")
+        } else if newest_configured.as_ref() == Some(version) {
+            Some("configured version")
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => println!(
+                "keep    {version}\t{}\t{reason}",
+                snapshot::format_size(size)
+            ),
+            None => {
+                println!("remove  {version}\t{}", snapshot::format_size(size));
+                if !args.dry_run {
+                    fs::remove_dir_all(path)?;
+                }
+                freed += size;
+                removed += 1;
+            }
+        }
+    }
+
+    let verb = if args.dry_run { "would free" } else { "freed" };
+    println!(
+        "{verb} {} from {removed} installation(s) in {}",
+        snapshot::format_size(freed),
+        installation_dir.display()
+    );
+    Ok(())
+}
+
+async fn handle_fetch(args: FetchArgs) -> AppResult<()> {
+    let mut settings = Settings {
+        version: VersionReq::parse(&args.pg_version)?,
+        ..Settings::default()
+    };
+    if let Some(releases_url) = args.releases_url.or_else(env_releases_url) {
+        settings.releases_url = releases_url;
+    }
+
+    let (version, installation) = offline::fetch(&settings, args.offline).await?;
+    // Warm pg_search too, so the first `pgx start` needs no network at all.
+    if !offline::has_pg_search(&installation.join("bin")) {
+        if args.offline {
+            tracing::warn!("pg_search is not cached and --offline forbids downloading it");
+        } else {
+            settings.installation_dir = installation.clone();
+            settings.version = VersionReq::parse(&format!("={version}"))?;
+            extensions::initialize()?;
+            extensions::install_pg_search(&settings).await?;
+        }
+    }
+
+    println!("PostgreSQL {version}\t{}", installation.display());
+    Ok(())
+}
+
+async fn handle_version(args: VersionArgs) -> AppResult<()> {
+    let mut settings = Settings {
+        version: VersionReq::parse(PG_VERSION_REQ)?,
+        ..Settings::default()
+    };
+    if let Some(releases_url) = env_releases_url() {
+        settings.releases_url = releases_url;
+    }
+
+    let installed = tools::installations(&settings.installation_dir)
+        .into_iter()
+        .filter(|(version, _)| settings.version.matches(version))
+        .max_by(|(left, _), (right, _)| left.cmp(right));
+    // Only ask the releases server when nothing is cached, so this works offline.
+    let resolved = match &installed {
+        Some((version, _)) => Ok(version.clone()),
+        None => postgresql_archive::get_version(&settings.releases_url, &settings.version)
+            .await
+            .map_err(|error| error.to_string()),
+    };
+
+    let data_dir = match args.data_dir {
+        Some(data_dir) => Some(resolve_data_dir(Some(data_dir))?),
+        None => resolve_data_dir(None).ok(),
+    };
+    let cluster_major = match &data_dir {
+        Some(data_dir) => cluster_major_version(data_dir)?,
+        None => None,
+    };
+
+    match args.format {
+        OutputFormat::Text => {
+            println!("pgx {}", env!("CARGO_PKG_VERSION"));
+            println!("postgresql requirement: {}", settings.version);
+            match &resolved {
+                Ok(version) => println!("postgresql resolved: {version}"),
+                Err(error) => println!("postgresql resolved: unknown ({error})"),
+            }
+            match &installed {
+                Some((_, path)) => println!("installed: yes ({})", path.display()),
+                None => println!("installed: no"),
+            }
+            if let Some(data_dir) = &data_dir {
+                match cluster_major {
+                    Some(major) => println!("cluster: PostgreSQL {major} ({})", data_dir.display()),
+                    None => println!("cluster: not initialized ({})", data_dir.display()),
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let output = serde_json::json!({
+                "pgx": env!("CARGO_PKG_VERSION"),
+                "pg_version_req": settings.version.to_string(),
+                "pg_version": resolved.as_ref().ok().map(ToString::to_string),
+                "resolve_error": resolved.as_ref().err(),
+                "installed": installed.is_some(),
+                "installation_dir": installed.as_ref().map(|(_, path)| path),
+                "data_dir": data_dir,
+                "cluster_pg_version": cluster_major,
+            });
+            print_json(&output)?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_info(args: InfoArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let state = read_state_file(&data_dir).ok().flatten();
+    let host = state.as_ref().map(|state| state.host.clone());
+    let liveness = postmaster::inspect(&data_dir, host.as_deref().unwrap_or("localhost"));
+
+    let live = match (liveness, load_runtime_context(data_dir.clone())) {
+        (Liveness::Running, Ok(runtime)) => {
+            let url = settings_url(runtime.postgresql.settings(), DEFAULT_DATABASE);
+            match info::live_details(&url).await {
+                Ok(live) => Some(live),
+                Err(error) => {
+                    tracing::warn!("cannot query the running server: {error}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let mut sidecars = std::collections::BTreeMap::new();
+    sidecars.insert("state", state_file_path(&data_dir));
+    sidecars.insert("password", password_file_path(&data_dir));
+    sidecars.insert("lock", lock_file_path(&data_dir));
+    for (name, path) in [
+        ("prewarm", prewarm_file_path(&data_dir)),
+        ("snapshots", snapshot_root_path(&data_dir)),
+        ("tls", tls_dir_path(&data_dir)),
+        ("app", app_credentials_path(&data_dir)),
+        ("roles", roles_path(&data_dir)),
+        ("templates", templates_path(&data_dir)),
+    ] {
+        if path.exists() {
+            sidecars.insert(name, path);
+        }
+    }
+    if let Some(log_file) = state.as_ref().and_then(|state| state.log_file.clone()) {
+        sidecars.insert("log", log_file);
+    }
+
+    let installation = installation_settings(&data_dir).ok();
+    let pg_version = match &installation {
+        Some((_, version)) => Some(version.to_string()),
+        None => cluster_major_version(&data_dir)
+            .ok()
+            .flatten()
+            .map(|major| major.to_string()),
+    };
+    let info = info::ClusterInfo {
+        status: match liveness {
+            Liveness::Running => "running",
+            Liveness::Stale => "stale",
+            Liveness::Stopped => "not running",
+        },
+        pg_version,
+        installation_dir: installation.map(|(settings, _)| settings.installation_dir),
+        host,
+        port: state.as_ref().map(|state| state.port),
+        superuser: state.as_ref().and_then(|state| state.superuser.clone()),
+        started_at: live.as_ref().map(|live| live.started_at.clone()),
+        uptime_seconds: live.as_ref().map(|live| live.uptime_seconds),
+        databases: live.map(|live| live.databases),
+        size_bytes: snapshot::tree_size(&data_dir).ok(),
+        sidecars,
+        state: state.and_then(|state| serde_json::to_value(state).ok()),
+        data_dir,
+    };
+
+    match args.format {
+        OutputFormat::Text => info::print_text(&info),
+        OutputFormat::Json => print_json(&info)?,
+    }
+    Ok(())
+}
+
+fn handle_systemd(args: SystemdArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let data_dir = match fs::canonicalize(&data_dir) {
+        Ok(data_dir) => data_dir,
+        Err(_) => std::env::current_dir()?.join(data_dir),
+    };
+    let name = args.name.unwrap_or_else(|| {
+        data_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "pgx-data".to_string())
+    });
+    let unit_name = systemd::unit_name(&name);
+    let unit_path = systemd::user_unit_dir()?.join(&unit_name);
+
+    if args.uninstall {
+        if let Err(error) = systemd::systemctl(&["disable", "--now", &unit_name]) {
+            tracing::warn!("{error}");
+        }
+        match fs::remove_file(&unit_path) {
+            Ok(()) => report(format!("removed {}", unit_path.display())),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                report(format!("{} is not installed", unit_path.display()));
+            }
+            Err(error) => return Err(error.into()),
+        }
+        if !args.no_reload {
+            systemd::systemctl(&["daemon-reload"])?;
+        }
+        return Ok(());
+    }
+
+    let mut environment = vec![(PGX_DATA_DIR_ENV, data_dir.to_string_lossy().to_string())];
+    for key in [
+        PGX_PORT_ENV,
+        PGX_HOST_ENV,
+        PGX_BINARIES_ENV,
+        PGX_RELEASES_URL_ENV,
+        PGX_LOG_FORMAT_ENV,
+    ] {
+        if let Some(value) = std::env::var_os(key).filter(|value| !value.is_empty()) {
+            environment.push((key, value.to_string_lossy().to_string()));
+        }
+    }
+    let unit = systemd::unit_text(
+        &std::env::current_exe()?,
+        &data_dir,
+        args.restart,
+        &environment,
+    );
+    if args.print {
+        print!("{unit}");
+        return Ok(());
+    }
+
+    fs::create_dir_all(unit_path.parent().unwrap_or_else(|| Path::new(".")))?;
+    fs::write(&unit_path, unit)?;
+    report(format!("wrote {}", unit_path.display()));
+    if !args.no_reload {
+        systemd::systemctl(&["daemon-reload"])?;
+    }
+    if args.enable {
+        systemd::systemctl(&["enable", "--now", &unit_name])?;
+        report(format!("enabled and started {unit_name}"));
+    } else {
+        report(format!(
+            "start it at login with: systemctl --user enable --now {unit_name}"
+        ));
+    }
+    Ok(())
+}
+
+fn handle_completions(args: CompletionsArgs) -> AppResult<()> {
+    clap_complete::generate(args.shell, &mut Cli::command(), "pgx", &mut io::stdout());
+    Ok(())
+}
+
+async fn handle_ext(args: ExtArgs) -> AppResult<()> {
+    extensions::initialize()?;
+    match args.command {
+        ExtCommand::Install(args) => handle_ext_install(args).await,
+        ExtCommand::List(args) => handle_ext_list(args).await,
+    }
+}
+
+async fn handle_ext_install(args: ExtInstallArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let (settings, pg_version) = installation_settings(&data_dir)?;
+
+    let available = postgresql_extensions::get_available_extensions().await?;
+    let candidates: Vec<_> = available
+        .iter()
+        .filter(|extension| {
+            args.namespace
+                .as_ref()
+                .is_none_or(|namespace| extension.namespace() == namespace)
+        })
+        .collect();
+    let mut matches: Vec<_> = candidates
+        .iter()
+        .copied()
+        .filter(|extension| extension.name() == args.name)
+        .collect();
+    if matches.is_empty() {
+        // `pgvector` for `pgvector_compiled`.
+        matches = candidates
+            .iter()
+            .copied()
+            .filter(|extension| {
+                extension
+                    .name()
+                    .strip_prefix(args.name.as_str())
+                    .is_some_and(|rest| rest.starts_with('_'))
+            })
+            .collect();
+    }
+    let extension = match matches.as_slice() {
+        [extension] => extension,
+        [] => {
+            return Err(io::Error::other(format!(
+                "no extension named '{}' is available; see `pgx ext list`",
+                args.name
+            ))
+            .into());
+        }
+        _ => {
+            let names: Vec<_> = matches
+                .iter()
+                .map(|extension| format!("{}/{}", extension.namespace(), extension.name()))
+                .collect();
+            return Err(io::Error::other(format!(
+                "'{}' matches {}; choose one with --namespace or the full name",
+                args.name,
+                names.join(", ")
+            ))
+            .into());
+        }
+    };
+
+    let (namespace, name) = (extension.namespace(), extension.name());
+    let version = VersionReq::parse(&args.version)?;
+    postgresql_extensions::install(&settings, namespace, name, &version)
+        .await
+        .map_err(|error| {
+            io::Error::other(format!(
+                "no build of {namespace}/{name} {version} for PostgreSQL {pg_version} on {}-{}: {error}",
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            ))
+        })?;
+    report(format!(
+        "installed {namespace}/{name} into PostgreSQL {pg_version}"
+    ));
+
+    if args.create {
+        let runtime = load_runtime_context(data_dir)?;
+        ensure_running(&runtime)?;
+        let installed = postgresql_extensions::get_installed_extensions(&settings).await?;
+        let sql_names = installed
+            .iter()
+            .filter(|installed| installed.namespace() == namespace && installed.name() == name)
+            .flat_map(|installed| installed.files())
+            .filter(|file| {
+                file.extension()
+                    .is_some_and(|extension| extension == "control")
+            })
+            .filter_map(|file| file.file_stem())
+            .map(|stem| stem.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        let sql_names = if sql_names.is_empty() {
+            vec![name.to_string()]
+        } else {
+            sql_names
+        };
+
+        let databases = if args.database.is_empty() {
+            vec![runtime.connection.database.clone()]
+        } else {
+            args.database.clone()
+        };
+        for database in &databases {
+            let url = settings_url(runtime.postgresql.settings(), database);
+            let pool = sqlx::postgres::PgPool::connect(&url).await?;
+            for sql_name in &sql_names {
+                let statement = format!(
+                    "CREATE EXTENSION IF NOT EXISTS \"{}\"",
+                    sql_name.replace('"', "\"\"")
+                );
+                sqlx::query(&statement).execute(&pool).await?;
+                report(format!("created extension {sql_name} in {database}"));
+            }
+            pool.close().await;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_ext_list(args: ExtListArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let (settings, pg_version) = installation_settings(&data_dir)?;
+
+    let installed = postgresql_extensions::get_installed_extensions(&settings).await?;
+    println!("installed in PostgreSQL {pg_version}:");
+    for extension in &installed {
+        println!(
+            "  {}/{} {}",
+            extension.namespace(),
+            extension.name(),
+            extension.version()
+        );
+    }
+
+    match postgresql_extensions::get_available_extensions().await {
+        Ok(available) => {
+            println!("available:");
+            for extension in available {
+                println!(
+                    "  {}/{}  {}",
+                    extension.namespace(),
+                    extension.name(),
+                    extension.description()
+                );
+            }
+        }
+        Err(error) => tracing::warn!("cannot list available extensions: {error}"),
+    }
+    Ok(())
+}
+
+/// Settings that point at the exact installation the cluster in `data_dir`
+/// runs on, so e.g. extension builds are chosen for that version.
+fn installation_settings(data_dir: &Path) -> AppResult<(Settings, semver::Version)> {
+    let mut settings = build_settings(data_dir, None, None, None)?;
+    let recorded = read_state_file(data_dir)?
+        .and_then(|state| state.pg_version)
+        .and_then(|version| semver::Version::parse(&version).ok());
+    let (version, installation) = tools::installations(&settings.installation_dir)
+        .into_iter()
+        .filter(|(version, _)| match &recorded {
+            Some(recorded) => version == recorded,
+            None => settings.version.matches(version),
+        })
+        .max_by(|(left, _), (right, _)| left.cmp(right))
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "no PostgreSQL installation for {} found in {}; run pgx start first",
+                data_dir.display(),
+                settings.installation_dir.display()
+            ))
+        })?;
+
+    settings.installation_dir = installation;
+    settings.version = VersionReq::parse(&format!("={version}"))?;
+    Ok((settings, version))
+}
+
+async fn handle_template(args: TemplateArgs) -> AppResult<()> {
+    match args.command {
+        TemplateCommand::Create(args) => handle_template_create(args).await,
+        TemplateCommand::List(args) => handle_template_list(args),
+        TemplateCommand::Drop(args) => handle_template_drop(args).await,
+    }
+}
+
+async fn handle_template_create(args: TemplateCreateArgs) -> AppResult<()> {
+    for script in &args.from_sql {
+        if !script.is_file() {
+            return Err(io::Error::other(format!("{} not found", script.display())).into());
+        }
+    }
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+    let settings = runtime.postgresql.settings();
+    let path = templates_path(&runtime.data_dir);
+    let mut recorded = templates::Templates::read(&path)?;
+    let name = args.name;
+
+    if templates::database_exists(settings, &name).await? {
+        if !recorded.templates.contains_key(&name) {
+            return Err(io::Error::other(format!(
+                "database {name} already exists and is not a template pgx created"
+            ))
+            .into());
+        }
+        if !args.replace {
+            return Err(io::Error::other(format!(
+                "template {name} already exists; pass --replace to rebuild it"
+            ))
+            .into());
+        }
+        templates::drop_template(settings, &name).await?;
+    }
+
+    templates::create_database(settings, &name, None)
+        .await
+        .map_err(|error| io::Error::other(format!("failed to create database {name}: {error}")))?;
+    let mut connection = runtime.connection.clone();
+    connection.database = name.clone();
+    for script in &args.from_sql {
+        if let Err(error) = run_sql_script(settings, &connection, script).await {
+            if let Err(cleanup) = templates::drop_template(settings, &name).await {
+                tracing::warn!("failed to drop the half-seeded database {name}: {cleanup}");
+            }
+            return Err(error.into());
+        }
+    }
+    templates::mark_template(settings, &name).await?;
+
+    recorded.templates.insert(
+        name.clone(),
+        templates::Template {
+            created_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            scripts: args
+                .from_sql
+                .iter()
+                .map(|script| fs::canonicalize(script).unwrap_or_else(|_| script.clone()))
+                .collect(),
+        },
+    );
+    recorded.write(&path)?;
+    report(format!(
+        "created template {name}; copy it with `pgx createdb <name> --from-template {name}`"
+    ));
+    Ok(())
+}
+
+fn handle_template_list(args: TemplateListArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir)?;
+    let recorded = templates::Templates::read(&templates_path(&data_dir))?;
+    if args.format == OutputFormat::Json {
+        return print_json(&recorded.templates);
+    }
+    for (name, template) in &recorded.templates {
+        let scripts: Vec<_> = template
+            .scripts
+            .iter()
+            .map(|script| script.display().to_string())
+            .collect();
+        println!("{name}\t{}\t{}", template.created_at, scripts.join(", "));
+    }
+    Ok(())
+}
+
+async fn handle_template_drop(args: TemplateNameArgs) -> AppResult<()> {
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+    let path = templates_path(&runtime.data_dir);
+    let mut recorded = templates::Templates::read(&path)?;
+    if recorded.templates.remove(&args.name).is_none() {
+        return Err(io::Error::other(format!(
+            "no template {} recorded; list them with `pgx template list`",
+            args.name
+        ))
+        .into());
+    }
+    templates::drop_template(runtime.postgresql.settings(), &args.name).await?;
+    recorded.write(&path)?;
+    report(format!("dropped template {}", args.name));
+    Ok(())
+}
+
+async fn handle_createdb(args: CreatedbArgs) -> AppResult<()> {
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+    let settings = runtime.postgresql.settings();
+    if let Some(template) = &args.from_template
+        && !templates::is_template(settings, template).await?
+    {
+        return Err(io::Error::other(format!(
+            "{template} is not a template; create it with `pgx template create {template} --from-sql <file>`"
+        ))
+        .into());
+    }
+    templates::create_database(settings, &args.name, args.from_template.as_deref())
+        .await
+        .map_err(|error| {
+            io::Error::other(format!("failed to create database {}: {error}", args.name))
+        })?;
+
+    let mut connection = runtime.connection.clone();
+    connection.database = args.name;
+    println!("{}", connection.url());
+    Ok(())
+}
+
+async fn handle_hba(args: HbaArgs) -> AppResult<()> {
+    match args.command {
+        HbaCommand::Add(args) => {
+            let data_dir = resolve_data_dir(args.data_dir)?;
+            let rule = hba::HbaRule {
+                kind: args.kind,
+                database: args.database,
+                user: args.user,
+                address: args.address,
+                netmask: None,
+                method: args.method,
+            };
+            if hba::add(&data_dir, rule.clone())? {
+                report(format!("added: {rule}"));
+                reload_if_running(&data_dir).await?;
+            } else {
+                report(format!("already present: {rule}"));
+            }
+        }
+        HbaCommand::List(args) => {
+            let data_dir = resolve_data_dir(args.data_dir)?;
+            for (index, rule) in hba::list(&data_dir)?.iter().enumerate() {
+                println!("{} {rule}", index + 1);
+            }
+        }
+        HbaCommand::Show(args) => {
+            let data_dir = resolve_data_dir(args.data_dir)?;
+            for (rule, managed) in hba::all_rules(&data_dir)? {
+                if managed {
+                    println!("{rule}  # pgx");
+                } else {
+                    println!("{rule}");
+                }
+            }
+        }
+        HbaCommand::Allow(args) => {
+            let data_dir = resolve_data_dir(args.data_dir)?;
+            let rule = hba::HbaRule {
+                method: args.method,
+                ..hba::HbaRule::password_host(&args.address)
+            };
+            if hba::add(&data_dir, rule.clone())? {
+                report(format!("added: {rule}"));
+                reload_if_running(&data_dir).await?;
+            } else {
+                report(format!("already present: {rule}"));
+            }
+            let listening = read_state_file(&data_dir)?
+                .map(|state| state.listen_addresses)
+                .unwrap_or_default();
+            if listening.iter().all(|address| hba::is_loopback(address)) {
+                tracing::warn!(
+                    "the server only listens on this machine; start it with --listen 0.0.0.0 to accept connections from {}",
+                    args.address
+                );
+            }
+        }
+        HbaCommand::Remove(args) => {
+            let data_dir = resolve_data_dir(args.data_dir)?;
+            let rule = hba::remove(&data_dir, args.index)?;
+            report(format!("removed: {rule}"));
+            reload_if_running(&data_dir).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Apply configuration file edits to the server for `data_dir`, if one runs.
+async fn reload_if_running(data_dir: &Path) -> AppResult<()> {
+    let Ok(runtime) = load_runtime_context(data_dir.to_path_buf()) else {
+        return Ok(());
+    };
+    if runtime_liveness(&runtime) != Liveness::Running {
+        return Ok(());
+    }
+
+    let pg_ctl = tools::tool_path(&tools::binary_dir(runtime.postgresql.settings())?, "pg_ctl")?;
+    postmaster::reload(&pg_ctl, data_dir).await?;
+    report("reloaded the running server");
+    Ok(())
+}
+
+/// Refresh `pgx top` until Ctrl-C or `q`. On a terminal each refresh
+/// redraws the screen; otherwise the samples are printed one after another.
+async fn handle_top(args: TopArgs) -> AppResult<()> {
+    use sqlx::Connection;
+    use std::io::{IsTerminal, Write};
+
+    if args.interval.is_zero() {
+        return Err(io::Error::other("--interval must be greater than zero").into());
+    }
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+    let url = database_url(&runtime);
+    let mut connection = sqlx::postgres::PgConnection::connect(&url).await?;
+
+    let interactive = io::stdout().is_terminal();
+    let keys = (interactive && io::stdin().is_terminal())
+        .then(top::KeyInput::enable)
+        .flatten();
+    let quit = async {
+        match &keys {
+            Some(_) => top::quit_requested().await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(quit);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    let mut ticker = interval(args.interval);
+    let mut previous = None;
+    loop {
+        tokio::select! {
+            _ = &mut ctrl_c => break,
+            _ = &mut quit => break,
+            _ = ticker.tick() => {}
+        }
+
+        let current = match top::sample(&mut connection).await {
+            Ok(sample) => sample,
+            Err(_) if runtime_liveness(&runtime) != Liveness::Running => {
+                return Err(io::Error::other(format!(
+                    "the server for {} stopped",
+                    runtime.data_dir.display()
+                ))
+                .into());
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let frame = top::render(&current, previous.as_ref());
+        if interactive {
+            let mut stdout = io::stdout().lock();
+            write!(
+                stdout,
+                "\x1b[2J\x1b[Hpgx top: {} every {} (q or Ctrl-C to quit)\n\n{frame}",
+                runtime.data_dir.display(),
+                humantime::format_duration(args.interval)
+            )?;
+            stdout.flush()?;
+        } else {
+            println!(
+                "{}\n{frame}",
+                humantime::format_rfc3339_seconds(SystemTime::now())
+            );
+        }
+        previous = Some(current);
+    }
+
+    let _ = connection.close().await;
+    Ok(())
+}
+
+/// Run the bundled vacuumdb with `mode` against each target database in
+/// turn, continuing past failures so one broken database does not hide the
+/// state of the others.
+async fn handle_maintenance(target: MaintenanceTarget, label: &str, mode: &str) -> AppResult<()> {
+    let runtime = load_runtime_context(resolve_data_dir(target.data_dir)?)?;
+    ensure_running(&runtime)?;
+    if mode == "--full" {
+        tracing::warn!(
+            "VACUUM FULL locks each table exclusively while rewriting it; queries against it wait until it is done"
+        );
+    }
+
+    let databases = match target.database {
+        Some(database) => vec![database],
+        None => {
+            info::database_names(&settings_url(
+                runtime.postgresql.settings(),
+                DEFAULT_DATABASE,
+            ))
+            .await?
+        }
+    };
+    let vacuumdb = tools::tool_path(
+        &tools::binary_dir(runtime.postgresql.settings())?,
+        "vacuumdb",
+    )?;
+
+    let mut failed = Vec::new();
+    for database in &databases {
+        report(format!("{label} {database}"));
+        let mut command = tokio::process::Command::new(&vacuumdb);
+        command
+            .arg(mode)
+            .arg("--verbose")
+            .arg(format!("--dbname={database}"))
+            .envs(connection_env(&runtime.connection));
+        // --full still needs the statistics refresh a plain run does.
+        if mode == "--full" {
+            command.arg("--analyze");
+        }
+        for table in &target.table {
+            command.arg(format!("--table={table}"));
+        }
+        // Server notices from --verbose stream through vacuumdb's stderr.
+        let status = command.status().await?;
+        if !status.success() {
+            tracing::error!("{label} failed for {database} ({status})");
+            failed.push(database.as_str());
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(io::Error::other(format!(
+            "{label} failed for {} of {} databases: {}",
+            failed.len(),
+            databases.len(),
+            failed.join(", ")
+        ))
+        .into());
+    }
+    report(format!(
+        "{label} finished for {} databases",
+        databases.len()
+    ));
+    Ok(())
+}
+
+fn handle_list(args: ListArgs) -> AppResult<()> {
+    if args.prune {
+        for data_dir in instances::prune_missing()? {
+            report(format!("forgot {} (no longer exists)", data_dir.display()));
+        }
+    }
+    let index = profiles::ProfileIndex::read()?;
+    let registry = instances::Registry::read()?;
+
+    // Profiles first, then clusters started with --data-dir.
+    let mut entries: Vec<(Option<&str>, PathBuf)> = index
+        .profiles
+        .iter()
+        .map(|(name, profile)| (Some(name.as_str()), profile.data_dir.clone()))
+        .collect();
+    for data_dir in registry.instances.keys() {
+        if index.owning(data_dir).is_none() {
+            entries.push((None, data_dir.clone()));
+        }
+    }
+
+    let rows: Vec<_> = entries
+        .into_iter()
+        .map(|(name, data_dir)| {
+            let state = read_state_file(&data_dir).ok().flatten();
+            let recorded = registry.instances.get(&data_dir);
+            let host = state
+                .as_ref()
+                .map_or("localhost", |state| state.host.as_str());
+            let status = match postmaster::inspect(&data_dir, host) {
+                Liveness::Running => "running",
+                Liveness::Stale => "stale",
+                Liveness::Stopped if state.is_some() => "stopped",
+                Liveness::Stopped if !data_dir.exists() && recorded.is_some() => "missing",
+                Liveness::Stopped => "never started",
+            };
+            let port = state
+                .as_ref()
+                .map(|state| state.port)
+                .or(recorded.map(|instance| instance.port));
+            let version = state
+                .as_ref()
+                .and_then(|state| state.pg_version.clone())
+                .or_else(|| recorded.and_then(|instance| instance.pg_version.clone()));
+            (name, data_dir, status, port, version)
+        })
+        .collect();
+
+    match args.format {
+        OutputFormat::Text => {
+            if rows.is_empty() {
+                report("no instances yet; start one with `pgx start`");
+            }
+            for (name, data_dir, status, port, version) in &rows {
+                let name = name.unwrap_or("-");
+                let port = port.map_or_else(|| "-".to_string(), |port| port.to_string());
+                let version = version.as_deref().unwrap_or("-");
+                println!(
+                    "{name:<16} {status:<14} {port:<6} {version:<8} {}",
+                    data_dir.display()
+                );
+            }
+        }
+        OutputFormat::Json => {
+            let output: Vec<_> = rows
+                .iter()
+                .map(|(name, data_dir, status, port, version)| {
+                    serde_json::json!({
+                        "name": name,
+                        "status": status,
+                        "port": port,
+                        "pg_version": version,
+                        "data_dir": data_dir.display().to_string(),
+                    })
+                })
+                .collect();
+            print_json(&output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Stop the server if it runs, empty its data directory and initialize it
+/// again with the recorded password, port, locale and encoding. Files in
+/// the reset sidecar mark a reset that has to be finished.
+async fn handle_reset(mut args: ResetArgs) -> AppResult<()> {
+    if args.server.ephemeral {
+        return Err(io::Error::other("an --ephemeral cluster cannot be reset").into());
+    }
+    let data_dir = resolve_data_dir(args.server.data_dir.clone())?;
+    if read_state_file(&data_dir)?.is_none() || read_managed_password_file(&data_dir)?.is_none() {
+        return Err(io::Error::other(format!(
+            "{} has no pgx state or password to keep; create the cluster with `pgx start`",
+            data_dir.display()
+        ))
+        .into());
+    }
+    if args.seed && init_scripts(&args.server.init_sql, &args.server.init_dir)?.is_empty() {
+        return Err(io::Error::other(
+            "--seed needs init scripts: pass --init-sql or --init-dir, or set init_scripts in pgx.toml",
+        )
+        .into());
+    }
+    if !args.yes && !confirm(&format!("Delete every database in {}?", data_dir.display()))? {
+        return Err(io::Error::other(format!(
+            "this deletes every database in {}; pass --yes to confirm",
+            data_dir.display()
+        ))
+        .into());
+    }
+
+    let runtime = load_runtime_context(data_dir)?;
+    let data_dir = runtime.data_dir.clone();
+    let operation_lock = acquire_operation_lock(&data_dir)?;
+    match runtime_liveness(&runtime) {
+        Liveness::Running => {
+            tracing::info!("stopping the server");
+            let pg_ctl =
+                tools::tool_path(&tools::binary_dir(runtime.postgresql.settings())?, "pg_ctl")?;
+            let stop = postmaster::stop(&pg_ctl, &data_dir, ShutdownMode::Fast);
+            tokio::time::timeout(RESET_TIMEOUT, stop)
+                .await
+                .map_err(|_| {
+                    io::Error::other(format!(
+                        "the server did not stop within {}; stop it with `pgx stop --force` and reset again",
+                        humantime::format_duration(RESET_TIMEOUT)
+                    ))
+                })??;
+            mark_state_stopped(&data_dir)?;
+        }
+        Liveness::Stale => clear_stale_pid_file(&data_dir, &runtime.connection.host)?,
+        Liveness::Stopped => {}
+    }
+
+    let reset_dir = reset_dir_path(&data_dir);
+    if reset_dir.exists() {
+        tracing::info!("finishing the interrupted reset of {}", data_dir.display());
+    }
+    reset::save_config(&data_dir, &reset_dir)?;
+    tracing::info!("deleting the contents of {}", data_dir.display());
+    reset::empty_dir(&data_dir)?;
+    // The relations it lists are gone.
+    let _ = fs::remove_file(prewarm_file_path(&data_dir));
+
+    let settings = runtime.postgresql.settings().clone();
+    let state = &runtime.state;
+    let init_options = initdb::InitOptions {
+        locale: state.locale.clone(),
+        encoding: state.encoding.clone(),
+        data_checksums: state.data_checksums.unwrap_or(false),
+    };
+    tracing::info!("initializing cluster in {}", data_dir.display());
+    let deadline = args.server.timeout.map(|limit| Instant::now() + limit);
+    let reinitialize = async {
+        if !init_options.is_default() {
+            let binary_dir = match tools::binary_dir(&settings) {
+                Ok(binary_dir) => binary_dir,
+                Err(_) => offline::fetch(&settings, false).await?.1.join("bin"),
+            };
+            initdb::run(&binary_dir, &settings, &init_options).await?;
+        }
+        PostgreSQL::new(settings.clone()).setup().await?;
+        AppResult::Ok(())
+    };
+    // The configuration stays saved in the reset directory, so running
+    // reset again after any failure here finishes the job.
+    let failure: Option<Box<dyn Error + Send + Sync>> = tokio::select! {
+        result = before_deadline(deadline, reinitialize) => match result {
+            Some(result) => result.err(),
+            // Only a deadline, and so a --timeout, ends it without a result.
+            None => {
+                let limit = args.server.timeout.unwrap_or_default();
+                Some(timed_out(limit, "initializing the cluster again").into())
+            }
+        },
+        _ = tokio::signal::ctrl_c() => Some(io::Error::other("interrupted").into()),
+    };
+    if let Some(error) = failure {
+        let _ = reset::empty_dir(&data_dir);
+        return Err(io::Error::other(format!(
+            "{error}; run `pgx reset` again to finish resetting {}",
+            data_dir.display()
+        ))
+        .into());
+    }
+    reset::restore_config(&data_dir, &reset_dir)?;
+
+    // The app user and declared roles went with the old cluster. Their
+    // sidecars go too, so `pgx url --app`/`--role` does not hand out
+    // credentials for roles that do not exist; --start creates them again
+    // with the same passwords.
+    let app_user = app_role::read(&app_credentials_path(&data_dir))?;
+    let declared = app_role::read_roles(&roles_path(&data_dir))?;
+    for sidecar in [app_credentials_path(&data_dir), roles_path(&data_dir)] {
+        match fs::remove_file(&sidecar) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+    }
+    if args.seed {
+        fs::write(init_marker_path(&data_dir), "")?;
+    }
+    drop(operation_lock);
+
+    if !args.start {
+        let mut message = format!(
+            "reset {}; start it with `pgx start --data-dir {}`",
+            data_dir.display(),
+            data_dir.display()
+        );
+        if app_user.is_some() || !declared.is_empty() {
+            message.push_str(", passing --app-user and --role again to recreate those roles");
+        }
+        report(message);
+        return Ok(());
+    }
+
+    // Start it the way `pgx start` would, on the recorded port so URLs
+    // handed out earlier keep working.
+    let start = &mut args.server;
+    start.data_dir = Some(data_dir);
+    start.daemon = true;
+    start.port.get_or_insert(state.port);
+    if let Some(app_user) = app_user
+        && start.app_user.is_none()
+    {
+        start.app_user = Some(app_user.user);
+        start.app_password = Some(app_user.password);
+        start.app_db = Some(app_user.database);
+    }
+    for role in declared.into_values() {
+        if !start.roles.iter().any(|given| given.name == role.name) {
+            start.roles.push(role);
+        }
+    }
+    handle_start(args.server).await
+}
+
+async fn handle_destroy(args: DestroyArgs) -> AppResult<()> {
+    let data_dir = resolve_data_dir(args.data_dir.clone())?;
+    let profile = if uses_profile(args.data_dir.as_deref()) {
+        Some(selected_profile())
+    } else {
+        profiles::ProfileIndex::read()
+            .ok()
+            .and_then(|index| index.owning(&data_dir).map(|(name, _)| name.to_string()))
+    };
+    if !looks_like_cluster(&data_dir)? {
+        return Err(io::Error::other(format!(
+            "{} is not a PostgreSQL data directory; refusing to delete it",
+            data_dir.display()
+        ))
+        .into());
+    }
+    if !args.yes
+        && !confirm(&format!(
+            "Delete {} and everything in it?",
+            data_dir.display()
+        ))?
+    {
+        return Err(io::Error::other(format!(
+            "this deletes {} and everything in it; pass --yes to confirm",
+            data_dir.display()
+        ))
+        .into());
+    }
+
+    let stop = StopOptions {
+        repair: true,
+        timeout: args.timeout,
+        ..StopOptions::default()
+    };
+    report(format!(
+        "stop: {}",
+        stop_cluster(data_dir.clone(), &stop).await?
+    ));
+
+    let operation_lock = acquire_operation_lock(&data_dir)?;
+    let host = read_state_file(&data_dir)
+        .ok()
+        .flatten()
+        .map_or_else(|| "localhost".to_string(), |state| state.host);
+    if postmaster::inspect(&data_dir, &host) == Liveness::Running {
+        return Err(io::Error::other(format!(
+            "{} was started again while stopping it; not deleting it",
+            data_dir.display()
+        ))
+        .into());
+    }
+
+    if data_dir.exists() {
+        fs::remove_dir_all(&data_dir)?;
+    }
+    drop(operation_lock);
+    remove_sidecars(&data_dir, &[])?;
+    instances::forget(&data_dir)?;
+    if let Some(name) = &profile {
+        profiles::unregister(name)?;
+    }
+
+    match profile {
+        Some(name) => report(format!("destroyed profile {name} ({})", data_dir.display())),
+        None => report(format!("destroyed {}", data_dir.display())),
+    }
+    Ok(())
+}
+
+/// Whether `data_dir` is safe for `destroy` to delete: missing, empty, or
+/// holding a cluster pgx or initdb created.
+fn looks_like_cluster(data_dir: &Path) -> io::Result<bool> {
+    if !data_dir.exists() || data_dir.join("PG_VERSION").exists() {
+        return Ok(true);
+    }
+    if read_state_file(data_dir).ok().flatten().is_some() {
+        return Ok(true);
+    }
+    Ok(fs::read_dir(data_dir)?.next().is_none())
+}
+
+/// Ask `question` on the terminal before deleting data. Without a terminal
+/// the answer is no, so scripts have to pass `--yes`.
+fn confirm(question: &str) -> io::Result<bool> {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{question} [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Remove the `<data dir>.pgx-*` sidecars next to `data_dir`, except those
+/// whose `*` part is listed in `keep`, along with the default daemon log and
+/// any state or password file recorded away from the data directory.
+fn remove_sidecars(data_dir: &Path, keep: &[&str]) -> io::Result<()> {
+    // Read the recorded locations before the state file and pointer go.
+    let elsewhere = [
+        state_file_path(data_dir),
+        password_file_path(data_dir),
+        sidecar_file_path(data_dir, "pgx.log"),
+    ];
+    for path in elsewhere {
+        match fs::remove_file(&path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+    }
+
+    let sidecar_prefix = format!(
+        "{}.pgx-",
+        data_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    );
+    let Some(parent) = data_dir.parent() else {
+        return Ok(());
+    };
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    for entry in fs::read_dir(parent)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some(suffix) = file_name.strip_prefix(&sidecar_prefix) else {
+            continue;
+        };
+        if keep.contains(&suffix) {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// `--from`/`--to` of `pgx clone`: a data directory, or a profile name with
+/// the profile it names.
+fn clone_location(value: &str) -> AppResult<(PathBuf, Option<String>)> {
+    let path = PathBuf::from(value);
+    let is_path = path.components().count() > 1 || path.is_absolute() || path.is_dir();
+    if is_path {
+        return Ok((path, None));
+    }
+    Ok((profiles::data_dir(value)?, Some(value.to_string())))
+}
+
+async fn handle_clone(args: CloneArgs) -> AppResult<()> {
+    let (source, _) = clone_location(&args.from)?;
+    let (target, target_profile) = clone_location(&args.to)?;
+    if !cluster_is_initialized(&source) {
+        return Err(io::Error::other(format!(
+            "{} has no initialized cluster to clone",
+            source.display()
+        ))
+        .into());
+    }
+    if target.exists() && fs::canonicalize(&target).ok() == fs::canonicalize(&source).ok() {
+        return Err(io::Error::other("--from and --to are the same data directory").into());
+    }
+
+    let source_state = read_state_file(&source)?;
+    let source_host = source_state
+        .as_ref()
+        .map_or("localhost", |state| state.host.as_str());
+    let target_has_data = fs::read_dir(&target)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if target_has_data {
+        if !args.force {
+            return Err(io::Error::other(format!(
+                "{} is not empty; pass --force to replace it with the clone",
+                target.display()
+            ))
+            .into());
+        }
+        if !looks_like_cluster(&target)? {
+            return Err(io::Error::other(format!(
+                "{} is not a PostgreSQL data directory; refusing to replace it",
+                target.display()
+            ))
+            .into());
+        }
+        // A target pgx never started is checked where the source listens.
+        let target_host = read_state_file(&target)
+            .ok()
+            .flatten()
+            .map_or_else(|| source_host.to_string(), |state| state.host);
+        if postmaster::inspect(&target, &target_host) == Liveness::Running {
+            return Err(io::Error::other(format!(
+                "{} is in use by a running server; stop it first",
+                target.display()
+            ))
+            .into());
+        }
+    }
+    if let Some(parent) = target
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let _target_lock = acquire_operation_lock(&target)?;
+    if target.exists() {
+        fs::remove_dir_all(&target)?;
+    }
+    // Everything of the old target goes; only the lock we hold stays.
+    remove_sidecars(&target, &["lock"])?;
+
+    let superuser = source_state
+        .as_ref()
+        .map_or(DEFAULT_SUPERUSER, StateFile::superuser)
+        .to_string();
+    let settings = build_settings(&source, None, None, None)?;
+    let binary_dir = tools::binary_dir(&settings)?;
+
+    let copied = if postmaster::inspect(&source, source_host) == Liveness::Running {
+        copy_running_cluster(&source, &target, &binary_dir).await
+    } else {
+        // Keep the source from being started half-way through the copy.
+        let _source_lock = acquire_operation_lock(&source)?;
+        snapshot::copy_tree(&source, &target).map_err(Into::into)
+    };
+    let password = Settings::default().password;
+    let prepared = copied.and_then(|()| prepare_clone(&target, &binary_dir, &superuser, &password));
+    if let Err(error) = prepared {
+        let _ = fs::remove_dir_all(&target);
+        let _ = fs::remove_file(password_file_path(&target));
+        return Err(error);
+    }
+    if let Some(state) = source_state {
+        let state = clone_state(&source, &target, state)?;
+        write_state_file(&target, &state)?;
+    }
+    // Roles inside the cluster came along, so their stored passwords still work.
+    for sidecar in [app_credentials_path, roles_path] {
+        if sidecar(&source).exists() {
+            fs::copy(sidecar(&source), sidecar(&target))?;
+            set_owner_only_permissions(&sidecar(&target))?;
+        }
+    }
+    if let Some(profile) = &target_profile {
+        profiles::register(profile, &target)?;
+    }
+
+    report(format!(
+        "cloned {} to {}; start it with `pgx start {}`",
+        source.display(),
+        target.display(),
+        match &target_profile {
+            Some(profile) => format!("-p {profile}"),
+            None => format!("--data-dir {}", target.display()),
+        }
+    ));
+    Ok(())
+}
+
+/// Copy a running cluster with pg_basebackup, streaming the WAL needed to
+/// make the copy consistent.
+async fn copy_running_cluster(source: &Path, target: &Path, binary_dir: &Path) -> AppResult<()> {
+    let runtime = load_runtime_context(source.to_path_buf())?;
+    let mut command = tokio::process::Command::new(tools::tool_path(binary_dir, "pg_basebackup")?);
+    command
+        .arg("--pgdata")
+        .arg(target)
+        .arg("--format=plain")
+        .arg("--checkpoint=fast")
+        .arg("--wal-method=stream")
+        .arg("--progress")
+        .arg("--no-password")
+        .envs(connection_env(&runtime.connection));
+    let (status, stderr) = basebackup::run_streaming(command).await?;
+    if !status.success() {
+        return Err(basebackup_failure(&runtime, status, &stderr));
+    }
+    Ok(())
+}
+
+/// Strip what belonged to the source instance from a copied data directory
+/// and give it a superuser password of its own.
+fn prepare_clone(
+    target: &Path,
+    binary_dir: &Path,
+    superuser: &str,
+    password: &str,
+) -> AppResult<()> {
+    for runtime_file in ["postmaster.pid", "postmaster.opts"] {
+        match fs::remove_file(target.join(runtime_file)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+    }
+    prepare_data_dir(target)?;
+    clone::reset_superuser_password(
+        &tools::tool_path(binary_dir, "postgres")?,
+        target,
+        superuser,
+        password,
+    )?;
+
+    let password_file = password_file_path(target);
+    fs::write(&password_file, password)?;
+    set_owner_only_permissions(&password_file)?;
+    Ok(())
+}
+
+/// The source's state for its clone: same superuser, database, settings and
+/// TLS, but stopped, on a free port of its own, and with paths of its own. A
+/// new certificate is generated on the clone's first start.
+fn clone_state(source: &Path, target: &Path, mut state: StateFile) -> AppResult<StateFile> {
+    state.port = postmaster::free_port(&state.host)?;
+    state.running = false;
+    state.pid = None;
+    state.started_at = None;
+    state.log_file = None;
+    state.password_file = custom_password_file(target);
+    state.data_dir = absolute_data_dir(target).ok();
+    if state.socket_dir.as_deref() == Some(socket_dir_path(source).as_path()) {
+        state.socket_dir = Some(socket_dir_path(target));
+    }
+    if let Some(cert) = &state.tls_cert {
+        state.tls_cert = Some(tls_dir_path(target).join(cert.file_name().unwrap_or_default()));
+    }
+    Ok(state)
+}
+
+async fn handle_bin(args: BinArgs) -> AppResult<()> {
+    if args.list || args.path.is_some() {
+        let data_dir = resolve_data_dir(args.data_dir)?;
+        let binary_dir = tools::binary_dir(&build_settings(&data_dir, None, None, None)?)?;
+
+        if let Some(tool) = args.path {
+            println!("{}", tools::tool_path(&binary_dir, &tool)?.display());
+        } else {
+            for tool in tools::available_tools(&binary_dir)? {
+                println!("{tool}");
+            }
+        }
+        return Ok(());
+    }
+
+    let tool = args
+        .tool
+        .ok_or_else(|| io::Error::other("missing tool name"))?;
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    let binary_dir = tools::binary_dir(runtime.postgresql.settings())?;
+
+    let mut command = Command::new(tools::tool_path(&binary_dir, &tool)?);
+    command
+        .args(&args.args)
+        .envs(connection_env(&runtime.connection));
+    Err(tools::exec(command).into())
+}
+
+async fn handle_assert(args: AssertArgs) -> AppResult<()> {
+    let mut spec = match &args.file {
+        Some(path) => toml::from_str::<assertions::CheckSpec>(&fs::read_to_string(path)?)
+            .map_err(|error| io::Error::other(format!("{}: {error}", path.display())))?,
+        None => assertions::CheckSpec::default(),
+    };
+    spec.merge(inline_check_spec(&args)?);
+    if spec.is_empty() {
+        return Err(io::Error::other("no checks given; pass --file or an inline check").into());
+    }
+
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+
+    let results = assertions::evaluate(
+        runtime.postgresql.settings(),
+        runtime.state.database(),
+        &spec,
+    )
+    .await;
+    let summary = assertions::Summary::of(&results);
+
+    match args.format {
+        OutputFormat::Text => {
+            for result in &results {
+                let verdict = if result.passed { "PASS" } else { "FAIL" };
+                println!("{verdict} {}: {}", result.check, result.detail);
+            }
+        }
+        OutputFormat::Json => {
+            let output = serde_json::json!({
+                "passed": summary.passed,
+                "failed": summary.failed.len(),
+                "checks": &results,
+            });
+            print_json(&output)?;
+        }
+    }
+
+    match summary.failure() {
+        Some(failure) => Err(io::Error::other(failure).into()),
+        None => Ok(()),
+    }
+}
+
+async fn handle_env(args: EnvArgs) -> AppResult<()> {
+    let runtime = load_runtime_context(resolve_data_dir(args.data_dir)?)?;
+    ensure_running(&runtime)?;
+
+    let variables = database_env(&runtime.connection);
+    if json_output() {
+        let object: serde_json::Map<_, _> = variables
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.into()))
+            .collect();
+        return print_json(&object);
+    }
+    let shell = if args.dotenv {
+        Shell::Dotenv
+    } else {
+        args.shell.unwrap_or_else(shell_env::detect)
+    };
+    print!("{}", shell_env::render(shell, &variables));
+    Ok(())
+}
+
+async fn handle_run(args: RunArgs) -> AppResult<()> {
+    if args.server.daemon {
+        return Err(io::Error::other("--daemon cannot be used with pgx run").into());
+    }
+
+    let (ephemeral_root, data_dir) = start_data_dir(&args.server)?;
+    let started = match postmaster::inspect(&data_dir, &resolve_host(args.server.host.clone())?) {
+        Liveness::Running => None,
+        _ if args.start || args.server.ephemeral => {
+            let options = cluster_options(&args.server, &data_dir)?;
+            Some(
+                Cluster::new(&data_dir, options)
+                    .start_server(interrupt())
+                    .await?,
+            )
+        }
+        _ => {
+            return Err(
+                io::Error::other("not running; pass --start to start the server first").into(),
+            );
+        }
+    };
+
+    let outcome = run_with_database_env(data_dir, &args.command).await;
+
+    if let Some(server) = started {
+        if args.server.ephemeral {
+            server.postgresql.stop().await?;
+        } else {
+            std::mem::forget(server.postgresql);
+        }
+    }
+    drop(ephemeral_root);
+
+    process::exit(tools::exit_code(outcome?));
+}
+
+async fn run_with_database_env(data_dir: PathBuf, command: &[OsString]) -> AppResult<ExitStatus> {
+    let runtime = load_runtime_context(data_dir)?;
+    let (program, arguments) = command
+        .split_first()
+        .ok_or_else(|| io::Error::other("missing command"))?;
+
+    let mut child = tokio::process::Command::new(program)
+        .args(arguments)
+        .envs(database_env(&runtime.connection))
+        .spawn()?;
+    Ok(tools::wait_forwarding_signals(&mut child).await?)
+}
+
+fn inline_check_spec(args: &AssertArgs) -> AppResult<assertions::CheckSpec> {
+    let mut settings = Vec::new();
+    for raw in &args.setting {
+        let (name, value) = parse_key_value(raw)?;
+        settings.push(assertions::SettingCheck { name, value });
+    }
+
+    Ok(assertions::CheckSpec {
+        server: args
+            .server_version
+            .clone()
+            .map(|version| assertions::ServerCheck { version }),
+        extensions: args
+            .extension
+            .iter()
+            .map(|raw| assertions::parse_extension_check(raw))
+            .collect(),
+        databases: args
+            .database
+            .iter()
+            .map(|name| assertions::DatabaseCheck { name: name.clone() })
+            .collect(),
+        tables: args
+            .table
+            .iter()
+            .map(|name| assertions::TableCheck {
+                name: name.clone(),
+                database: None,
+                rows: args.rows,
+                min_rows: args.min_rows,
+            })
+            .collect(),
+        settings,
+        roles: args
+            .role
+            .iter()
+            .map(|name| assertions::RoleCheck {
+                name: name.clone(),
+                superuser: None,
+                login: None,
+                createdb: None,
+                createrole: None,
+                replication: None,
+            })
+            .collect(),
+    })
+}
+
+/// Parse durations such as `30s`, `2m` or `1h 30m`; bare numbers are seconds.
+fn parse_duration(raw: &str) -> Result<Duration, String> {
+    if let Ok(seconds) = raw.trim().parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    humantime::parse_duration(raw).map_err(|error| error.to_string())
+}
+
+/// Parse `--pg-version`: a version requirement such as `=16` or `>=16.2, <17`,
+/// where a bare version like `16` or `16.4.0` means exactly that.
+fn parse_pg_version(raw: &str) -> Result<VersionReq, String> {
+    let raw = raw.trim();
+    let requirement = if raw.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("={raw}")
+    } else {
+        raw.to_string()
+    };
+    VersionReq::parse(&requirement)
+        .map_err(|error| format!("invalid PostgreSQL version {raw:?}: {error}"))
+}
+
+/// Parse `--set KEY=VALUE`. Setting names are case-insensitive, so they are
+/// recorded in lower case; an empty value is kept to drop the setting.
+fn parse_server_setting(raw: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = raw.split_once('=') else {
+        return Err("expected KEY=VALUE, e.g. shared_buffers=256MB".to_string());
+    };
+    let key = key.trim().to_ascii_lowercase();
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if !valid {
+        return Err(format!("invalid setting name '{key}'"));
+    }
+    Ok((key, value.trim().to_string()))
+}
+
+/// Validate `--env-key` as a portable environment variable name.
+fn parse_env_key(raw: &str) -> Result<String, String> {
+    let valid = raw
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && raw.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(raw.to_string())
+    } else {
+        Err("use letters, digits and '_', not starting with a digit".to_string())
+    }
+}
+
+/// Validate `--database`: a plain identifier, folded to lower case as
+/// PostgreSQL folds unquoted names, or a double-quoted name kept as written
+/// (`""` stands for a literal quote).
+fn parse_database_name(raw: &str) -> Result<String, String> {
+    parse_identifier(raw, "database")
+}
+
+/// Validate `--superuser` like [`parse_database_name`].
+fn parse_role_name(raw: &str) -> Result<String, String> {
+    parse_identifier(raw, "role")
+}
+
+/// Parse `start --role name:password[:options]`, options separated by commas.
+fn parse_role_spec(raw: &str) -> Result<app_role::Role, String> {
+    let mut parts = raw.splitn(3, ':');
+    let name = parts.next().unwrap_or_default();
+    let Some(password) = parts.next() else {
+        return Err(format!("expected NAME:PASSWORD[:OPTIONS], got {raw:?}"));
+    };
+    let options: Vec<&str> = parts
+        .next()
+        .map_or_else(Vec::new, |options| options.split(',').collect());
+    role_spec(name, password, &options)
+}
+
+fn role_spec(name: &str, password: &str, options: &[&str]) -> Result<app_role::Role, String> {
+    if password.is_empty() {
+        return Err(format!("role {name} needs a password"));
+    }
+    Ok(app_role::Role {
+        name: parse_role_name(name)?,
+        password: password.to_string(),
+        attributes: app_role::parse_attributes(options)?,
+    })
+}
+
+fn parse_identifier(raw: &str, kind: &str) -> Result<String, String> {
+    let name = if let Some(quoted) = raw
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        if quoted.replace("\"\"", "").contains('"') {
+            return Err("a quote inside a quoted name must be doubled".to_string());
+        }
+        quoted.replace("\"\"", "\"")
+    } else {
+        let mut chars = raw.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+        if !valid {
+            return Err(format!(
+                "{raw:?} is not a valid identifier; double-quote it, e.g. '\"{raw}\"'"
+            ));
+        }
+        raw.to_ascii_lowercase()
+    };
+    if name.is_empty() || name.contains('\0') {
+        return Err(format!(
+            "{kind} names must be non-empty and cannot contain NUL"
+        ));
+    }
+    if name.len() > 63 {
+        return Err(format!(
+            "{kind} names are limited to 63 bytes; {name:?} has {}",
+            name.len()
+        ));
+    }
+    Ok(name)
+}
+
+/// Parse sizes such as `1GiB`, `500MB` or `2g`; units are powers of 1024
+/// as in postgresql.conf, and bare numbers are bytes.
+fn parse_size(raw: &str) -> Result<u64, String> {
+    let raw = raw.trim();
+    let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (number, unit) = raw.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected a size such as 1GiB, got '{raw}'"))?;
+    let shift = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        "t" | "tb" | "tib" => 40,
+        _ => return Err(format!("unknown size unit '{unit}' in '{raw}'")),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size '{raw}' is too large"))
+}
+
+fn parse_key_value(raw: &str) -> AppResult<(String, String)> {
+    match raw.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(io::Error::other(format!("expected KEY=VALUE, got '{raw}'")).into()),
+    }
+}
+
+/// Send pgx's log output to stderr (or the daemon log file) in the chosen
+/// format. Results such as connection URLs go to stdout and stay plain.
+fn init_tracing(cli: &Cli) -> AppResult<()> {
+    let format = match cli.log_format {
+        Some(format) => format,
+        None => match std::env::var(PGX_LOG_FORMAT_ENV) {
+            Ok(raw) if !raw.is_empty() => LogFormat::from_str(&raw, true).map_err(|_| {
+                io::Error::other(format!(
+                    "invalid {PGX_LOG_FORMAT_ENV} '{raw}'; expected pretty, compact or json"
+                ))
+            })?,
+            _ => LogFormat::Pretty,
+        },
+    };
+
+    QUIET.store(cli.quiet, Ordering::Relaxed);
+    if cli.quiet {
+        progress::hide();
+    }
+    let env_filter = match (cli.quiet, cli.verbose) {
+        (true, _) => EnvFilter::new("error"),
+        (false, 0) => {
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn,pgx=info"))
+        }
+        (false, 1) => EnvFilter::new("info,pgx=debug"),
+        (false, _) => EnvFilter::new("debug,pgx=trace"),
+    };
+    let (writer, ansi) = match daemon_log_writer(&cli.command)? {
+        Some(file) => (BoxMakeWriter::new(std::sync::Mutex::new(file)), false),
+        None => (BoxMakeWriter::new(io::stderr), true),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Pretty => subscriber.init(),
+        LogFormat::Compact => subscriber.compact().init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    Ok(())
+}
+
+/// Whether `--quiet` was given.
+fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Whether `--json` was given.
+fn json_output() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Point every command that takes `--format` at JSON, for `--json`.
+fn use_json_format(command: &mut Commands) {
+    let format = match command {
+        Commands::Status(args) => &mut args.format,
+        Commands::Url(args) => &mut args.format,
+        Commands::Doctor(args) => &mut args.format,
+        Commands::Info(args) => &mut args.format,
+        Commands::List(args) => &mut args.format,
+        Commands::Version(args) => &mut args.format,
+        Commands::Assert(args) => &mut args.format,
+        Commands::Snapshot(SnapshotArgs {
+            command: SnapshotCommand::List(args),
+        }) => &mut args.format,
+        Commands::Template(TemplateArgs {
+            command: TemplateCommand::List(args),
+        }) => &mut args.format,
+        _ => return,
+    };
+    *format = OutputFormat::Json;
+}
+
+/// Print a result as pretty JSON on stdout; every JSON result goes through here.
+fn print_json(value: &impl serde::Serialize) -> AppResult<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Print an informational message that `--quiet` suppresses. With `--json`
+/// it goes to stderr, so stdout holds nothing but the JSON result.
+fn report(message: impl std::fmt::Display) {
+    if quiet() {
+        return;
+    }
+    if json_output() {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
+
+/// What `start` prints once the server runs: its URL, or with `--json` the
+/// server's status, address, version, data directory and URL.
+fn print_started(data_dir: &Path, url: &str) -> AppResult<()> {
+    if !json_output() {
+        println!("{url}");
+        return Ok(());
+    }
+    let state = read_state_file(data_dir)?;
+    print_json(&serde_json::json!({
+        "status": "running",
+        "host": state.as_ref().map(|state| state.host.clone()),
+        "port": state.as_ref().map(|state| state.port),
+        "version": state.as_ref().and_then(|state| state.pg_version.clone()),
+        "data_dir": data_dir.display().to_string(),
+        "url": url,
+    }))
+}
+
+/// The log file `start --daemon` should use, or `None` for `--log-file -`.
+fn daemon_log_file_path(args: &StartArgs, data_dir: &Path) -> Option<PathBuf> {
+    match &args.log_file {
+        Some(path) if path.as_os_str() == "-" => None,
+        Some(path) => Some(path.clone()),
+        None => Some(sidecar_file_path(data_dir, "pgx.log")),
+    }
+}
+
+/// The [`ClusterOptions`] that `pgx start` flags, the environment, the
+/// profile and `pgx.toml` select for `data_dir`.
+fn cluster_options(args: &StartArgs, data_dir: &Path) -> AppResult<ClusterOptions> {
+    let profile = profile_defaults(data_dir);
+    let host = resolve_host(args.host.clone())?;
+    let port = match resolve_port(args.port)? {
+        Some(port) => Some(port),
+        None => profile.as_ref().and_then(|profile| profile.port),
+    };
+    let password = match (&args.password, &args.password_from_file) {
+        (Some(password), _) => Some(password.clone()),
+        (None, Some(path)) => Some(
+            fs::read_to_string(path)
+                .map_err(|error| io::Error::other(format!("{}: {error}", path.display())))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        ),
+        (None, None) => None,
+    };
+    let mut databases: Vec<String> = args.database.iter().skip(1).cloned().collect();
+    for raw in project().map_or(&[][..], |project| project.config.databases.as_slice()) {
+        let name = parse_database_name(raw)
+            .map_err(|reason| io::Error::other(format!("{}: {reason}", project::FILE_NAME)))?;
+        databases.push(name);
+    }
+    let tls = if args.tls {
+        Some(true)
+    } else if args.no_tls {
+        Some(false)
+    } else {
+        None
+    };
+
+    Ok(ClusterOptions {
+        host,
+        port,
+        version: args.pg_version.clone(),
+        releases_url: args.releases_url.clone(),
+        binaries: args.binaries.clone(),
+        offline: args.offline,
+        download_retries: args.download_retries,
+        ready_timeout: args.ready_timeout,
+        timeout: args.timeout,
+        provision: ProvisionLimits::machine_wide(
+            args.provision_limit
+                .unwrap_or_else(provision::default_limit),
+        ),
+        min_free_space: args.min_free_space,
+        force: args.force,
+        server_settings: args.server_settings.clone(),
+        database: args
+            .database
+            .first()
+            .cloned()
+            .or_else(|| profile.and_then(|profile| profile.database)),
+        databases,
+        init_sql: args.init_sql.clone(),
+        init_dir: args.init_dir.clone(),
+        init: initdb::InitOptions {
+            locale: args.locale.clone(),
+            encoding: args.encoding.clone(),
+            data_checksums: args.data_checksums,
+        },
+        strict: args.strict,
+        listen: args.listen.clone(),
+        allow: args.allow.clone(),
+        socket_dir: args.socket_dir.clone(),
+        trust_local: args.trust_local,
+        superuser: args.superuser.clone(),
+        password,
+        app_user: args.app_user.clone(),
+        app_password: args.app_password.clone(),
+        app_db: args.app_db.clone(),
+        roles: args.roles.clone(),
+        tls,
+        prewarm: args.prewarm,
+        pre_start: args.pre_start.clone(),
+        post_start: args.post_start.clone(),
+        hook_timeout: args.hook_timeout,
+        log_file: if args.daemon {
+            daemon_log_file_path(args, data_dir)
+        } else {
+            None
+        },
+        port_file: args.port_file.clone(),
+        env_file: args.env_file.clone(),
+        env_key: args.env_key.clone(),
+        register: !args.ephemeral,
+    })
+}
+
+/// Open the daemon log file for pgx's own tracing output, if this invocation
+/// starts a daemon.
+fn daemon_log_writer(command: &Commands) -> AppResult<Option<fs::File>> {
+    let Commands::Start(args) = command else {
+        return Ok(None);
+    };
+    if !args.daemon {
+        return Ok(None);
+    }
+
+    let data_dir = absolute_data_dir(&raw_data_dir(args.data_dir.clone())?)?;
+    match daemon_log_file_path(args, &data_dir) {
+        Some(path) => Ok(Some(open_log_file(&path)?)),
+        None => Ok(None),
+    }
+}
+
+/// The data directory `start` should use: a fresh temporary one for
+/// `--ephemeral`, otherwise the usual `--data-dir`/`PGX_DATA_DIR` resolution.
+fn start_data_dir(args: &StartArgs) -> AppResult<(Option<EphemeralRoot>, PathBuf)> {
+    if args.ephemeral {
+        let root = EphemeralRoot::create()?;
+        let data_dir = root.data_dir();
+        bind_sidecar_paths(&data_dir);
+        return Ok((Some(root), data_dir));
+    }
+
+    // Create the directory first so symlinks above it resolve the same way
+    // now as for every later command.
+    let data_dir = raw_data_dir(args.data_dir.clone())?;
+    prepare_data_dir(&data_dir)?;
+    let data_dir = absolute_data_dir(&data_dir)?;
+    bind_sidecar_paths(&data_dir);
+    Ok((None, data_dir))
+}
+
+/// The port asked for with `--port` or `PGX_PORT`, if any.
+fn resolve_port(cli_port: Option<u16>) -> AppResult<Option<u16>> {
+    let env_port = env_override(PGX_PORT_ENV, "--port", cli_port.as_ref(), |raw| {
+        raw.parse()
+            .map_err(|_| "expected a port number between 0 and 65535")
+    })?;
+    Ok(env_port.or(cli_port))
+}
+
+fn resolve_host(cli_host: Option<String>) -> AppResult<String> {
+    let env_host = env_override(PGX_HOST_ENV, "--host", cli_host.as_ref(), |raw| {
+        Ok(raw.to_string())
+    })?;
+    Ok(env_host
+        .or(cli_host)
+        .unwrap_or_else(|| "localhost".to_string()))
+}
+
+/// Read a start option from environment variable `name`. As with
+/// `PGX_DATA_DIR`, a set variable wins over the command-line flag, with a
+/// warning when the two disagree.
+fn env_override<T: PartialEq>(
+    name: &str,
+    flag: &str,
+    cli_value: Option<&T>,
+    parse: impl FnOnce(&str) -> Result<T, &'static str>,
+) -> AppResult<Option<T>> {
+    let Some(raw) = std::env::var_os(name) else {
+        return Ok(None);
+    };
+    let raw = raw.to_string_lossy();
+    if raw.trim().is_empty() {
+        return Err(io::Error::other(format!("{name} is set but empty")).into());
+    }
+
+    let value = parse(raw.trim())
+        .map_err(|reason| io::Error::other(format!("invalid {name} '{raw}': {reason}")))?;
+    if cli_value.is_some_and(|cli_value| *cli_value != value) {
+        tracing::warn!("{flag} is ignored because {name} is set");
+    }
+    Ok(Some(value))
+}
+
+/// The data directory a command works on, absolute and with symlinks
+/// resolved; see [`raw_data_dir`] for where it comes from.
+fn resolve_data_dir(cli_data_dir: Option<PathBuf>) -> AppResult<PathBuf> {
+    let raw = raw_data_dir(cli_data_dir)?;
+    let data_dir = absolute_data_dir(&raw)?;
+    if raw.is_relative() && !data_dir.exists() {
+        tracing::warn!(
+            "{} resolves to {} from this directory, which does not exist; use an absolute path to reach the same cluster from anywhere",
+            raw.display(),
+            data_dir.display()
+        );
+    }
+    bind_sidecar_paths(&data_dir);
+    Ok(data_dir)
+}
+
+/// Apply `--state-file` and `--password-file` to `data_dir`, the one cluster
+/// the command works on.
+fn bind_sidecar_paths(data_dir: &Path) {
+    if let Some((state_file, password_file)) = SIDECAR_PATHS.get() {
+        override_sidecar_paths(data_dir, state_file.clone(), password_file.clone());
+    }
+}
+
+/// The commands that work on several clusters, or on a second data
+/// directory of their own, where a single custom state or password file
+/// would be shared by all of them.
+fn multi_cluster_command(command: &Commands) -> Option<&'static str> {
+    match command {
+        Commands::Stop(args) if args.all || args.data_dir.len() > 1 => Some("stop --all"),
+        Commands::List(_) => Some("list"),
+        Commands::Prune(_) => Some("prune"),
+        Commands::Clone(_) => Some("clone"),
+        Commands::Snapshot(_) => Some("snapshot"),
+        _ => None,
+    }
+}
+
+/// `PGX_DATA_DIR`, else `--data-dir`, else `--profile`, else the `data_dir`
+/// of `pgx.toml`, else the default profile's directory, as given. A set
+/// `PGX_DATA_DIR` wins over `--data-dir`, with a warning when they disagree.
+fn raw_data_dir(cli_data_dir: Option<PathBuf>) -> AppResult<PathBuf> {
+    if let Some(env_data_dir_raw) = std::env::var_os(PGX_DATA_DIR_ENV) {
+        if env_data_dir_raw.is_empty() {
+            return Err(io::Error::other(format!("{PGX_DATA_DIR_ENV} is set but empty")).into());
+        }
+        let env_data_dir = PathBuf::from(env_data_dir_raw);
+        match cli_data_dir {
+            Some(cli_data_dir) if cli_data_dir != env_data_dir => {
+                tracing::warn!("--data-dir is ignored because {PGX_DATA_DIR_ENV} is set");
+            }
+            Some(_) => {}
+            None => {
+                if let Some(profile) = PROFILE.get() {
+                    tracing::warn!(
+                        "--profile {profile} is ignored because {PGX_DATA_DIR_ENV} is set"
+                    );
+                }
+            }
+        }
+        return Ok(env_data_dir);
+    }
+
+    if let Some(cli_data_dir) = cli_data_dir {
+        if let Some(profile) = PROFILE.get() {
+            tracing::warn!("--profile {profile} is ignored because --data-dir is given");
+        }
+        return Ok(cli_data_dir);
+    }
+
+    if let Some(data_dir) = project_data_dir() {
+        return Ok(data_dir);
+    }
+    Ok(profiles::data_dir(&selected_profile())?)
+}
+
+fn project() -> Option<&'static project::Project> {
+    PROJECT.get().and_then(Option::as_ref)
+}
+
+/// The data directory `pgx.toml` names, unless `--profile` picks another.
+fn project_data_dir() -> Option<PathBuf> {
+    if PROFILE.get().is_some() {
+        return None;
+    }
+    let project = project()?;
+    let data_dir = project.config.data_dir.as_ref()?;
+    Some(project.resolve(data_dir))
+}
+
+/// Fill in the start options that neither the command line nor a `PGX_*`
+/// variable set from `pgx.toml`.
+fn apply_project_config(args: &mut StartArgs) -> AppResult<()> {
+    match project() {
+        Some(project) => merge_project_config(project, args),
+        None => Ok(()),
+    }
+}
+
+fn merge_project_config(project: &project::Project, args: &mut StartArgs) -> AppResult<()> {
+    let config = &project.config;
+    let invalid =
+        |reason: String| io::Error::other(format!("{}: {reason}", project.path.display()));
+    if args.port.is_none() && std::env::var_os(PGX_PORT_ENV).is_none() {
+        args.port = config.port;
+    }
+    if args.host.is_none() && std::env::var_os(PGX_HOST_ENV).is_none() {
+        args.host = config.host.clone();
+    }
+    if args.pg_version.is_none()
+        && let Some(version) = &config.pg_version
+    {
+        args.pg_version = Some(parse_pg_version(version).map_err(invalid)?);
+    }
+    if args.database.is_empty()
+        && let Some(database) = &config.database
+    {
+        args.database = vec![parse_database_name(database).map_err(invalid)?];
+    }
+    // Later --set values win, so the file's go first.
+    let mut server_settings = Vec::new();
+    for (key, value) in &config.settings {
+        let value = match value {
+            toml::Value::String(value) => value.clone(),
+            other => other.to_string(),
+        };
+        server_settings.push(parse_server_setting(&format!("{key}={value}")).map_err(invalid)?);
+    }
+    server_settings.append(&mut args.server_settings);
+    args.server_settings = server_settings;
+    let mut init_sql: Vec<PathBuf> = config
+        .init_scripts
+        .iter()
+        .map(|script| project.resolve(script))
+        .collect();
+    init_sql.append(&mut args.init_sql);
+    args.init_sql = init_sql;
+    // Roles given with --role are applied after, and so win over, the file's.
+    let mut roles = Vec::new();
+    for (name, role) in &config.roles {
+        let options: Vec<&str> = role.options.iter().map(String::as_str).collect();
+        roles.push(role_spec(name, &role.password, &options).map_err(invalid)?);
+    }
+    roles.append(&mut args.roles);
+    args.roles = roles;
+    if args.pre_start.is_empty() {
+        args.pre_start = config.pre_start.clone();
+    }
+    if args.post_start.is_empty() {
+        args.post_start = config.post_start.clone();
+    }
+    Ok(())
+}
+
+/// The profile a command without `--data-dir` or `PGX_DATA_DIR` uses.
+fn selected_profile() -> String {
+    PROFILE
+        .get()
+        .cloned()
+        .unwrap_or_else(|| profiles::DEFAULT_PROFILE.to_string())
+}
+
+/// Whether `resolve_data_dir` falls back to a profile for `cli_data_dir`.
+fn uses_profile(cli_data_dir: Option<&Path>) -> bool {
+    cli_data_dir.is_none()
+        && std::env::var_os(PGX_DATA_DIR_ENV).is_none()
+        && project_data_dir().is_none()
+}
+
+/// Print a timestamped observation each time the server for `data_dir`
+/// changes between running and not running, until interrupted.
+async fn watch_status(data_dir: &Path, period: Duration, format: OutputFormat) -> AppResult<()> {
+    let mut previous = None;
+
+    poll_until_signal(period, || {
+        let host = read_state_file(data_dir)
+            .ok()
+            .flatten()
+            .map(|state| state.host)
+            .unwrap_or_else(|| "localhost".to_string());
+        let status = match postmaster::inspect(data_dir, &host) {
+            Liveness::Running => "running",
+            Liveness::Stale => "not running (stale postmaster.pid found)",
+            Liveness::Stopped => "not running",
+        };
+        if previous == Some(status) {
+            return None::<()>;
+        }
+        previous = Some(status);
+
+        let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+        match format {
+            OutputFormat::Text => println!("{timestamp} {status}"),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::json!({ "timestamp": timestamp.to_string(), "status": status })
+            ),
+        }
+        None
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Poll until the server for `data_dir` is running and answers a query with
+/// the managed credentials, failing after `limit` with the last observed state.
+async fn wait_for_ready_runtime(data_dir: &Path, limit: Duration) -> AppResult<RuntimeContext> {
+    let deadline = Instant::now() + limit;
+
+    loop {
+        let observed = match load_runtime_context(data_dir.to_path_buf()) {
+            Err(error) => error.to_string(),
+            Ok(runtime) => match runtime_liveness(&runtime) {
+                Liveness::Running => {
+                    let url = database_url(&runtime);
+                    match before_deadline(Some(deadline), readiness::probe(&url)).await {
+                        Some(Ok(())) => return Ok(runtime),
+                        Some(Err(error)) => {
+                            format!("running but not accepting connections: {error}")
+                        }
+                        None => "running but not accepting connections".to_string(),
+                    }
+                }
+                Liveness::Stale => "not running (stale postmaster.pid found)".to_string(),
+                Liveness::Stopped => "not running".to_string(),
+            },
+        };
+
+        if Instant::now() >= deadline {
+            return Err(timed_out(
+                limit,
+                format_args!("waiting for the server; last observed: {observed}"),
+            )
+            .into());
+        }
+        tokio::time::sleep(STATUS_WAIT_INTERVAL).await;
+    }
+}
+
+fn print_status_json(
+    runtime: &RuntimeContext,
+    repair: bool,
+    connectivity: Option<&readiness::Connectivity>,
+    connections: Option<&[info::Backend]>,
+) -> AppResult<()> {
+    let liveness = runtime_liveness(runtime);
+    if liveness == Liveness::Stale && repair {
+        postmaster::remove_pid_file(&runtime.data_dir)?;
+    }
+
+    let (status, url) = match liveness {
+        Liveness::Running => ("running", Some(runtime.connection.url())),
+        Liveness::Stale if !repair => ("stale", None),
+        Liveness::Stale | Liveness::Stopped => ("not running", None),
+    };
+
+    let mut output = serde_json::to_value(&runtime.state)?;
+    output["status"] = status.into();
+    output["cluster_exists"] = cluster_is_initialized(&runtime.data_dir).into();
+    output["url"] = url.into();
+    if let Some(connectivity) = connectivity {
+        output["connectivity"] = connectivity.to_string().into();
+    }
+    if let Some(connections) = connections {
+        output["connections"] = serde_json::to_value(connections)?;
+    }
+    output["data_dir"] = runtime.data_dir.display().to_string().into();
+    let (size, free) = disk_usage(&runtime.data_dir);
+    output["data_dir_size_bytes"] = size.into();
+    output["free_space_bytes"] = free.into();
+    print_json(&output)?;
+    Ok(())
+}
+
+fn report_stale_pid_file(data_dir: &Path, repair: bool) -> AppResult<()> {
+    println!("{}", stale_pid_file_message(data_dir, repair)?);
+    Ok(())
+}
+
+/// Await `future`, failing with [`TimedOut`] once `limit` has passed; a zero
+/// `limit` waits forever.
+async fn bounded<T, E>(
+    limit: Duration,
+    waiting_for: &str,
+    future: impl Future<Output = Result<T, E>>,
+) -> AppResult<T>
+where
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    if limit.is_zero() {
+        return future.await.map_err(Into::into);
+    }
+    match tokio::time::timeout(limit, future).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(_) => Err(timed_out(limit, format_args!("waiting for {waiting_for}")).into()),
+    }
+}
+
+/// Bring a supervised server back up after it stopped unexpectedly and
+/// record the new postmaster in the state file.
+async fn restart_server(
+    postgresql: &mut PostgreSQL,
+    data_dir: &Path,
+    ready_timeout: Duration,
+) -> AppResult<()> {
+    if postmaster::inspect(data_dir, &postgresql.settings().host) == Liveness::Stale {
+        clear_stale_pid_file(data_dir, &postgresql.settings().host)?;
+    }
+    postgresql.start().await?;
+    readiness::wait_until_ready(
+        &settings_url(postgresql.settings(), DEFAULT_DATABASE),
+        ready_timeout,
+    )
+    .await?;
+
+    if let Some(mut state) = read_state_file(data_dir)? {
+        state.host = postgresql.settings().host.clone();
+        state.port = postgresql.settings().port;
+        state.running = true;
+        state.pid = postmaster::read_pid(data_dir);
+        state.started_at = Some(humantime::format_rfc3339_seconds(SystemTime::now()).to_string());
+        write_state_file(data_dir, &state)?;
+    }
+    tracing::info!(
+        "PostgreSQL restarted on port {}",
+        postgresql.settings().port
+    );
+    Ok(())
+}
+
+/// Sleep for `delay`; `false` if SIGINT or SIGTERM (Ctrl-C) arrived first.
+async fn sleep_unless_signal(delay: Duration) -> AppResult<bool> {
+    // The first tick fires immediately, the second after `delay`.
+    let mut ticks = 0;
+    let elapsed = poll_until_signal(delay, || {
+        ticks += 1;
+        (ticks > 1).then_some(())
+    })
+    .await?;
+    Ok(elapsed.is_some())
+}
+
+/// Wait until the server stops, a shutdown signal arrives or the server has
+/// been idle for `idle_timeout`. With `detect_crash`, a postmaster that died
+/// without removing its pid file also counts as stopped.
+async fn wait_for_shutdown_signal_or_server_stop(
+    postgresql: &PostgreSQL,
+    mut log_tail: Option<&mut server_log::LogTail>,
+    poll_interval: Duration,
+    idle_timeout: Option<Duration>,
+    detect_crash: bool,
+) -> AppResult<ShutdownOutcome> {
+    let data_dir = &postgresql.settings().data_dir;
+    let exit_watch =
+        postmaster::read_pid(data_dir).and_then(|pid| postmaster::watch_process_exit(pid).ok());
+    // A watched postmaster reports its own exit, so unless the log is echoed
+    // the ticker is only a fallback and can run rarely.
+    let period = if exit_watch.is_some() && log_tail.is_none() {
+        WATCHED_POLL_INTERVAL.max(poll_interval)
+    } else {
+        poll_interval
+    };
+    let stopped = poll_until_signal(period, || {
+        if let Some(log_tail) = &mut log_tail {
+            log_tail.print_new_lines();
+        }
+        let crashed = detect_crash
+            && postmaster::inspect(data_dir, &postgresql.settings().host) == Liveness::Stale;
+        (crashed || postgresql.status() != Status::Started).then_some(())
+    });
+    let idle = async {
+        match idle_timeout {
+            Some(limit) => {
+                let url = settings_url(postgresql.settings(), DEFAULT_DATABASE);
+                idle::wait_until_idle(&url, limit).await;
+                limit
+            }
+            None => std::future::pending().await,
+        }
+    };
+
+    let exited = async {
+        let exited = match exit_watch {
+            Some(exit_watch) => exit_watch.wait().await.is_ok(),
+            None => false,
+        };
+        if !exited {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    let outcome = tokio::select! {
+        stopped = stopped => match stopped? {
+            Some(()) => ShutdownOutcome::ServerStopped,
+            None => ShutdownOutcome::Signal,
+        },
+        () = exited => ShutdownOutcome::ServerStopped,
+        limit = idle => ShutdownOutcome::Idle(limit),
+    };
+    if let ShutdownOutcome::ServerStopped = outcome
+        && let Some(log_tail) = &mut log_tail
+    {
+        log_tail.print_new_lines();
+    }
+    Ok(outcome)
+}
+
+/// Call `observe` every `period` until it returns `Some`, or until SIGINT or
+/// SIGTERM arrives, in which case `None` is returned.
+#[cfg(unix)]
+async fn poll_until_signal<T>(
+    period: Duration,
+    mut observe: impl FnMut() -> Option<T>,
+) -> AppResult<Option<T>> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut ticker = interval(period);
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    loop {
+        tokio::select! {
+            _ = sigint.recv() => return Ok(None),
+            _ = sigterm.recv() => return Ok(None),
+            _ = ticker.tick() => {
+                if let Some(value) = observe() {
+                    return Ok(Some(value));
+                }
+            }
+        }
+    }
+}
+
+/// Call `observe` every `period` until it returns `Some`, or until Ctrl-C,
+/// Ctrl-Break, the console closing, logoff or system shutdown, in which case
+/// `None` is returned.
+#[cfg(windows)]
+async fn poll_until_signal<T>(
+    period: Duration,
+    mut observe: impl FnMut() -> Option<T>,
+) -> AppResult<Option<T>> {
+    use tokio::signal::windows::{ctrl_break, ctrl_c, ctrl_close, ctrl_logoff, ctrl_shutdown};
+
+    let mut ticker = interval(period);
+    let mut ctrl_c = ctrl_c()?;
+    let mut ctrl_break = ctrl_break()?;
+    let mut ctrl_close = ctrl_close()?;
+    let mut ctrl_logoff = ctrl_logoff()?;
+    let mut ctrl_shutdown = ctrl_shutdown()?;
+
+    loop {
+        tokio::select! {
+            _ = ctrl_c.recv() => return Ok(None),
+            _ = ctrl_break.recv() => return Ok(None),
+            _ = ctrl_close.recv() => return Ok(None),
+            _ = ctrl_logoff.recv() => return Ok(None),
+            _ = ctrl_shutdown.recv() => return Ok(None),
+            _ = ticker.tick() => {
+                if let Some(value) = observe() {
+                    return Ok(Some(value));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, PoisonError};
+
+    /// Held by every test that sets environment variables.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Run `test` with each variable in `vars` set, or removed for `None`,
+    /// restoring the previous values afterwards.
+    fn with_env<T>(vars: &[(&str, Option<&str>)], test: impl FnOnce() -> T) -> T {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let saved: Vec<_> = vars
+            .iter()
+            .map(|(name, _)| (*name, std::env::var_os(name)))
+            .collect();
+        let apply = |name: &str, value: Option<&std::ffi::OsStr>| {
+            // SAFETY: ENV_LOCK serializes the tests that touch the environment,
+            // and the variables set here are only read by pgx itself.
+            unsafe {
+                match value {
+                    Some(value) => std::env::set_var(name, value),
+                    None => std::env::remove_var(name),
+                }
+            }
+        };
+        for (name, value) in vars {
+            apply(name, value.map(std::ffi::OsStr::new));
+        }
+        let result = test();
+        for (name, value) in &saved {
+            apply(name, value.as_deref());
+        }
+        result
+    }
+
+    #[test]
+    fn port_from_the_command_line_without_pgx_port() {
+        with_env(&[(PGX_PORT_ENV, None)], || {
+            assert_eq!(resolve_port(Some(5433)).unwrap(), Some(5433));
+            assert_eq!(resolve_port(None).unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn pgx_port_wins_over_the_command_line() {
+        with_env(&[(PGX_PORT_ENV, Some(" 6543 "))], || {
+            assert_eq!(resolve_port(Some(5433)).unwrap(), Some(6543));
+            assert_eq!(resolve_port(None).unwrap(), Some(6543));
+        });
+    }
+
+    #[test]
+    fn invalid_pgx_port_is_refused() {
+        with_env(&[(PGX_PORT_ENV, Some("70000"))], || {
+            assert_eq!(
+                resolve_port(Some(5433)).unwrap_err().to_string(),
+                "invalid PGX_PORT '70000': expected a port number between 0 and 65535"
+            );
+        });
+        with_env(&[(PGX_PORT_ENV, Some("five"))], || {
+            assert_eq!(
+                resolve_port(None).unwrap_err().to_string(),
+                "invalid PGX_PORT 'five': expected a port number between 0 and 65535"
+            );
+        });
+    }
+
+    #[test]
+    fn host_defaults_to_localhost() {
+        with_env(&[(PGX_HOST_ENV, None)], || {
+            assert_eq!(resolve_host(None).unwrap(), "localhost");
+            assert_eq!(resolve_host(Some("127.0.0.1".into())).unwrap(), "127.0.0.1");
+        });
+    }
+
+    #[test]
+    fn pgx_host_wins_over_the_command_line() {
+        with_env(&[(PGX_HOST_ENV, Some("0.0.0.0"))], || {
+            assert_eq!(resolve_host(Some("127.0.0.1".into())).unwrap(), "0.0.0.0");
+            assert_eq!(resolve_host(None).unwrap(), "0.0.0.0");
+        });
+    }
+
+    #[test]
+    fn empty_pgx_host_is_refused() {
+        for empty in ["", "  "] {
+            with_env(&[(PGX_HOST_ENV, Some(empty))], || {
+                assert_eq!(
+                    resolve_host(Some("127.0.0.1".into()))
+                        .unwrap_err()
+                        .to_string(),
+                    "PGX_HOST is set but empty"
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn pgx_data_dir_wins_over_the_command_line() {
+        with_env(&[(PGX_DATA_DIR_ENV, Some("/env/db"))], || {
+            assert_eq!(
+                raw_data_dir(Some("/cli/db".into())).unwrap(),
+                Path::new("/env/db")
+            );
+            assert_eq!(raw_data_dir(None).unwrap(), Path::new("/env/db"));
+        });
+        with_env(&[(PGX_DATA_DIR_ENV, None)], || {
+            assert_eq!(
+                raw_data_dir(Some("/cli/db".into())).unwrap(),
+                Path::new("/cli/db")
+            );
+        });
+        with_env(&[(PGX_DATA_DIR_ENV, Some(""))], || {
+            assert_eq!(
+                raw_data_dir(Some("/cli/db".into()))
+                    .unwrap_err()
+                    .to_string(),
+                "PGX_DATA_DIR is set but empty"
+            );
+        });
+    }
+
+    fn start_args(args: &[&str]) -> StartArgs {
+        let cli = Cli::try_parse_from(["pgx", "start"].iter().chain(args)).unwrap();
+        match cli.command {
+            Commands::Start(args) => args,
+            _ => unreachable!(),
+        }
+    }
+
+    fn project(raw: &str) -> project::Project {
+        project::Project {
+            path: PathBuf::from("/work/app/pgx.toml"),
+            config: toml::from_str(raw).unwrap(),
+        }
+    }
+
+    const PROJECT_FILE: &str = r#"
+        port = 6000
+        host = "127.0.0.1"
+        pg_version = "16"
+        database = "app"
+        init_scripts = ["db/schema.sql"]
+        pre_start = ["make migrations"]
+
+        [settings]
+        shared_buffers = "256MB"
+        max_connections = 50
+
+        [roles.reader]
+        password = "secret"
+        options = ["createdb"]
+    "#;
+
+    #[test]
+    fn project_config_fills_in_what_nothing_else_set() {
+        with_env(&[(PGX_PORT_ENV, None), (PGX_HOST_ENV, None)], || {
+            let mut args = start_args(&[]);
+            merge_project_config(&project(PROJECT_FILE), &mut args).unwrap();
+
+            assert_eq!(args.port, Some(6000));
+            assert_eq!(args.host.as_deref(), Some("127.0.0.1"));
+            assert_eq!(args.pg_version, Some(parse_pg_version("16").unwrap()));
+            assert_eq!(args.database, ["app"]);
+            assert_eq!(args.init_sql, [PathBuf::from("/work/app/db/schema.sql")]);
+            assert_eq!(args.pre_start, ["make migrations"]);
+            assert_eq!(
+                args.server_settings,
+                [
+                    ("max_connections".to_string(), "50".to_string()),
+                    ("shared_buffers".to_string(), "256MB".to_string()),
+                ]
+            );
+            assert_eq!(args.roles.len(), 1);
+            assert_eq!(args.roles[0].attributes, ["CREATEDB"]);
+        });
+    }
+
+    #[test]
+    fn flags_and_environment_win_over_project_config() {
+        with_env(
+            &[(PGX_PORT_ENV, None), (PGX_HOST_ENV, Some("0.0.0.0"))],
+            || {
+                let mut args = start_args(&[
+                    "--port",
+                    "7000",
+                    "--pg-version",
+                    "17",
+                    "--database",
+                    "other",
+                    "--set",
+                    "shared_buffers=1GB",
+                    "--init-sql",
+                    "extra.sql",
+                    "--role",
+                    "reader:override",
+                    "--pre-start",
+                    "true",
+                ]);
+                merge_project_config(&project(PROJECT_FILE), &mut args).unwrap();
+
+                assert_eq!(args.port, Some(7000));
+                // PGX_HOST is applied later; the file must not shadow it.
+                assert_eq!(args.host, None);
+                assert_eq!(args.pg_version, Some(parse_pg_version("17").unwrap()));
+                assert_eq!(args.database, ["other"]);
+                assert_eq!(args.pre_start, ["true"]);
+                // List options keep the file's entries first, so later flags win.
+                assert_eq!(
+                    args.server_settings.last(),
+                    Some(&("shared_buffers".to_string(), "1GB".to_string()))
+                );
+                assert_eq!(
+                    args.init_sql,
+                    [
+                        PathBuf::from("/work/app/db/schema.sql"),
+                        PathBuf::from("extra.sql")
+                    ]
+                );
+                let passwords: Vec<_> = args
+                    .roles
+                    .iter()
+                    .map(|role| role.password.as_str())
+                    .collect();
+                assert_eq!(passwords, ["secret", "override"]);
+            },
+        );
+    }
+
+    #[test]
+    fn invalid_project_values_name_the_file() {
+        let mut args = start_args(&[]);
+        let error = merge_project_config(&project(r#"database = "bad name;""#), &mut args)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("/work/app/pgx.toml: "), "{error}");
+    }
+}
@@ -0,0 +1,46 @@
+//! Support for `pgx clone`: giving a copied cluster credentials of its own.
+
+use crate::app_role::{quote_identifier, quote_literal};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Set `superuser`'s password in the stopped cluster at `data_dir` by running
+/// `postgres` in single-user mode, so the clone never has to listen on a port
+/// with the source's password. The password goes through stdin, not argv.
+pub fn reset_superuser_password(
+    postgres: &Path,
+    data_dir: &Path,
+    superuser: &str,
+    password: &str,
+) -> io::Result<()> {
+    let mut child = Command::new(postgres)
+        .arg("--single")
+        .arg("-D")
+        .arg(data_dir)
+        .arg("postgres")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    {
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        writeln!(
+            stdin,
+            "ALTER ROLE {} WITH PASSWORD {};",
+            quote_identifier(superuser),
+            quote_literal(password)
+        )?;
+    }
+    let output = child.wait_with_output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Single-user mode reports SQL errors on stderr but still exits 0.
+    if !output.status.success() || stderr.contains("ERROR:") {
+        return Err(io::Error::other(format!(
+            "failed to set the superuser password in {}: {}",
+            data_dir.display(),
+            stderr.trim()
+        )));
+    }
+    Ok(())
+}
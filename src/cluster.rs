@@ -0,0 +1,286 @@
+use crate::app_role::Role;
+use crate::error::PgxError;
+use crate::initdb::InitOptions;
+use crate::lifecycle::{self, StartedServer, StopOptions};
+use crate::postmaster::{self, Liveness};
+use crate::provision::ProvisionLimits;
+use crate::runtime::{self, RuntimeContext, UrlRequest};
+use crate::state::{
+    StateFile, absolute_data_dir, password_file_path, read_managed_password_file, read_state_file,
+    state_file_path,
+};
+use postgresql_embedded::{Settings, VersionReq};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// PostgreSQL versions pgx runs unless told otherwise.
+pub const PG_VERSION_REQ: &str = "=17";
+
+/// How [`Cluster::start`] sets up and starts the server: the options of
+/// `pgx start`, which builds one of these from its flags. The defaults are
+/// those of `pgx start` without flags.
+#[derive(Debug, Clone)]
+pub struct ClusterOptions {
+    pub host: String,
+    /// 0 picks a free port. `None` reuses the port of the previous start
+    /// when it is still free.
+    pub port: Option<u16>,
+    /// `None` keeps the version the cluster last ran, else the major version
+    /// it was initialized with, else [`PG_VERSION_REQ`].
+    pub version: Option<VersionReq>,
+    /// Where to download PostgreSQL releases from [default: `PGX_RELEASES_URL`,
+    /// else GitHub].
+    pub releases_url: Option<String>,
+    /// A pre-downloaded release archive or installation directory to install from.
+    pub binaries: Option<PathBuf>,
+    /// Fail instead of downloading PostgreSQL or extensions.
+    pub offline: bool,
+    /// Retries of a download that failed with a transient network error.
+    pub download_retries: u32,
+    /// How long `start` waits for the server to accept connections.
+    pub ready_timeout: Duration,
+    /// Limit on setup, startup and readiness together.
+    pub timeout: Option<Duration>,
+    /// Queue shared with other clusters so that only so many set up,
+    /// initialize and start at once.
+    pub provision: ProvisionLimits,
+    /// Warn below this much free space on the data directory's filesystem.
+    pub min_free_space: u64,
+    /// Start even on an almost full filesystem.
+    pub force: bool,
+    /// postgresql.conf settings to record and apply; an empty value drops one.
+    pub server_settings: Vec<(String, String)>,
+    /// Database that URLs point at, created if missing [default: the
+    /// recorded one, else `postgres`].
+    pub database: Option<String>,
+    /// More databases to create.
+    pub databases: Vec<String>,
+    /// SQL files to run after the first start of a new cluster.
+    pub init_sql: Vec<PathBuf>,
+    /// Directories whose `.sql` files run after `init_sql`, in name order.
+    pub init_dir: Vec<PathBuf>,
+    /// initdb options for a new cluster.
+    pub init: InitOptions,
+    /// Fail instead of warning when `init` disagrees with an existing cluster.
+    pub strict: bool,
+    /// Addresses to listen on besides `host`.
+    pub listen: Vec<String>,
+    /// Networks allowed to log in when listening beyond loopback [default: samenet].
+    pub allow: Vec<String>,
+    /// Also listen on a Unix socket in this directory; `Some(None)` picks
+    /// `<data_dir>.pgx-socket`. `None` keeps a recorded one.
+    pub socket_dir: Option<Option<PathBuf>>,
+    /// Let local connections in without a password.
+    pub trust_local: bool,
+    /// Superuser of a new cluster [default: postgres].
+    pub superuser: Option<String>,
+    /// Superuser password of a new cluster instead of a generated one.
+    pub password: Option<String>,
+    /// Non-superuser login role to create.
+    pub app_user: Option<String>,
+    /// Password of `app_user`, or `generate` [default: generate].
+    pub app_password: Option<String>,
+    /// Database owned by `app_user` [default: its name].
+    pub app_db: Option<String>,
+    /// Login roles to create or update.
+    pub roles: Vec<Role>,
+    /// Serve TLS with a self-signed certificate. `None` keeps what the
+    /// previous start did.
+    pub tls: Option<bool>,
+    /// Load the relations recorded on the previous stop into shared buffers.
+    pub prewarm: bool,
+    /// Shell commands to run before setup.
+    pub pre_start: Vec<String>,
+    /// Shell commands to run once the server accepts connections.
+    pub post_start: Vec<String>,
+    pub hook_timeout: Duration,
+    /// File for the server log, appended to across restarts [default: in
+    /// the data directory].
+    pub log_file: Option<PathBuf>,
+    /// File to write the port to once the server is up.
+    pub port_file: Option<PathBuf>,
+    /// `.env` file in which to set `env_key` to the URL.
+    pub env_file: Option<PathBuf>,
+    pub env_key: String,
+    /// Record the cluster in the instance registry that `pgx list` and
+    /// `pgx stop --all` read.
+    pub register: bool,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        ClusterOptions {
+            host: "localhost".to_string(),
+            port: None,
+            version: None,
+            releases_url: None,
+            binaries: None,
+            offline: false,
+            download_retries: 3,
+            ready_timeout: Duration::from_secs(30),
+            timeout: None,
+            provision: ProvisionLimits::default(),
+            min_free_space: 1 << 30,
+            force: false,
+            server_settings: Vec::new(),
+            database: None,
+            databases: Vec::new(),
+            init_sql: Vec::new(),
+            init_dir: Vec::new(),
+            init: InitOptions::default(),
+            strict: false,
+            listen: Vec::new(),
+            allow: Vec::new(),
+            socket_dir: None,
+            trust_local: false,
+            superuser: None,
+            password: None,
+            app_user: None,
+            app_password: None,
+            app_db: None,
+            roles: Vec::new(),
+            tls: None,
+            prewarm: false,
+            pre_start: Vec::new(),
+            post_start: Vec::new(),
+            hook_timeout: Duration::from_secs(5 * 60),
+            log_file: None,
+            port_file: None,
+            env_file: None,
+            env_key: "DATABASE_URL".to_string(),
+            register: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClusterStatus {
+    Running,
+    /// pgx has started the cluster before, but no server is running now.
+    Stopped,
+    /// `postmaster.pid` was left behind by a server that no longer exists.
+    StalePidFile,
+    /// pgx has no state for the data directory.
+    NeverStarted,
+}
+
+/// A data directory managed the same way as by the `pgx` CLI, sharing its
+/// sidecar files, so either can start, stop or inspect what the other started.
+///
+/// A started server is not tied to the `Cluster` value: it keeps running
+/// until [`Cluster::stop`] is called, even after the value is dropped.
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    data_dir: PathBuf,
+    options: ClusterOptions,
+}
+
+impl Cluster {
+    pub fn new(data_dir: impl Into<PathBuf>, options: ClusterOptions) -> Self {
+        Cluster {
+            data_dir: data_dir.into(),
+            options,
+        }
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn state_file_path(&self) -> PathBuf {
+        state_file_path(&self.data_dir)
+    }
+
+    pub fn password_file_path(&self) -> PathBuf {
+        password_file_path(&self.data_dir)
+    }
+
+    /// What the state sidecar records, or `None` if pgx never started the cluster.
+    pub fn state(&self) -> Result<Option<StateFile>, PgxError> {
+        read_state_file(&self.data_dir)
+    }
+
+    /// The managed superuser password, or `None` before the first start.
+    pub fn password(&self) -> Result<Option<String>, PgxError> {
+        read_managed_password_file(&self.data_dir)
+    }
+
+    pub fn status(&self) -> Result<ClusterStatus, PgxError> {
+        let Some(state) = self.state()? else {
+            return Ok(ClusterStatus::NeverStarted);
+        };
+
+        Ok(match postmaster::inspect(&self.data_dir, &state.host) {
+            Liveness::Running => ClusterStatus::Running,
+            Liveness::Stale => ClusterStatus::StalePidFile,
+            Liveness::Stopped => ClusterStatus::Stopped,
+        })
+    }
+
+    /// The URL `pgx url` prints: the superuser's, for the database recorded
+    /// at start, requiring TLS when the server was started with `--tls`.
+    pub fn connection_url(&self) -> Result<String, PgxError> {
+        self.url(&UrlRequest::default()).map(|(url, _)| url)
+    }
+
+    /// The URL `request` asks for, and the query parameters appended to it.
+    pub(crate) fn url(
+        &self,
+        request: &UrlRequest,
+    ) -> Result<(String, BTreeMap<String, String>), PgxError> {
+        let runtime = self.runtime()?;
+        runtime::ensure_running(&runtime)?;
+        runtime::request_url(&runtime, request)
+    }
+
+    /// The recorded state, connection details and server settings.
+    pub(crate) fn runtime(&self) -> Result<RuntimeContext, PgxError> {
+        runtime::load_runtime_context(self.data_dir.clone())
+    }
+
+    /// Set up and start the server as `pgx start` does: install PostgreSQL
+    /// and pg_search if needed, initialize the cluster on first use, wait
+    /// until it accepts connections, then run the init scripts and hooks in
+    /// [`ClusterOptions`]. Returns the URL `pgx start` prints.
+    ///
+    /// Installing, initializing and starting wait for a turn in
+    /// [`ClusterOptions::provision`].
+    pub async fn start(&self) -> Result<String, PgxError> {
+        let server = self.start_server(std::future::pending()).await?;
+        // Dropping the handle would stop the server; `stop` uses pg_ctl instead.
+        std::mem::forget(server.postgresql);
+        Ok(server.url)
+    }
+
+    /// [`Cluster::start`], keeping the handle that stops the server when
+    /// dropped. Setup gives up, removing its partial files, once
+    /// `interrupted` completes.
+    pub(crate) async fn start_server(
+        &self,
+        interrupted: impl Future<Output = ()>,
+    ) -> Result<StartedServer, PgxError> {
+        let data_dir = absolute_data_dir(&self.data_dir)?;
+        lifecycle::start_server(&data_dir, &self.options, interrupted).await
+    }
+
+    /// Stop the server with a fast shutdown and record it as stopped.
+    pub async fn stop(&self) -> Result<(), PgxError> {
+        runtime::ensure_running(&self.runtime()?)?;
+        self.stop_with(&StopOptions::default()).await.map(drop)
+    }
+
+    /// Stop as `pgx stop` does, returning the message it prints.
+    pub(crate) async fn stop_with(&self, options: &StopOptions) -> Result<String, PgxError> {
+        lifecycle::stop_cluster(self.data_dir.clone(), options).await
+    }
+}
+
+/// The exact PostgreSQL version `setup()` resolved the version requirement to.
+pub fn resolved_version(settings: &Settings) -> String {
+    settings
+        .version
+        .to_string()
+        .trim_start_matches('=')
+        .to_string()
+}
@@ -0,0 +1,439 @@
+use crate::postmaster::{self, Liveness};
+use crate::snapshot::format_size;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
+
+/// Free space below which the data directory's filesystem is reported,
+/// unless `start --min-free-space` says otherwise.
+pub const LOW_DISK_SPACE: u64 = 1024 * 1024 * 1024;
+/// Free space below which `start` refuses to run without `--force`: the
+/// server would soon fail writes and may loop in crash recovery.
+pub const DISK_SPACE_FLOOR: u64 = 100 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpaceLevel {
+    Enough,
+    /// Below the warning threshold.
+    Low,
+    /// Below [`DISK_SPACE_FLOOR`].
+    Critical,
+}
+
+/// Where free space figures come from: [`free_space`] itself, or a fixed
+/// answer in tests.
+pub trait SpaceProbe {
+    /// Bytes available on the filesystem holding `path`, or `None` where
+    /// the platform cannot tell.
+    fn free_space(&self, path: &Path) -> io::Result<Option<u64>>;
+}
+
+impl<F: Fn(&Path) -> io::Result<Option<u64>>> SpaceProbe for F {
+    fn free_space(&self, path: &Path) -> io::Result<Option<u64>> {
+        self(path)
+    }
+}
+
+/// Classify `free` bytes against the warning threshold `min_free`.
+pub fn space_level(free: u64, min_free: u64) -> SpaceLevel {
+    if free < DISK_SPACE_FLOOR {
+        SpaceLevel::Critical
+    } else if free < min_free {
+        SpaceLevel::Low
+    } else {
+        SpaceLevel::Enough
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Verdict {
+    pub fn label(self) -> &'static str {
+        match self {
+            Verdict::Pass => "PASS",
+            Verdict::Warn => "WARN",
+            Verdict::Fail => "FAIL",
+        }
+    }
+}
+
+/// Outcome of one diagnostic, with a suggested fix when it did not pass.
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub check: &'static str,
+    pub verdict: Verdict,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remedy: Option<String>,
+}
+
+impl Finding {
+    pub fn pass(check: &'static str, detail: impl Into<String>) -> Self {
+        Finding {
+            check,
+            verdict: Verdict::Pass,
+            detail: detail.into(),
+            remedy: None,
+        }
+    }
+
+    pub fn warn(check: &'static str, detail: impl Into<String>, remedy: impl Into<String>) -> Self {
+        Finding {
+            check,
+            verdict: Verdict::Warn,
+            detail: detail.into(),
+            remedy: Some(remedy.into()),
+        }
+    }
+
+    pub fn fail(check: &'static str, detail: impl Into<String>, remedy: impl Into<String>) -> Self {
+        Finding {
+            check,
+            verdict: Verdict::Fail,
+            detail: detail.into(),
+            remedy: Some(remedy.into()),
+        }
+    }
+}
+
+pub fn data_dir_writable(data_dir: &Path) -> Finding {
+    const CHECK: &str = "data directory";
+    if !data_dir.is_dir() {
+        return Finding::fail(
+            CHECK,
+            format!("{} does not exist", data_dir.display()),
+            "run `pgx start` to create and initialize it",
+        );
+    }
+
+    let probe = data_dir.join(".pgx-doctor-probe");
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            Finding::pass(
+                CHECK,
+                format!("{} exists and is writable", data_dir.display()),
+            )
+        }
+        Err(error) => Finding::fail(
+            CHECK,
+            format!("{} is not writable: {error}", data_dir.display()),
+            "make the directory owned by and writable for the user running pgx",
+        ),
+    }
+}
+
+/// Whether `path` is closed to other users: mode 0700 for a directory and
+/// 0600 for a file. A missing path is only reported when `required`.
+#[cfg(unix)]
+pub fn owner_only_permissions(check: &'static str, path: &Path, required: bool) -> Finding {
+    use std::os::unix::fs::PermissionsExt;
+
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let mode = metadata.permissions().mode() & 0o777;
+            let expected = if metadata.is_dir() { 0o700 } else { 0o600 };
+            if mode == expected {
+                Finding::pass(check, format!("{expected:04o}"))
+            } else {
+                Finding::warn(
+                    check,
+                    format!("{} has mode {mode:04o}", path.display()),
+                    format!("chmod {expected:o} {}", path.display()),
+                )
+            }
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => missing(check, path, required),
+        Err(error) => Finding::fail(
+            check,
+            format!("cannot read {}: {error}", path.display()),
+            format!("check the ownership of {}", path.display()),
+        ),
+    }
+}
+
+/// Whether the ACL of `path` grants access to the current user only, as
+/// `pgx start` leaves it. A missing path is only reported when `required`.
+#[cfg(windows)]
+pub fn owner_only_permissions(check: &'static str, path: &Path, required: bool) -> Finding {
+    if !path.exists() {
+        return missing(check, path, required);
+    }
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    let output = match std::process::Command::new("icacls").arg(path).output() {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            return Finding::fail(
+                check,
+                format!(
+                    "icacls failed for {}: {}",
+                    path.display(),
+                    String::from_utf8_lossy(&output.stdout).trim()
+                ),
+                format!("check the ownership of {}", path.display()),
+            );
+        }
+        Err(error) => {
+            return Finding::fail(
+                check,
+                format!("cannot run icacls: {error}"),
+                "make sure icacls is on PATH",
+            );
+        }
+    };
+
+    // The first line starts with the path itself; each entry is `principal:(rights)`.
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let path_text = path.display().to_string();
+    let others: Vec<&str> = listing
+        .lines()
+        .map(|line| line.trim_start_matches(path_text.as_str()).trim())
+        .filter_map(|entry| entry.split_once(":(").map(|(principal, _)| principal))
+        .filter(|principal| {
+            let name = principal.rsplit('\\').next().unwrap_or(principal);
+            !name.eq_ignore_ascii_case(&user)
+        })
+        .collect();
+    if others.is_empty() {
+        Finding::pass(check, format!("only {user} has access"))
+    } else {
+        Finding::warn(
+            check,
+            format!("{} is accessible to {}", path.display(), others.join(", ")),
+            format!(
+                "icacls \"{}\" /inheritance:r /grant:r {user}:F",
+                path.display()
+            ),
+        )
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn owner_only_permissions(check: &'static str, _path: &Path, _required: bool) -> Finding {
+    Finding::pass(check, "not applicable on this platform")
+}
+
+fn missing(check: &'static str, path: &Path, required: bool) -> Finding {
+    if required {
+        Finding::warn(
+            check,
+            format!("{} does not exist", path.display()),
+            "run `pgx start` to create it",
+        )
+    } else {
+        Finding::pass(check, "not created yet")
+    }
+}
+
+/// Whether `postmaster.pid` agrees with the process table and with the pid
+/// pgx recorded when it started the server.
+pub fn pid_file_consistency(data_dir: &Path, host: &str, recorded_pid: Option<u32>) -> Finding {
+    const CHECK: &str = "postmaster.pid";
+    match postmaster::inspect(data_dir, host) {
+        Liveness::Stopped => Finding::pass(CHECK, "server is not running"),
+        Liveness::Stale => Finding::warn(
+            CHECK,
+            "stale postmaster.pid left behind by a server that is no longer running",
+            "run `pgx stop --repair`",
+        ),
+        Liveness::Running => {
+            let pid = postmaster::read_pid(data_dir);
+            match (pid, recorded_pid) {
+                (Some(pid), Some(recorded)) if pid != recorded => Finding::warn(
+                    CHECK,
+                    format!("server runs as pid {pid} but pgx recorded pid {recorded}"),
+                    "the server was restarted outside pgx; run `pgx stop` and `pgx start`",
+                ),
+                (Some(pid), _) => Finding::pass(CHECK, format!("server is running as pid {pid}")),
+                (None, _) => Finding::pass(CHECK, "server is running"),
+            }
+        }
+    }
+}
+
+/// A running server should answer on its port; a stopped one needs the port free.
+pub fn port(host: &str, port: u16, running: bool) -> Finding {
+    const CHECK: &str = "port";
+    if running {
+        if postmaster::accepts_connections(host, port) {
+            return Finding::pass(
+                CHECK,
+                format!("server accepts connections on {host}:{port}"),
+            );
+        }
+        return Finding::fail(
+            CHECK,
+            format!("server is running but nothing accepts connections on {host}:{port}"),
+            "check the server log in the data directory and the host name in the state file",
+        );
+    }
+
+    match TcpListener::bind((host, port)) {
+        Ok(_) => Finding::pass(CHECK, format!("{host}:{port} is free")),
+        Err(error) => Finding::fail(
+            CHECK,
+            format!("{host}:{port} is not available: {error}"),
+            "stop whatever is using the port or start with a different --port",
+        ),
+    }
+}
+
+pub fn disk_space(probe: &impl SpaceProbe, data_dir: &Path) -> Finding {
+    const CHECK: &str = "disk space";
+    let used = crate::snapshot::tree_size(data_dir)
+        .map(|size| format!("; the data directory uses {}", format_size(size)))
+        .unwrap_or_default();
+    match probe.free_space(data_dir) {
+        Ok(Some(free)) => match space_level(free, LOW_DISK_SPACE) {
+            SpaceLevel::Enough => Finding::pass(CHECK, format!("{} free{used}", format_size(free))),
+            SpaceLevel::Low => Finding::warn(
+                CHECK,
+                format!("only {} free{used}", format_size(free)),
+                "free up space on the data directory's filesystem",
+            ),
+            SpaceLevel::Critical => Finding::fail(
+                CHECK,
+                format!("only {} free{used}", format_size(free)),
+                "free up space before starting; `pgx start` refuses to run below 100 MiB without --force",
+            ),
+        },
+        Ok(None) => Finding::pass(CHECK, "not measured on this platform"),
+        Err(error) => Finding::warn(
+            CHECK,
+            format!("cannot measure free space: {error}"),
+            "check that the data directory exists",
+        ),
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stats` is a valid out-pointer.
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: statvfs succeeded, so it initialized `stats`.
+    let stats = unsafe { stats.assume_init() };
+    // The field widths differ between platforms.
+    #[allow(clippy::unnecessary_cast)]
+    let free = stats.f_bavail as u64 * stats.f_frsize as u64;
+    Ok(Some(free))
+}
+
+/// Bytes available to the current user on the volume holding `path`.
+#[cfg(windows)]
+pub fn free_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut free = 0u64;
+    // SAFETY: `path` is NUL-terminated and the out-pointers are valid or null.
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut free,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(free))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn free_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+pub fn print_text(findings: &[Finding]) {
+    for finding in findings {
+        println!(
+            "{} {}: {}",
+            finding.verdict.label(),
+            finding.check,
+            finding.detail
+        );
+        if let Some(remedy) = &finding.remedy {
+            println!("     fix: {remedy}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn disk_space_with(free: impl Fn() -> io::Result<Option<u64>>) -> Finding {
+        let data_dir = tempfile::tempdir().unwrap();
+        disk_space(&|_: &Path| free(), data_dir.path())
+    }
+
+    #[test]
+    fn classifies_free_space() {
+        assert_eq!(
+            space_level(2 * LOW_DISK_SPACE, LOW_DISK_SPACE),
+            SpaceLevel::Enough
+        );
+        assert_eq!(space_level(500 * MIB, LOW_DISK_SPACE), SpaceLevel::Low);
+        assert_eq!(space_level(50 * MIB, LOW_DISK_SPACE), SpaceLevel::Critical);
+        // The floor applies even when the warning threshold is lower.
+        assert_eq!(space_level(50 * MIB, 10 * MIB), SpaceLevel::Critical);
+        assert_eq!(space_level(DISK_SPACE_FLOOR, 0), SpaceLevel::Enough);
+    }
+
+    #[test]
+    fn enough_space_passes() {
+        let finding = disk_space_with(|| Ok(Some(2 * LOW_DISK_SPACE)));
+        assert_eq!(finding.verdict, Verdict::Pass);
+        assert!(
+            finding.detail.starts_with("2.0 GiB free"),
+            "{}",
+            finding.detail
+        );
+    }
+
+    #[test]
+    fn low_space_warns() {
+        let finding = disk_space_with(|| Ok(Some(500 * MIB)));
+        assert_eq!(finding.verdict, Verdict::Warn);
+        assert!(
+            finding.detail.starts_with("only 500.0 MiB free"),
+            "{}",
+            finding.detail
+        );
+    }
+
+    #[test]
+    fn critical_space_fails() {
+        let finding = disk_space_with(|| Ok(Some(50 * MIB)));
+        assert_eq!(finding.verdict, Verdict::Fail);
+        assert!(finding.remedy.unwrap().contains("without --force"));
+    }
+
+    #[test]
+    fn unmeasurable_space_is_not_a_failure() {
+        assert_eq!(disk_space_with(|| Ok(None)).verdict, Verdict::Pass);
+        let finding = disk_space_with(|| Err(io::Error::other("statvfs failed")));
+        assert_eq!(finding.verdict, Verdict::Warn);
+        assert_eq!(finding.detail, "cannot measure free space: statvfs failed");
+    }
+}
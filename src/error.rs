@@ -0,0 +1,131 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors returned by the library API, so callers can tell a cluster that is
+/// simply not running apart from missing metadata or an I/O failure.
+#[derive(Debug)]
+pub enum PgxError {
+    /// A live postmaster already owns the data directory.
+    AlreadyRunning {
+        data_dir: PathBuf,
+        pid: Option<u32>,
+    },
+    /// No postmaster is running for the data directory.
+    NotRunning {
+        data_dir: PathBuf,
+        /// How it stopped, e.g. "stopped (cluster exists)".
+        reason: &'static str,
+    },
+    /// `postmaster.pid` names a process that no longer exists.
+    StalePidFile {
+        data_dir: PathBuf,
+    },
+    /// There is no state file: pgx never started this data directory.
+    NeverStarted {
+        data_dir: PathBuf,
+    },
+    /// The state or password sidecar is missing or unreadable.
+    MetadataMissing,
+    /// No PostgreSQL installation satisfies the version requirement.
+    NotInstalled(String),
+    /// The server started but did not accept connections in time.
+    NotReady(String),
+    Io(io::Error),
+    /// The state file is not valid JSON for this version of pgx.
+    State(serde_json::Error),
+    /// The state file was written by a newer pgx with a schema this one
+    /// does not know.
+    UnsupportedStateSchema {
+        path: PathBuf,
+        version: u32,
+    },
+    Postgres(postgresql_embedded::Error),
+    /// An operation ran out of its time limit. The `pgx` command line exits
+    /// with status 124 for it.
+    TimedOut(String),
+    /// A failed step of starting or stopping, such as a hook or an extension
+    /// install.
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for PgxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgxError::AlreadyRunning { data_dir, pid } => {
+                let pid = pid.map_or_else(|| "unknown".to_string(), |pid| pid.to_string());
+                write!(
+                    f,
+                    "another postmaster (PID {pid}) is already using {}; stop it with `pgx stop --data-dir {}` first",
+                    data_dir.display(),
+                    data_dir.display()
+                )
+            }
+            PgxError::NotRunning { data_dir, reason } => write!(
+                f,
+                "{reason}; start it with `pgx start --data-dir {}`",
+                data_dir.display()
+            ),
+            PgxError::StalePidFile { .. } => {
+                f.write_str("not running (stale postmaster.pid found)")
+            }
+            PgxError::NeverStarted { data_dir } => write!(
+                f,
+                "no pgx state for {}; it has never been started with pgx",
+                data_dir.display()
+            ),
+            PgxError::MetadataMissing => {
+                f.write_str("connection details unavailable (missing state or password metadata)")
+            }
+            PgxError::NotInstalled(message) | PgxError::NotReady(message) => f.write_str(message),
+            PgxError::Io(error) => error.fmt(f),
+            PgxError::State(error) => error.fmt(f),
+            PgxError::UnsupportedStateSchema { path, version } => write!(
+                f,
+                "{} uses state schema {version}, but this pgx only understands up to {}; it was written by a newer pgx, so upgrade pgx to manage this cluster",
+                path.display(),
+                crate::state::STATE_SCHEMA_VERSION
+            ),
+            PgxError::Postgres(error) => error.fmt(f),
+            PgxError::TimedOut(message) => f.write_str(message),
+            PgxError::Other(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for PgxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PgxError::Io(error) => Some(error),
+            PgxError::State(error) => Some(error),
+            PgxError::Postgres(error) => Some(error),
+            PgxError::Other(error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PgxError {
+    fn from(error: io::Error) -> Self {
+        PgxError::Io(error)
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for PgxError {
+    fn from(error: Box<dyn Error + Send + Sync>) -> Self {
+        PgxError::Other(error)
+    }
+}
+
+impl From<serde_json::Error> for PgxError {
+    fn from(error: serde_json::Error) -> Self {
+        PgxError::State(error)
+    }
+}
+
+impl From<postgresql_embedded::Error> for PgxError {
+    fn from(error: postgresql_embedded::Error) -> Self {
+        PgxError::Postgres(error)
+    }
+}
@@ -11,7 +11,7 @@ const REPO_URL: &str = "https://github.com/usecontextlayer/pg_search_compiled";
 pub struct PgSearchRepository;
 
 impl PgSearchRepository {
-    fn new() -> postgresql_extensions::Result<Box<dyn Repository>> {
+    fn boxed() -> postgresql_extensions::Result<Box<dyn Repository>> {
         Ok(Box::new(Self))
     }
 }
@@ -35,25 +35,37 @@ pub fn initialize() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     )?;
     postgresql_extensions::repository::registry::register(
         "contextlayer",
-        Box::new(|| PgSearchRepository::new()),
+        Box::new(PgSearchRepository::boxed),
     )?;
     Ok(())
 }
 
 /// Install pg_search into the PostgreSQL installation managed by the given settings.
+///
+/// postgresql_extensions' install future is not `Send`, so it runs on a
+/// blocking thread; a `Cluster::start` can then be spawned as a task.
 pub async fn install_pg_search(
     settings: &postgresql_embedded::Settings,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let version_req = VersionReq::parse(">=0.17, <0.18")?;
-    postgresql_extensions::install(settings, "contextlayer", "pg_search", &version_req).await?;
-    Ok(())
+    let settings = settings.clone();
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        runtime.block_on(async {
+            let version_req = VersionReq::parse(">=0.17, <0.18")?;
+            postgresql_extensions::install(&settings, "contextlayer", "pg_search", &version_req)
+                .await?;
+            Ok(())
+        })
+    })
+    .await?
 }
 
-/// Connect to the running database and enable pg_search.
+/// Connect to `database` on the running server and enable pg_search there.
 pub async fn enable_pg_search(
     settings: &postgresql_embedded::Settings,
+    database: &str,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let database_url = settings.url("postgres");
+    let database_url = crate::state::settings_url(settings, database);
     let pool = sqlx::postgres::PgPool::connect(&database_url).await?;
     sqlx::query("CREATE EXTENSION IF NOT EXISTS pg_search")
         .execute(&pool)
@@ -0,0 +1,593 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Rules between these markers belong to pgx; everything else in
+/// pg_hba.conf is left exactly as it is.
+const BLOCK_BEGIN: &str = "# BEGIN pgx managed rules (edit with `pgx hba`)";
+const BLOCK_END: &str = "# END pgx managed rules";
+
+const CONNECTION_TYPES: [&str; 6] = [
+    "local",
+    "host",
+    "hostssl",
+    "hostnossl",
+    "hostgssenc",
+    "hostnogssenc",
+];
+const METHODS: [&str; 13] = [
+    "trust",
+    "reject",
+    "scram-sha-256",
+    "md5",
+    "password",
+    "gss",
+    "sspi",
+    "ident",
+    "peer",
+    "pam",
+    "ldap",
+    "radius",
+    "cert",
+];
+
+/// One pg_hba.conf record. `address` is absent for `local` rules, and
+/// `netmask` is only present when the address is written as a plain IP
+/// followed by a separate mask field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HbaRule {
+    pub kind: String,
+    pub database: String,
+    pub user: String,
+    pub address: Option<String>,
+    pub netmask: Option<String>,
+    pub method: String,
+}
+
+impl HbaRule {
+    /// Password logins over TCP from `address` to every database.
+    pub fn password_host(address: &str) -> Self {
+        HbaRule {
+            kind: "host".to_string(),
+            database: "all".to_string(),
+            user: "all".to_string(),
+            address: Some(address.to_string()),
+            netmask: None,
+            method: "scram-sha-256".to_string(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !CONNECTION_TYPES.contains(&self.kind.as_str()) {
+            return Err(format!(
+                "unknown connection type '{}'; expected one of {}",
+                self.kind,
+                CONNECTION_TYPES.join(", ")
+            ));
+        }
+        if !METHODS.contains(&self.method.as_str()) {
+            return Err(format!(
+                "unknown method '{}'; expected one of {}",
+                self.method,
+                METHODS.join(", ")
+            ));
+        }
+        for (field, value) in [("database", &self.database), ("user", &self.user)] {
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                return Err(format!("{field} must be a non-empty name without spaces"));
+            }
+        }
+
+        match (&self.address, self.kind == "local") {
+            (Some(_), true) => Err("local rules take no address".to_string()),
+            (None, false) => Err(format!("{} rules need an address", self.kind)),
+            (Some(address), false) => match &self.netmask {
+                Some(netmask) => validate_netmask(address, netmask),
+                None => validate_address(address),
+            },
+            (None, true) => Ok(()),
+        }
+    }
+
+    fn parse(line: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (kind, rest) = fields.split_first().ok_or("empty rule")?;
+        let expected = if *kind == "local" { 4 } else { 5 };
+        if rest.len() + 1 < expected {
+            return Err(format!(
+                "expected at least {expected} fields, found {}",
+                fields.len()
+            ));
+        }
+
+        let address = (*kind != "local").then(|| rest[2].to_string());
+        // An IP address may be followed by a separate netmask field.
+        let netmask = match (&address, rest.get(3)) {
+            (Some(_), Some(field)) if field.parse::<IpAddr>().is_ok() => Some(field.to_string()),
+            _ => None,
+        };
+        let method_index = match (&address, &netmask) {
+            (None, _) => 2,
+            (Some(_), None) => 3,
+            (Some(_), Some(_)) => 4,
+        };
+        let method = rest
+            .get(method_index)
+            .ok_or_else(|| "missing authentication method".to_string())?;
+        if !CONNECTION_TYPES.contains(kind) {
+            return Err(format!("unknown connection type '{kind}'"));
+        }
+
+        Ok(HbaRule {
+            kind: kind.to_string(),
+            database: rest[0].to_string(),
+            user: rest[1].to_string(),
+            address,
+            netmask,
+            method: method.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for HbaRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.kind, self.database, self.user)?;
+        if let Some(address) = &self.address {
+            write!(f, " {address}")?;
+        }
+        if let Some(netmask) = &self.netmask {
+            write!(f, " {netmask}")?;
+        }
+        write!(f, " {}", self.method)
+    }
+}
+
+/// pg_hba.conf split around the pgx block.
+struct HbaFile {
+    path: PathBuf,
+    before: Vec<String>,
+    managed: Vec<HbaRule>,
+    after: Vec<String>,
+}
+
+impl HbaFile {
+    /// Read and check the whole file, reporting problems with line numbers
+    /// rather than rewriting anything pgx does not understand.
+    fn read(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join("pg_hba.conf");
+        let raw = fs::read_to_string(&path)
+            .map_err(|error| io::Error::other(format!("{}: {error}", path.display())))?;
+        let malformed = |number: usize, reason: &str| {
+            io::Error::other(format!("{}:{number}: {reason}", path.display()))
+        };
+
+        let mut file = HbaFile {
+            path: path.clone(),
+            before: Vec::new(),
+            managed: Vec::new(),
+            after: Vec::new(),
+        };
+        let mut in_block = false;
+        let mut seen_block = false;
+        for (index, line) in raw.lines().enumerate() {
+            let number = index + 1;
+            let trimmed = line.trim();
+            if trimmed == BLOCK_BEGIN {
+                if seen_block {
+                    return Err(malformed(number, "second pgx block"));
+                }
+                in_block = true;
+                seen_block = true;
+                continue;
+            }
+            if trimmed == BLOCK_END {
+                if !in_block {
+                    return Err(malformed(number, "end of pgx block without a beginning"));
+                }
+                in_block = false;
+                continue;
+            }
+
+            let rule = match rule_content(line) {
+                Some(content) => {
+                    Some(HbaRule::parse(content).map_err(|reason| malformed(number, &reason))?)
+                }
+                None => None,
+            };
+            match (in_block, rule) {
+                (true, Some(rule)) => file.managed.push(rule),
+                (true, None) => {}
+                (false, _) if seen_block => file.after.push(line.to_string()),
+                (false, _) => file.before.push(line.to_string()),
+            }
+        }
+        if in_block {
+            return Err(io::Error::other(format!(
+                "{}: pgx block is not closed with '{BLOCK_END}'",
+                path.display()
+            )));
+        }
+        Ok(file)
+    }
+
+    /// Move the pgx block above the first rule outside it, so its rules are
+    /// checked before initdb's defaults.
+    fn move_block_first(&mut self) {
+        if let Some(first_rule) = self
+            .before
+            .iter()
+            .position(|line| rule_content(line).is_some())
+        {
+            let moved = self.before.split_off(first_rule);
+            self.after.splice(0..0, moved);
+        }
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let mut lines = self.before.clone();
+        if !self.managed.is_empty() {
+            lines.push(BLOCK_BEGIN.to_string());
+            lines.extend(self.managed.iter().map(ToString::to_string));
+            lines.push(BLOCK_END.to_string());
+        }
+        lines.extend(self.after.iter().cloned());
+
+        let staging = self.path.with_extension("conf.pgx-tmp");
+        fs::write(&staging, lines.join("\n") + "\n")?;
+        fs::rename(&staging, &self.path)
+    }
+}
+
+/// Rules in the pgx block, in the order PostgreSQL checks them.
+pub fn list(data_dir: &Path) -> io::Result<Vec<HbaRule>> {
+    Ok(HbaFile::read(data_dir)?.managed)
+}
+
+/// Every rule in pg_hba.conf in the order PostgreSQL checks them, each with
+/// whether it belongs to the pgx block.
+pub fn all_rules(data_dir: &Path) -> io::Result<Vec<(HbaRule, bool)>> {
+    let file = HbaFile::read(data_dir)?;
+    // `read` has already checked every line, so none fails to parse here.
+    let unmanaged = |lines: &[String]| -> Vec<(HbaRule, bool)> {
+        lines
+            .iter()
+            .filter_map(|line| rule_content(line))
+            .filter_map(|content| HbaRule::parse(content).ok())
+            .map(|rule| (rule, false))
+            .collect()
+    };
+    let mut rules = unmanaged(&file.before);
+    rules.extend(file.managed.iter().cloned().map(|rule| (rule, true)));
+    rules.extend(unmanaged(&file.after));
+    Ok(rules)
+}
+
+/// Let every local connection in without a password: Unix sockets and
+/// loopback TCP. The rules go first in the pgx block, and the block above
+/// initdb's defaults, so they take effect.
+pub fn trust_local(data_dir: &Path) -> io::Result<bool> {
+    let wanted = [
+        HbaRule {
+            kind: "local".to_string(),
+            database: "all".to_string(),
+            user: "all".to_string(),
+            address: None,
+            netmask: None,
+            method: "trust".to_string(),
+        },
+        HbaRule {
+            method: "trust".to_string(),
+            ..HbaRule::password_host("127.0.0.1/32")
+        },
+        HbaRule {
+            method: "trust".to_string(),
+            ..HbaRule::password_host("::1/128")
+        },
+    ];
+    let mut file = HbaFile::read(data_dir)?;
+    let before_rules = file.before.clone();
+    file.move_block_first();
+    if file.managed.starts_with(&wanted) && file.before == before_rules {
+        return Ok(false);
+    }
+    file.managed.retain(|rule| !wanted.contains(rule));
+    file.managed.splice(0..0, wanted);
+    file.write()?;
+    Ok(true)
+}
+
+/// Append `rule` to the pgx block. Returns `false` if it was already there.
+pub fn add(data_dir: &Path, rule: HbaRule) -> io::Result<bool> {
+    rule.validate().map_err(io::Error::other)?;
+    let mut file = HbaFile::read(data_dir)?;
+    if file.managed.contains(&rule) {
+        return Ok(false);
+    }
+    file.managed.push(rule);
+    file.write()?;
+    Ok(true)
+}
+
+/// Remove the rule at 1-based `index` in the pgx block.
+pub fn remove(data_dir: &Path, index: usize) -> io::Result<HbaRule> {
+    let mut file = HbaFile::read(data_dir)?;
+    if index == 0 || index > file.managed.len() {
+        return Err(io::Error::other(format!(
+            "no pgx rule number {index}; there are {} (see `pgx hba list`)",
+            file.managed.len()
+        )));
+    }
+    let removed = file.managed.remove(index - 1);
+    file.write()?;
+    Ok(removed)
+}
+
+/// Allow password logins over TCP from `address` (a CIDR, `samenet` or
+/// `all`), unless pgx already added that rule.
+pub fn allow_host(data_dir: &Path, address: &str) -> io::Result<()> {
+    add(data_dir, HbaRule::password_host(address)).map(|_| ())
+}
+
+/// Whether `address` (as written in `listen_addresses`) only accepts
+/// connections from this machine.
+pub fn is_loopback(address: &str) -> bool {
+    match address.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback(),
+        Err(_) => address.eq_ignore_ascii_case("localhost"),
+    }
+}
+
+/// Whether `address` makes the server listen on every interface.
+pub fn is_wildcard(address: &str) -> bool {
+    matches!(address, "*" | "0.0.0.0" | "::")
+}
+
+/// The rule on `line` without its comment, or `None` for blank, comment
+/// and include lines.
+fn rule_content(line: &str) -> Option<&str> {
+    let content = line.split('#').next().unwrap_or_default().trim();
+    (!content.is_empty() && !content.starts_with("include")).then_some(content)
+}
+
+fn validate_address(address: &str) -> Result<(), String> {
+    if matches!(address, "all" | "samehost" | "samenet") {
+        return Ok(());
+    }
+    if let Some((ip, prefix)) = address.split_once('/') {
+        let ip: IpAddr = ip
+            .parse()
+            .map_err(|_| format!("'{address}' is not a valid CIDR address"))?;
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        return match prefix.parse::<u8>() {
+            Ok(prefix) if prefix <= max => Ok(()),
+            _ => Err(format!("'{address}' has an invalid prefix length")),
+        };
+    }
+    if address.parse::<IpAddr>().is_ok() {
+        return Err(format!(
+            "'{address}' needs a prefix length, e.g. {address}/32"
+        ));
+    }
+    // Host names, optionally with a leading dot to match a domain suffix.
+    let is_host_name = !address.is_empty()
+        && address
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._".contains(c));
+    if is_host_name {
+        Ok(())
+    } else {
+        Err(format!("'{address}' is not a valid address"))
+    }
+}
+
+fn validate_netmask(address: &str, netmask: &str) -> Result<(), String> {
+    let ip: IpAddr = address
+        .parse()
+        .map_err(|_| format!("a netmask needs a plain IP address, not '{address}'"))?;
+    match netmask.parse::<IpAddr>() {
+        Ok(mask) if mask.is_ipv4() == ip.is_ipv4() => Ok(()),
+        _ => Err(format!("'{netmask}' is not a valid netmask for {address}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// initdb's defaults, trimmed to the rules.
+    const DEFAULTS: &str = "# TYPE  DATABASE  USER  ADDRESS  METHOD\n\
+        local all all trust\n\
+        host all all 127.0.0.1/32 trust\n\
+        host all all ::1/128 trust\n";
+
+    fn data_dir_with_hba(contents: &str) -> tempfile::TempDir {
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::write(data_dir.path().join("pg_hba.conf"), contents).unwrap();
+        data_dir
+    }
+
+    fn read_hba(data_dir: &tempfile::TempDir) -> String {
+        fs::read_to_string(data_dir.path().join("pg_hba.conf")).unwrap()
+    }
+
+    #[test]
+    fn parses_local_and_host_rules() {
+        let local = HbaRule::parse("local all postgres peer").unwrap();
+        assert_eq!(local.address, None);
+        assert_eq!(local.method, "peer");
+
+        let host = HbaRule::parse("hostssl app alice 10.0.0.0/8 scram-sha-256").unwrap();
+        assert_eq!(
+            host,
+            HbaRule {
+                kind: "hostssl".to_string(),
+                database: "app".to_string(),
+                user: "alice".to_string(),
+                address: Some("10.0.0.0/8".to_string()),
+                netmask: None,
+                method: "scram-sha-256".to_string(),
+            }
+        );
+        assert_eq!(
+            host.to_string(),
+            "hostssl app alice 10.0.0.0/8 scram-sha-256"
+        );
+    }
+
+    #[test]
+    fn keeps_a_separate_ipv4_netmask() {
+        let rule = HbaRule::parse("host all all 192.168.0.0 255.255.0.0 md5").unwrap();
+        assert_eq!(rule.address.as_deref(), Some("192.168.0.0"));
+        assert_eq!(rule.netmask.as_deref(), Some("255.255.0.0"));
+        assert_eq!(rule.method, "md5");
+        assert_eq!(rule.to_string(), "host all all 192.168.0.0 255.255.0.0 md5");
+        assert_eq!(rule.validate(), Ok(()));
+
+        let mismatched = HbaRule {
+            netmask: Some("ffff::".to_string()),
+            ..rule
+        };
+        assert!(mismatched.validate().is_err());
+    }
+
+    #[test]
+    fn refuses_short_or_unknown_rules() {
+        assert_eq!(HbaRule::parse("").unwrap_err(), "empty rule");
+        assert_eq!(
+            HbaRule::parse("host all all 10.0.0.0/8").unwrap_err(),
+            "expected at least 5 fields, found 4"
+        );
+        assert_eq!(
+            HbaRule::parse("host all all 10.0.0.0 255.0.0.0").unwrap_err(),
+            "missing authentication method"
+        );
+        assert_eq!(
+            HbaRule::parse("remote all all 10.0.0.0/8 trust").unwrap_err(),
+            "unknown connection type 'remote'"
+        );
+    }
+
+    #[test]
+    fn validates_addresses() {
+        for address in [
+            "all",
+            "samenet",
+            "10.0.0.0/8",
+            "::1/128",
+            "db.example.com",
+            ".example.com",
+        ] {
+            assert_eq!(validate_address(address), Ok(()), "{address}");
+        }
+        assert_eq!(
+            validate_address("10.0.0.1").unwrap_err(),
+            "'10.0.0.1' needs a prefix length, e.g. 10.0.0.1/32"
+        );
+        assert_eq!(
+            validate_address("10.0.0.0/33").unwrap_err(),
+            "'10.0.0.0/33' has an invalid prefix length"
+        );
+        assert_eq!(
+            validate_address("10.0.0/8").unwrap_err(),
+            "'10.0.0/8' is not a valid CIDR address"
+        );
+        assert_eq!(
+            validate_address("bad host").unwrap_err(),
+            "'bad host' is not a valid address"
+        );
+    }
+
+    #[test]
+    fn splits_the_file_around_the_pgx_block() {
+        let data_dir = data_dir_with_hba(&format!(
+            "local all all peer\n{BLOCK_BEGIN}\n# a note\nhost all all 10.0.0.0/8 md5\n{BLOCK_END}\nhost all all all reject\n"
+        ));
+        let file = HbaFile::read(data_dir.path()).unwrap();
+        assert_eq!(file.before, ["local all all peer"]);
+        assert_eq!(
+            file.managed,
+            [HbaRule {
+                method: "md5".to_string(),
+                ..HbaRule::password_host("10.0.0.0/8")
+            }]
+        );
+        assert_eq!(file.after, ["host all all all reject"]);
+
+        let all = all_rules(data_dir.path()).unwrap();
+        let managed: Vec<bool> = all.iter().map(|(_, managed)| *managed).collect();
+        assert_eq!(managed, [false, true, false]);
+    }
+
+    #[test]
+    fn refuses_a_broken_pgx_block() {
+        let cases = [
+            (
+                format!("{BLOCK_BEGIN}\n{BLOCK_END}\n{BLOCK_BEGIN}\n{BLOCK_END}\n"),
+                ":3: second pgx block",
+            ),
+            (
+                format!("{BLOCK_END}\n"),
+                ":1: end of pgx block without a beginning",
+            ),
+            (
+                format!("{BLOCK_BEGIN}\nlocal all all trust\n"),
+                "is not closed",
+            ),
+            (
+                "host all all\n".to_string(),
+                ":1: expected at least 5 fields, found 3",
+            ),
+        ];
+        for (contents, expected) in cases {
+            let data_dir = data_dir_with_hba(&contents);
+            let error = HbaFile::read(data_dir.path()).err().unwrap().to_string();
+            assert!(error.contains(expected), "{error}");
+        }
+    }
+
+    #[test]
+    fn moves_the_pgx_block_above_the_first_rule() {
+        let data_dir = data_dir_with_hba(&format!(
+            "# comment\nlocal all all peer\n{BLOCK_BEGIN}\nlocal all all trust\n{BLOCK_END}\n"
+        ));
+        let mut file = HbaFile::read(data_dir.path()).unwrap();
+        file.move_block_first();
+        assert_eq!(file.before, ["# comment"]);
+        assert_eq!(file.after, ["local all all peer"]);
+        file.write().unwrap();
+        assert_eq!(
+            read_hba(&data_dir),
+            format!(
+                "# comment\n{BLOCK_BEGIN}\nlocal all all trust\n{BLOCK_END}\nlocal all all peer\n"
+            )
+        );
+    }
+
+    #[test]
+    fn trust_local_is_idempotent() {
+        let data_dir = data_dir_with_hba(DEFAULTS);
+        assert!(trust_local(data_dir.path()).unwrap());
+        let trusted = read_hba(&data_dir);
+        assert!(trusted.starts_with(&format!(
+            "# TYPE  DATABASE  USER  ADDRESS  METHOD\n{BLOCK_BEGIN}\nlocal all all trust\n"
+        )));
+
+        assert!(!trust_local(data_dir.path()).unwrap());
+        assert_eq!(read_hba(&data_dir), trusted);
+    }
+
+    #[test]
+    fn add_and_remove_rules() {
+        let data_dir = data_dir_with_hba(DEFAULTS);
+        let rule = HbaRule::password_host("10.0.0.0/8");
+        assert!(add(data_dir.path(), rule.clone()).unwrap());
+        assert!(!add(data_dir.path(), rule.clone()).unwrap());
+        assert_eq!(list(data_dir.path()).unwrap(), std::slice::from_ref(&rule));
+
+        assert!(remove(data_dir.path(), 2).is_err());
+        assert_eq!(remove(data_dir.path(), 1).unwrap(), rule);
+        assert_eq!(read_hba(&data_dir), DEFAULTS);
+    }
+}
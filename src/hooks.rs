@@ -0,0 +1,80 @@
+use std::io;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+
+/// Run `command` through the platform shell with `env` added, streaming its
+/// output with a `[stage]` prefix. Fails on a non-zero exit or once `limit`
+/// has passed, in which case the hook is killed.
+pub async fn run(
+    stage: &str,
+    command: &str,
+    env: &[(&'static str, String)],
+    limit: Duration,
+) -> io::Result<()> {
+    tracing::info!("running {stage} hook: {command}");
+    let mut child = shell(command)
+        .envs(env.iter().cloned())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|error| {
+            io::Error::other(format!("cannot run {stage} hook '{command}': {error}"))
+        })?;
+
+    let prefix = format!("[{stage}]");
+    let stdout = forward_lines(child.stdout.take(), prefix.clone(), false);
+    let stderr = forward_lines(child.stderr.take(), prefix, true);
+    let finished = async {
+        let (status, (), ()) = tokio::join!(child.wait(), stdout, stderr);
+        status
+    };
+
+    let status = match timeout(limit, finished).await {
+        Ok(status) => status?,
+        Err(_) => {
+            return Err(io::Error::other(format!(
+                "{stage} hook '{command}' did not finish within {}",
+                humantime::format_duration(limit)
+            )));
+        }
+    };
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{stage} hook '{command}' failed with {status}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+async fn forward_lines(stream: Option<impl AsyncRead + Unpin>, prefix: String, to_stderr: bool) {
+    let Some(stream) = stream else {
+        return;
+    };
+
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if to_stderr {
+            eprintln!("{prefix} {line}");
+        } else {
+            println!("{prefix} {line}");
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use sqlx::Connection;
+use sqlx::postgres::PgConnection;
+use tokio::time::{Duration, Instant, interval};
+
+/// Upper bound on how often client connections are counted.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Client backends other than the connection doing the counting.
+const CLIENT_CONNECTIONS_QUERY: &str = "SELECT count(*) FROM pg_stat_activity \
+     WHERE backend_type = 'client backend' AND pid <> pg_backend_pid()";
+
+/// Resolve once the server at `url` has had no client connections for
+/// `limit`. The timer restarts whenever a client is seen; failed checks are
+/// logged and leave the timer running.
+pub async fn wait_until_idle(url: &str, limit: Duration) {
+    let mut ticker = interval(CHECK_INTERVAL.min(limit));
+    let mut connection: Option<PgConnection> = None;
+    let mut idle_since = Instant::now();
+
+    loop {
+        ticker.tick().await;
+        match client_connections(url, &mut connection).await {
+            Ok(0) => {}
+            Ok(_) => idle_since = Instant::now(),
+            Err(error) => {
+                tracing::debug!("cannot count client connections: {error}");
+                connection = None;
+            }
+        }
+        if idle_since.elapsed() >= limit {
+            if let Some(connection) = connection {
+                let _ = connection.close().await;
+            }
+            return;
+        }
+    }
+}
+
+async fn client_connections(
+    url: &str,
+    connection: &mut Option<PgConnection>,
+) -> Result<i64, sqlx::Error> {
+    let connection = match connection {
+        Some(connection) => connection,
+        None => connection.insert(PgConnection::connect(url).await?),
+    };
+    sqlx::query_scalar::<_, i64>(CLIENT_CONNECTIONS_QUERY)
+        .fetch_one(connection)
+        .await
+}
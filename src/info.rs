@@ -0,0 +1,209 @@
+use serde::Serialize;
+use sqlx::Connection;
+use sqlx::postgres::PgConnection;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Everything `pgx info` knows about a cluster. Fields that could not be
+/// determined are `None` and serialize as null.
+#[derive(Debug, Serialize)]
+pub struct ClusterInfo {
+    pub data_dir: PathBuf,
+    pub status: &'static str,
+    pub pg_version: Option<String>,
+    pub installation_dir: Option<PathBuf>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub superuser: Option<String>,
+    pub started_at: Option<String>,
+    pub uptime_seconds: Option<i64>,
+    pub databases: Option<Vec<String>>,
+    pub size_bytes: Option<u64>,
+    pub sidecars: BTreeMap<&'static str, PathBuf>,
+    pub state: Option<serde_json::Value>,
+}
+
+/// What only a running server can tell.
+pub struct LiveDetails {
+    pub started_at: String,
+    pub uptime_seconds: i64,
+    pub databases: Vec<String>,
+}
+
+pub async fn live_details(url: &str) -> Result<LiveDetails, sqlx::Error> {
+    let mut connection = PgConnection::connect(url).await?;
+    let (started_at, uptime_seconds) = sqlx::query_as::<_, (String, i64)>(
+        "SELECT pg_postmaster_start_time()::text, \
+         extract(epoch FROM now() - pg_postmaster_start_time())::bigint",
+    )
+    .fetch_one(&mut connection)
+    .await?;
+    let databases = list_databases(&mut connection).await?;
+    connection.close().await?;
+
+    Ok(LiveDetails {
+        started_at,
+        uptime_seconds,
+        databases,
+    })
+}
+
+/// Names of the non-template databases, sorted.
+pub async fn database_names(url: &str) -> Result<Vec<String>, sqlx::Error> {
+    let mut connection = PgConnection::connect(url).await?;
+    let databases = list_databases(&mut connection).await?;
+    connection.close().await?;
+    Ok(databases)
+}
+
+async fn list_databases(connection: &mut PgConnection) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar::<_, String>(
+        "SELECT datname::text FROM pg_database WHERE NOT datistemplate ORDER BY datname",
+    )
+    .fetch_all(connection)
+    .await
+}
+
+/// One client backend from `pg_stat_activity`.
+#[derive(Debug, Serialize)]
+pub struct Backend {
+    pub pid: i32,
+    pub database: Option<String>,
+    pub user: Option<String>,
+    pub application_name: Option<String>,
+    pub client_addr: Option<String>,
+    pub state: Option<String>,
+    pub backend_start: Option<String>,
+    pub query_start: Option<String>,
+    pub query: Option<String>,
+}
+
+/// Client backends connected to the server, leaving out the connection
+/// used to ask.
+pub async fn connections(url: &str) -> Result<Vec<Backend>, sqlx::Error> {
+    let mut connection = PgConnection::connect(url).await?;
+    type Row = (
+        i32,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    );
+    let rows = sqlx::query_as::<_, Row>(
+        "SELECT pid, datname::text, usename::text, application_name, \
+         client_addr::text, state, backend_start::text, query_start::text, query \
+         FROM pg_stat_activity \
+         WHERE backend_type = 'client backend' AND pid <> pg_backend_pid() \
+         ORDER BY backend_start, pid",
+    )
+    .fetch_all(&mut connection)
+    .await?;
+    connection.close().await?;
+
+    Ok(rows
+        .into_iter()
+        .map(
+            |(
+                pid,
+                database,
+                user,
+                application_name,
+                client_addr,
+                state,
+                backend_start,
+                query_start,
+                query,
+            )| Backend {
+                pid,
+                database,
+                user,
+                application_name,
+                client_addr,
+                state,
+                backend_start,
+                query_start,
+                query,
+            },
+        )
+        .collect())
+}
+
+/// Characters of the current query `print_connections` shows.
+const QUERY_PREVIEW_CHARS: usize = 60;
+
+pub fn print_connections(backends: &[Backend]) {
+    fn or_dash(value: &Option<String>) -> &str {
+        value.as_deref().unwrap_or("-")
+    }
+
+    for backend in backends {
+        let query = backend
+            .query
+            .as_deref()
+            .unwrap_or("")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let preview: String = query.chars().take(QUERY_PREVIEW_CHARS).collect();
+        println!(
+            "{:<8} {:<16} {:<16} {:<16} {:<20} {preview}",
+            backend.pid,
+            or_dash(&backend.database),
+            or_dash(&backend.user),
+            or_dash(&backend.client_addr),
+            or_dash(&backend.state),
+        );
+    }
+    let noun = if backends.len() == 1 {
+        "connection"
+    } else {
+        "connections"
+    };
+    println!("{} {noun}", backends.len());
+}
+
+pub fn print_text(info: &ClusterInfo) {
+    fn or_dash(value: Option<String>) -> String {
+        value.unwrap_or_else(|| "-".to_string())
+    }
+
+    let rows = [
+        ("data dir", Some(info.data_dir.display().to_string())),
+        ("status", Some(info.status.to_string())),
+        ("pg version", info.pg_version.clone()),
+        (
+            "binaries",
+            info.installation_dir
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        ),
+        ("host", info.host.clone()),
+        ("port", info.port.map(|port| port.to_string())),
+        ("superuser", info.superuser.clone()),
+        ("started at", info.started_at.clone()),
+        (
+            "uptime",
+            info.uptime_seconds.map(|seconds| {
+                let seconds = seconds.max(0) as u64;
+                humantime::format_duration(std::time::Duration::from_secs(seconds)).to_string()
+            }),
+        ),
+        (
+            "databases",
+            info.databases
+                .as_ref()
+                .map(|databases| databases.join(", ")),
+        ),
+        ("size", info.size_bytes.map(crate::snapshot::format_size)),
+    ];
+    for (label, value) in rows {
+        println!("{label:<12} {}", or_dash(value));
+    }
+    for (name, path) in &info.sidecars {
+        println!("{:<12} {}", format!("{name} path"), path.display());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::state::settings_url;
+use postgresql_embedded::Settings;
+use sqlx::Connection;
+use sqlx::postgres::PgConnection;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Encoding postgresql_embedded initializes clusters with.
+const DEFAULT_ENCODING: &str = "UTF8";
+
+/// initdb options pgx exposes on top of what postgresql_embedded passes.
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    pub locale: Option<String>,
+    pub encoding: Option<String>,
+    pub data_checksums: bool,
+}
+
+impl InitOptions {
+    pub fn is_default(&self) -> bool {
+        self.locale.is_none() && self.encoding.is_none() && !self.data_checksums
+    }
+
+    /// How the properties of an existing cluster differ from these options.
+    pub fn mismatches(&self, actual: &ClusterProperties) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if let Some(locale) = &self.locale
+            && normalize(locale) != normalize(&actual.locale)
+        {
+            mismatches.push(format!("locale is {} rather than {locale}", actual.locale));
+        }
+        if let Some(encoding) = &self.encoding
+            && normalize(encoding) != normalize(&actual.encoding)
+        {
+            mismatches.push(format!(
+                "encoding is {} rather than {encoding}",
+                actual.encoding
+            ));
+        }
+        if self.data_checksums && !actual.data_checksums {
+            mismatches.push("data checksums are disabled".to_string());
+        }
+        mismatches
+    }
+}
+
+/// Properties fixed when a cluster is initialized, as reported by the server.
+#[derive(Debug)]
+pub struct ClusterProperties {
+    pub locale: String,
+    pub encoding: String,
+    pub data_checksums: bool,
+}
+
+/// Initialize `settings.data_dir` the way postgresql_embedded would, plus
+/// `options`. `setup()` then finds an initialized cluster and skips initdb.
+/// Dropping the future kills initdb, so a timeout or Ctrl-C stops it.
+pub async fn run(binary_dir: &Path, settings: &Settings, options: &InitOptions) -> io::Result<()> {
+    fs::write(&settings.password_file, &settings.password)?;
+
+    let mut initdb =
+        Command::new(binary_dir.join(format!("initdb{}", std::env::consts::EXE_SUFFIX)));
+    initdb
+        .arg("-D")
+        .arg(&settings.data_dir)
+        .arg(format!("--username={}", settings.username))
+        .arg("--auth=password")
+        .arg("--pwfile")
+        .arg(&settings.password_file)
+        .arg(format!(
+            "--encoding={}",
+            options.encoding.as_deref().unwrap_or(DEFAULT_ENCODING)
+        ));
+    if let Some(locale) = &options.locale {
+        initdb.arg(format!("--locale={locale}"));
+    }
+    if options.data_checksums {
+        initdb.arg("--data-checksums");
+    }
+
+    let output = initdb.kill_on_drop(true).output().await?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(io::Error::other(format!(
+        "initdb failed: {}",
+        stderr.trim()
+    )))
+}
+
+/// Read the locale, encoding and checksum setting of the running cluster.
+pub async fn properties(settings: &Settings) -> Result<ClusterProperties, sqlx::Error> {
+    let mut connection = PgConnection::connect(&settings_url(settings, "postgres")).await?;
+    let (locale, encoding) = sqlx::query_as::<_, (String, String)>(
+        "SELECT datcollate, pg_encoding_to_char(encoding) FROM pg_database \
+         WHERE datname = current_database()",
+    )
+    .fetch_one(&mut connection)
+    .await?;
+    let data_checksums = sqlx::query_scalar::<_, String>("SHOW data_checksums")
+        .fetch_one(&mut connection)
+        .await?;
+    connection.close().await?;
+
+    Ok(ClusterProperties {
+        locale,
+        encoding,
+        data_checksums: data_checksums == "on",
+    })
+}
+
+/// Create the database `name` unless it exists, as the default database
+/// chosen with `start --database`.
+pub async fn ensure_database(settings: &Settings, name: &str) -> Result<(), sqlx::Error> {
+    let mut connection = PgConnection::connect(&settings_url(settings, "postgres")).await?;
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1)",
+    )
+    .bind(name)
+    .fetch_one(&mut connection)
+    .await?;
+    if !exists {
+        let statement = format!(
+            "CREATE DATABASE {}",
+            crate::app_role::quote_identifier(name)
+        );
+        sqlx::query(&statement).execute(&mut connection).await?;
+    }
+    connection.close().await
+}
+
+/// `en_US.UTF-8` and `en_US.utf8` name the same locale; `UTF-8` and `utf8`
+/// the same encoding.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
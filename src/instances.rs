@@ -0,0 +1,90 @@
+//! Every data directory pgx has started, profile or not, so `pgx list` and
+//! `stop --all` can find clusters started with `--data-dir`. Whether one is
+//! running comes from its own sidecars, not from the registry.
+
+use crate::profiles;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instance {
+    /// Port and version of the most recent start.
+    pub port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pg_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_started: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Registry {
+    /// Keyed by absolute data directory.
+    #[serde(default)]
+    pub instances: BTreeMap<PathBuf, Instance>,
+}
+
+impl Registry {
+    pub fn read() -> io::Result<Self> {
+        let path = registry_path()?;
+        let raw = match fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+        serde_json::from_str(&raw)
+            .map_err(|error| io::Error::other(format!("{}: {error}", path.display())))
+    }
+
+    /// Replace the registry through a staging file, so readers never see half of it.
+    pub fn write(&self) -> io::Result<()> {
+        let path = registry_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let raw = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        let staging = path.with_extension("json.tmp");
+        fs::write(&staging, raw)?;
+        fs::rename(&staging, &path)
+    }
+}
+
+fn registry_path() -> io::Result<PathBuf> {
+    Ok(profiles::home_dir()?.join("instances.json"))
+}
+
+/// Record a start of the cluster in `data_dir`.
+pub fn record(data_dir: &Path, instance: Instance) -> io::Result<()> {
+    let mut registry = Registry::read()?;
+    registry.instances.insert(data_dir.to_path_buf(), instance);
+    registry.write()
+}
+
+/// Drop `data_dir` from the registry, e.g. once it has been destroyed.
+pub fn forget(data_dir: &Path) -> io::Result<()> {
+    let mut registry = Registry::read()?;
+    if registry.instances.remove(data_dir).is_some() {
+        registry.write()?;
+    }
+    Ok(())
+}
+
+/// Drop entries whose data directory no longer exists, returning them.
+pub fn prune_missing() -> io::Result<Vec<PathBuf>> {
+    let mut registry = Registry::read()?;
+    let missing: Vec<_> = registry
+        .instances
+        .keys()
+        .filter(|data_dir| !data_dir.exists())
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        for data_dir in &missing {
+            registry.instances.remove(data_dir);
+        }
+        registry.write()?;
+    }
+    Ok(missing)
+}
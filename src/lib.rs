@@ -0,0 +1,55 @@
+//! Run embedded PostgreSQL clusters from Rust. [`Cluster`] starts, stops and
+//! inspects a data directory exactly as the `pgx` command line does, sharing
+//! its sidecar files.
+
+pub mod cluster;
+pub mod error;
+pub mod state;
+
+pub use app_role::Role;
+pub use cluster::{Cluster, ClusterOptions, ClusterStatus};
+pub use error::PgxError;
+pub use initdb::InitOptions;
+pub use provision::{ProvisionEvent, ProvisionLimits, ProvisionPermit};
+pub use state::StateFile;
+
+/// The `pgx` binary's entry point, not a stable API.
+#[doc(hidden)]
+pub mod cli;
+
+// Building blocks of the `pgx` binary.
+pub(crate) mod app_role;
+pub(crate) mod assertions;
+pub(crate) mod basebackup;
+pub(crate) mod clone;
+pub(crate) mod doctor;
+pub(crate) mod extensions;
+pub(crate) mod hba;
+pub(crate) mod hooks;
+pub(crate) mod idle;
+pub(crate) mod info;
+pub(crate) mod initdb;
+pub(crate) mod instances;
+pub(crate) mod lifecycle;
+pub(crate) mod offline;
+pub(crate) mod postmaster;
+pub(crate) mod prewarm;
+pub(crate) mod profiles;
+pub(crate) mod progress;
+pub(crate) mod project;
+pub(crate) mod provision;
+pub(crate) mod readiness;
+pub(crate) mod reload;
+pub(crate) mod reset;
+pub(crate) mod runtime;
+pub(crate) mod server_log;
+#[cfg(windows)]
+pub(crate) mod service;
+pub(crate) mod shell_env;
+pub(crate) mod snapshot;
+pub(crate) mod supervise;
+pub(crate) mod systemd;
+pub(crate) mod templates;
+pub(crate) mod tls;
+pub(crate) mod tools;
+pub(crate) mod top;
@@ -0,0 +1,1294 @@
+//! Starting and stopping a cluster: the work behind [`Cluster::start`] and
+//! [`Cluster::stop`], which `pgx start` and `pgx stop` share.
+//!
+//! [`Cluster::start`]: crate::Cluster::start
+//! [`Cluster::stop`]: crate::Cluster::stop
+
+use crate::cluster::{ClusterOptions, resolved_version};
+use crate::error::PgxError;
+use crate::postmaster::{self, Liveness, ShutdownMode};
+use crate::runtime::{
+    PGX_BINARIES_ENV, RuntimeConnectionDetails, acquire_operation_lock, build_settings,
+    check_cluster_version, connection_env, database_env, load_runtime_context, runtime_liveness,
+};
+use crate::state::{
+    AUTH_MODE, DEFAULT_DATABASE, DEFAULT_SUPERUSER, STATE_SCHEMA_VERSION, StateFile,
+    absolute_data_dir, app_credentials_path, cluster_is_initialized, custom_password_file,
+    init_marker_path, mark_state_stopped, password_file_path, prewarm_file_path,
+    read_managed_password_file, read_state_file, reset_dir_path, roles_path, secure_data_dir,
+    set_owner_only_permissions, settings_url, socket_dir_path, state_file_path, tls_dir_path,
+    write_state_file,
+};
+use crate::{
+    app_role, doctor, extensions, hba, hooks, initdb, instances, offline, prewarm, readiness,
+    server_log, shell_env, snapshot, tls, tools,
+};
+use postgresql_embedded::{PostgreSQL, Settings};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+use tokio::time::{Duration, Instant};
+
+/// Wait before the first download retry; doubled for each further attempt.
+const DOWNLOAD_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How [`stop_cluster`] shuts the server down: the options of `pgx stop`.
+#[derive(Debug, Clone)]
+pub struct StopOptions {
+    pub mode: ShutdownMode,
+    /// How long a graceful stop may take; zero waits forever.
+    pub timeout: Duration,
+    /// Escalate to signals after `timeout`, and remove a stale `postmaster.pid`.
+    pub force: bool,
+    /// Remove a stale `postmaster.pid`.
+    pub repair: bool,
+}
+
+impl Default for StopOptions {
+    fn default() -> Self {
+        StopOptions {
+            mode: ShutdownMode::Fast,
+            timeout: Duration::from_secs(30),
+            force: false,
+            repair: false,
+        }
+    }
+}
+
+/// A server started by this process. Dropping `postgresql` stops it.
+pub struct StartedServer {
+    pub postgresql: PostgreSQL,
+    pub url: String,
+}
+
+/// Set up and start the server for `data_dir` and record its state. The
+/// operation lock is held only until the server is up, so `pgx stop` can reach
+/// a foreground server. Setup is abandoned and its partial files removed
+/// once `interrupted` completes; the CLI passes Ctrl-C.
+pub async fn start_server(
+    data_dir: &Path,
+    options: &ClusterOptions,
+    interrupted: impl Future<Output = ()>,
+) -> Result<StartedServer, PgxError> {
+    if reset_dir_path(data_dir).exists() {
+        return Err(io::Error::other(format!(
+            "a reset of {} did not finish; run `pgx reset --data-dir {} --yes` to complete it",
+            data_dir.display(),
+            data_dir.display()
+        ))
+        .into());
+    }
+    prepare_data_dir(data_dir)?;
+    if let Some(parent) = password_file_path(data_dir).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    check_free_space(
+        &doctor::free_space,
+        data_dir,
+        options.min_free_space,
+        options.force,
+    )?;
+    let _operation_lock = acquire_operation_lock(data_dir)?;
+
+    let password = start_password(data_dir, options.password.as_deref())?;
+    check_superuser(data_dir, options.superuser.as_deref())?;
+    let host = options.host.clone();
+    let port = match options.port {
+        Some(port) => port,
+        None => previous_port(data_dir, &host),
+    };
+    let listen_addresses = listen_addresses(&host, &options.listen);
+    let mut settings = build_settings(data_dir, Some(host), Some(port), password)?;
+    if let Some(version) = &options.version {
+        settings.version = version.clone();
+    }
+    if let Some(releases_url) = &options.releases_url {
+        settings.releases_url = releases_url.clone();
+    }
+    if let Some(superuser) = &options.superuser {
+        settings.username = superuser.clone();
+    }
+    if !listen_addresses.is_empty() {
+        settings
+            .configuration
+            .insert("listen_addresses".to_string(), listen_addresses.join(","));
+    }
+    let tls = match options.tls {
+        Some(tls) => tls,
+        None => read_state_file(data_dir)?.is_some_and(|state| state.tls_cert.is_some()),
+    };
+    let tls_files = if tls {
+        let mut hosts = vec!["localhost".to_string(), settings.host.clone()];
+        hosts.extend(
+            listen_addresses
+                .iter()
+                .filter(|address| !hba::is_wildcard(address))
+                .cloned(),
+        );
+        hosts.sort();
+        hosts.dedup();
+        let files = tls::ensure(&tls_dir_path(data_dir), &hosts)?;
+        settings.configuration.extend(tls::configuration(&files));
+        Some(files)
+    } else {
+        None
+    };
+    for address in listen_addresses
+        .iter()
+        .filter(|address| !hba::is_loopback(address))
+    {
+        tracing::warn!(
+            "listening on {address}: the database is reachable from other machines with the password in {}",
+            password_file_path(data_dir).display()
+        );
+    }
+    let log_file = options.log_file.clone();
+    if let Some(log_file) = &log_file {
+        // Have the logging collector append to the same file as pgx itself.
+        open_log_file(log_file)?;
+        let log_file = fs::canonicalize(log_file)?;
+        let directory = log_file.parent().unwrap_or_else(|| Path::new("."));
+        let file_name = log_file.file_name().unwrap_or_default();
+        settings.configuration.extend([
+            (
+                "log_directory".to_string(),
+                directory.to_string_lossy().to_string(),
+            ),
+            (
+                "log_filename".to_string(),
+                file_name.to_string_lossy().to_string(),
+            ),
+            ("log_rotation_size".to_string(), "0".to_string()),
+        ]);
+    }
+    let socket_dir = start_socket_dir(data_dir, options.socket_dir.as_ref())?;
+    if let Some(socket_dir) = &socket_dir {
+        settings.configuration.insert(
+            "unix_socket_directories".to_string(),
+            socket_dir.to_string_lossy().to_string(),
+        );
+    }
+    let server_settings = merge_server_settings(data_dir, &options.server_settings);
+    settings.configuration.extend(server_settings.clone());
+    let mut postgresql = PostgreSQL::new(settings);
+
+    match postmaster::inspect(data_dir, &postgresql.settings().host) {
+        Liveness::Running => {
+            return Err(PgxError::AlreadyRunning {
+                data_dir: data_dir.to_path_buf(),
+                pid: postmaster::read_pid(data_dir),
+            });
+        }
+        Liveness::Stale => clear_stale_pid_file(data_dir, &postgresql.settings().host)?,
+        Liveness::Stopped => {}
+    }
+    if cluster_is_initialized(data_dir) {
+        check_cluster_version(data_dir, &postgresql.settings().version)?;
+    }
+
+    let provision_permit = options
+        .provision
+        .acquire(&postgresql.settings().installation_dir, data_dir)
+        .await?;
+
+    if let Some(binaries) = options
+        .binaries
+        .clone()
+        .or_else(|| std::env::var_os(PGX_BINARIES_ENV).map(PathBuf::from))
+    {
+        offline::import(&binaries, postgresql.settings()).await?;
+    }
+    if options.offline && tools::binary_dir(postgresql.settings()).is_err() {
+        return Err(io::Error::other(format!(
+            "PostgreSQL {} is not installed in {} and --offline forbids downloading it; pass --binaries <archive or directory>",
+            postgresql.settings().version,
+            postgresql.settings().installation_dir.display()
+        ))
+        .into());
+    }
+
+    let hook_env = [("PGDATA", data_dir.to_string_lossy().to_string())];
+    for command in &options.pre_start {
+        hooks::run("pre-start", command, &hook_env, options.hook_timeout).await?;
+    }
+
+    let deadline = options.timeout.map(|limit| Instant::now() + limit);
+    let was_initialized = cluster_is_initialized(data_dir);
+    let was_empty = fs::read_dir(data_dir)?.next().is_none();
+    let init_scripts = init_scripts(&options.init_sql, &options.init_dir)?;
+    let init_marker = init_marker_path(data_dir);
+    if !was_initialized && !init_scripts.is_empty() {
+        fs::write(&init_marker, "")?;
+    } else if !was_initialized && init_marker.exists() {
+        // Left by an earlier attempt that had init scripts; this one has none.
+        fs::remove_file(&init_marker)?;
+    } else if !init_scripts.is_empty() && !init_marker.exists() {
+        tracing::info!(
+            "{} is already initialized; init scripts only run for a new cluster",
+            data_dir.display()
+        );
+    }
+    let init_options = &options.init;
+    let custom_initdb = !was_initialized && !init_options.is_default();
+    let downloading = tools::binary_dir(postgresql.settings()).is_err();
+    if downloading {
+        tracing::info!(
+            "downloading PostgreSQL binaries from {}",
+            postgresql.settings().releases_url
+        );
+    }
+    if !was_initialized {
+        tracing::info!("initializing cluster in {}", data_dir.display());
+    }
+
+    let installation_dir = postgresql.settings().installation_dir.clone();
+    let requirement = postgresql.settings().version.clone();
+    let releases_url = postgresql.settings().releases_url.clone();
+    let existing_installations = tools::installation_candidates(&installation_dir, &requirement);
+    // Every step that downloads or runs initdb is in here, so --timeout
+    // limits it and Ctrl-C cleans up after it.
+    let setup = async {
+        if custom_initdb {
+            // postgresql_embedded's initdb call takes no extra options, so
+            // initialize the cluster here and let setup() skip that step.
+            let binary_dir = match tools::binary_dir(postgresql.settings()) {
+                Ok(binary_dir) => binary_dir,
+                Err(_) => {
+                    let fetch = offline::fetch(postgresql.settings(), options.offline);
+                    match before_deadline(deadline, fetch).await? {
+                        Ok((_, installation)) => installation.join("bin"),
+                        Err(error) => return Some(Err(install_failure(&releases_url, error))),
+                    }
+                }
+            };
+            let initdb = initdb::run(&binary_dir, postgresql.settings(), init_options);
+            if let Err(error) = before_deadline(deadline, initdb).await? {
+                return Some(Err(error.into()));
+            }
+        }
+        if downloading {
+            setup_with_retries(&mut postgresql, options.download_retries, deadline)
+                .await
+                .map(|result| result.map_err(|error| install_failure(&releases_url, error)))
+        } else {
+            before_deadline(deadline, postgresql.setup())
+                .await
+                .map(|result| result.map_err(Into::into))
+        }
+    };
+    let setup_result = tokio::select! {
+        result = setup => result,
+        () = interrupted => {
+            // Dropping the setup future already removed pgx's own staging
+            // directory; an install setup() had started is removed here.
+            for partial in tools::installation_candidates(&installation_dir, &requirement) {
+                if !existing_installations.contains(&partial) {
+                    let _ = fs::remove_dir_all(&partial);
+                }
+            }
+            if !was_initialized && was_empty {
+                discard_partial_cluster(data_dir);
+            }
+            return Err(io::Error::other("interrupted during setup; partial files were removed").into());
+        }
+    };
+    if !matches!(setup_result, Some(Ok(()))) && !was_initialized && was_empty {
+        discard_partial_cluster(data_dir);
+    }
+    match setup_result {
+        Some(result) => result?,
+        None => {
+            let phase = if tools::binary_dir(postgresql.settings()).is_err() {
+                "download"
+            } else {
+                "initdb"
+            };
+            return Err(phase_timeout_error(options.timeout, phase));
+        }
+    }
+
+    if options.offline {
+        // setup() has pointed installation_dir at the version directory.
+        if !offline::has_pg_search(&postgresql.settings().installation_dir.join("bin")) {
+            return Err(io::Error::other(
+                "pg_search is not installed and --offline forbids downloading it; use --binaries with an installation copied from a machine where pgx start has run",
+            )
+            .into());
+        }
+    } else {
+        extensions::initialize()?;
+        tracing::info!("installing pg_search extension");
+        before_deadline(
+            deadline,
+            extensions::install_pg_search(postgresql.settings()),
+        )
+        .await
+        .ok_or_else(|| phase_timeout_error(options.timeout, "extension install"))??;
+        tracing::info!("pg_search extension installed");
+    }
+
+    if listen_addresses
+        .iter()
+        .any(|address| !hba::is_loopback(address))
+    {
+        let allowed = if options.allow.is_empty() {
+            vec!["samenet".to_string()]
+        } else {
+            options.allow.clone()
+        };
+        for address in &allowed {
+            hba::allow_host(data_dir, address)?;
+        }
+    }
+    if options.trust_local && hba::trust_local(data_dir)? {
+        tracing::warn!("local connections are trusted: no password is needed from this machine");
+    }
+
+    if let Err(error) = server_log::prune(data_dir) {
+        tracing::warn!("failed to prune old server logs: {error}");
+    }
+    let (host, port) = (&postgresql.settings().host, postgresql.settings().port);
+    if port != 0 && postmaster::port_in_use(host, port) {
+        return Err(io::Error::other(format!(
+            "port {port} on {host} is already in use; pass --port 0 to pick a free port"
+        ))
+        .into());
+    }
+    tracing::info!("starting server");
+    match before_deadline(deadline, postgresql.start()).await {
+        Some(result) => result?,
+        None => {
+            stop_unready_server(&postgresql).await;
+            return Err(phase_timeout_error(options.timeout, "start"));
+        }
+    }
+
+    tracing::info!("waiting for the server to accept connections");
+    let ready_url = settings_url(postgresql.settings(), DEFAULT_DATABASE);
+    let ready_limit = match deadline {
+        Some(deadline) => options
+            .ready_timeout
+            .min(deadline.saturating_duration_since(Instant::now())),
+        None => options.ready_timeout,
+    };
+    if let Err(error) = readiness::wait_until_ready(&ready_url, ready_limit).await {
+        stop_unready_server(&postgresql).await;
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(phase_timeout_error(options.timeout, "readiness"));
+        }
+        return Err(error.into());
+    }
+    drop(provision_permit);
+
+    let database = options
+        .database
+        .clone()
+        .or_else(|| {
+            read_state_file(data_dir)
+                .ok()
+                .flatten()
+                .and_then(|state| state.database)
+        })
+        .unwrap_or_else(|| DEFAULT_DATABASE.to_string());
+    if let Err(error) = enable_default_database(postgresql.settings(), &database).await {
+        stop_unready_server(&postgresql).await;
+        return Err(error);
+    }
+    for name in &options.databases {
+        if let Err(error) = enable_default_database(postgresql.settings(), name).await {
+            stop_unready_server(&postgresql).await;
+            return Err(error);
+        }
+    }
+    tracing::info!("pg_search extension enabled");
+
+    if options.prewarm
+        && let Err(error) =
+            prewarm::prewarm(postgresql.settings(), &prewarm_file_path(data_dir)).await
+    {
+        tracing::warn!("prewarm failed: {error}");
+    }
+
+    if let Some(user) = &options.app_user {
+        let credentials = match app_credentials(
+            data_dir,
+            user,
+            options.app_password.as_deref(),
+            options.app_db.as_deref(),
+        ) {
+            Ok(credentials) => credentials,
+            Err(error) => {
+                stop_unready_server(&postgresql).await;
+                return Err(error);
+            }
+        };
+        if let Err(error) = app_role::ensure(postgresql.settings(), &credentials).await {
+            stop_unready_server(&postgresql).await;
+            return Err(io::Error::other(format!(
+                "failed to create application role {user}: {error}"
+            ))
+            .into());
+        }
+        if let Err(error) = app_role::write(&app_credentials_path(data_dir), &credentials) {
+            stop_unready_server(&postgresql).await;
+            return Err(error.into());
+        }
+    }
+
+    if !options.roles.is_empty() {
+        let mut declared = match app_role::read_roles(&roles_path(data_dir)) {
+            Ok(declared) => declared,
+            Err(error) => {
+                stop_unready_server(&postgresql).await;
+                return Err(error.into());
+            }
+        };
+        for role in &options.roles {
+            if role.name == postgresql.settings().username {
+                stop_unready_server(&postgresql).await;
+                return Err(io::Error::other(format!(
+                    "--role cannot redefine the superuser {}",
+                    role.name
+                ))
+                .into());
+            }
+            if let Err(error) = app_role::ensure_role(postgresql.settings(), role).await {
+                stop_unready_server(&postgresql).await;
+                return Err(io::Error::other(format!(
+                    "failed to create role {}: {error}",
+                    role.name
+                ))
+                .into());
+            }
+            declared.insert(role.name.clone(), role.clone());
+        }
+        if let Err(error) = app_role::write_roles(&roles_path(data_dir), &declared) {
+            stop_unready_server(&postgresql).await;
+            return Err(error.into());
+        }
+    }
+
+    let properties = match initdb::properties(postgresql.settings()).await {
+        Ok(properties) => Some(properties),
+        Err(error) => {
+            tracing::warn!("cannot read the cluster's locale and encoding: {error}");
+            None
+        }
+    };
+    if was_initialized && let Some(properties) = &properties {
+        let mismatches = init_options.mismatches(properties);
+        if !mismatches.is_empty() {
+            let message = format!(
+                "{} was initialized with different options: {}; --locale, --encoding and --data-checksums only apply to new clusters",
+                data_dir.display(),
+                mismatches.join(", ")
+            );
+            if options.strict {
+                stop_unready_server(&postgresql).await;
+                return Err(io::Error::other(message).into());
+            }
+            tracing::warn!("{message}");
+        }
+    }
+
+    let running = postgresql.settings();
+    let password = match managed_password_for_connection(data_dir, running) {
+        Ok(password) => password,
+        Err(error) => {
+            stop_unready_server(&postgresql).await;
+            return Err(error);
+        }
+    };
+    let connection = RuntimeConnectionDetails {
+        user: running.username.clone(),
+        host: running.host.clone(),
+        port: running.port,
+        password: password.clone(),
+        database,
+        tls_cert: tls_files.map(|files| files.cert),
+    };
+    // A failed script leaves the marker, so the next start runs them all again.
+    if init_marker.exists() {
+        for script in &init_scripts {
+            if let Err(error) = run_sql_script(running, &connection, script).await {
+                stop_unready_server(&postgresql).await;
+                return Err(error);
+            }
+        }
+        if let Err(error) = fs::remove_file(&init_marker) {
+            stop_unready_server(&postgresql).await;
+            return Err(error.into());
+        }
+    }
+    let mut post_start_env = database_env(&connection);
+    post_start_env.extend(hook_env);
+    for command in &options.post_start {
+        if let Err(error) =
+            hooks::run("post-start", command, &post_start_env, options.hook_timeout).await
+        {
+            stop_unready_server(&postgresql).await;
+            return Err(error.into());
+        }
+    }
+
+    if let Some(port_file) = &options.port_file
+        && let Err(error) = write_port_file(port_file, running.port)
+    {
+        stop_unready_server(&postgresql).await;
+        return Err(io::Error::other(format!(
+            "failed to write port file {}: {error}",
+            port_file.display()
+        ))
+        .into());
+    }
+    if let Some(env_file) = &options.env_file
+        && let Err(error) = shell_env::update_dotenv(env_file, &options.env_key, &connection.url())
+    {
+        stop_unready_server(&postgresql).await;
+        return Err(io::Error::other(format!(
+            "failed to write env file {}: {error}",
+            env_file.display()
+        ))
+        .into());
+    }
+    let state = StateFile {
+        schema_version: STATE_SCHEMA_VERSION,
+        host: running.host.clone(),
+        port: running.port,
+        prewarm: options.prewarm,
+        running: true,
+        pid: postmaster::read_pid(data_dir),
+        pg_version: Some(resolved_version(running)),
+        superuser: Some(running.username.clone()),
+        database: Some(connection.database.clone()),
+        auth_mode: Some(AUTH_MODE.to_string()),
+        started_at: Some(humantime::format_rfc3339_seconds(SystemTime::now()).to_string()),
+        log_file,
+        locale: properties
+            .as_ref()
+            .map(|properties| properties.locale.clone()),
+        encoding: properties
+            .as_ref()
+            .map(|properties| properties.encoding.clone()),
+        data_checksums: properties.map(|properties| properties.data_checksums),
+        listen_addresses,
+        tls_cert: connection.tls_cert.clone(),
+        password_file: custom_password_file(data_dir),
+        data_dir: Some(data_dir.to_path_buf()),
+        socket_dir,
+        server_settings,
+    };
+    if let Err(error) = write_state_file(data_dir, &state) {
+        stop_unready_server(&postgresql).await;
+        let _ = fs::remove_file(state_file_path(data_dir));
+        return Err(error);
+    }
+    if options.register {
+        let instance = instances::Instance {
+            port: state.port,
+            pg_version: state.pg_version.clone(),
+            last_started: state.started_at.clone(),
+        };
+        if let Err(error) = instances::record(data_dir, instance) {
+            tracing::warn!("failed to record the cluster in the instance registry: {error}");
+        }
+    }
+
+    let url = connection.url();
+    Ok(StartedServer { postgresql, url })
+}
+
+/// Remove a `postmaster.pid` whose process is gone, as after a power loss,
+/// unless the port it records still answers: then a postmaster pgx cannot
+/// see may own the data directory and removing the file would be unsafe.
+pub fn clear_stale_pid_file(data_dir: &Path, host: &str) -> Result<(), PgxError> {
+    let pid_file = postmaster::pid_file_path(data_dir);
+    if let Some(port) = postmaster::recorded_port(data_dir)
+        && postmaster::accepts_connections(host, port)
+    {
+        return Err(io::Error::other(format!(
+            "{} names a postmaster that is not running, but port {port} is accepting connections; \
+             if no other postmaster uses {}, remove {} and start again",
+            pid_file.display(),
+            data_dir.display(),
+            pid_file.display()
+        ))
+        .into());
+    }
+
+    tracing::info!(
+        "removing stale {} left behind by an unclean shutdown",
+        pid_file.display()
+    );
+    postmaster::remove_pid_file(data_dir)?;
+    Ok(())
+}
+
+fn resolve_start_password(data_dir: &Path) -> Result<Option<String>, PgxError> {
+    if let Some(password) = read_managed_password_file(data_dir)? {
+        return Ok(Some(password));
+    }
+
+    if cluster_is_initialized(data_dir) {
+        return Err(io::Error::other(format!(
+            "missing managed password file for initialized data directory {}. reset the postgres password and write it to {}",
+            data_dir.display(),
+            password_file_path(data_dir).display(),
+        ))
+        .into());
+    }
+
+    Ok(None)
+}
+
+/// The port the cluster last ran on, so URLs handed out after a `--port 0`
+/// start stay valid. Falls back to a random port (0) when there is no
+/// previous port or it has since been taken.
+fn previous_port(data_dir: &Path, host: &str) -> u16 {
+    let Some(port) = read_state_file(data_dir)
+        .ok()
+        .flatten()
+        .map(|state| state.port)
+        .filter(|port| *port != 0)
+    else {
+        return 0;
+    };
+
+    if postmaster::port_in_use(host, port) {
+        tracing::warn!(
+            "port {port} used by the previous start is taken; picking a new random port"
+        );
+        return 0;
+    }
+    tracing::debug!("reusing port {port} from the previous start");
+    port
+}
+
+/// `listen_addresses` for `--listen`, plus `host` so the printed URL keeps
+/// working. Empty without `--listen`, leaving the server's default.
+pub fn listen_addresses(host: &str, listen: &[String]) -> Vec<String> {
+    if listen.is_empty() {
+        return Vec::new();
+    }
+
+    let mut addresses = listen.to_vec();
+    if !addresses
+        .iter()
+        .any(|address| address == host || hba::is_wildcard(address))
+    {
+        addresses.insert(0, host.to_string());
+    }
+    addresses
+}
+
+/// Open `path` for appending, creating it readable only by the owner.
+pub fn open_log_file(path: &Path) -> Result<fs::File, PgxError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options
+        .open(path)
+        .map_err(|error| io::Error::other(format!("{}: {error}", path.display())))?;
+    // The mode above only applies to a new file.
+    set_owner_only_permissions(path)?;
+    Ok(file)
+}
+
+/// The Unix socket directory for this start: `--socket-dir`, else the one
+/// recorded by an earlier start. Created readable only by the owner.
+fn start_socket_dir(
+    data_dir: &Path,
+    requested: Option<&Option<PathBuf>>,
+) -> Result<Option<PathBuf>, PgxError> {
+    let socket_dir = match requested {
+        Some(Some(socket_dir)) => socket_dir.clone(),
+        Some(None) => socket_dir_path(data_dir),
+        None => match read_state_file(data_dir)?.and_then(|state| state.socket_dir) {
+            Some(socket_dir) => socket_dir,
+            None => return Ok(None),
+        },
+    };
+    if cfg!(windows) {
+        return Err(io::Error::other(
+            "--socket-dir needs Unix domain sockets, which pgx supports only on Unix",
+        )
+        .into());
+    }
+
+    secure_data_dir(&socket_dir)?;
+    let socket_dir = absolute_data_dir(&socket_dir)?;
+    // Socket paths are limited to about 107 bytes, and the server appends
+    // `/.s.PGSQL.<port>`.
+    if socket_dir.as_os_str().len() + "/.s.PGSQL.65535".len() > 107 {
+        return Err(io::Error::other(format!(
+            "the socket directory {} is too long for a Unix socket path; choose a shorter --socket-dir",
+            socket_dir.display()
+        ))
+        .into());
+    }
+    Ok(Some(socket_dir))
+}
+
+/// The settings recorded by earlier starts with `--set` values applied on top.
+fn merge_server_settings(
+    data_dir: &Path,
+    overrides: &[(String, String)],
+) -> std::collections::BTreeMap<String, String> {
+    let mut merged = read_state_file(data_dir)
+        .ok()
+        .flatten()
+        .map(|state| state.server_settings)
+        .unwrap_or_default();
+    for (key, value) in overrides {
+        if value.is_empty() {
+            merged.remove(key);
+        } else {
+            merged.insert(key.clone(), value.clone());
+        }
+    }
+    merged
+}
+
+/// Empty a data directory that initdb left half-populated, so the next attempt
+/// starts from scratch instead of tripping over the leftovers.
+fn discard_partial_cluster(data_dir: &Path) {
+    tracing::info!(
+        "removing partially initialized cluster in {}",
+        data_dir.display()
+    );
+    if let Err(error) = fs::remove_dir_all(data_dir) {
+        tracing::warn!("failed to clean up {}: {error}", data_dir.display());
+    } else if let Err(error) = secure_data_dir(data_dir) {
+        tracing::warn!("failed to recreate {}: {error}", data_dir.display());
+    }
+}
+
+/// Refuse to start on an almost full filesystem unless `force`, and warn
+/// below `min_free`: a full disk leaves the server failing writes and
+/// looping in crash recovery, with the cause only in its log.
+fn check_free_space(
+    probe: &impl doctor::SpaceProbe,
+    data_dir: &Path,
+    min_free: u64,
+    force: bool,
+) -> Result<(), PgxError> {
+    let free = match probe.free_space(data_dir) {
+        Ok(Some(free)) => free,
+        Ok(None) => return Ok(()),
+        Err(error) => {
+            tracing::warn!(
+                "cannot measure free space for {}: {error}",
+                data_dir.display()
+            );
+            return Ok(());
+        }
+    };
+    match doctor::space_level(free, min_free) {
+        doctor::SpaceLevel::Enough => {}
+        doctor::SpaceLevel::Low => tracing::warn!(
+            "LOW DISK SPACE: only {} free on the filesystem holding {}",
+            snapshot::format_size(free),
+            data_dir.display()
+        ),
+        doctor::SpaceLevel::Critical if force => tracing::warn!(
+            "LOW DISK SPACE: only {} free on the filesystem holding {}; starting anyway because of --force",
+            snapshot::format_size(free),
+            data_dir.display()
+        ),
+        doctor::SpaceLevel::Critical => {
+            return Err(io::Error::other(format!(
+                "only {} free on the filesystem holding {}; free up space or pass --force to start anyway",
+                snapshot::format_size(free),
+                data_dir.display()
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Create the data directory readable only by its owner, tightening one
+/// that already exists with looser permissions.
+pub fn prepare_data_dir(data_dir: &Path) -> Result<(), PgxError> {
+    if let Some(mode) = secure_data_dir(data_dir)? {
+        tracing::warn!(
+            "{} was accessible to other users (mode {mode:04o}); changed it to 0700",
+            data_dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// Download the binaries with progress reporting and run `setup()`, retrying
+/// transient download failures with exponential backoff. Installation
+/// directories left behind by a failed attempt are removed so the next
+/// attempt does not mistake them for a usable install.
+async fn setup_with_retries(
+    postgresql: &mut PostgreSQL,
+    retries: u32,
+    deadline: Option<Instant>,
+) -> Option<Result<(), Box<dyn Error + Send + Sync>>> {
+    let installation_dir = postgresql.settings().installation_dir.clone();
+    let requirement = postgresql.settings().version.clone();
+    let existing: Vec<PathBuf> = tools::installation_candidates(&installation_dir, &requirement);
+
+    let mut attempt = 0;
+    loop {
+        let result = before_deadline(deadline, download_and_setup(postgresql)).await;
+        let Some(Err(error)) = &result else {
+            return result;
+        };
+        if attempt >= retries || !is_transient_download_error(error.as_ref()) {
+            return result;
+        }
+
+        attempt += 1;
+        let wait = DOWNLOAD_RETRY_BACKOFF * 2u32.pow(attempt - 1);
+        tracing::warn!(
+            "download failed: {error}; retrying in {} (attempt {attempt} of {retries})",
+            humantime::format_duration(wait)
+        );
+        for partial in tools::installation_candidates(&installation_dir, &requirement) {
+            if !existing.contains(&partial)
+                && let Err(error) = fs::remove_dir_all(&partial)
+            {
+                tracing::warn!("failed to remove {}: {error}", partial.display());
+            }
+        }
+        before_deadline(deadline, tokio::time::sleep(wait)).await?;
+    }
+}
+
+/// A failed download or install, naming where the binaries came from unless
+/// the error already does.
+fn install_failure(
+    releases_url: &str,
+    error: Box<dyn Error + Send + Sync>,
+) -> Box<dyn Error + Send + Sync> {
+    if error.is::<offline::DownloadError>() {
+        return error;
+    }
+    io::Error::other(format!(
+        "failed to install PostgreSQL from {releases_url}: {error}"
+    ))
+    .into()
+}
+
+async fn download_and_setup(
+    postgresql: &mut PostgreSQL,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    offline::fetch(postgresql.settings(), false).await?;
+    postgresql.setup().await?;
+    Ok(())
+}
+
+/// Connection resets, timeouts and TLS hiccups are worth retrying; a missing
+/// release (404) or an unsupported platform is not.
+fn is_transient_download_error(error: &(dyn Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(io_error) = error.downcast_ref::<io::Error>()
+            && matches!(
+                io_error.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::Interrupted
+            )
+        {
+            return true;
+        }
+        current = error.source();
+    }
+
+    let message = error.to_string().to_ascii_lowercase();
+    let permanent = ["404", "not found", "unsupported", "no release"];
+    let transient = [
+        "connection",
+        "timed out",
+        "timeout",
+        "reset",
+        "tls",
+        "dns",
+        "error sending request",
+        "broken pipe",
+        "eof",
+        "502",
+        "503",
+        "504",
+    ];
+    !permanent.iter().any(|marker| message.contains(marker))
+        && transient.iter().any(|marker| message.contains(marker))
+}
+
+/// Await `future` unless `deadline` passes first, in which case `None` is returned.
+pub async fn before_deadline<F: Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+pub fn phase_timeout_error(limit: Option<Duration>, phase: &str) -> PgxError {
+    let message = match limit {
+        Some(limit) => format!(
+            "start timed out after {} during {phase}",
+            humantime::format_duration(limit)
+        ),
+        None => format!("start timed out during {phase}"),
+    };
+    PgxError::TimedOut(message)
+}
+
+/// An operation that ran out of `limit` while doing `what`.
+pub fn timed_out(limit: Duration, what: impl std::fmt::Display) -> PgxError {
+    PgxError::TimedOut(format!(
+        "timed out after {} {what}",
+        humantime::format_duration(limit)
+    ))
+}
+
+pub async fn stop_unready_server(postgresql: &PostgreSQL) {
+    if let Err(error) = postgresql.stop().await {
+        tracing::warn!("failed to stop the server after a failed start: {error}");
+    }
+}
+
+/// Create `database` if it is missing and enable pg_search in it as well as
+/// in `postgres`.
+pub async fn enable_default_database(settings: &Settings, database: &str) -> Result<(), PgxError> {
+    if database != DEFAULT_DATABASE {
+        initdb::ensure_database(settings, database)
+            .await
+            .map_err(|error| {
+                io::Error::other(format!("failed to create database {database}: {error}"))
+            })?;
+    }
+    extensions::enable_pg_search(settings, DEFAULT_DATABASE).await?;
+    if database != DEFAULT_DATABASE {
+        extensions::enable_pg_search(settings, database).await?;
+    }
+    Ok(())
+}
+
+/// The password to start with: the managed one once the cluster exists,
+/// otherwise `requested` if given.
+fn start_password(data_dir: &Path, requested: Option<&str>) -> Result<Option<String>, PgxError> {
+    let managed = resolve_start_password(data_dir)?;
+    let Some(requested) = requested else {
+        return Ok(managed);
+    };
+    if requested.is_empty() || requested.trim() != requested {
+        return Err(io::Error::other(
+            "the superuser password must not be empty or begin or end with whitespace",
+        )
+        .into());
+    }
+    match managed {
+        Some(managed) if managed != requested => Err(io::Error::other(format!(
+            "{} already has a different superuser password; --password only applies to a new cluster",
+            data_dir.display()
+        ))
+        .into()),
+        _ => Ok(Some(requested.to_string())),
+    }
+}
+
+/// Refuse `--superuser` naming someone other than the superuser an existing
+/// cluster was initialized with.
+fn check_superuser(data_dir: &Path, requested: Option<&str>) -> Result<(), PgxError> {
+    let Some(requested) = requested else {
+        return Ok(());
+    };
+    if !cluster_is_initialized(data_dir) {
+        return Ok(());
+    }
+    let recorded = read_state_file(data_dir)?
+        .and_then(|state| state.superuser)
+        .unwrap_or_else(|| DEFAULT_SUPERUSER.to_string());
+    if *requested != recorded {
+        return Err(io::Error::other(format!(
+            "{} was initialized with superuser {recorded}; --superuser only applies to a new cluster",
+            data_dir.display()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Credentials for `--app-user`. A generated password is kept across starts
+/// so URLs handed out earlier stay valid.
+fn app_credentials(
+    data_dir: &Path,
+    user: &str,
+    password: Option<&str>,
+    database: Option<&str>,
+) -> Result<app_role::AppCredentials, PgxError> {
+    let stored =
+        app_role::read(&app_credentials_path(data_dir))?.filter(|stored| stored.user == user);
+    let password = match password {
+        Some(password) if password != "generate" => password.to_string(),
+        _ => match stored {
+            Some(stored) => stored.password,
+            // Same generator postgresql_embedded uses for the superuser.
+            None => Settings::default().password,
+        },
+    };
+
+    Ok(app_role::AppCredentials {
+        user: user.to_string(),
+        password,
+        database: database.unwrap_or(user).to_string(),
+    })
+}
+
+fn managed_password_for_connection(
+    data_dir: &Path,
+    running: &Settings,
+) -> Result<String, PgxError> {
+    let password = read_managed_password_file(data_dir)?
+        .ok_or_else(|| io::Error::other("managed password file missing after startup"))?;
+    if running.password.trim().is_empty() {
+        return Err(io::Error::other("database started with an empty password").into());
+    }
+    set_owner_only_permissions(&password_file_path(data_dir))?;
+    Ok(password)
+}
+
+/// `--init-sql` files followed by the `.sql` files of each `--init-dir`.
+pub fn init_scripts(init_sql: &[PathBuf], init_dir: &[PathBuf]) -> Result<Vec<PathBuf>, PgxError> {
+    let mut scripts = init_sql.to_vec();
+    for dir in init_dir {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)
+            .map_err(|error| io::Error::other(format!("{}: {error}", dir.display())))?
+        {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|extension| extension == "sql") {
+                files.push(path);
+            }
+        }
+        files.sort();
+        scripts.append(&mut files);
+    }
+    for script in &scripts {
+        if !script.is_file() {
+            return Err(
+                io::Error::other(format!("init script {} not found", script.display())).into(),
+            );
+        }
+    }
+    Ok(scripts)
+}
+
+/// Run the SQL file at `path` with the bundled psql against the recorded
+/// database, stopping at its first error. Query output is discarded.
+pub async fn run_sql_script(
+    settings: &Settings,
+    connection: &RuntimeConnectionDetails,
+    path: &Path,
+) -> Result<(), PgxError> {
+    tracing::info!("running {}", path.display());
+    // setup() has pointed installation_dir at the version directory.
+    let psql = tools::tool_path(&settings.installation_dir.join("bin"), "psql")?;
+    let status = tokio::process::Command::new(psql)
+        .args([
+            "--no-psqlrc",
+            "--quiet",
+            "--set",
+            "ON_ERROR_STOP=1",
+            "--file",
+        ])
+        .arg(path)
+        .envs(connection_env(connection))
+        .stdout(process::Stdio::null())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} failed ({status})", path.display())).into());
+    }
+    Ok(())
+}
+
+/// Write `port` to `path` through a temporary file and a rename, so readers
+/// never see a partial number.
+pub fn write_port_file(path: &Path, port: u16) -> io::Result<()> {
+    let mut staging = path.as_os_str().to_owned();
+    staging.push(".tmp");
+    let staging = PathBuf::from(staging);
+    fs::write(&staging, format!("{port}\n"))?;
+    set_owner_only_permissions(&staging).map_err(io::Error::other)?;
+    fs::rename(&staging, path).inspect_err(|_| {
+        let _ = fs::remove_file(&staging);
+    })
+}
+
+pub async fn record_prewarm_relations(data_dir: &Path, settings: &Settings) {
+    if let Err(error) = prewarm::capture(settings, &prewarm_file_path(data_dir)).await {
+        tracing::warn!("failed to record buffered relations for prewarm: {error}");
+    }
+}
+
+/// Stop the cluster in `data_dir`, returning a one-line description of the outcome.
+pub async fn stop_cluster(data_dir: PathBuf, options: &StopOptions) -> Result<String, PgxError> {
+    if read_state_file(&data_dir)?.is_none() || read_managed_password_file(&data_dir)?.is_none() {
+        return stop_without_metadata(&data_dir);
+    }
+    let runtime = load_runtime_context(data_dir)?;
+    let _operation_lock = acquire_operation_lock(&runtime.data_dir)?;
+
+    match runtime_liveness(&runtime) {
+        Liveness::Running => {}
+        Liveness::Stale => {
+            return stale_pid_file_message(&runtime.data_dir, options.repair || options.force);
+        }
+        Liveness::Stopped => return Ok("not running".to_string()),
+    }
+    let pid = postmaster::read_pid(&runtime.data_dir);
+
+    if runtime.state.prewarm {
+        record_prewarm_relations(&runtime.data_dir, runtime.postgresql.settings()).await;
+    }
+
+    if options.mode == ShutdownMode::Immediate {
+        tracing::warn!(
+            "immediate shutdown skips the shutdown checkpoint; crash recovery will run on next start"
+        );
+    }
+
+    let pg_ctl = tools::tool_path(&tools::binary_dir(runtime.postgresql.settings())?, "pg_ctl")?;
+    let started = Instant::now();
+    let graceful = postmaster::stop(&pg_ctl, &runtime.data_dir, options.mode);
+    let outcome = if options.timeout.is_zero() {
+        Ok(graceful.await)
+    } else {
+        tokio::time::timeout(options.timeout, graceful).await
+    };
+    match outcome {
+        Ok(result) => result?,
+        Err(_) if options.force => {
+            let Some(pid) = pid else {
+                return Err(
+                    io::Error::other("cannot force stop: postmaster.pid has no PID").into(),
+                );
+            };
+            tracing::warn!(
+                "graceful stop did not finish within {}; escalating",
+                humantime::format_duration(options.timeout)
+            );
+            let escalation = postmaster::force_stop(&pg_ctl, &runtime.data_dir, pid).await?;
+            if postmaster::pid_file_path(&runtime.data_dir).exists() {
+                postmaster::remove_pid_file(&runtime.data_dir)?;
+            }
+            mark_state_stopped(&runtime.data_dir)?;
+            return Ok(format!(
+                "stopped with {} in {:.1}s",
+                escalation.signal_name(),
+                started.elapsed().as_secs_f64()
+            ));
+        }
+        Err(_) => {
+            return Err(timed_out(
+                options.timeout,
+                "waiting for the server to stop; pass --force to escalate",
+            ));
+        }
+    }
+    mark_state_stopped(&runtime.data_dir)?;
+    Ok(format!(
+        "stopped in {:.1}s",
+        started.elapsed().as_secs_f64()
+    ))
+}
+
+/// `pgx stop` for a data directory without pgx's sidecar files: there is
+/// nothing pgx started, so this succeeds unless a server is running anyway.
+fn stop_without_metadata(data_dir: &Path) -> Result<String, PgxError> {
+    match postmaster::inspect(data_dir, "localhost") {
+        Liveness::Running => Err(io::Error::other(format!(
+            "a server is running in {} but pgx has no state or password for it; stop it with pg_ctl",
+            data_dir.display()
+        ))
+        .into()),
+        Liveness::Stale => Ok("not running (no pgx state; stale postmaster.pid found)".to_string()),
+        Liveness::Stopped => Ok(format!(
+            "nothing to stop (no pgx state for {})",
+            data_dir.display()
+        )),
+    }
+}
+
+pub fn stale_pid_file_message(data_dir: &Path, repair: bool) -> Result<String, PgxError> {
+    if repair {
+        postmaster::remove_pid_file(data_dir)?;
+        Ok("not running (removed stale postmaster.pid)".to_string())
+    } else {
+        Ok("not running (stale postmaster.pid found; pass --repair to remove it)".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn start_space_check(free: Option<u64>, force: bool) -> Result<(), PgxError> {
+        let data_dir = tempfile::tempdir().unwrap();
+        check_free_space(
+            &|_: &Path| Ok(free),
+            data_dir.path(),
+            doctor::LOW_DISK_SPACE,
+            force,
+        )
+    }
+
+    #[test]
+    fn start_proceeds_with_enough_or_low_space() {
+        start_space_check(Some(2 * doctor::LOW_DISK_SPACE), false).unwrap();
+        start_space_check(Some(500 * MIB), false).unwrap();
+    }
+
+    #[test]
+    fn start_refuses_critical_space_without_force() {
+        let error = start_space_check(Some(50 * MIB), false).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("only 50.0 MiB free on the filesystem holding "),
+            "{error}"
+        );
+        assert!(error.to_string().ends_with("pass --force to start anyway"));
+    }
+
+    #[test]
+    fn start_forces_through_critical_space() {
+        start_space_check(Some(50 * MIB), true).unwrap();
+    }
+
+    #[test]
+    fn start_proceeds_when_space_cannot_be_measured() {
+        start_space_check(None, false).unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        check_free_space(
+            &|_: &Path| Err(io::Error::other("statvfs failed")),
+            data_dir.path(),
+            doctor::LOW_DISK_SPACE,
+            false,
+        )
+        .unwrap();
+    }
+}
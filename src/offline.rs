@@ -0,0 +1,398 @@
+use crate::{progress, snapshot, tools};
+use postgresql_embedded::{Settings, VersionReq};
+use regex_lite::Regex;
+use semver::Version;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+type ImportResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// A failed release download, keeping the cause reachable through
+/// `source()` so callers can tell transient network errors apart.
+#[derive(Debug)]
+pub struct DownloadError {
+    releases_url: String,
+    source: postgresql_archive::Error,
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to download PostgreSQL from {}: {}",
+            self.releases_url, self.source
+        )
+    }
+}
+
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Make the PostgreSQL build at `source` available in the installation cache
+/// so `setup()` finds it instead of downloading. `source` is either a release
+/// archive as published by theseus-rs/postgresql-binaries or an extracted
+/// installation directory containing `bin/`.
+pub async fn import(source: &Path, settings: &Settings) -> ImportResult<Version> {
+    let version = if source.is_dir() {
+        let version = installed_version(source)?;
+        check_requirement(source, &version, &settings.version)?;
+        let target = settings.installation_dir.join(version.to_string());
+        if !target.exists() {
+            fs::create_dir_all(&settings.installation_dir)?;
+            stage_into(&target, |staging| snapshot::copy_tree(source, staging))?;
+        }
+        version
+    } else {
+        let version = archive_version(source)?;
+        check_requirement(source, &version, &settings.version)?;
+        let target = settings.installation_dir.join(version.to_string());
+        if !target.exists() {
+            let bytes = fs::read(source)
+                .map_err(|error| io::Error::other(format!("{}: {error}", source.display())))?;
+            fs::create_dir_all(&settings.installation_dir)?;
+            let staging = staging_path(&target);
+            if staging.exists() {
+                fs::remove_dir_all(&staging)?;
+            }
+            // The extractor is chosen by URL; archives always use the upstream layout
+            // even when `releases_url` points at a mirror.
+            let upstream_url = Settings::default().releases_url;
+            postgresql_archive::extract(&upstream_url, &bytes, &staging).await?;
+            let extracted = installed_version(&staging);
+            if !matches!(&extracted, Ok(extracted) if *extracted == version) {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(io::Error::other(format!(
+                    "{} does not contain PostgreSQL {version}",
+                    source.display()
+                ))
+                .into());
+            }
+            fs::rename(&staging, &target)?;
+        }
+        version
+    };
+
+    tracing::info!("using PostgreSQL {version} from {}", source.display());
+    Ok(version)
+}
+
+/// Download and extract the newest release matching `settings.version` into
+/// the installation cache, unless a matching build is already there. Returns
+/// the exact version and its installation directory. Both phases report
+/// progress, and a partial extraction is removed even when the future is
+/// dropped part-way, e.g. on Ctrl-C.
+pub async fn fetch(settings: &Settings, offline: bool) -> ImportResult<(Version, PathBuf)> {
+    let cached = tools::installations(&settings.installation_dir)
+        .into_iter()
+        .filter(|(version, _)| settings.version.matches(version))
+        .max_by(|(left, _), (right, _)| left.cmp(right));
+    if let Some((version, path)) = cached {
+        return Ok((version, path));
+    }
+    if offline {
+        return Err(io::Error::other(format!(
+            "PostgreSQL {} is not cached in {} and --offline forbids downloading it",
+            settings.version,
+            settings.installation_dir.display()
+        ))
+        .into());
+    }
+
+    tracing::info!(
+        "downloading PostgreSQL {} from {}",
+        settings.version,
+        settings.releases_url
+    );
+    // The archive API offers no byte counts while downloading.
+    let (version, bytes) = progress::track(
+        &format!("downloading PostgreSQL {}", settings.version),
+        || None,
+        postgresql_archive::get_archive(&settings.releases_url, &settings.version),
+    )
+    .await
+    .map_err(|source| DownloadError {
+        releases_url: settings.releases_url.clone(),
+        source,
+    })?;
+    tracing::info!(
+        "downloaded PostgreSQL {version} ({})",
+        snapshot::format_size(bytes.len() as u64)
+    );
+    let target = settings.installation_dir.join(version.to_string());
+    if !target.exists() {
+        fs::create_dir_all(&settings.installation_dir)?;
+        let staging = StagingDir::create(staging_path(&target))?;
+        progress::track(
+            &format!("extracting PostgreSQL {version}"),
+            || snapshot::tree_size(&staging.0).ok(),
+            postgresql_archive::extract(&settings.releases_url, &bytes, &staging.0),
+        )
+        .await?;
+        staging.commit(&target)?;
+    }
+    Ok((version, target))
+}
+
+/// Whether the pg_search extension files are present in an installation.
+pub fn has_pg_search(binary_dir: &Path) -> bool {
+    let Some(root) = binary_dir.parent() else {
+        return false;
+    };
+    ["share/extension", "share/postgresql/extension"]
+        .iter()
+        .any(|dir| root.join(dir).join("pg_search.control").exists())
+}
+
+/// A staging directory that is removed on drop unless committed.
+struct StagingDir(PathBuf);
+
+impl StagingDir {
+    fn create(path: PathBuf) -> io::Result<Self> {
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        Ok(StagingDir(path))
+    }
+
+    fn commit(self, target: &Path) -> io::Result<()> {
+        fs::rename(&self.0, target)
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if self.0.exists() {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
+
+fn stage_into(target: &Path, populate: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let staging = staging_path(target);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    if let Err(error) = populate(&staging) {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(error);
+    }
+    fs::rename(&staging, target)
+}
+
+fn staging_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".pgx-import-{name}"))
+}
+
+fn check_requirement(source: &Path, version: &Version, requirement: &VersionReq) -> io::Result<()> {
+    if requirement.matches(version) {
+        return Ok(());
+    }
+    Err(io::Error::other(format!(
+        "{} contains PostgreSQL {version}, which does not satisfy {requirement}",
+        source.display()
+    )))
+}
+
+/// Release archives are named like `postgresql-17.5.0-x86_64-unknown-linux-gnu.tar.gz`.
+fn archive_version(archive: &Path) -> ImportResult<Version> {
+    let name = archive
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let pattern = Regex::new(r"^postgresql-(\d+\.\d+\.\d+)-")?;
+    let Some(captures) = pattern.captures(&name) else {
+        return Err(io::Error::other(format!(
+            "cannot tell the PostgreSQL version of {}; expected a release archive named like postgresql-17.5.0-<target>.tar.gz",
+            archive.display()
+        ))
+        .into());
+    };
+    Ok(Version::parse(&captures[1])?)
+}
+
+/// Ask the `postgres` binary of an installation for its version.
+fn installed_version(installation: &Path) -> ImportResult<Version> {
+    let postgres = installation
+        .join("bin")
+        .join(format!("postgres{}", std::env::consts::EXE_SUFFIX));
+    let output = Command::new(&postgres)
+        .arg("--version")
+        .output()
+        .map_err(|error| {
+            io::Error::other(format!(
+                "{} is not a PostgreSQL installation: {error}",
+                installation.display()
+            ))
+        })?;
+
+    // e.g. "postgres (PostgreSQL) 17.5"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let raw = stdout.split_whitespace().last().unwrap_or_default();
+    let mut parts = raw.split('.').map(|part| {
+        part.chars()
+            .take_while(char::is_ascii_digit)
+            .collect::<String>()
+    });
+    let major = parts.next().and_then(|part| part.parse().ok());
+    let minor = parts.next().and_then(|part| part.parse().ok()).unwrap_or(0);
+    match major {
+        Some(major) => Ok(Version::new(major, minor, 0)),
+        None => Err(io::Error::other(format!(
+            "unexpected output from {}: {}",
+            postgres.display(),
+            stdout.trim()
+        ))
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(requirement: &str, installation_dir: &Path) -> Settings {
+        Settings {
+            version: VersionReq::parse(requirement).unwrap(),
+            installation_dir: installation_dir.to_path_buf(),
+            ..Settings::default()
+        }
+    }
+
+    /// An extracted installation whose `postgres --version` reports `version`.
+    #[cfg(unix)]
+    fn fake_installation(root: &Path, version: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let installation = root.join(format!("postgresql-{version}"));
+        let postgres = installation.join("bin/postgres");
+        fs::create_dir_all(postgres.parent().unwrap()).unwrap();
+        fs::write(
+            &postgres,
+            format!("#!/bin/sh\necho 'postgres (PostgreSQL) {version}'\n"),
+        )
+        .unwrap();
+        fs::set_permissions(&postgres, fs::Permissions::from_mode(0o755)).unwrap();
+        // Another test forking while the script was open for writing keeps
+        // it busy until that child execs.
+        while matches!(
+            Command::new(&postgres).output(),
+            Err(error) if error.kind() == io::ErrorKind::ExecutableFileBusy
+        ) {
+            std::thread::yield_now();
+        }
+        installation
+    }
+
+    #[test]
+    fn reads_the_version_from_a_release_archive_name() {
+        let archive = Path::new("/tmp/postgresql-17.5.0-x86_64-unknown-linux-gnu.tar.gz");
+        assert_eq!(archive_version(archive).unwrap(), Version::new(17, 5, 0));
+
+        let error = archive_version(Path::new("/tmp/postgres.tar.gz"))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("cannot tell the PostgreSQL version"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn refuses_a_build_outside_the_requirement() {
+        let version = Version::new(16, 4, 0);
+        let source = Path::new("pg16.tar.gz");
+        assert!(check_requirement(source, &version, &VersionReq::parse("^16").unwrap()).is_ok());
+        assert_eq!(
+            check_requirement(source, &version, &VersionReq::parse("^17").unwrap())
+                .unwrap_err()
+                .to_string(),
+            "pg16.tar.gz contains PostgreSQL 16.4.0, which does not satisfy ^17"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn imports_an_installation_directory_into_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = fake_installation(dir.path(), "17.5");
+        let cache = dir.path().join("cache");
+
+        let error = import(&source, &settings("^16", &cache)).await.unwrap_err();
+        assert!(
+            error.to_string().contains("does not satisfy ^16"),
+            "{error}"
+        );
+        assert!(!cache.exists());
+
+        let version = import(&source, &settings("^17", &cache)).await.unwrap();
+        assert_eq!(version, Version::new(17, 5, 0));
+        assert!(cache.join("17.5.0/bin/postgres").is_file());
+        assert!(!cache.join(".pgx-import-17.5.0").exists());
+    }
+
+    #[test]
+    fn a_directory_without_postgres_is_not_an_installation() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = installed_version(dir.path()).unwrap_err().to_string();
+        assert!(
+            error.contains("is not a PostgreSQL installation"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn offline_fetches_use_the_cache_or_fail() {
+        let cache = tempfile::tempdir().unwrap();
+        fs::create_dir_all(cache.path().join("16.4.0/bin")).unwrap();
+        fs::create_dir_all(cache.path().join("16.9.0/bin")).unwrap();
+
+        let (version, path) = fetch(&settings("^16", cache.path()), true).await.unwrap();
+        assert_eq!(version, Version::new(16, 9, 0));
+        assert_eq!(path, cache.path().join("16.9.0"));
+
+        let error = fetch(&settings("^17", cache.path()), true)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("--offline forbids downloading it"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn a_failed_import_leaves_no_staging_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("17.5.0");
+
+        let result = stage_into(&target, |staging| {
+            fs::create_dir(staging)?;
+            fs::write(staging.join("half"), "copied")?;
+            Err(io::Error::other("disk full"))
+        });
+
+        assert_eq!(result.unwrap_err().to_string(), "disk full");
+        assert!(!target.exists());
+        assert!(!staging_path(&target).exists());
+    }
+
+    #[test]
+    fn finds_pg_search_in_either_extension_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary_dir = dir.path().join("bin");
+        assert!(!has_pg_search(&binary_dir));
+        let extensions = dir.path().join("share/postgresql/extension");
+        fs::create_dir_all(&extensions).unwrap();
+        fs::write(extensions.join("pg_search.control"), "").unwrap();
+        assert!(has_pg_search(&binary_dir));
+    }
+}
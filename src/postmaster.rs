@@ -0,0 +1,465 @@
+use clap::ValueEnum;
+use std::fs;
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long each `--force` escalation step waits for the postmaster to exit.
+const ESCALATION_GRACE: Duration = Duration::from_secs(5);
+
+/// What `postmaster.pid` says about a data directory, verified against the
+/// process table rather than trusted on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    /// There is no `postmaster.pid`.
+    Stopped,
+    /// `postmaster.pid` names a live postgres process.
+    Running,
+    /// `postmaster.pid` was left behind by a postmaster that no longer exists.
+    Stale,
+}
+
+/// PostgreSQL shutdown modes, as accepted by `pg_ctl stop -m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ShutdownMode {
+    /// Wait for all clients to disconnect.
+    Smart,
+    /// Disconnect clients and shut down cleanly.
+    Fast,
+    /// Abort without a shutdown checkpoint; crash recovery runs on next start.
+    Immediate,
+}
+
+impl ShutdownMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShutdownMode::Smart => "smart",
+            ShutdownMode::Fast => "fast",
+            ShutdownMode::Immediate => "immediate",
+        }
+    }
+}
+
+/// Signals `pgx stop --force` sends to a postmaster that ignored a graceful
+/// stop, in the order they are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escalation {
+    /// Fast shutdown.
+    Interrupt,
+    /// Immediate shutdown.
+    Quit,
+    /// Unconditional termination; leaves `postmaster.pid` behind.
+    Kill,
+}
+
+impl Escalation {
+    const ORDER: [Escalation; 3] = [Escalation::Interrupt, Escalation::Quit, Escalation::Kill];
+
+    pub fn signal_name(self) -> &'static str {
+        match self {
+            Escalation::Interrupt => "SIGINT",
+            Escalation::Quit => "SIGQUIT",
+            Escalation::Kill => "SIGKILL",
+        }
+    }
+}
+
+/// Fields of `postmaster.pid` that pgx cares about.
+struct PidFile {
+    pid: u32,
+    port: Option<u16>,
+}
+
+pub fn pid_file_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("postmaster.pid")
+}
+
+/// Check whether the postmaster recorded in `data_dir` is actually alive. When
+/// the process table cannot be queried, fall back to a TCP connect to the port
+/// recorded in the pid file. A postgres process whose PID was reused after an
+/// unclean shutdown is not mistaken for the owner where its working directory
+/// can be read.
+pub fn inspect(data_dir: &Path, host: &str) -> Liveness {
+    if !pid_file_path(data_dir).exists() {
+        return Liveness::Stopped;
+    }
+
+    let Ok(Some(pid_file)) = read_pid_file(data_dir) else {
+        return Liveness::Stale;
+    };
+
+    let alive = match process_looks_like_postgres(pid_file.pid) {
+        Some(alive) => alive && process_serves(pid_file.pid, data_dir) != Some(false),
+        None => pid_file
+            .port
+            .is_some_and(|port| accepts_connections(host, port)),
+    };
+
+    if alive {
+        Liveness::Running
+    } else {
+        Liveness::Stale
+    }
+}
+
+/// Port recorded in `postmaster.pid`, if there is one.
+pub fn recorded_port(data_dir: &Path) -> Option<u16> {
+    read_pid_file(data_dir).ok().flatten()?.port
+}
+
+/// PID of the postmaster recorded in `postmaster.pid`, if there is one.
+pub fn read_pid(data_dir: &Path) -> Option<u32> {
+    read_pid_file(data_dir)
+        .ok()
+        .flatten()
+        .map(|pid_file| pid_file.pid)
+}
+
+pub fn remove_pid_file(data_dir: &Path) -> io::Result<()> {
+    fs::remove_file(pid_file_path(data_dir))
+}
+
+/// Stop the postmaster for `data_dir` with `pg_ctl stop`, waiting until it
+/// has exited.
+pub async fn stop(pg_ctl: &Path, data_dir: &Path, mode: ShutdownMode) -> io::Result<()> {
+    run_pg_ctl(
+        tokio::process::Command::new(pg_ctl)
+            .arg("stop")
+            .arg("-D")
+            .arg(data_dir)
+            .args(["-m", mode.as_str(), "-w"]),
+    )
+    .await
+}
+
+/// Signal the postmaster for `data_dir` to re-read its configuration files.
+pub async fn reload(pg_ctl: &Path, data_dir: &Path) -> io::Result<()> {
+    run_pg_ctl(
+        tokio::process::Command::new(pg_ctl)
+            .arg("reload")
+            .arg("-D")
+            .arg(data_dir),
+    )
+    .await
+}
+
+/// Signal `pid` with increasingly forceful signals until it exits, returning
+/// the step that worked.
+pub async fn force_stop(pg_ctl: &Path, data_dir: &Path, pid: u32) -> io::Result<Escalation> {
+    for escalation in Escalation::ORDER {
+        tracing::warn!(
+            pid,
+            signal = escalation.signal_name(),
+            "escalating shutdown"
+        );
+        send_escalation(pg_ctl, pid, escalation).await?;
+        if wait_for_exit(data_dir, pid, ESCALATION_GRACE).await {
+            return Ok(escalation);
+        }
+    }
+
+    Err(io::Error::other(format!(
+        "postmaster (pid {pid}) is still running after SIGKILL"
+    )))
+}
+
+#[cfg(unix)]
+async fn send_escalation(_pg_ctl: &Path, pid: u32, escalation: Escalation) -> io::Result<()> {
+    let signal = match escalation {
+        Escalation::Interrupt => libc::SIGINT,
+        Escalation::Quit => libc::SIGQUIT,
+        Escalation::Kill => libc::SIGKILL,
+    };
+    // SAFETY: kill(2) has no memory-safety preconditions.
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        return Ok(());
+    }
+
+    let error = io::Error::last_os_error();
+    if error.raw_os_error() == Some(libc::ESRCH) {
+        // The postmaster exited between checks.
+        return Ok(());
+    }
+    Err(error)
+}
+
+/// Windows has no signals; `pg_ctl kill` emulates INT and QUIT through the
+/// postmaster's signal pipe, and `taskkill /F` terminates the process tree.
+#[cfg(not(unix))]
+async fn send_escalation(pg_ctl: &Path, pid: u32, escalation: Escalation) -> io::Result<()> {
+    let pid = pid.to_string();
+    match escalation {
+        Escalation::Interrupt => {
+            run_pg_ctl(tokio::process::Command::new(pg_ctl).args(["kill", "INT", &pid])).await
+        }
+        Escalation::Quit => {
+            run_pg_ctl(tokio::process::Command::new(pg_ctl).args(["kill", "QUIT", &pid])).await
+        }
+        Escalation::Kill => {
+            let status = tokio::process::Command::new("taskkill")
+                .args(["/F", "/T", "/PID", &pid])
+                .status()
+                .await?;
+            if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("taskkill failed with {status}")))
+            }
+        }
+    }
+}
+
+/// Poll until `pid` has exited or `limit` elapses. When the process table is
+/// unavailable, the postmaster removing its pid file counts as exiting.
+async fn wait_for_exit(data_dir: &Path, pid: u32, limit: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + limit;
+    loop {
+        let alive = match process_looks_like_postgres(pid) {
+            Some(alive) => alive,
+            None => pid_file_path(data_dir).exists(),
+        };
+        if !alive {
+            return true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+    }
+}
+
+/// A watch on a process's exit, from [`watch_process_exit`].
+pub struct ProcessExit {
+    /// Readable once the process has exited; `None` if it already had.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fd: Option<tokio::io::unix::AsyncFd<std::os::fd::OwnedFd>>,
+}
+
+impl ProcessExit {
+    /// Resolve as soon as the process exits.
+    pub async fn wait(self) -> io::Result<()> {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if let Some(fd) = self.fd {
+            let _ = fd.readable().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Watch `pid` for its exit without polling: through a pidfd on Linux and a
+/// kqueue process filter on macOS. Fails with `Unsupported` elsewhere, or
+/// when the kernel lacks the facility, so callers can fall back to polling.
+#[cfg(target_os = "linux")]
+pub fn watch_process_exit(pid: u32) -> io::Result<ProcessExit> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use tokio::io::unix::AsyncFd;
+
+    // SAFETY: pidfd_open(2) takes plain integers and returns a new descriptor.
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ESRCH) => Ok(ProcessExit { fd: None }),
+            Some(libc::ENOSYS) => Err(io::Error::from(io::ErrorKind::Unsupported)),
+            _ => Err(error),
+        };
+    }
+    // SAFETY: `fd` was just returned by pidfd_open and is owned by nothing else.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+    // A pidfd becomes readable when the process exits.
+    Ok(ProcessExit {
+        fd: Some(AsyncFd::new(fd)?),
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn watch_process_exit(pid: u32) -> io::Result<ProcessExit> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use tokio::io::unix::AsyncFd;
+
+    // SAFETY: kqueue(2) takes no arguments and returns a new descriptor.
+    let queue = unsafe { libc::kqueue() };
+    if queue < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `queue` was just returned by kqueue and is owned by nothing else.
+    let queue = unsafe { OwnedFd::from_raw_fd(queue) };
+    let change = libc::kevent {
+        ident: pid as libc::uintptr_t,
+        filter: libc::EVFILT_PROC,
+        flags: libc::EV_ADD | libc::EV_ONESHOT,
+        fflags: libc::NOTE_EXIT,
+        data: 0,
+        udata: std::ptr::null_mut(),
+    };
+    // SAFETY: one valid change record in, no event buffer out.
+    let registered = unsafe {
+        libc::kevent(
+            queue.as_raw_fd(),
+            &change,
+            1,
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null(),
+        )
+    };
+    if registered < 0 {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ESRCH) => Ok(ProcessExit { fd: None }),
+            _ => Err(error),
+        };
+    }
+    // The kqueue becomes readable once the exit event is pending.
+    Ok(ProcessExit {
+        fd: Some(AsyncFd::new(queue)?),
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn watch_process_exit(_pid: u32) -> io::Result<ProcessExit> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+async fn run_pg_ctl(command: &mut tokio::process::Command) -> io::Result<()> {
+    // A caller that gives up on a hung `pg_ctl` should not leave it behind.
+    let output = command.kill_on_drop(true).output().await?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(io::Error::other(format!(
+        "pg_ctl failed: {}",
+        stderr.trim()
+    )))
+}
+
+pub fn accepts_connections(host: &str, port: u16) -> bool {
+    let Ok(addresses) = (host, port).to_socket_addrs() else {
+        return false;
+    };
+
+    addresses
+        .into_iter()
+        .any(|address| TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).is_ok())
+}
+
+/// A port on `host` that nothing listens on right now.
+pub fn free_port(host: &str) -> io::Result<u16> {
+    Ok(TcpListener::bind((host, 0))?.local_addr()?.port())
+}
+
+/// Whether something already listens on `port` at any address `host`
+/// resolves to. The probe listener is released immediately.
+pub fn port_in_use(host: &str, port: u16) -> bool {
+    let Ok(addresses) = (host, port).to_socket_addrs() else {
+        return false;
+    };
+
+    addresses.into_iter().any(|address| {
+        matches!(
+            TcpListener::bind(address),
+            Err(error) if error.kind() == io::ErrorKind::AddrInUse
+        )
+    })
+}
+
+fn read_pid_file(data_dir: &Path) -> io::Result<Option<PidFile>> {
+    let raw = fs::read_to_string(pid_file_path(data_dir))?;
+    let mut lines = raw.lines();
+
+    let Some(pid) = lines.next().and_then(|line| line.trim().parse().ok()) else {
+        return Ok(None);
+    };
+    let port = lines.nth(2).and_then(|line| line.trim().parse().ok());
+
+    Ok(Some(PidFile { pid, port }))
+}
+
+/// `Some(true)` if `pid` is a running postgres process, `Some(false)` if it is
+/// gone or belongs to something else, `None` if the process table is unavailable.
+#[cfg(unix)]
+fn process_looks_like_postgres(pid: u32) -> Option<bool> {
+    let output = Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "comm="])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return Some(false);
+    }
+
+    let name = String::from_utf8_lossy(&output.stdout);
+    Some(name.contains("postgres") || name.contains("postmaster"))
+}
+
+/// Whether `pid` runs in `data_dir`, as a postmaster does. `None` when the
+/// working directory of another process cannot be read.
+#[cfg(target_os = "linux")]
+fn process_serves(pid: u32, data_dir: &Path) -> Option<bool> {
+    let cwd = fs::read_link(format!("/proc/{pid}/cwd")).ok()?;
+    let data_dir = fs::canonicalize(data_dir).ok()?;
+    Some(cwd == data_dir)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_serves(_pid: u32, _data_dir: &Path) -> Option<bool> {
+    None
+}
+
+#[cfg(not(unix))]
+fn process_looks_like_postgres(pid: u32) -> Option<bool> {
+    let output = Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/FO", "CSV", "/NH"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let listing = String::from_utf8_lossy(&output.stdout).to_ascii_lowercase();
+    Some(listing.contains("postgres"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(contents: &str) -> Option<(u32, Option<u16>)> {
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::write(pid_file_path(data_dir.path()), contents).unwrap();
+        read_pid_file(data_dir.path())
+            .unwrap()
+            .map(|pid_file| (pid_file.pid, pid_file.port))
+    }
+
+    #[test]
+    fn reads_the_pid_and_port_of_a_postmaster_pid_file() {
+        let contents =
+            "4242\n/srv/pgx/data\n1735732800\n5433\n/tmp\nlocalhost\n  5432001  0\nready   \n";
+        assert_eq!(read(contents), Some((4242, Some(5433))));
+    }
+
+    #[test]
+    fn a_pid_file_still_being_written_has_no_port() {
+        assert_eq!(read("4242\n/srv/pgx/data\n"), Some((4242, None)));
+        assert_eq!(
+            read("4242\n/srv/pgx/data\n1735732800\n\n"),
+            Some((4242, None))
+        );
+    }
+
+    #[test]
+    fn an_unreadable_pid_is_no_pid_file() {
+        assert_eq!(read(""), None);
+        assert_eq!(read("not a pid\n/srv/pgx/data\n"), None);
+
+        let data_dir = tempfile::tempdir().unwrap();
+        let error = read_pid_file(data_dir.path()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::state::settings_url;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::fs;
+use std::path::Path;
+
+/// Upper bound on the number of relations recorded on stop and replayed on start.
+const CAPTURE_LIMIT: i64 = 64;
+/// Number of indexes prewarmed per database when no recording exists yet.
+const HEURISTIC_INDEX_LIMIT: i64 = 5;
+
+const CONNECTABLE_DATABASES_QUERY: &str = "\
+SELECT datname::text FROM pg_database
+WHERE datallowconn AND NOT datistemplate
+ORDER BY datname";
+
+const CAPTURE_QUERY: &str = "\
+SELECT n.nspname::text, c.relname::text, count(*)::bigint AS buffers
+FROM pg_buffercache b
+JOIN pg_class c ON pg_relation_filenode(c.oid) = b.relfilenode
+JOIN pg_namespace n ON n.oid = c.relnamespace
+WHERE b.reldatabase = (SELECT oid FROM pg_database WHERE datname = current_database())
+  AND n.nspname NOT IN ('pg_catalog', 'information_schema')
+  AND n.nspname NOT LIKE 'pg_toast%'
+GROUP BY n.nspname, c.relname
+ORDER BY buffers DESC
+LIMIT $1";
+
+const PREWARM_RELATION_QUERY: &str = "\
+SELECT pg_prewarm(c.oid::regclass)
+FROM pg_class c
+JOIN pg_namespace n ON n.oid = c.relnamespace
+WHERE n.nspname = $1 AND c.relname = $2";
+
+const PREWARM_LARGEST_INDEXES_QUERY: &str = "\
+SELECT coalesce(sum(pg_prewarm(largest.oid::regclass)), 0)::bigint
+FROM (
+  SELECT c.oid
+  FROM pg_class c
+  JOIN pg_namespace n ON n.oid = c.relnamespace
+  WHERE c.relkind = 'i'
+    AND n.nspname NOT IN ('pg_catalog', 'information_schema')
+    AND n.nspname NOT LIKE 'pg_toast%'
+  ORDER BY pg_relation_size(c.oid) DESC
+  LIMIT $1
+) AS largest";
+
+/// Relations that were resident in shared buffers when the server was last stopped.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Recording {
+    relations: Vec<RecordedRelation>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedRelation {
+    database: String,
+    schema: String,
+    name: String,
+    buffers: i64,
+}
+
+/// Record the most heavily buffered relations of every database so the next
+/// `--prewarm` start can load them again. A server without pg_buffercache
+/// leaves the previous recording untouched.
+pub async fn capture(
+    settings: &postgresql_embedded::Settings,
+    recording_path: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut relations = Vec::new();
+
+    for database in connectable_databases(settings).await? {
+        let pool = PgPool::connect(&settings_url(settings, &database)).await?;
+        if !create_extension(&pool, "pg_buffercache").await {
+            pool.close().await;
+            tracing::warn!("pg_buffercache is unavailable; skipping prewarm recording");
+            return Ok(());
+        }
+
+        let rows = sqlx::query_as::<_, (String, String, i64)>(CAPTURE_QUERY)
+            .bind(CAPTURE_LIMIT)
+            .fetch_all(&pool)
+            .await?;
+        pool.close().await;
+
+        relations.extend(
+            rows.into_iter()
+                .map(|(schema, name, buffers)| RecordedRelation {
+                    database: database.clone(),
+                    schema,
+                    name,
+                    buffers,
+                }),
+        );
+    }
+
+    relations.sort_by_key(|relation| std::cmp::Reverse(relation.buffers));
+    relations.truncate(CAPTURE_LIMIT as usize);
+    tracing::info!(
+        relations = relations.len(),
+        "recorded buffered relations for prewarm"
+    );
+
+    let raw = serde_json::to_string_pretty(&Recording { relations })?;
+    fs::write(recording_path, raw)?;
+    Ok(())
+}
+
+/// Load the relations recorded by [`capture`] into shared buffers, or the
+/// largest few indexes of each database when nothing has been recorded yet.
+/// A server without pg_prewarm makes this a no-op.
+pub async fn prewarm(
+    settings: &postgresql_embedded::Settings,
+    recording_path: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let recording = read_recording(recording_path)?;
+    let databases = match &recording {
+        Some(recording) => {
+            let mut databases: Vec<String> = Vec::new();
+            for relation in &recording.relations {
+                if !databases.contains(&relation.database) {
+                    databases.push(relation.database.clone());
+                }
+            }
+            databases
+        }
+        None => connectable_databases(settings).await?,
+    };
+
+    let mut blocks = 0;
+    for database in databases {
+        let pool = PgPool::connect(&settings_url(settings, &database)).await?;
+        if !create_extension(&pool, "pg_prewarm").await {
+            pool.close().await;
+            tracing::warn!("pg_prewarm is unavailable; skipping prewarm");
+            return Ok(());
+        }
+
+        match &recording {
+            Some(recording) => {
+                for relation in recording
+                    .relations
+                    .iter()
+                    .filter(|relation| relation.database == database)
+                {
+                    let loaded = sqlx::query_scalar::<_, i64>(PREWARM_RELATION_QUERY)
+                        .bind(&relation.schema)
+                        .bind(&relation.name)
+                        .fetch_optional(&pool)
+                        .await?;
+                    blocks += loaded.unwrap_or(0);
+                }
+            }
+            None => {
+                blocks += sqlx::query_scalar::<_, i64>(PREWARM_LARGEST_INDEXES_QUERY)
+                    .bind(HEURISTIC_INDEX_LIMIT)
+                    .fetch_one(&pool)
+                    .await?;
+            }
+        }
+        pool.close().await;
+    }
+
+    tracing::info!(blocks, recorded = recording.is_some(), "prewarm complete");
+    Ok(())
+}
+
+fn read_recording(
+    recording_path: &Path,
+) -> Result<Option<Recording>, Box<dyn std::error::Error + Send + Sync>> {
+    if !recording_path.exists() {
+        return Ok(None);
+    }
+
+    let raw = fs::read_to_string(recording_path)?;
+    let recording = serde_json::from_str::<Recording>(&raw)?;
+    if recording.relations.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(recording))
+}
+
+async fn connectable_databases(
+    settings: &postgresql_embedded::Settings,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let pool = PgPool::connect(&settings_url(settings, "postgres")).await?;
+    let databases = sqlx::query_scalar::<_, String>(CONNECTABLE_DATABASES_QUERY)
+        .fetch_all(&pool)
+        .await?;
+    pool.close().await;
+    Ok(databases)
+}
+
+/// Create a contrib extension, reporting whether it is usable afterwards.
+async fn create_extension(pool: &PgPool, name: &str) -> bool {
+    let statement = format!("CREATE EXTENSION IF NOT EXISTS {name}");
+    match sqlx::query(&statement).execute(pool).await {
+        Ok(_) => true,
+        Err(error) => {
+            tracing::debug!("CREATE EXTENSION {name} failed: {error}");
+            false
+        }
+    }
+}
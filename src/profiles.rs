@@ -0,0 +1,224 @@
+//! Named data directories under the pgx home directory, so `-p <name>` can
+//! stand in for `--data-dir`. An index records every profile pgx has started
+//! along with optional per-profile defaults.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Used when neither `--data-dir`, `PGX_DATA_DIR` nor `--profile` is given.
+pub const DEFAULT_PROFILE: &str = "default";
+pub const PGX_HOME_ENV: &str = "PGX_HOME";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub data_dir: PathBuf,
+    /// Port `start` uses when neither `--port` nor `PGX_PORT` is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// PostgreSQL version requirement, e.g. `=16`, instead of pgx's default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pg_version: Option<String>,
+    /// Database `start` creates and records as the default when `--database`
+    /// is not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProfileIndex {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl ProfileIndex {
+    pub fn read() -> io::Result<Self> {
+        let path = index_path()?;
+        let raw = match fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+        serde_json::from_str(&raw)
+            .map_err(|error| io::Error::other(format!("{}: {error}", path.display())))
+    }
+
+    /// Replace the index through a staging file, so readers never see half of it.
+    pub fn write(&self) -> io::Result<()> {
+        let path = index_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let raw = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        let staging = path.with_extension("json.tmp");
+        fs::write(&staging, raw)?;
+        fs::rename(&staging, &path)
+    }
+
+    /// The profile whose data directory is `data_dir`, if any. Entries
+    /// recorded before data directories were canonicalized may name it
+    /// through a symlink.
+    pub fn owning(&self, data_dir: &Path) -> Option<(&str, &Profile)> {
+        self.profiles
+            .iter()
+            .find(|(_, profile)| {
+                profile.data_dir == data_dir
+                    || fs::canonicalize(&profile.data_dir).is_ok_and(|path| path == data_dir)
+            })
+            .map(|(name, profile)| (name.as_str(), profile))
+    }
+}
+
+/// `$PGX_HOME`, else `$XDG_DATA_HOME/pgx`, else `~/.local/share/pgx`
+/// (`%LOCALAPPDATA%\pgx` on Windows).
+pub fn home_dir() -> io::Result<PathBuf> {
+    let non_empty = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+    if let Some(home) = non_empty(PGX_HOME_ENV) {
+        return Ok(PathBuf::from(home));
+    }
+    if let Some(data_home) = non_empty("XDG_DATA_HOME") {
+        return Ok(PathBuf::from(data_home).join("pgx"));
+    }
+    if cfg!(windows)
+        && let Some(local_app_data) = non_empty("LOCALAPPDATA")
+    {
+        return Ok(PathBuf::from(local_app_data).join("pgx"));
+    }
+    let home = non_empty("HOME").ok_or_else(|| {
+        io::Error::other(format!(
+            "cannot locate the pgx home directory: set {PGX_HOME_ENV} or HOME"
+        ))
+    })?;
+    Ok(PathBuf::from(home).join(".local").join("share").join("pgx"))
+}
+
+fn index_path() -> io::Result<PathBuf> {
+    Ok(home_dir()?.join("profiles.json"))
+}
+
+pub fn validate_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "invalid profile name '{name}': use letters, digits, '-', '_' and '.'"
+        )))
+    }
+}
+
+/// Data directory of profile `name`: the one recorded in the index, or
+/// `<pgx home>/profiles/<name>` for a profile that has not been started yet.
+pub fn data_dir(name: &str) -> io::Result<PathBuf> {
+    validate_name(name)?;
+    if let Some(profile) = ProfileIndex::read()?.profiles.get(name) {
+        return Ok(profile.data_dir.clone());
+    }
+    Ok(home_dir()?.join("profiles").join(name))
+}
+
+/// Record profile `name` in the index if it is not there yet.
+pub fn register(name: &str, data_dir: &Path) -> io::Result<()> {
+    let mut index = ProfileIndex::read()?;
+    if index.profiles.contains_key(name) {
+        return Ok(());
+    }
+    index.profiles.insert(
+        name.to_string(),
+        Profile {
+            data_dir: data_dir.to_path_buf(),
+            port: None,
+            pg_version: None,
+            database: None,
+        },
+    );
+    index.write()
+}
+
+/// Drop profile `name` from the index, returning what it recorded.
+pub fn unregister(name: &str) -> io::Result<Option<Profile>> {
+    let mut index = ProfileIndex::read()?;
+    let removed = index.profiles.remove(name);
+    if removed.is_some() {
+        index.write()?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(data_dir: &Path) -> Profile {
+        Profile {
+            data_dir: data_dir.to_path_buf(),
+            port: None,
+            pg_version: None,
+            database: None,
+        }
+    }
+
+    #[test]
+    fn profile_names_are_single_path_components() {
+        for name in ["default", "dev-2", "feature_x", "v1.2"] {
+            assert!(validate_name(name).is_ok(), "{name}");
+        }
+        for name in ["", ".hidden", "a/b", "../up", "with space", "ü"] {
+            let error = validate_name(name).unwrap_err().to_string();
+            assert!(error.starts_with("invalid profile name"), "{error}");
+        }
+    }
+
+    #[test]
+    fn the_index_records_only_the_defaults_that_are_set() {
+        let mut index = ProfileIndex::default();
+        index
+            .profiles
+            .insert("dev".into(), profile(Path::new("/srv/dev")));
+        let mut pinned = profile(Path::new("/srv/pinned"));
+        pinned.port = Some(5433);
+        index.profiles.insert("pinned".into(), pinned);
+
+        let raw = serde_json::to_value(&index).unwrap();
+        assert_eq!(
+            raw,
+            serde_json::json!({
+                "profiles": {
+                    "dev": { "data_dir": "/srv/dev" },
+                    "pinned": { "data_dir": "/srv/pinned", "port": 5433 },
+                }
+            })
+        );
+        let read: ProfileIndex = serde_json::from_value(raw).unwrap();
+        assert_eq!(read.profiles["pinned"].port, Some(5433));
+        assert_eq!(read.profiles["dev"].database, None);
+        let empty: ProfileIndex = serde_json::from_str("{}").unwrap();
+        assert!(empty.profiles.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn owning_matches_data_dirs_recorded_through_a_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = fs::canonicalize(dir.path()).unwrap().join("data");
+        fs::create_dir(&real).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let mut index = ProfileIndex::default();
+        index.profiles.insert("dev".into(), profile(&link));
+        index
+            .profiles
+            .insert("other".into(), profile(&dir.path().join("other")));
+
+        assert_eq!(index.owning(&real).map(|(name, _)| name), Some("dev"));
+        assert_eq!(index.owning(&link).map(|(name, _)| name), Some("dev"));
+        assert!(index.owning(&dir.path().join("elsewhere")).is_none());
+    }
+}
@@ -0,0 +1,80 @@
+//! Progress for long setup phases such as the first download of the
+//! PostgreSQL binaries, so a slow network does not look like a hang.
+
+use crate::snapshot::format_size;
+use std::future::Future;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tokio::time::{Duration, interval};
+
+/// Redraw rate of the status line on a terminal.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+/// How often progress is logged when stderr is not a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+static HIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Suppress progress output, e.g. for `--quiet`.
+pub fn hide() {
+    HIDDEN.store(true, Ordering::Relaxed);
+}
+
+/// Run `future` while reporting `phase`: a status line redrawn in place on a
+/// terminal, or a log line every few seconds otherwise. `measure` returns
+/// how many bytes are done so far, if that can be told.
+pub async fn track<F: Future>(
+    phase: &str,
+    measure: impl Fn() -> Option<u64>,
+    future: F,
+) -> F::Output {
+    if HIDDEN.load(Ordering::Relaxed) {
+        return future.await;
+    }
+    let terminal = io::stderr().is_terminal();
+    let started = Instant::now();
+    let mut ticker = interval(if terminal {
+        REDRAW_INTERVAL
+    } else {
+        LOG_INTERVAL
+    });
+    // The first tick fires immediately; nothing is worth showing yet.
+    ticker.tick().await;
+    tokio::pin!(future);
+
+    let mut frame = 0;
+    let output = loop {
+        tokio::select! {
+            output = &mut future => break output,
+            _ = ticker.tick() => {}
+        }
+        let elapsed = started.elapsed().as_secs();
+        let done = measure()
+            .map(|bytes| format!(", {}", format_size(bytes)))
+            .unwrap_or_default();
+        if terminal {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(
+                stderr,
+                "\r\x1b[2K{} {phase} ({elapsed}s{done})",
+                SPINNER[frame % SPINNER.len()]
+            );
+            let _ = stderr.flush();
+            frame += 1;
+        } else {
+            tracing::info!("{phase}: {elapsed}s elapsed{done}");
+        }
+    };
+
+    if terminal && frame > 0 {
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+    }
+    tracing::info!(
+        "{phase}: done in {}",
+        humantime::format_duration(Duration::from_secs(started.elapsed().as_secs()))
+    );
+    output
+}
@@ -0,0 +1,154 @@
+//! `pgx.toml`: per-project defaults found by walking up from the working
+//! directory, so `pgx start` inside a configured project needs no flags.
+//! Command-line flags and `PGX_*` variables win over the file.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = "pgx.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Relative paths are taken from the directory holding `pgx.toml`.
+    pub data_dir: Option<PathBuf>,
+    pub port: Option<u16>,
+    pub host: Option<String>,
+    /// `16` or a requirement such as `=16.4.0`, as for `start --pg-version`.
+    pub pg_version: Option<String>,
+    /// Database URLs point at, as for `start --database`.
+    pub database: Option<String>,
+    /// Further databases created on every start if missing.
+    #[serde(default)]
+    pub databases: Vec<String>,
+    /// SQL files run once, after the first start, as for `start --init-sql`.
+    #[serde(default)]
+    pub init_scripts: Vec<PathBuf>,
+    /// postgresql.conf settings, as for `start --set`; numbers and booleans
+    /// need no quotes.
+    #[serde(default)]
+    pub settings: BTreeMap<String, toml::Value>,
+    /// Login roles by name, as for `start --role`.
+    #[serde(default)]
+    pub roles: BTreeMap<String, ProjectRole>,
+    #[serde(default)]
+    pub pre_start: Vec<String>,
+    #[serde(default)]
+    pub post_start: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectRole {
+    pub password: String,
+    /// Role attributes such as `createdb`, as after the second `:` of `--role`.
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Project {
+    /// The `pgx.toml` the configuration came from.
+    pub path: PathBuf,
+    pub config: ProjectConfig,
+}
+
+impl Project {
+    /// `path` relative to the directory holding `pgx.toml`, unless absolute.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        match self.path.parent() {
+            Some(root) if path.is_relative() => root.join(path),
+            _ => path.to_path_buf(),
+        }
+    }
+}
+
+/// The nearest `pgx.toml` in `start` or one of its ancestors.
+pub fn find(start: &Path) -> io::Result<Option<Project>> {
+    for dir in start.ancestors() {
+        let path = dir.join(FILE_NAME);
+        let raw = match fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error),
+        };
+        let config = toml::from_str(&raw)
+            .map_err(|error| io::Error::other(format!("{}: {error}", path.display())))?;
+        return Ok(Some(Project { path, config }));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_option() {
+        let config: ProjectConfig = toml::from_str(
+            r#"
+            data_dir = ".pgx/data"
+            port = 6000
+            pg_version = "=16.4.0"
+            databases = ["app", "app_test"]
+            init_scripts = ["db/schema.sql"]
+            post_start = ["make seed"]
+
+            [settings]
+            shared_buffers = "256MB"
+            fsync = false
+
+            [roles.reader]
+            password = "secret"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.data_dir, Some(PathBuf::from(".pgx/data")));
+        assert_eq!(config.port, Some(6000));
+        assert_eq!(config.host, None);
+        assert_eq!(config.pg_version.as_deref(), Some("=16.4.0"));
+        assert_eq!(config.databases, ["app", "app_test"]);
+        assert_eq!(config.post_start, ["make seed"]);
+        assert!(config.pre_start.is_empty());
+        assert_eq!(config.settings["fsync"], toml::Value::Boolean(false));
+        assert!(config.roles["reader"].options.is_empty());
+    }
+
+    #[test]
+    fn the_nearest_file_wins_and_resolves_paths_from_its_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let nested = root.path().join("app/src");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.path().join(FILE_NAME), "port = 5000").unwrap();
+        fs::write(root.path().join("app").join(FILE_NAME), "port = 6000").unwrap();
+
+        let project = find(&nested).unwrap().unwrap();
+        assert_eq!(project.path, root.path().join("app").join(FILE_NAME));
+        assert_eq!(project.config.port, Some(6000));
+        assert_eq!(
+            project.resolve(Path::new("data")),
+            root.path().join("app/data")
+        );
+        assert_eq!(project.resolve(Path::new("/srv/db")), Path::new("/srv/db"));
+
+        let project = find(root.path()).unwrap().unwrap();
+        assert_eq!(project.config.port, Some(5000));
+    }
+
+    #[test]
+    fn unknown_options_are_refused_with_the_file_name() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(FILE_NAME), "prot = 6000").unwrap();
+
+        let error = find(root.path()).unwrap_err().to_string();
+        assert!(
+            error.starts_with(&root.path().join(FILE_NAME).display().to_string()),
+            "{error}"
+        );
+        assert!(error.contains("unknown field `prot`"), "{error}");
+    }
+}
@@ -0,0 +1,374 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, broadcast};
+use tokio::time::{Duration, interval};
+use tracing::Instrument;
+
+const SLOT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Events kept for a subscriber that falls behind before it starts missing some.
+const EVENT_BACKLOG: usize = 256;
+
+/// How many clusters may be set up, initialized and started at the same
+/// time. Clones share one queue, and [`ProvisionLimits::default`] is shared
+/// by the whole process, so a harness starting many clusters at once does
+/// not saturate the CPUs and the disk.
+#[derive(Clone)]
+pub struct ProvisionLimits {
+    inner: Arc<Limiter>,
+}
+
+struct Limiter {
+    limit: usize,
+    machine_wide: bool,
+    semaphore: Arc<Semaphore>,
+    queued: AtomicUsize,
+    events: broadcast::Sender<ProvisionEvent>,
+}
+
+/// Contention on a [`ProvisionLimits`] queue, as seen by
+/// [`ProvisionLimits::subscribe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProvisionEvent {
+    /// `data_dir` is waiting for its turn; `queue_depth` counts it.
+    Queued {
+        data_dir: PathBuf,
+        queue_depth: usize,
+    },
+    /// `data_dir` may proceed after waiting `waited`.
+    Acquired {
+        data_dir: PathBuf,
+        waited: Duration,
+        queue_depth: usize,
+    },
+    /// `data_dir` is done, letting the next one in the queue proceed.
+    Released { data_dir: PathBuf },
+}
+
+impl ProvisionLimits {
+    /// At most `limit` (at least one) operations at a time in this process.
+    pub fn new(limit: usize) -> Self {
+        ProvisionLimits::build(limit, false)
+    }
+
+    /// At most `limit` operations at a time in this process and in every
+    /// other one sharing the installation directory, such as `pgx start`,
+    /// coordinated through lock files there.
+    pub fn machine_wide(limit: usize) -> Self {
+        ProvisionLimits::build(limit, true)
+    }
+
+    fn build(limit: usize, machine_wide: bool) -> Self {
+        let limit = limit.max(1);
+        ProvisionLimits {
+            inner: Arc::new(Limiter {
+                limit,
+                machine_wide,
+                semaphore: Arc::new(Semaphore::new(limit)),
+                queued: AtomicUsize::new(0),
+                events: broadcast::channel(EVENT_BACKLOG).0,
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    pub fn is_machine_wide(&self) -> bool {
+        self.inner.machine_wide
+    }
+
+    /// Operations waiting for their turn right now.
+    pub fn queue_depth(&self) -> usize {
+        self.inner.queued.load(Ordering::Relaxed)
+    }
+
+    /// Operations holding a turn right now.
+    pub fn in_progress(&self) -> usize {
+        self.inner.limit - self.inner.semaphore.available_permits()
+    }
+
+    /// Every event from now on. A receiver that falls far behind gets
+    /// `RecvError::Lagged` rather than slowing provisioning down.
+    pub fn subscribe(&self) -> broadcast::Receiver<ProvisionEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Wait for `data_dir`'s turn, recording the wait in a `provision_wait`
+    /// span. With [`ProvisionLimits::machine_wide`], the turn also takes one
+    /// of the slot lock files in `lock_dir`.
+    pub async fn acquire(&self, lock_dir: &Path, data_dir: &Path) -> io::Result<ProvisionPermit> {
+        let span = tracing::info_span!(
+            "provision_wait",
+            data_dir = %data_dir.display(),
+            limit = self.inner.limit,
+            queue_depth = tracing::field::Empty,
+            waited_ms = tracing::field::Empty,
+        );
+        self.wait_turn(lock_dir, data_dir).instrument(span).await
+    }
+
+    async fn wait_turn(&self, lock_dir: &Path, data_dir: &Path) -> io::Result<ProvisionPermit> {
+        let inner = &self.inner;
+        let started = Instant::now();
+        let queued = QueueEntry::join(&inner.queued);
+        let queue_depth = queued.depth;
+        tracing::Span::current().record("queue_depth", queue_depth);
+        let _ = inner.events.send(ProvisionEvent::Queued {
+            data_dir: data_dir.to_path_buf(),
+            queue_depth,
+        });
+
+        let permit = Arc::clone(&inner.semaphore)
+            .acquire_owned()
+            .await
+            .expect("the provisioning semaphore is never closed");
+        let slot = if inner.machine_wide {
+            acquire(lock_dir, inner.limit).await.map(Some)
+        } else {
+            Ok(None)
+        };
+        let queue_depth = queued.leave();
+        let slot = slot?;
+
+        let waited = started.elapsed();
+        tracing::Span::current().record("waited_ms", waited.as_millis() as u64);
+        if !waited.is_zero() {
+            tracing::debug!(queue_depth, "provisioning turn acquired");
+        }
+        let _ = inner.events.send(ProvisionEvent::Acquired {
+            data_dir: data_dir.to_path_buf(),
+            waited,
+            queue_depth,
+        });
+        Ok(ProvisionPermit {
+            limits: self.clone(),
+            data_dir: data_dir.to_path_buf(),
+            _permit: permit,
+            _slot: slot,
+        })
+    }
+}
+
+/// A place in a [`ProvisionLimits`] queue, given up when the wait ends or is
+/// cancelled, so a dropped `acquire` does not leave the depth raised.
+struct QueueEntry<'a> {
+    queued: &'a AtomicUsize,
+    depth: usize,
+}
+
+impl<'a> QueueEntry<'a> {
+    fn join(queued: &'a AtomicUsize) -> Self {
+        let depth = queued.fetch_add(1, Ordering::Relaxed) + 1;
+        QueueEntry { queued, depth }
+    }
+
+    /// Leave the queue, returning how many are still waiting.
+    fn leave(self) -> usize {
+        let depth = self.queued.fetch_sub(1, Ordering::Relaxed) - 1;
+        std::mem::forget(self);
+        depth
+    }
+}
+
+impl Drop for QueueEntry<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The limits every [`ProvisionLimits::default`] shares: half the available
+/// CPUs, and at least one, operations at a time in this process.
+impl Default for ProvisionLimits {
+    fn default() -> Self {
+        static SHARED: OnceLock<ProvisionLimits> = OnceLock::new();
+        SHARED
+            .get_or_init(|| ProvisionLimits::new(default_limit()))
+            .clone()
+    }
+}
+
+impl std::fmt::Debug for ProvisionLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProvisionLimits")
+            .field("limit", &self.inner.limit)
+            .field("machine_wide", &self.inner.machine_wide)
+            .field("queue_depth", &self.queue_depth())
+            .field("in_progress", &self.in_progress())
+            .finish()
+    }
+}
+
+/// A turn to provision a cluster. Dropping it lets the next one in the
+/// queue proceed.
+pub struct ProvisionPermit {
+    limits: ProvisionLimits,
+    data_dir: PathBuf,
+    _permit: OwnedSemaphorePermit,
+    _slot: Option<ProvisionSlot>,
+}
+
+impl Drop for ProvisionPermit {
+    fn drop(&mut self) {
+        let _ = self.limits.inner.events.send(ProvisionEvent::Released {
+            data_dir: std::mem::take(&mut self.data_dir),
+        });
+    }
+}
+
+/// A machine-wide provisioning slot. Dropping it lets the next queued pgx
+/// process proceed.
+pub struct ProvisionSlot {
+    _file: File,
+}
+
+/// Default number of concurrent setup/initdb/start operations: half the
+/// available CPUs, and at least one.
+pub fn default_limit() -> usize {
+    thread::available_parallelism()
+        .map(|cpus| cpus.get() / 2)
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// Wait until one of `limit` slot lock files in `lock_dir` can be locked.
+/// Every pgx process sharing `lock_dir` competes for the same slots, which
+/// keeps large batches of parallel starts from saturating the disk.
+pub async fn acquire(lock_dir: &Path, limit: usize) -> io::Result<ProvisionSlot> {
+    fs::create_dir_all(lock_dir)?;
+    let started = Instant::now();
+    let mut ticker = interval(SLOT_POLL_INTERVAL);
+    let mut announced = false;
+
+    loop {
+        for slot in 0..limit.max(1) {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(lock_dir.join(format!(".pgx-provision-{slot}.lock")))?;
+
+            match file.try_lock() {
+                Ok(()) => {
+                    tracing::info!(
+                        slot,
+                        limit,
+                        waited_ms = started.elapsed().as_millis() as u64,
+                        "acquired provisioning slot"
+                    );
+                    return Ok(ProvisionSlot { _file: file });
+                }
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(error)) => return Err(error),
+            }
+        }
+
+        if !announced {
+            tracing::info!(limit, "all provisioning slots are busy; waiting");
+            announced = true;
+        }
+        ticker.tick().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queues_beyond_the_limit_and_reports_it() {
+        let limits = ProvisionLimits::new(1);
+        let mut events = limits.subscribe();
+        let lock_dir = Path::new("unused");
+
+        let first = limits.acquire(lock_dir, Path::new("a")).await.unwrap();
+        let waiting = tokio::spawn({
+            let limits = limits.clone();
+            async move { limits.acquire(lock_dir, Path::new("b")).await.unwrap() }
+        });
+        while limits.queue_depth() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(limits.in_progress(), 1);
+        drop(first);
+        let second = waiting.await.unwrap();
+        assert_eq!(limits.queue_depth(), 0);
+        drop(second);
+        assert_eq!(limits.in_progress(), 0);
+
+        let mut seen = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            seen.push(match event {
+                ProvisionEvent::Queued {
+                    data_dir,
+                    queue_depth,
+                } => format!("queued {} {queue_depth}", data_dir.display()),
+                ProvisionEvent::Acquired {
+                    data_dir,
+                    queue_depth,
+                    ..
+                } => format!("acquired {} {queue_depth}", data_dir.display()),
+                ProvisionEvent::Released { data_dir } => {
+                    format!("released {}", data_dir.display())
+                }
+            });
+        }
+        assert_eq!(
+            seen,
+            [
+                "queued a 1",
+                "acquired a 0",
+                "queued b 1",
+                "released a",
+                "acquired b 0",
+                "released b",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_wait_leaves_the_queue() {
+        let limits = ProvisionLimits::new(1);
+        let lock_dir = Path::new("unused");
+
+        let first = limits.acquire(lock_dir, Path::new("a")).await.unwrap();
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(10),
+            limits.acquire(lock_dir, Path::new("b")),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        assert_eq!(limits.queue_depth(), 0);
+        assert_eq!(limits.in_progress(), 1);
+
+        let mut events = limits.subscribe();
+        drop(first);
+        let second = limits.acquire(lock_dir, Path::new("c")).await.unwrap();
+        assert_eq!(limits.queue_depth(), 0);
+        drop(second);
+        assert_eq!(limits.in_progress(), 0);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(ProvisionEvent::Released { data_dir }) if data_dir == Path::new("a")
+        ));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(ProvisionEvent::Queued { queue_depth: 1, .. })
+        ));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(ProvisionEvent::Acquired { queue_depth: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn limits_are_at_least_one() {
+        assert_eq!(ProvisionLimits::new(0).limit(), 1);
+        assert!(default_limit() >= 1);
+    }
+}
@@ -0,0 +1,86 @@
+use sqlx::Connection;
+use sqlx::postgres::PgConnection;
+use std::fmt;
+use std::io;
+use tokio::time::{Duration, Instant, sleep, timeout};
+
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Poll `url` until a connection succeeds and answers `SELECT 1`, or fail
+/// after `limit` with the last connection error.
+pub async fn wait_until_ready(
+    url: &str,
+    limit: Duration,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let deadline = Instant::now() + limit;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let last_error = match timeout(remaining, probe(url)).await {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(error)) => error.to_string(),
+            Err(_) => "connection attempt timed out".to_string(),
+        };
+
+        if Instant::now() + PROBE_INTERVAL >= deadline {
+            return Err(io::Error::other(format!(
+                "server did not accept connections within {}: {last_error}",
+                humantime::format_duration(limit)
+            ))
+            .into());
+        }
+        sleep(PROBE_INTERVAL).await;
+    }
+}
+
+/// Open a fresh connection to `url` and run `SELECT 1`.
+pub async fn probe(url: &str) -> Result<(), sqlx::Error> {
+    let mut connection = PgConnection::connect(url).await?;
+    sqlx::query("SELECT 1").execute(&mut connection).await?;
+    connection.close().await
+}
+
+/// Result of one connection attempt against a server whose postmaster is up.
+pub enum Connectivity {
+    Ok,
+    /// The server rejected the password.
+    AuthenticationFailed,
+    /// Nothing accepted the connection on the port.
+    Refused,
+    /// Any other failure, including no answer within the time limit.
+    Failed(String),
+}
+
+impl fmt::Display for Connectivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Connectivity::Ok => f.write_str("ok"),
+            Connectivity::AuthenticationFailed => f.write_str("authentication failed"),
+            Connectivity::Refused => f.write_str("connection refused"),
+            Connectivity::Failed(reason) => write!(f, "connection failed: {reason}"),
+        }
+    }
+}
+
+/// Whether the server rejected the credentials rather than failing otherwise.
+pub fn is_authentication_failure(error: &sqlx::Error) -> bool {
+    // invalid_password, invalid_authorization_specification
+    matches!(error, sqlx::Error::Database(error)
+        if matches!(error.code().as_deref(), Some("28P01" | "28000")))
+}
+
+/// Connect to `url` once and run `SELECT 1`, giving up after `limit`.
+pub async fn connectivity(url: &str, limit: Duration) -> Connectivity {
+    match timeout(limit, probe(url)).await {
+        Ok(Ok(())) => Connectivity::Ok,
+        Ok(Err(error)) if is_authentication_failure(&error) => Connectivity::AuthenticationFailed,
+        Ok(Err(sqlx::Error::Io(error))) if error.kind() == io::ErrorKind::ConnectionRefused => {
+            Connectivity::Refused
+        }
+        Ok(Err(error)) => Connectivity::Failed(error.to_string()),
+        Err(_) => Connectivity::Failed(format!(
+            "no answer within {}",
+            humantime::format_duration(limit)
+        )),
+    }
+}
@@ -0,0 +1,55 @@
+use crate::postmaster;
+use sqlx::Connection;
+use sqlx::postgres::PgConnection;
+use std::path::Path;
+use tokio::time::{Duration, Instant, sleep};
+
+/// How long to wait for the server to pick up the new configuration.
+const APPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const APPLY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const CONF_LOAD_TIME_QUERY: &str = "SELECT pg_conf_load_time()::text";
+
+const PENDING_RESTART_QUERY: &str = "\
+SELECT name::text FROM pg_settings
+WHERE pending_restart
+ORDER BY name";
+
+/// Ask the postmaster for `data_dir` to re-read its configuration files and
+/// return the parameters whose new values only take effect after a restart.
+pub async fn reload(
+    pg_ctl: &Path,
+    data_dir: &Path,
+    url: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut connection = PgConnection::connect(url).await?;
+    let loaded_before = conf_load_time(&mut connection).await?;
+
+    postmaster::reload(pg_ctl, data_dir).await?;
+
+    // SIGHUP is handled asynchronously; a backend reloads its configuration
+    // before its next query, so poll this session until it has done so.
+    let deadline = Instant::now() + APPLY_TIMEOUT;
+    while conf_load_time(&mut connection).await? == loaded_before {
+        if Instant::now() >= deadline {
+            tracing::warn!(
+                "server did not report a configuration reload within {}",
+                humantime::format_duration(APPLY_TIMEOUT)
+            );
+            break;
+        }
+        sleep(APPLY_POLL_INTERVAL).await;
+    }
+
+    let pending = sqlx::query_scalar::<_, String>(PENDING_RESTART_QUERY)
+        .fetch_all(&mut connection)
+        .await?;
+    connection.close().await?;
+    Ok(pending)
+}
+
+async fn conf_load_time(connection: &mut PgConnection) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar::<_, String>(CONF_LOAD_TIME_QUERY)
+        .fetch_one(connection)
+        .await
+}
@@ -0,0 +1,160 @@
+//! `pgx reset`: empty a stopped cluster and initialize it again, keeping its
+//! configuration files. They are set aside in a sidecar directory first, so
+//! a reset that dies halfway can be finished by running it again.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Configuration kept across a reset; everything else in the data directory
+/// is deleted.
+const KEPT_FILES: [&str; 4] = [
+    "postgresql.conf",
+    "postgresql.auto.conf",
+    "pg_hba.conf",
+    "pg_ident.conf",
+];
+
+/// Copy the kept files from `data_dir` into `reset_dir`. The copy is staged
+/// under another name and renamed into place, so `reset_dir` only exists once
+/// it holds everything. An existing `reset_dir` is left alone: it belongs to
+/// an interrupted reset and `data_dir` may already be half emptied.
+pub fn save_config(data_dir: &Path, reset_dir: &Path) -> io::Result<()> {
+    if reset_dir.exists() {
+        return Ok(());
+    }
+
+    let mut staging = reset_dir.as_os_str().to_owned();
+    staging.push(".partial");
+    let staging = Path::new(&staging);
+    if staging.exists() {
+        fs::remove_dir_all(staging)?;
+    }
+    fs::create_dir_all(staging)?;
+    for name in KEPT_FILES {
+        let source = data_dir.join(name);
+        if source.exists()
+            && let Err(error) = fs::copy(&source, staging.join(name))
+        {
+            let _ = fs::remove_dir_all(staging);
+            return Err(error);
+        }
+    }
+    fs::rename(staging, reset_dir)
+}
+
+/// Delete everything inside `data_dir`, keeping the directory itself and
+/// with it the permissions pgx gave it.
+pub fn empty_dir(data_dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(data_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Put the files saved by [`save_config`] back over the ones initdb wrote,
+/// then remove `reset_dir`, which marks the reset as finished.
+pub fn restore_config(data_dir: &Path, reset_dir: &Path) -> io::Result<()> {
+    for name in KEPT_FILES {
+        let saved = reset_dir.join(name);
+        if saved.exists() {
+            fs::copy(&saved, data_dir.join(name))?;
+        }
+    }
+    fs::remove_dir_all(reset_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        fs::create_dir_all(data_dir.join("base/1")).unwrap();
+        fs::write(data_dir.join("base/1/16384"), "rows").unwrap();
+        fs::write(data_dir.join("PG_VERSION"), "17\n").unwrap();
+        fs::write(data_dir.join("postgresql.conf"), "port = 5433\n").unwrap();
+        fs::write(
+            data_dir.join("pg_hba.conf"),
+            "host all all 10.0.0.0/8 md5\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn configuration_survives_a_reset() {
+        let dir = cluster();
+        let data_dir = dir.path().join("data");
+        let reset_dir = dir.path().join("data.pgx-reset");
+
+        save_config(&data_dir, &reset_dir).unwrap();
+        empty_dir(&data_dir).unwrap();
+        assert!(data_dir.is_dir());
+        assert_eq!(fs::read_dir(&data_dir).unwrap().count(), 0);
+
+        // What initdb writes is replaced by the saved files.
+        fs::write(data_dir.join("postgresql.conf"), "# initdb\n").unwrap();
+        fs::write(data_dir.join("pg_ident.conf"), "# initdb\n").unwrap();
+        restore_config(&data_dir, &reset_dir).unwrap();
+
+        assert!(!reset_dir.exists());
+        assert_eq!(
+            fs::read_to_string(data_dir.join("postgresql.conf")).unwrap(),
+            "port = 5433\n"
+        );
+        assert_eq!(
+            fs::read_to_string(data_dir.join("pg_hba.conf")).unwrap(),
+            "host all all 10.0.0.0/8 md5\n"
+        );
+        // A file the cluster did not have keeps initdb's version.
+        assert_eq!(
+            fs::read_to_string(data_dir.join("pg_ident.conf")).unwrap(),
+            "# initdb\n"
+        );
+        assert!(!data_dir.join("base").exists());
+    }
+
+    #[test]
+    fn an_interrupted_reset_keeps_the_first_saved_configuration() {
+        let dir = cluster();
+        let data_dir = dir.path().join("data");
+        let reset_dir = dir.path().join("data.pgx-reset");
+        save_config(&data_dir, &reset_dir).unwrap();
+
+        // Interrupted after emptying: running again must not save the
+        // emptied directory over the configuration kept so far.
+        empty_dir(&data_dir).unwrap();
+        save_config(&data_dir, &reset_dir).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(reset_dir.join("postgresql.conf")).unwrap(),
+            "port = 5433\n"
+        );
+    }
+
+    #[test]
+    fn a_stale_partial_save_is_replaced() {
+        let dir = cluster();
+        let data_dir = dir.path().join("data");
+        let reset_dir = dir.path().join("data.pgx-reset");
+        let staging = dir.path().join("data.pgx-reset.partial");
+        fs::create_dir(&staging).unwrap();
+        fs::write(staging.join("postgresql.conf"), "half written").unwrap();
+
+        save_config(&data_dir, &reset_dir).unwrap();
+
+        assert!(!staging.exists());
+        assert_eq!(
+            fs::read_to_string(reset_dir.join("postgresql.conf")).unwrap(),
+            "port = 5433\n"
+        );
+        assert!(!reset_dir.join("pg_ident.conf").exists());
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Directory inside the data dir that the logging collector writes to.
+pub const LOG_DIRECTORY: &str = "log";
+/// Rotated server log files kept in `LOG_DIRECTORY`; older ones are deleted.
+const LOG_FILES_KEPT: usize = 5;
+const LOG_FILE_PREFIX: &str = "postgresql-";
+/// Bytes `last_lines` reads at a time, working back from the end of a log.
+const TAIL_CHUNK: u64 = 64 * 1024;
+
+/// Server settings that route the postmaster's output into size-rotated
+/// files under `LOG_DIRECTORY`.
+pub fn configuration() -> HashMap<String, String> {
+    [
+        ("logging_collector", "on"),
+        ("log_directory", LOG_DIRECTORY),
+        ("log_filename", "postgresql-%Y%m%d-%H%M%S.log"),
+        ("log_rotation_size", "10MB"),
+        ("log_rotation_age", "0"),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect()
+}
+
+/// Delete all but the newest `LOG_FILES_KEPT` server log files.
+pub fn prune(data_dir: &Path) -> io::Result<()> {
+    let log_dir = data_dir.join(LOG_DIRECTORY);
+    if !log_dir.is_dir() {
+        return Ok(());
+    }
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&log_dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(LOG_FILE_PREFIX))
+        })
+        .collect();
+    // The timestamped names sort chronologically.
+    files.sort();
+    let excess = files.len().saturating_sub(LOG_FILES_KEPT);
+    for path in files.into_iter().take(excess) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Follows whichever file the logging collector currently writes to, as
+/// named by `current_logfiles` in the data dir.
+pub struct LogTail {
+    data_dir: PathBuf,
+    current: Option<PathBuf>,
+    offset: u64,
+    partial: String,
+}
+
+impl LogTail {
+    pub fn new(data_dir: &Path) -> Self {
+        LogTail {
+            data_dir: data_dir.to_path_buf(),
+            current: None,
+            offset: 0,
+            partial: String::new(),
+        }
+    }
+
+    /// Follow `path` from its current end, so only lines written from now
+    /// on are printed.
+    pub fn from_end(data_dir: &Path, path: PathBuf) -> io::Result<Self> {
+        let offset = fs::metadata(&path)?.len();
+        Ok(LogTail {
+            data_dir: data_dir.to_path_buf(),
+            current: Some(path),
+            offset,
+            partial: String::new(),
+        })
+    }
+
+    /// Print the complete lines appended since the last call.
+    pub fn print_new_lines(&mut self) {
+        if let Err(error) = self.read_new_lines() {
+            tracing::debug!("cannot follow the server log: {error}");
+        }
+    }
+
+    fn read_new_lines(&mut self) -> io::Result<()> {
+        let Some(path) = current_log_file(&self.data_dir)? else {
+            return Ok(());
+        };
+        if self.current.as_ref() != Some(&path) {
+            self.current = Some(path.clone());
+            self.offset = 0;
+            self.partial.clear();
+            let _ = prune(&self.data_dir);
+        }
+
+        let mut file = fs::File::open(&path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut appended = Vec::new();
+        self.offset += file.read_to_end(&mut appended)? as u64;
+
+        self.partial.push_str(&String::from_utf8_lossy(&appended));
+        if let Some(end) = self.partial.rfind('\n') {
+            for line in self.partial[..end].lines() {
+                println!("{line}");
+            }
+            self.partial.drain(..=end);
+        }
+        Ok(())
+    }
+}
+
+/// The most recent line in the current server log that explains why the
+/// server went down, e.g. a PANIC or a backend killed by a signal.
+pub fn last_failure(data_dir: &Path) -> Option<String> {
+    let path = current_log_file(data_dir).ok()??;
+    let raw = fs::read(path).ok()?;
+    String::from_utf8_lossy(&raw)
+        .lines()
+        .rev()
+        .find(|line| {
+            [
+                "PANIC:",
+                "FATAL:",
+                "terminated by signal",
+                "exited with exit code",
+            ]
+            .iter()
+            .any(|marker| line.contains(marker))
+        })
+        .map(|line| line.trim().to_string())
+}
+
+/// The server log to show for `data_dir`: the file the logging collector
+/// writes to, else `recorded` (the daemon log file from the state), else the
+/// newest rotated file under `LOG_DIRECTORY`.
+pub fn log_file(data_dir: &Path, recorded: Option<&Path>) -> io::Result<Option<PathBuf>> {
+    if let Some(path) = current_log_file(data_dir)?
+        && path.is_file()
+    {
+        return Ok(Some(path));
+    }
+    if let Some(path) = recorded.filter(|path| path.is_file()) {
+        return Ok(Some(path.to_path_buf()));
+    }
+
+    let log_dir = data_dir.join(LOG_DIRECTORY);
+    if !log_dir.is_dir() {
+        return Ok(None);
+    }
+    Ok(fs::read_dir(&log_dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(LOG_FILE_PREFIX))
+        })
+        .max())
+}
+
+/// The last `count` lines of `path`, read backwards from the end in
+/// `TAIL_CHUNK`-sized steps. The daemon log is never rotated, so reading it
+/// whole could mean reading gigabytes to show a screenful.
+pub fn last_lines(path: &Path, count: usize) -> io::Result<Vec<String>> {
+    let mut file = fs::File::open(path)?;
+    let mut start = file.seek(SeekFrom::End(0))?;
+    let mut raw = Vec::new();
+    let mut newlines = 0;
+    // One newline more than `count` proves the first wanted line is whole.
+    while start > 0 && newlines <= count {
+        let step = start.min(TAIL_CHUNK);
+        start -= step;
+        file.seek(SeekFrom::Start(start))?;
+        let mut chunk = vec![0; step as usize];
+        file.read_exact(&mut chunk)?;
+        newlines += chunk.iter().filter(|&&byte| byte == b'\n').count();
+        chunk.append(&mut raw);
+        raw = chunk;
+    }
+
+    let text = String::from_utf8_lossy(&raw);
+    let lines: Vec<&str> = text.lines().collect();
+    let skip = lines.len().saturating_sub(count);
+    Ok(lines[skip..].iter().map(|line| line.to_string()).collect())
+}
+
+/// The file the logging collector currently writes to, as named by
+/// `current_logfiles`. Relative names are resolved against `data_dir`.
+fn current_log_file(data_dir: &Path) -> io::Result<Option<PathBuf>> {
+    let raw = match fs::read_to_string(data_dir.join("current_logfiles")) {
+        Ok(raw) => raw,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    // Lines look like `stderr log/postgresql-20250101-120000.log`.
+    Ok(raw
+        .lines()
+        .find_map(|line| line.strip_prefix("stderr "))
+        .map(|relative| data_dir.join(relative.trim())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_with(contents: &str) -> tempfile::NamedTempFile {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), contents).unwrap();
+        file
+    }
+
+    #[test]
+    fn last_lines_reads_back_across_chunks() {
+        // Several chunks' worth, so the wanted lines straddle a boundary.
+        let contents: String = (0..20_000).map(|line| format!("line {line}\n")).collect();
+        assert!(contents.len() as u64 > 3 * TAIL_CHUNK);
+        let log = log_with(&contents);
+
+        let lines = last_lines(log.path(), 3).unwrap();
+        assert_eq!(lines, ["line 19997", "line 19998", "line 19999"]);
+        let lines = last_lines(log.path(), 10_000).unwrap();
+        assert_eq!(lines.len(), 10_000);
+        assert_eq!(lines[0], "line 10000");
+    }
+
+    #[test]
+    fn last_lines_of_a_short_or_unterminated_log() {
+        let log = log_with("first\r\nsecond\nthird");
+        assert_eq!(last_lines(log.path(), 2).unwrap(), ["second", "third"]);
+        assert_eq!(
+            last_lines(log.path(), 50).unwrap(),
+            ["first", "second", "third"]
+        );
+        assert!(last_lines(log.path(), 0).unwrap().is_empty());
+        assert!(last_lines(log_with("").path(), 5).unwrap().is_empty());
+    }
+}
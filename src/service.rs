@@ -0,0 +1,90 @@
+//! Minimal Windows service host for `pgx start --windows-service`: reports
+//! the service as running and turns SCM stop and shutdown requests into a
+//! signal for the server loop.
+
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::{define_windows_service, service_dispatcher};
+
+type ServeResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+type Serve = Box<dyn FnOnce(oneshot::Receiver<()>) -> ServeResult + Send>;
+
+/// The name SCM dispatches to; a service process hosts only this one.
+const SERVICE_NAME: &str = "pgx";
+
+/// Handed from `run` to the dispatcher thread, which has no other way in.
+static SERVE: Mutex<Option<Serve>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Connect to the service control manager and run `serve` until it returns.
+/// `serve` receives a channel that fires when SCM asks the service to stop.
+/// Blocks the calling thread.
+pub fn run(
+    serve: impl FnOnce(oneshot::Receiver<()>) -> ServeResult + Send + 'static,
+) -> windows_service::Result<()> {
+    *SERVE.lock().unwrap_or_else(|error| error.into_inner()) = Some(Box::new(serve));
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(error) = run_service() {
+        tracing::error!("windows service failed: {error}");
+    }
+}
+
+fn run_service() -> ServeResult {
+    let serve = SERVE
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .take()
+        .ok_or("service started twice")?;
+
+    let (stop_sender, stop_receiver) = oneshot::channel();
+    let stop_sender = Mutex::new(Some(stop_sender));
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |event| match event {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                if let Some(sender) = stop_sender
+                    .lock()
+                    .unwrap_or_else(|error| error.into_inner())
+                    .take()
+                {
+                    let _ = sender.send(());
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+    let status = |state, accepted, exit_code| ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    };
+    status_handle.set_service_status(status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        0,
+    ))?;
+
+    let result = serve(stop_receiver);
+    let exit_code = if result.is_ok() { 0 } else { 1 };
+    status_handle.set_service_status(status(
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        exit_code,
+    ))?;
+    result
+}
@@ -0,0 +1,615 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+const METADATA_FILE: &str = "snapshot.json";
+/// Every snapshot's metadata and size, so listing does not walk each copy.
+/// Snapshots taken before it existed are still found by their directories.
+const MANIFEST_FILE: &str = "manifest.json";
+const DATA_DIR: &str = "data";
+/// Copied alongside the data so a restored cluster keeps a matching password.
+const PASSWORD_FILE: &str = "password";
+/// Runtime files the postmaster owns; copying them would make a snapshot look live.
+const SKIPPED_FILES: [&str; 2] = ["postmaster.pid", "postmaster.opts"];
+
+/// Recorded next to each snapshot's data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotMetadata {
+    pub pg_version: Option<String>,
+    /// RFC 3339 timestamp.
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+    #[serde(flatten)]
+    metadata: SnapshotMetadata,
+    size: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    snapshots: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    fn read(root: &Path) -> io::Result<Self> {
+        let path = root.join(MANIFEST_FILE);
+        let raw = match fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+        serde_json::from_str(&raw)
+            .map_err(|error| io::Error::other(format!("{}: {error}", path.display())))
+    }
+
+    /// Replace the manifest through a staging file, so readers never see half of it.
+    fn write(&self, root: &Path) -> io::Result<()> {
+        let path = root.join(MANIFEST_FILE);
+        let staging = root.join(format!(".{MANIFEST_FILE}.tmp"));
+        let raw = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(&staging, raw)?;
+        fs::rename(&staging, &path)
+    }
+}
+
+pub struct SnapshotInfo {
+    pub name: String,
+    pub metadata: Option<SnapshotMetadata>,
+    pub size: u64,
+}
+
+/// Copy a stopped cluster into `root/name`. The copy is staged under a hidden
+/// name and renamed into place, so an interrupted snapshot never shows up.
+pub fn create(
+    data_dir: &Path,
+    password_file: &Path,
+    root: &Path,
+    name: &str,
+    pg_version: Option<String>,
+) -> io::Result<SnapshotInfo> {
+    validate_name(name)?;
+    let target = root.join(name);
+    if target.exists() {
+        return Err(io::Error::other(format!(
+            "snapshot '{name}' already exists in {}",
+            root.display()
+        )));
+    }
+
+    fs::create_dir_all(root)?;
+    let staging = root.join(format!(".{name}.partial"));
+    remove_if_exists(&staging)?;
+    fs::create_dir(&staging)?;
+
+    let metadata = SnapshotMetadata {
+        pg_version,
+        created_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+    };
+    let staged = copy_tree(data_dir, &staging.join(DATA_DIR))
+        .and_then(|()| copy_password_file(password_file, &staging.join(PASSWORD_FILE)))
+        .and_then(|()| {
+            let raw = serde_json::to_string_pretty(&metadata).map_err(io::Error::other)?;
+            fs::write(staging.join(METADATA_FILE), raw)
+        });
+    if let Err(error) = staged {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(error);
+    }
+    fs::rename(&staging, &target)?;
+
+    let size = tree_size(&target.join(DATA_DIR))?;
+    let mut manifest = Manifest::read(root)?;
+    manifest.snapshots.insert(
+        name.to_string(),
+        ManifestEntry {
+            metadata: metadata.clone(),
+            size,
+        },
+    );
+    manifest.write(root)?;
+
+    Ok(SnapshotInfo {
+        name: name.to_string(),
+        size,
+        metadata: Some(metadata),
+    })
+}
+
+/// Delete snapshot `name` and its manifest entry.
+pub fn delete(root: &Path, name: &str) -> io::Result<()> {
+    validate_name(name)?;
+    let target = root.join(name);
+    if !target.is_dir() {
+        return Err(io::Error::other(format!(
+            "no snapshot named '{name}' in {}; list them with `pgx snapshot list`",
+            root.display()
+        )));
+    }
+    // Out of the manifest first: a half-deleted copy must not be listed.
+    let mut manifest = Manifest::read(root)?;
+    if manifest.snapshots.remove(name).is_some() {
+        manifest.write(root)?;
+    }
+    fs::remove_dir_all(target)
+}
+
+/// Every completed snapshot under `root`, oldest first.
+pub fn list(root: &Path) -> io::Result<Vec<SnapshotInfo>> {
+    if !root.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut manifest = Manifest::read(root)?;
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(recorded) = manifest.snapshots.remove(&name) {
+            snapshots.push(SnapshotInfo {
+                name,
+                metadata: Some(recorded.metadata),
+                size: recorded.size,
+            });
+            continue;
+        }
+        let metadata = fs::read_to_string(entry.path().join(METADATA_FILE))
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok());
+        snapshots.push(SnapshotInfo {
+            name,
+            metadata,
+            size: tree_size(&entry.path().join(DATA_DIR))?,
+        });
+    }
+
+    snapshots.sort_by(|left, right| {
+        let created_at = |info: &SnapshotInfo| {
+            info.metadata
+                .as_ref()
+                .map(|metadata| metadata.created_at.clone())
+        };
+        created_at(left)
+            .cmp(&created_at(right))
+            .then_with(|| left.name.cmp(&right.name))
+    });
+    Ok(snapshots)
+}
+
+/// Replace the contents of `data_dir` with snapshot `name`.
+///
+/// The snapshot is first copied next to `data_dir` (so the final step never
+/// crosses devices), then swapped in with two renames. If a previous restore
+/// was interrupted between those renames, the original data dir is put back
+/// before anything else happens.
+pub fn restore(
+    data_dir: &Path,
+    password_file: &Path,
+    root: &Path,
+    name: &str,
+    staging: &Path,
+    replaced: &Path,
+) -> io::Result<SnapshotMetadata> {
+    validate_name(name)?;
+    let source = root.join(name);
+    if !source.join(DATA_DIR).is_dir() {
+        return Err(io::Error::other(format!(
+            "no snapshot named '{name}' in {}; list them with `pgx snapshot list`",
+            root.display()
+        )));
+    }
+    let metadata = fs::read_to_string(source.join(METADATA_FILE))
+        .map_err(|error| io::Error::other(format!("snapshot '{name}' has no metadata: {error}")))?;
+    let metadata: SnapshotMetadata = serde_json::from_str(&metadata).map_err(io::Error::other)?;
+
+    recover_interrupted_restore(data_dir, replaced)?;
+    remove_if_exists(staging)?;
+    if let Err(error) = copy_tree(&source.join(DATA_DIR), staging) {
+        let _ = fs::remove_dir_all(staging);
+        return Err(error);
+    }
+
+    if data_dir.exists() {
+        fs::rename(data_dir, replaced)?;
+    }
+    fs::rename(staging, data_dir)?;
+    remove_if_exists(replaced)?;
+
+    let snapshot_password = source.join(PASSWORD_FILE);
+    if snapshot_password.exists() {
+        copy_password_file(&snapshot_password, password_file)?;
+    }
+    Ok(metadata)
+}
+
+fn recover_interrupted_restore(data_dir: &Path, replaced: &Path) -> io::Result<()> {
+    if !replaced.exists() {
+        return Ok(());
+    }
+
+    if data_dir.exists() {
+        // The swap completed but cleanup did not.
+        fs::remove_dir_all(replaced)
+    } else {
+        tracing::warn!(
+            "an earlier restore was interrupted; putting back {}",
+            data_dir.display()
+        );
+        fs::rename(replaced, data_dir)
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+fn validate_name(name: &str) -> io::Result<()> {
+    let is_valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if is_valid {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "invalid snapshot name '{name}'; use letters, digits, '-', '_' and '.'"
+        )))
+    }
+}
+
+/// Recursively copy `source` to `target`, preserving file and directory
+/// permissions (PostgreSQL refuses to start on a group-writable data dir).
+pub fn copy_tree(source: &Path, target: &Path) -> io::Result<()> {
+    fs::create_dir(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if SKIPPED_FILES.iter().any(|skipped| file_name == *skipped) {
+            continue;
+        }
+
+        let from = entry.path();
+        let to = target.join(&file_name);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_tree(&from, &to)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&from, &to)?;
+        } else {
+            copy_file(&from, &to)?;
+        }
+    }
+    fs::set_permissions(target, fs::metadata(source)?.permissions())
+}
+
+/// Copy a file, sharing its blocks with the original where the filesystem
+/// can (Btrfs and XFS here; `fs::copy` already clones on APFS), so copies
+/// of a large cluster take no time or space until either side changes.
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    if reflink(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).map(|_| ())
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    // _IOW(0x94, 9, int) from linux/fs.h.
+    const FICLONE: u32 = 0x4004_9409;
+
+    let source = fs::File::open(from)?;
+    let target = fs::File::create_new(to)?;
+    // SAFETY: FICLONE takes the source descriptor by value and reads no
+    // memory; both descriptors are open for the duration of the call.
+    if unsafe { libc::ioctl(target.as_raw_fd(), FICLONE as _, source.as_raw_fd()) } != 0 {
+        let error = io::Error::last_os_error();
+        drop(target);
+        let _ = fs::remove_file(to);
+        return Err(error);
+    }
+    target.set_permissions(source.metadata()?.permissions())
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to).map(|_| ())
+}
+
+fn copy_password_file(from: &Path, to: &Path) -> io::Result<()> {
+    if from.exists() {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+pub fn tree_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += tree_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A temporary directory on a different filesystem than `near`, where
+    /// this machine has one (`/dev/shm` is tmpfs on most Linux systems).
+    #[cfg(unix)]
+    fn other_device(near: &Path) -> Option<tempfile::TempDir> {
+        use std::os::unix::fs::MetadataExt;
+
+        let shm = Path::new("/dev/shm");
+        let device = |path: &Path| fs::metadata(path).map(|metadata| metadata.dev()).ok();
+        if device(shm)? == device(near)? {
+            return None;
+        }
+        tempfile::tempdir_in(shm).ok()
+    }
+
+    fn cluster_in(parent: &Path) -> PathBuf {
+        let data_dir = parent.join("data");
+        fs::create_dir_all(data_dir.join("base/1")).unwrap();
+        fs::write(data_dir.join("PG_VERSION"), "17\n").unwrap();
+        fs::write(data_dir.join("base/1/16384"), vec![7; 64 * 1024]).unwrap();
+        fs::write(data_dir.join("postmaster.pid"), "4242\n").unwrap();
+        data_dir
+    }
+
+    #[test]
+    fn recovery_puts_back_a_data_dir_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        let replaced = dir.path().join(".data.replaced");
+        fs::create_dir(&replaced).unwrap();
+        fs::write(replaced.join("PG_VERSION"), "17\n").unwrap();
+
+        recover_interrupted_restore(&data_dir, &replaced).unwrap();
+
+        assert!(!replaced.exists());
+        assert_eq!(
+            fs::read_to_string(data_dir.join("PG_VERSION")).unwrap(),
+            "17\n"
+        );
+    }
+
+    #[test]
+    fn recovery_finishes_the_cleanup_of_a_completed_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        let replaced = dir.path().join(".data.replaced");
+        fs::create_dir(&data_dir).unwrap();
+        fs::write(data_dir.join("PG_VERSION"), "16\n").unwrap();
+        fs::create_dir(&replaced).unwrap();
+        fs::write(replaced.join("PG_VERSION"), "17\n").unwrap();
+
+        recover_interrupted_restore(&data_dir, &replaced).unwrap();
+
+        assert!(!replaced.exists());
+        assert_eq!(
+            fs::read_to_string(data_dir.join("PG_VERSION")).unwrap(),
+            "16\n"
+        );
+        // With nothing moved aside there is nothing to do.
+        recover_interrupted_restore(&data_dir, &replaced).unwrap();
+        assert!(data_dir.exists());
+    }
+
+    #[test]
+    fn restore_recovers_an_interrupted_swap_before_restoring() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = cluster_in(dir.path());
+        let root = dir.path().join("data.pgx-snapshots");
+        let password_file = dir.path().join("data.pgx-password");
+        create(&data_dir, &password_file, &root, "seeded", None).unwrap();
+
+        // Interrupted between the two renames: only the moved-aside copy is left.
+        let replaced = dir.path().join(".data.replaced");
+        fs::rename(&data_dir, &replaced).unwrap();
+        let staging = dir.path().join(".data.restoring");
+        restore(
+            &data_dir,
+            &password_file,
+            &root,
+            "seeded",
+            &staging,
+            &replaced,
+        )
+        .unwrap();
+
+        assert!(!replaced.exists());
+        assert!(!staging.exists());
+        assert_eq!(
+            fs::read(data_dir.join("base/1/16384")).unwrap(),
+            vec![7; 64 * 1024]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn snapshots_copy_across_filesystems() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let Some(store) = other_device(dir.path()) else {
+            return;
+        };
+        let data_dir = cluster_in(dir.path());
+        fs::set_permissions(&data_dir, fs::Permissions::from_mode(0o700)).unwrap();
+        fs::set_permissions(
+            data_dir.join("PG_VERSION"),
+            fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+        let password_file = dir.path().join("data.pgx-password");
+        fs::write(&password_file, "secret").unwrap();
+        let root = store.path().join("snapshots");
+
+        create(
+            &data_dir,
+            &password_file,
+            &root,
+            "seeded",
+            Some("17.2".into()),
+        )
+        .unwrap();
+        fs::write(data_dir.join("base/1/16384"), b"changed").unwrap();
+        fs::write(&password_file, "changed").unwrap();
+        let metadata = restore(
+            &data_dir,
+            &password_file,
+            &root,
+            "seeded",
+            &dir.path().join(".data.restoring"),
+            &dir.path().join(".data.replaced"),
+        )
+        .unwrap();
+
+        assert_eq!(metadata.pg_version.as_deref(), Some("17.2"));
+        assert_eq!(
+            fs::read(data_dir.join("base/1/16384")).unwrap(),
+            vec![7; 64 * 1024]
+        );
+        assert_eq!(fs::read_to_string(&password_file).unwrap(), "secret");
+        assert!(!data_dir.join("postmaster.pid").exists());
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&data_dir), 0o700);
+        assert_eq!(mode(&data_dir.join("PG_VERSION")), 0o600);
+    }
+
+    #[test]
+    fn the_manifest_records_what_list_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = cluster_in(dir.path());
+        let root = dir.path().join("data.pgx-snapshots");
+        let password_file = dir.path().join("data.pgx-password");
+        let created = create(
+            &data_dir,
+            &password_file,
+            &root,
+            "seeded",
+            Some("17.2".into()),
+        )
+        .unwrap();
+
+        let manifest = Manifest::read(&root).unwrap();
+        let entry = &manifest.snapshots["seeded"];
+        assert_eq!(entry.size, created.size);
+        assert_eq!(entry.metadata.pg_version.as_deref(), Some("17.2"));
+        manifest.write(&root).unwrap();
+        assert_eq!(
+            serde_json::to_value(Manifest::read(&root).unwrap()).unwrap(),
+            serde_json::to_value(&manifest).unwrap()
+        );
+
+        // Listing trusts the manifest over walking the copy.
+        fs::write(root.join("seeded/data/extra"), vec![0; 4096]).unwrap();
+        let listed = list(&root).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "seeded");
+        assert_eq!(listed[0].size, created.size);
+        assert!(!root.join(format!(".{MANIFEST_FILE}.tmp")).exists());
+    }
+
+    #[test]
+    fn deleting_a_missing_snapshot_fails_and_keeps_the_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = cluster_in(dir.path());
+        let root = dir.path().join("data.pgx-snapshots");
+        let password_file = dir.path().join("data.pgx-password");
+        create(&data_dir, &password_file, &root, "seeded", None).unwrap();
+
+        let error = delete(&root, "missing").unwrap_err();
+        assert!(error.to_string().contains("no snapshot named 'missing'"));
+        assert!(delete(&dir.path().join("nowhere"), "seeded").is_err());
+        assert!(
+            Manifest::read(&root)
+                .unwrap()
+                .snapshots
+                .contains_key("seeded")
+        );
+
+        delete(&root, "seeded").unwrap();
+        assert!(list(&root).unwrap().is_empty());
+        assert!(Manifest::read(&root).unwrap().snapshots.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn a_failed_reflink_leaves_no_file_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("copy");
+
+        // FICLONE rejects a directory as its source.
+        assert!(reflink(dir.path(), &target).is_err());
+        assert!(!target.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn files_are_copied_when_reflink_is_refused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        // FICLONE never works across filesystems, whatever they support.
+        let Some(other) = other_device(dir.path()) else {
+            return;
+        };
+        let from = dir.path().join("16384");
+        fs::write(&from, vec![7; 64 * 1024]).unwrap();
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o600)).unwrap();
+        let to = other.path().join("16384");
+        assert!(reflink(&from, &to).is_err());
+
+        copy_file(&from, &to).unwrap();
+
+        assert_eq!(fs::read(&to).unwrap(), vec![7; 64 * 1024]);
+        assert_eq!(
+            fs::metadata(&to).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+}
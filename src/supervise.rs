@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use tokio::time::{Duration, Instant};
+
+/// Wait before the first restart; doubled for each further restart in the window.
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Limits `--supervise` to `max` restarts within a sliding `window`, so a
+/// server that crashes on every start is eventually given up on.
+pub struct RestartBudget {
+    max: u32,
+    window: Duration,
+    recent: VecDeque<Instant>,
+}
+
+impl RestartBudget {
+    pub fn new(max: u32, window: Duration) -> Self {
+        RestartBudget {
+            max,
+            window,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Record a restart and return how long to back off before it, or `None`
+    /// once `max` restarts have happened within the window.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        self.next_delay_at(Instant::now())
+    }
+
+    fn next_delay_at(&mut self, now: Instant) -> Option<Duration> {
+        while self
+            .recent
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) > self.window)
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.max as usize {
+            return None;
+        }
+
+        let delay = RESTART_BACKOFF
+            .saturating_mul(1 << self.recent.len().min(16))
+            .min(MAX_RESTART_BACKOFF);
+        self.recent.push_back(now);
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let start = Instant::now();
+        let mut budget = RestartBudget::new(10, Duration::from_secs(3600));
+        let delays: Vec<_> = (0..8)
+            .map(|restart| budget.next_delay_at(start + Duration::from_secs(restart)))
+            .map(|delay| delay.unwrap().as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    #[test]
+    fn the_budget_runs_out_within_the_window() {
+        let start = Instant::now();
+        let mut budget = RestartBudget::new(3, MINUTE);
+        for second in 0..3 {
+            assert!(
+                budget
+                    .next_delay_at(start + Duration::from_secs(second))
+                    .is_some()
+            );
+        }
+        assert_eq!(budget.next_delay_at(start + Duration::from_secs(10)), None);
+        // Refusing does not count as a restart.
+        assert_eq!(budget.next_delay_at(start + Duration::from_secs(20)), None);
+    }
+
+    #[test]
+    fn restarts_older_than_the_window_are_forgotten() {
+        let start = Instant::now();
+        let mut budget = RestartBudget::new(2, MINUTE);
+        budget.next_delay_at(start).unwrap();
+        budget
+            .next_delay_at(start + Duration::from_secs(30))
+            .unwrap();
+        assert_eq!(budget.next_delay_at(start + MINUTE), None);
+
+        // The first restart has left the window; the backoff follows the one left.
+        let later = start + MINUTE + Duration::from_secs(1);
+        assert_eq!(budget.next_delay_at(later), Some(Duration::from_secs(2)));
+        // Once every restart is older than the window, it starts over.
+        let much_later = later + 2 * MINUTE;
+        assert_eq!(budget.next_delay_at(much_later), Some(RESTART_BACKOFF));
+    }
+}
@@ -0,0 +1,161 @@
+use clap::ValueEnum;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Value of the unit's `Restart=` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RestartPolicy {
+    No,
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    fn as_str(self) -> &'static str {
+        match self {
+            RestartPolicy::No => "no",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+        }
+    }
+}
+
+/// `pgx-<name>.service`, with `name` reduced to characters systemd accepts.
+pub fn unit_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("pgx-{name}.service")
+}
+
+/// A user unit that runs `pgx start` in the foreground for `data_dir`. Paths
+/// are absolute so the unit does not depend on the login shell's environment.
+pub fn unit_text(
+    pgx: &Path,
+    data_dir: &Path,
+    restart: RestartPolicy,
+    environment: &[(&str, String)],
+) -> String {
+    let pgx = exec_arg(&pgx.to_string_lossy());
+    let data_dir_arg = exec_arg(&data_dir.to_string_lossy());
+
+    let mut unit = String::new();
+    let _ = writeln!(unit, "[Unit]");
+    let _ = writeln!(
+        unit,
+        "Description=PostgreSQL for {} (pgx)",
+        data_dir.display().to_string().replace('%', "%%")
+    );
+    let _ = writeln!(unit, "After=network.target");
+    let _ = writeln!(unit);
+    let _ = writeln!(unit, "[Service]");
+    let _ = writeln!(unit, "Type=exec");
+    for (key, value) in environment {
+        let _ = writeln!(unit, "Environment={}", quote(&format!("{key}={value}")));
+    }
+    let _ = writeln!(unit, "ExecStart={pgx} start --data-dir {data_dir_arg}");
+    let _ = writeln!(unit, "ExecStop={pgx} stop --data-dir {data_dir_arg}");
+    let _ = writeln!(unit, "Restart={}", restart.as_str());
+    let _ = writeln!(unit, "TimeoutStopSec=60");
+    let _ = writeln!(unit);
+    let _ = writeln!(unit, "[Install]");
+    let _ = writeln!(unit, "WantedBy=default.target");
+    unit
+}
+
+/// `$XDG_CONFIG_HOME/systemd/user`, falling back to `~/.config/systemd/user`.
+pub fn user_unit_dir() -> io::Result<PathBuf> {
+    if let Some(config) = std::env::var_os("XDG_CONFIG_HOME").filter(|config| !config.is_empty()) {
+        return Ok(PathBuf::from(config).join("systemd").join("user"));
+    }
+    let home = std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .ok_or_else(|| {
+            io::Error::other("HOME is not set; cannot locate the systemd user unit directory")
+        })?;
+    Ok(PathBuf::from(home)
+        .join(".config")
+        .join("systemd")
+        .join("user"))
+}
+
+/// Run `systemctl --user` with `args`.
+pub fn systemctl(args: &[&str]) -> io::Result<()> {
+    let output = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .output()
+        .map_err(|error| io::Error::other(format!("cannot run systemctl: {error}")))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(io::Error::other(format!(
+        "systemctl --user {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    )))
+}
+
+/// Quote a value for a unit file: double quotes when needed, with `%`
+/// escaped so systemd does not expand it as a specifier.
+fn quote(value: &str) -> String {
+    let escaped = value.replace('%', "%%");
+    if escaped.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+
+/// Quote an `ExecStart=`/`ExecStop=` argument. Command lines also expand
+/// `$NAME`, so `$` is doubled as well as `%`, and every argument is quoted
+/// rather than guessing which characters systemd splits on.
+fn exec_arg(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{escaped}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_for_a_path_with_spaces_and_specifiers() {
+        let unit = unit_text(
+            Path::new("/opt/my tools/pgx"),
+            Path::new("/home/me/100% $HOME/db"),
+            RestartPolicy::OnFailure,
+            &[("PGX_PORT", "5433".to_string())],
+        );
+        let lines: Vec<&str> = unit.lines().collect();
+
+        assert!(lines.contains(&"Description=PostgreSQL for /home/me/100%% $HOME/db (pgx)"));
+        assert!(lines.contains(&"Environment=PGX_PORT=5433"));
+        assert!(lines.contains(
+            &r#"ExecStart="/opt/my tools/pgx" start --data-dir "/home/me/100%% $$HOME/db""#
+        ));
+        assert!(lines.contains(
+            &r#"ExecStop="/opt/my tools/pgx" stop --data-dir "/home/me/100%% $$HOME/db""#
+        ));
+        assert!(lines.contains(&"Restart=on-failure"));
+    }
+
+    #[test]
+    fn exec_arguments_escape_quotes_and_backslashes() {
+        assert_eq!(exec_arg("/srv/db"), r#""/srv/db""#);
+        assert_eq!(exec_arg(r#"a"b\c"#), r#""a\"b\\c""#);
+    }
+}
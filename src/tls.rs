@@ -0,0 +1,66 @@
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const CERT_FILE: &str = "server.crt";
+const KEY_FILE: &str = "server.key";
+
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// The server certificate and key in `dir`, generating a self-signed pair for
+/// `hosts` the first time. Existing files are reused so the fingerprint stays
+/// stable across restarts.
+pub fn ensure(dir: &Path, hosts: &[String]) -> io::Result<TlsFiles> {
+    let files = TlsFiles {
+        cert: dir.join(CERT_FILE),
+        key: dir.join(KEY_FILE),
+    };
+    if files.cert.exists() && files.key.exists() {
+        return Ok(files);
+    }
+
+    fs::create_dir_all(dir)?;
+    let mut params = CertificateParams::new(hosts.to_vec()).map_err(io::Error::other)?;
+    // Marked as a CA so clients can pin it as their root certificate.
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let key_pair = KeyPair::generate().map_err(io::Error::other)?;
+    let cert = params.self_signed(&key_pair).map_err(io::Error::other)?;
+
+    write_private(&files.key, &key_pair.serialize_pem())?;
+    fs::write(&files.cert, cert.pem())?;
+    tracing::info!("generated a self-signed certificate in {}", dir.display());
+    Ok(files)
+}
+
+/// Server settings that enable TLS with `files`.
+pub fn configuration(files: &TlsFiles) -> [(String, String); 3] {
+    [
+        ("ssl".to_string(), "on".to_string()),
+        (
+            "ssl_cert_file".to_string(),
+            files.cert.to_string_lossy().to_string(),
+        ),
+        (
+            "ssl_key_file".to_string(),
+            files.key.to_string_lossy().to_string(),
+        ),
+    ]
+}
+
+/// PostgreSQL refuses a key file that others can read.
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::write(path, contents)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
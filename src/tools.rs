@@ -0,0 +1,193 @@
+use postgresql_embedded::Settings;
+use semver::{Version, VersionReq};
+use std::env::consts::EXE_SUFFIX;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use tokio::process::Child;
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+/// Locate the `bin` directory of the newest installed PostgreSQL build that
+/// satisfies the version requirement in `settings`.
+pub fn binary_dir(
+    settings: &Settings,
+) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    let installed = installations(&settings.installation_dir)
+        .into_iter()
+        .filter(|(version, _)| settings.version.matches(version))
+        .max_by(|(left, _), (right, _)| left.cmp(right));
+
+    match installed {
+        Some((_, installation)) => Ok(installation.join("bin")),
+        None => Err(io::Error::other(format!(
+            "no PostgreSQL installation matching {} found in {}; run pgx start first",
+            settings.version,
+            settings.installation_dir.display(),
+        ))
+        .into()),
+    }
+}
+
+/// Every PostgreSQL build under `installation_dir`, identified by a directory
+/// named after its exact version that contains a `bin` directory.
+pub fn installations(installation_dir: &Path) -> Vec<(Version, PathBuf)> {
+    fs::read_dir(installation_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let version = Version::parse(entry.file_name().to_str()?).ok()?;
+            let path = entry.path();
+            path.join("bin").is_dir().then_some((version, path))
+        })
+        .collect()
+}
+
+/// Version-named entries under `installation_dir` that satisfy `requirement`,
+/// complete or not. Used to find partial extractions after a failed download.
+pub fn installation_candidates(installation_dir: &Path, requirement: &VersionReq) -> Vec<PathBuf> {
+    fs::read_dir(installation_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .and_then(|name| Version::parse(name).ok())
+                .is_some_and(|version| requirement.matches(&version))
+        })
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// Names of the executables shipped in `binary_dir`, without platform suffixes.
+pub fn available_tools(binary_dir: &Path) -> io::Result<Vec<String>> {
+    let mut tools = Vec::new();
+    for entry in fs::read_dir(binary_dir)? {
+        let path = entry?.path();
+        if !is_executable(&path) {
+            continue;
+        }
+        let Some(name) = path.file_name().and_then(OsStr::to_str) else {
+            continue;
+        };
+        tools.push(name.strip_suffix(EXE_SUFFIX).unwrap_or(name).to_string());
+    }
+
+    tools.sort();
+    Ok(tools)
+}
+
+/// Absolute path of a bundled tool such as `pg_dump` or `psql`.
+pub fn tool_path(
+    binary_dir: &Path,
+    tool: &str,
+) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    let is_plain_name = !tool.is_empty() && Path::new(tool).file_name() == Some(OsStr::new(tool));
+    let path = binary_dir.join(format!("{tool}{EXE_SUFFIX}"));
+    if is_plain_name && is_executable(&path) {
+        return Ok(path);
+    }
+
+    let available = available_tools(binary_dir)?;
+    Err(io::Error::other(format!(
+        "unknown tool '{tool}'; available tools: {}",
+        available.join(", ")
+    ))
+    .into())
+}
+
+/// Replace the current process with `command`, returning only if that fails.
+#[cfg(unix)]
+pub fn exec(mut command: Command) -> io::Error {
+    use std::os::unix::process::CommandExt;
+
+    command.exec()
+}
+
+/// Run `command` to completion and exit with its status code.
+#[cfg(not(unix))]
+pub fn exec(mut command: Command) -> io::Error {
+    match command.status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(error) => error,
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exe"))
+}
+
+/// Wait for `child` to exit while pgx stays alive to forward termination
+/// signals to it.
+#[cfg(unix)]
+pub async fn wait_forwarding_signals(child: &mut Child) -> io::Result<ExitStatus> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+
+    loop {
+        tokio::select! {
+            status = child.wait() => return status,
+            // Ctrl-C is delivered to the whole foreground process group, so the
+            // child has already received it; pgx only needs to survive it.
+            _ = sigint.recv() => {}
+            _ = sigterm.recv() => forward_signal(child, libc::SIGTERM),
+            _ = sighup.recv() => forward_signal(child, libc::SIGHUP),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn wait_forwarding_signals(child: &mut Child) -> io::Result<ExitStatus> {
+    loop {
+        tokio::select! {
+            status = child.wait() => return status,
+            // The console delivers Ctrl-C to the child as well.
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+}
+
+#[cfg(unix)]
+fn forward_signal(child: &Child, signal: libc::c_int) {
+    if let Some(pid) = child.id() {
+        // SAFETY: kill(2) has no memory-safety preconditions.
+        unsafe {
+            libc::kill(pid as libc::pid_t, signal);
+        }
+    }
+}
+
+/// Exit code to propagate for a finished child, using the shell convention of
+/// 128 + signal number for children killed by a signal.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    status.code().unwrap_or(1)
+}
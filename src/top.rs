@@ -0,0 +1,275 @@
+//! `pgx top`: a periodically refreshed summary of server activity built from
+//! `pg_stat_database`, `pg_stat_activity` and `pg_database_size()`.
+
+use sqlx::postgres::PgConnection;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Characters of the longest-running query that are shown.
+const QUERY_PREVIEW_CHARS: usize = 60;
+
+/// One round of statistics. Counters are cumulative, so rates need two.
+pub struct Sample {
+    pub taken_at: Instant,
+    pub transactions: i64,
+    pub blocks_hit: i64,
+    pub blocks_read: i64,
+    pub active: i64,
+    pub idle: i64,
+    pub idle_in_transaction: i64,
+    pub longest: Option<LongestQuery>,
+    pub databases: Vec<(String, i64)>,
+}
+
+pub struct LongestQuery {
+    pub pid: i32,
+    pub database: Option<String>,
+    pub seconds: f64,
+    pub query: String,
+}
+
+pub async fn sample(connection: &mut PgConnection) -> Result<Sample, sqlx::Error> {
+    let (transactions, blocks_hit, blocks_read) = sqlx::query_as::<_, (i64, i64, i64)>(
+        "SELECT coalesce(sum(xact_commit + xact_rollback), 0)::bigint, \
+         coalesce(sum(blks_hit), 0)::bigint, coalesce(sum(blks_read), 0)::bigint \
+         FROM pg_stat_database",
+    )
+    .fetch_one(&mut *connection)
+    .await?;
+
+    // Our own backend is always active; leave it out.
+    let (active, idle, idle_in_transaction) = sqlx::query_as::<_, (i64, i64, i64)>(
+        "SELECT count(*) FILTER (WHERE state = 'active'), \
+         count(*) FILTER (WHERE state = 'idle'), \
+         count(*) FILTER (WHERE state LIKE 'idle in transaction%') \
+         FROM pg_stat_activity \
+         WHERE backend_type = 'client backend' AND pid <> pg_backend_pid()",
+    )
+    .fetch_one(&mut *connection)
+    .await?;
+
+    let longest = sqlx::query_as::<_, (i32, Option<String>, f64, Option<String>)>(
+        "SELECT pid, datname::text, extract(epoch FROM now() - query_start)::float8, query \
+         FROM pg_stat_activity \
+         WHERE state = 'active' AND backend_type = 'client backend' \
+         AND pid <> pg_backend_pid() AND query_start IS NOT NULL \
+         ORDER BY query_start LIMIT 1",
+    )
+    .fetch_optional(&mut *connection)
+    .await?
+    .map(|(pid, database, seconds, query)| LongestQuery {
+        pid,
+        database,
+        seconds,
+        query: query.unwrap_or_default(),
+    });
+
+    let databases = sqlx::query_as::<_, (String, i64)>(
+        "SELECT datname::text, pg_database_size(oid) FROM pg_database \
+         WHERE NOT datistemplate ORDER BY datname",
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+
+    Ok(Sample {
+        taken_at: Instant::now(),
+        transactions,
+        blocks_hit,
+        blocks_read,
+        active,
+        idle,
+        idle_in_transaction,
+        longest,
+        databases,
+    })
+}
+
+/// Format `current` as a block of text. Transactions per second need the
+/// `previous` sample and show as `-` on the first one.
+pub fn render(current: &Sample, previous: Option<&Sample>) -> String {
+    let tps = previous
+        .map(|previous| {
+            let elapsed = current
+                .taken_at
+                .duration_since(previous.taken_at)
+                .max(Duration::from_millis(1));
+            let committed = (current.transactions - previous.transactions).max(0);
+            format!("{:.1}", committed as f64 / elapsed.as_secs_f64())
+        })
+        .unwrap_or_else(|| "-".to_string());
+    let blocks = current.blocks_hit + current.blocks_read;
+    let hit_ratio = if blocks == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.2}%", current.blocks_hit as f64 * 100.0 / blocks as f64)
+    };
+
+    let mut text = String::new();
+    let _ = writeln!(text, "{:<22} {tps}", "transactions/s");
+    let _ = writeln!(
+        text,
+        "{:<22} {} active, {} idle, {} idle in transaction",
+        "connections", current.active, current.idle, current.idle_in_transaction
+    );
+    let _ = writeln!(text, "{:<22} {hit_ratio}", "cache hit ratio");
+    match &current.longest {
+        Some(longest) => {
+            let query = longest
+                .query
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            let preview: String = query.chars().take(QUERY_PREVIEW_CHARS).collect();
+            let _ = writeln!(
+                text,
+                "{:<22} {:.1}s pid {} on {}: {preview}",
+                "longest query",
+                longest.seconds,
+                longest.pid,
+                longest.database.as_deref().unwrap_or("-"),
+            );
+        }
+        None => {
+            let _ = writeln!(text, "{:<22} -", "longest query");
+        }
+    }
+    let _ = writeln!(text, "databases");
+    for (name, size) in &current.databases {
+        let _ = writeln!(
+            text,
+            "  {name:<20} {}",
+            crate::snapshot::format_size((*size).max(0) as u64)
+        );
+    }
+    text
+}
+
+/// Puts the terminal into non-canonical, no-echo mode so single key presses
+/// reach us, restoring the previous mode on drop. Ctrl-C still raises SIGINT.
+#[cfg(unix)]
+pub struct KeyInput {
+    saved: libc::termios,
+}
+
+#[cfg(unix)]
+impl KeyInput {
+    /// `None` when stdin is not a terminal.
+    pub fn enable() -> Option<Self> {
+        let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: fd 0 is stdin and `termios` is a valid out-pointer.
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        // SAFETY: tcgetattr succeeded, so it initialized `termios`.
+        let saved = unsafe { termios.assume_init() };
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: `raw` is a fully initialized termios.
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return None;
+        }
+        Some(KeyInput { saved })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for KeyInput {
+    fn drop(&mut self) {
+        // SAFETY: `saved` came from tcgetattr on the same descriptor.
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+/// Without a terminal mode to switch, keys arrive a line at a time: `q`
+/// needs Enter.
+#[cfg(not(unix))]
+pub struct KeyInput;
+
+#[cfg(not(unix))]
+impl KeyInput {
+    pub fn enable() -> Option<Self> {
+        Some(KeyInput)
+    }
+}
+
+/// Resolves once `q` or `Q` is read from stdin; never resolves if stdin
+/// closes first.
+pub async fn quit_requested() {
+    use std::io::Read;
+
+    // A plain thread rather than tokio's stdin: its blocking read cannot be
+    // cancelled and would hold up runtime shutdown until a key is pressed.
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        let mut byte = [0u8; 1];
+        let mut stdin = std::io::stdin();
+        while let Ok(1) = stdin.read(&mut byte) {
+            if byte[0].eq_ignore_ascii_case(&b'q') {
+                let _ = sender.send(());
+                return;
+            }
+        }
+    });
+    if receiver.await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(taken_at: Instant, transactions: i64) -> Sample {
+        Sample {
+            taken_at,
+            transactions,
+            blocks_hit: 990,
+            blocks_read: 10,
+            active: 2,
+            idle: 3,
+            idle_in_transaction: 1,
+            longest: None,
+            databases: vec![("app".into(), 8 * 1024 * 1024), ("postgres".into(), 512)],
+        }
+    }
+
+    #[test]
+    fn renders_rates_connections_and_sizes() {
+        let start = Instant::now();
+        let previous = sample(start, 1_000);
+        let mut current = sample(start + Duration::from_secs(2), 1_100);
+        current.longest = Some(LongestQuery {
+            pid: 4242,
+            database: Some("app".into()),
+            seconds: 12.345,
+            query: format!("SELECT *\n  FROM events\tWHERE {}", "x".repeat(80)),
+        });
+
+        let expected = format!(
+            "transactions/s         50.0\n\
+             connections            2 active, 3 idle, 1 idle in transaction\n\
+             cache hit ratio        99.00%\n\
+             longest query          12.3s pid 4242 on app: SELECT * FROM events WHERE {}\n\
+             databases\n\
+             \x20 app                  8.0 MiB\n\
+             \x20 postgres             512 B\n",
+            "x".repeat(QUERY_PREVIEW_CHARS - "SELECT * FROM events WHERE ".len())
+        );
+        assert_eq!(render(&current, Some(&previous)), expected);
+    }
+
+    #[test]
+    fn the_first_sample_has_no_rate() {
+        let mut current = sample(Instant::now(), 1_000);
+        current.blocks_hit = 0;
+        current.blocks_read = 0;
+        let text = render(&current, None);
+
+        let lines: Vec<&str> = text.lines().take(4).collect();
+        assert_eq!(lines[0], "transactions/s         -");
+        assert_eq!(lines[2], "cache hit ratio        -");
+        assert_eq!(lines[3], "longest query          -");
+    }
+}
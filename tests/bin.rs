@@ -0,0 +1,66 @@
+mod common;
+
+use common::{Sandbox, stderr};
+use std::path::Path;
+
+#[test]
+fn pg_isready_runs_against_the_managed_server() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let url = sandbox.start(&[]);
+    let port = url.rsplit(':').next().unwrap().split('/').next().unwrap();
+    let data_dir = sandbox.data_dir();
+    let data_dir_arg = data_dir.to_string_lossy();
+
+    let ready = sandbox.run(&["bin", "pg_isready", "--data-dir", &data_dir_arg]);
+    assert_eq!(ready, format!("localhost:{port} - accepting connections\n"));
+
+    // The tool's own exit code comes back: 2 means no response.
+    sandbox.stop();
+    let output = sandbox.output(&["bin", "pg_isready", "--data-dir", &data_dir_arg]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn pg_dump_connects_with_the_injected_credentials() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    sandbox.start(&[]);
+    sandbox.sql("CREATE TABLE dumped (id integer PRIMARY KEY, note text)");
+    let data_dir = sandbox.data_dir();
+
+    let dump = sandbox.run(&[
+        "bin",
+        "pg_dump",
+        "--data-dir",
+        &data_dir.to_string_lossy(),
+        "--",
+        "--schema-only",
+        "--table",
+        "dumped",
+    ]);
+    assert!(dump.contains("CREATE TABLE public.dumped ("), "{dump}");
+}
+
+#[test]
+fn path_and_list_name_the_bundled_tools() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    sandbox.start(&[]);
+    let data_dir = sandbox.data_dir();
+    let data_dir_arg = data_dir.to_string_lossy();
+
+    let path = sandbox.run(&["bin", "--data-dir", &data_dir_arg, "--path", "pg_dump"]);
+    let path = Path::new(path.trim());
+    assert!(path.is_absolute() && path.exists(), "{}", path.display());
+
+    let list = sandbox.run(&["bin", "--data-dir", &data_dir_arg, "--list"]);
+    assert!(list.lines().any(|tool| tool == "pg_isready"), "{list}");
+
+    let output = sandbox.output(&["bin", "no_such_tool", "--data-dir", &data_dir_arg]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("pg_dump"), "{}", stderr(&output));
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use common::{Sandbox, cluster_options, stderr, stdout, succeeded};
+use pgx::{Cluster, ClusterStatus};
+use std::fs;
+use std::path::Path;
+
+/// What `pgx` prints to stderr when a command fails with `error`.
+fn cli_error(error: impl std::fmt::Display) -> String {
+    format!("error: {error}\n")
+}
+
+/// Stops a cluster when a test fails before stopping it itself; the sandbox
+/// only stops clusters the CLI registered in its own home.
+struct StopOnDrop<'a>(&'a Sandbox, &'a Path);
+
+impl Drop for StopOnDrop<'_> {
+    fn drop(&mut self) {
+        let data_dir = self.1.to_string_lossy();
+        let _ = self.0.output(&["--quiet", "stop", "--data-dir", &data_dir]);
+    }
+}
+
+fn cluster(data_dir: &Path) -> Cluster {
+    let mut options = cluster_options();
+    options.port = Some(0);
+    Cluster::new(data_dir, options)
+}
+
+#[tokio::test]
+async fn a_never_started_cluster_reads_the_same_through_both() {
+    let sandbox = Sandbox::without_server();
+    let data_dir = sandbox.data_dir();
+    let data_dir_arg = data_dir.to_string_lossy();
+    let cluster = cluster(&data_dir);
+
+    assert_eq!(cluster.status().unwrap(), ClusterStatus::NeverStarted);
+    assert_eq!(
+        sandbox.run(&["status", "--data-dir", &data_dir_arg]),
+        "never started (no cluster or pgx state found)\n"
+    );
+
+    let url = sandbox.output(&["url", "--data-dir", &data_dir_arg]);
+    assert!(!url.status.success());
+    assert_eq!(stdout(&url), "");
+    assert_eq!(
+        stderr(&url),
+        cli_error(cluster.connection_url().unwrap_err())
+    );
+    assert_eq!(
+        stderr(&url),
+        format!(
+            "error: no pgx state for {}; it has never been started with pgx\n",
+            data_dir.display()
+        )
+    );
+
+    assert_eq!(
+        sandbox.run(&["stop", "--data-dir", &data_dir_arg]),
+        format!(
+            "nothing to stop (no pgx state for {})\n",
+            data_dir.display()
+        )
+    );
+}
+
+#[tokio::test]
+async fn a_library_start_looks_like_a_cli_start() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let data_dir = fs::canonicalize(sandbox.path()).unwrap().join("data");
+    let data_dir_arg = data_dir.to_string_lossy();
+    let cluster = cluster(&data_dir);
+
+    let _stop = StopOnDrop(&sandbox, &data_dir);
+    let url = cluster.start().await.unwrap();
+    assert_eq!(cluster.status().unwrap(), ClusterStatus::Running);
+    assert_eq!(cluster.connection_url().unwrap(), url);
+    assert_eq!(
+        sandbox.run(&["url", "--data-dir", &data_dir_arg]),
+        format!("{url}\n")
+    );
+    let status = sandbox.run(&["status", "--data-dir", &data_dir_arg]);
+    let lines: Vec<&str> = status.lines().collect();
+    assert_eq!(lines[..2], ["running", url.as_str()]);
+    assert!(lines[2].starts_with("data dir size "), "{status}");
+
+    // Set up as `pgx start` does, pg_search included.
+    assert_eq!(
+        sandbox.sql("select extname from pg_extension order by 1"),
+        "pg_search\nplpgsql"
+    );
+
+    let again = sandbox.start_command(&data_dir, &[]).output().unwrap();
+    assert!(!again.status.success());
+    let error = cluster.start().await.unwrap_err();
+    assert_eq!(stderr(&again), cli_error(&error));
+    assert!(
+        error.to_string().ends_with(&format!(
+            "is already using {data_dir_arg}; stop it with `pgx stop --data-dir {data_dir_arg}` first"
+        )),
+        "{error}"
+    );
+    cluster.stop().await.unwrap();
+
+    assert_eq!(cluster.status().unwrap(), ClusterStatus::Stopped);
+    assert_eq!(
+        sandbox
+            .run(&["status", "--data-dir", &data_dir_arg])
+            .lines()
+            .next(),
+        Some("stopped (cluster exists)")
+    );
+    let url = sandbox.output(&["url", "--data-dir", &data_dir_arg]);
+    assert_eq!(
+        stderr(&url),
+        cli_error(cluster.connection_url().unwrap_err())
+    );
+    assert_eq!(
+        stderr(&url),
+        format!(
+            "error: stopped (cluster exists); start it with `pgx start --data-dir {data_dir_arg}`\n"
+        )
+    );
+}
+
+#[tokio::test]
+async fn a_cli_start_looks_like_a_library_start() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let data_dir = fs::canonicalize(sandbox.path()).unwrap().join("data");
+    let data_dir_arg = data_dir.to_string_lossy();
+    let cluster = cluster(&data_dir);
+    let _stop = StopOnDrop(&sandbox, &data_dir);
+
+    let output = succeeded(
+        sandbox
+            .start_command(&data_dir, &["--tls"])
+            .output()
+            .unwrap(),
+    );
+    let url = stdout(&output);
+    assert!(url.ends_with("?sslmode=require\n"), "{url}");
+    assert_eq!(format!("{}\n", cluster.connection_url().unwrap()), url);
+    assert_eq!(sandbox.run(&["url", "--data-dir", &data_dir_arg]), url);
+
+    let stopped = sandbox.run(&["stop", "--data-dir", &data_dir_arg]);
+    let seconds = stopped
+        .strip_prefix("stopped in ")
+        .and_then(|rest| rest.strip_suffix("s\n"))
+        .unwrap_or_else(|| panic!("{stopped:?}"));
+    seconds.parse::<f64>().unwrap();
+    assert_eq!(cluster.status().unwrap(), ClusterStatus::Stopped);
+    assert_eq!(
+        sandbox.run(&["stop", "--data-dir", &data_dir_arg]),
+        "not running\n"
+    );
+
+    // The library keeps the TLS the CLI chose, as `pgx start` would.
+    let url = cluster.start().await.unwrap();
+    cluster.stop().await.unwrap();
+    assert!(url.ends_with("?sslmode=require"), "{url}");
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use common::{Sandbox, stderr};
+use std::fs;
+
+#[test]
+fn clone_force_refuses_to_replace_a_directory_that_is_not_a_cluster() {
+    let sandbox = Sandbox::without_server();
+    let source = sandbox.data_dir();
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("PG_VERSION"), "17\n").unwrap();
+    fs::write(source.join("postgresql.conf"), "").unwrap();
+    let target = sandbox.path().join("project");
+    fs::create_dir_all(&target).unwrap();
+    fs::write(target.join("notes.txt"), "keep me").unwrap();
+
+    let output = sandbox.output(&[
+        "clone",
+        "--from",
+        &source.to_string_lossy(),
+        "--to",
+        &target.to_string_lossy(),
+        "--force",
+    ]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("is not a PostgreSQL data directory"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        fs::read_to_string(target.join("notes.txt")).unwrap(),
+        "keep me"
+    );
+}
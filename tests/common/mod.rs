@@ -0,0 +1,178 @@
+//! Shared setup for the integration tests, which drive the `pgx` binary
+//! against clusters in temporary directories.
+//!
+//! The first run downloads PostgreSQL into ~/.theseus/postgresql, just as
+//! `pgx start` does. Set `PGX_TEST_PG_VERSION` to test another major version
+//! and `PGX_TEST_OFFLINE=1` to use only what is already cached. PostgreSQL
+//! refuses to run as root, so the tests skip themselves there.
+
+// Each test binary uses a different part of this module.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Variables that would point pgx at the caller's own clusters.
+const PGX_ENV: [&str; 8] = [
+    "PGX_DATA_DIR",
+    "PGX_PORT",
+    "PGX_HOST",
+    "PGX_STATE_FILE",
+    "PGX_PASSWORD_FILE",
+    "PGX_BINARIES",
+    "PGX_LOG_FORMAT",
+    "PGX_RELEASES_URL",
+];
+
+/// A temporary directory holding data directories and a pgx home of its own.
+/// Dropping it stops every cluster started through it.
+pub struct Sandbox {
+    root: tempfile::TempDir,
+}
+
+impl Sandbox {
+    /// A fresh sandbox, or `None` after saying why PostgreSQL cannot run here.
+    pub fn new() -> Option<Self> {
+        if is_root() {
+            eprintln!("skipped: PostgreSQL refuses to run as root");
+            return None;
+        }
+        Some(Sandbox::without_server())
+    }
+
+    /// A sandbox for tests that never start a server, which run anywhere.
+    pub fn without_server() -> Self {
+        Sandbox {
+            root: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.root.path()
+    }
+
+    /// The data directory commands get unless told otherwise.
+    pub fn data_dir(&self) -> PathBuf {
+        self.path().join("data")
+    }
+
+    /// `pgx` run from the sandbox with its own home directory and none of
+    /// the caller's PGX_* settings.
+    pub fn pgx(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_pgx"));
+        for name in PGX_ENV {
+            command.env_remove(name);
+        }
+        command
+            .env("PGX_HOME", self.path().join("home"))
+            .current_dir(self.path());
+        command
+    }
+
+    /// Run `pgx args`, whatever the outcome.
+    pub fn output(&self, args: &[&str]) -> Output {
+        self.pgx().args(args).output().unwrap()
+    }
+
+    /// Run `pgx args`, failing the test unless it succeeds; returns stdout.
+    pub fn run(&self, args: &[&str]) -> String {
+        stdout(&succeeded(self.output(args)))
+    }
+
+    /// `pgx start --daemon` on `data_dir` with the test's version settings.
+    pub fn start_command(&self, data_dir: &Path, args: &[&str]) -> Command {
+        let mut command = self.pgx();
+        command
+            .arg("start")
+            .arg("--daemon")
+            .arg("--data-dir")
+            .arg(data_dir)
+            .args(start_args())
+            .args(args);
+        command
+    }
+
+    /// Start [`Sandbox::data_dir`] in the background; returns its URL.
+    pub fn start(&self, args: &[&str]) -> String {
+        let output = self.start_command(&self.data_dir(), args).output().unwrap();
+        stdout(&succeeded(output)).trim().to_string()
+    }
+
+    pub fn stop(&self) -> String {
+        self.run(&["stop", "--data-dir", &self.data_dir().to_string_lossy()])
+    }
+
+    /// Run `sql` against [`Sandbox::data_dir`], returning psql's unaligned
+    /// tuples-only output.
+    pub fn sql(&self, sql: &str) -> String {
+        let data_dir = self.data_dir();
+        let data_dir = data_dir.to_string_lossy();
+        self.run(&["psql", "--data-dir", &data_dir, "--", "-XtAc", sql])
+            .trim()
+            .to_string()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = self
+            .pgx()
+            .args(["--quiet", "stop", "--all", "--force", "--timeout", "10s"])
+            .output();
+    }
+}
+
+/// `--port 0` plus `--pg-version` and `--offline` as the environment asks.
+pub fn start_args() -> Vec<String> {
+    let mut args = vec!["--port".to_string(), "0".to_string()];
+    if let Ok(version) = std::env::var("PGX_TEST_PG_VERSION") {
+        args.extend(["--pg-version".to_string(), version]);
+    }
+    if std::env::var_os("PGX_TEST_OFFLINE").is_some_and(|value| value == "1") {
+        args.push("--offline".to_string());
+    }
+    args
+}
+
+/// `output`, after failing the test with its stderr unless it succeeded.
+pub fn succeeded(output: Output) -> Output {
+    assert!(
+        output.status.success(),
+        "pgx failed ({}):\n{}",
+        output.status,
+        stderr(&output)
+    );
+    output
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: geteuid(2) cannot fail and has no preconditions.
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+/// Library options for the version the environment asks for.
+pub fn cluster_options() -> pgx::ClusterOptions {
+    let mut options = pgx::ClusterOptions::default();
+    if let Ok(version) = std::env::var("PGX_TEST_PG_VERSION") {
+        options.version =
+            Some(postgresql_embedded::VersionReq::parse(&format!("={version}")).unwrap());
+    }
+    options.offline = std::env::var_os("PGX_TEST_OFFLINE").is_some_and(|value| value == "1");
+    // Keep test clusters out of the caller's own `pgx list`.
+    options.register = false;
+    options
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::{Sandbox, stderr, stdout, succeeded};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The data directory the state file next to `data_dir` records.
+fn recorded_data_dir(data_dir: &Path) -> PathBuf {
+    let mut state_file = data_dir.as_os_str().to_owned();
+    state_file.push(".pgx-state.json");
+    let state: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(state_file).unwrap()).unwrap();
+    PathBuf::from(state["data_dir"].as_str().unwrap())
+}
+
+#[test]
+fn relative_pgx_data_dir_is_recorded_absolute() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let subdir = sandbox.path().join("src");
+    fs::create_dir(&subdir).unwrap();
+
+    let started = succeeded(
+        sandbox
+            .pgx()
+            .env("PGX_DATA_DIR", "./db")
+            .args(["start", "--daemon"])
+            .args(common::start_args())
+            .output()
+            .unwrap(),
+    );
+    let root = fs::canonicalize(sandbox.path()).unwrap();
+    assert_eq!(recorded_data_dir(&root.join("db")), root.join("db"));
+
+    // The same relative path from elsewhere names another, missing directory.
+    let elsewhere = sandbox
+        .pgx()
+        .env("PGX_DATA_DIR", "./db")
+        .arg("url")
+        .current_dir(&subdir)
+        .output()
+        .unwrap();
+    assert!(!elsewhere.status.success());
+    assert!(
+        stderr(&elsewhere).contains(&format!(
+            "./db resolves to {} from this directory, which does not exist",
+            root.join("src").join("db").display()
+        )),
+        "{}",
+        stderr(&elsewhere)
+    );
+
+    let url = succeeded(
+        sandbox
+            .pgx()
+            .env("PGX_DATA_DIR", "../db")
+            .arg("url")
+            .current_dir(&subdir)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(stdout(&url), stdout(&started));
+    succeeded(
+        sandbox
+            .pgx()
+            .env("PGX_DATA_DIR", "../db")
+            .arg("stop")
+            .current_dir(&subdir)
+            .output()
+            .unwrap(),
+    );
+}
+
+#[test]
+fn relative_data_dir_flag_reaches_the_same_cluster_from_a_subdirectory() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let subdir = sandbox.path().join("src");
+    fs::create_dir(&subdir).unwrap();
+
+    let started = succeeded(
+        sandbox
+            .pgx()
+            .args(["start", "--daemon", "--data-dir", "db"])
+            .args(common::start_args())
+            .output()
+            .unwrap(),
+    );
+    let root = fs::canonicalize(sandbox.path()).unwrap();
+    assert_eq!(recorded_data_dir(&root.join("db")), root.join("db"));
+
+    let url = succeeded(
+        sandbox
+            .pgx()
+            .args(["url", "--data-dir", "../db"])
+            .current_dir(&subdir)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(stdout(&url), stdout(&started));
+    let status = succeeded(
+        sandbox
+            .pgx()
+            .args(["status", "--data-dir", "../db"])
+            .current_dir(&subdir)
+            .output()
+            .unwrap(),
+    );
+    assert!(
+        stdout(&status).starts_with("running\n"),
+        "{}",
+        stdout(&status)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_data_dir_shares_sidecars_with_its_target() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let root = fs::canonicalize(sandbox.path()).unwrap();
+    let target = root.join("volumes").join("db");
+    fs::create_dir_all(&target).unwrap();
+    let link = root.join("db");
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    let started = succeeded(sandbox.start_command(&link, &[]).output().unwrap());
+    assert_eq!(recorded_data_dir(&target), target);
+    assert!(!root.join("db.pgx-state.json").exists());
+
+    let target_arg = target.to_string_lossy();
+    assert_eq!(
+        sandbox.run(&["url", "--data-dir", &target_arg]),
+        stdout(&started)
+    );
+    sandbox.run(&["stop", "--data-dir", &link.to_string_lossy()]);
+    assert!(
+        sandbox
+            .run(&["status", "--data-dir", &target_arg])
+            .starts_with("stopped")
+    );
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::Sandbox;
+use std::fs;
+
+#[test]
+fn destroy_leaves_nothing_named_after_the_data_directory() {
+    let sandbox = Sandbox::without_server();
+    let data_dir = sandbox.data_dir();
+    fs::create_dir_all(&data_dir).unwrap();
+    fs::write(data_dir.join("PG_VERSION"), "17\n").unwrap();
+
+    // A state and password file kept elsewhere, found through the pointer
+    // and the state file as `--state-file`/`--password-file` leave them.
+    let elsewhere = sandbox.path().join("elsewhere");
+    fs::create_dir_all(&elsewhere).unwrap();
+    let state_file = elsewhere.join("state.json");
+    let password_file = elsewhere.join("password");
+    let state = serde_json::json!({
+        "schema_version": 3,
+        "port": 5432,
+        "host": "localhost",
+        "running": false,
+        "password_file": password_file,
+    });
+    fs::write(&state_file, state.to_string()).unwrap();
+    fs::write(&password_file, "secret").unwrap();
+    fs::write(
+        sandbox.path().join("data.pgx-state-path"),
+        format!("{}\n", state_file.display()),
+    )
+    .unwrap();
+    fs::write(sandbox.path().join("data.pgx.log"), "old log\n").unwrap();
+    fs::write(sandbox.path().join("data.pgx-prewarm.json"), "[]").unwrap();
+    fs::write(sandbox.path().join("database.pgx-password"), "other").unwrap();
+
+    let data_dir_arg = data_dir.to_string_lossy();
+    sandbox.run(&["destroy", "--yes", "--data-dir", &data_dir_arg]);
+
+    let left: Vec<String> = fs::read_dir(sandbox.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name == "data" || name.starts_with("data."))
+        .collect();
+    assert!(left.is_empty(), "{left:?}");
+    assert!(!state_file.exists());
+    assert!(!password_file.exists());
+    // Another cluster whose name merely starts the same way is untouched.
+    assert!(sandbox.path().join("database.pgx-password").exists());
+}
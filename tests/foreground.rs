@@ -0,0 +1,55 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+use std::fs;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+#[test]
+fn foreground_start_exits_promptly_when_the_postmaster_is_killed() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let data_dir = sandbox.data_dir();
+    let data_dir_arg = data_dir.to_string_lossy().to_string();
+    // --quiet leaves no log to echo, so only the exit watch can notice in time.
+    let mut foreground = sandbox
+        .pgx()
+        .args(["--quiet", "start", "--data-dir", &data_dir_arg])
+        .args(common::start_args())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    sandbox.run(&[
+        "status",
+        "--data-dir",
+        &data_dir_arg,
+        "--wait",
+        "--timeout",
+        "2m",
+    ]);
+
+    let pid_file = fs::read_to_string(data_dir.join("postmaster.pid")).unwrap();
+    let pid: i32 = pid_file.lines().next().unwrap().parse().unwrap();
+    // SAFETY: kill(2) has no memory-safety preconditions.
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGKILL) }, 0);
+
+    // Well under the fallback poll, which the exit watch makes rare.
+    let killed = Instant::now();
+    loop {
+        if foreground.try_wait().unwrap().is_some() {
+            break;
+        }
+        if killed.elapsed() > Duration::from_secs(3) {
+            let _ = foreground.kill();
+            panic!(
+                "pgx still running {:?} after the postmaster was killed",
+                killed.elapsed()
+            );
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
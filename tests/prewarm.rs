@@ -0,0 +1,72 @@
+mod common;
+
+use common::Sandbox;
+use std::fs;
+
+/// Shared buffers holding pages of table `warm`.
+const WARM_BUFFERS: &str = "\
+SELECT count(*) FROM pg_buffercache b
+JOIN pg_class c ON b.relfilenode = pg_relation_filenode(c.oid)
+WHERE c.relname = 'warm'
+  AND b.reldatabase = (SELECT oid FROM pg_database WHERE datname = current_database())";
+
+/// Start with `--prewarm` and fill and read a table, so stopping records it.
+fn start_with_a_buffered_table(sandbox: &Sandbox) {
+    sandbox.start(&["--prewarm"]);
+    sandbox.sql(
+        "CREATE TABLE warm AS SELECT g AS id, repeat('x', 200) AS filler \
+         FROM generate_series(1, 20000) AS g",
+    );
+    assert_eq!(sandbox.sql("SELECT count(*) FROM warm"), "20000");
+}
+
+fn warm_buffers(sandbox: &Sandbox) -> u64 {
+    sandbox.sql(WARM_BUFFERS).parse().unwrap()
+}
+
+#[test]
+fn prewarmed_relations_are_buffered_after_restart() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    start_with_a_buffered_table(&sandbox);
+    sandbox.stop();
+    let recording = fs::read_to_string(sandbox.path().join("data.pgx-prewarm.json")).unwrap();
+    assert!(recording.contains("\"warm\""), "{recording}");
+
+    // Without --prewarm nothing reads the table back in.
+    sandbox.start(&[]);
+    assert_eq!(warm_buffers(&sandbox), 0);
+    sandbox.stop();
+
+    sandbox.start(&["--prewarm"]);
+    assert!(warm_buffers(&sandbox) > 0);
+}
+
+#[test]
+fn start_succeeds_without_pg_prewarm() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    start_with_a_buffered_table(&sandbox);
+    // A function with pg_prewarm's signature makes CREATE EXTENSION fail, as
+    // on a server built without the contrib modules.
+    sandbox.sql(
+        "DROP EXTENSION IF EXISTS pg_prewarm; \
+         CREATE FUNCTION public.pg_prewarm(regclass, text DEFAULT 'buffer', text DEFAULT 'main', \
+         bigint DEFAULT NULL, bigint DEFAULT NULL) RETURNS bigint LANGUAGE sql AS 'SELECT 0::bigint'",
+    );
+    sandbox.stop();
+
+    sandbox.start(&["--prewarm"]);
+    assert_eq!(
+        sandbox.sql("SELECT count(*) FROM pg_extension WHERE extname = 'pg_prewarm'"),
+        "0"
+    );
+    assert_eq!(warm_buffers(&sandbox), 0);
+    let log = fs::read_to_string(sandbox.path().join("data.pgx.log")).unwrap();
+    assert!(
+        log.contains("pg_prewarm is unavailable; skipping prewarm"),
+        "{log}"
+    );
+}
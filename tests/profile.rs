@@ -0,0 +1,90 @@
+mod common;
+
+use common::{Sandbox, start_args, stderr, stdout, succeeded};
+use std::fs;
+
+/// Record profile `dev` in the sandbox's pgx home with the given defaults.
+fn write_profile(sandbox: &Sandbox, defaults: serde_json::Value) {
+    let home = sandbox.path().join("home");
+    let mut profile = serde_json::json!({ "data_dir": home.join("profiles").join("dev") });
+    profile
+        .as_object_mut()
+        .unwrap()
+        .extend(defaults.as_object().unwrap().clone());
+    fs::create_dir_all(&home).unwrap();
+    fs::write(
+        home.join("profiles.json"),
+        serde_json::json!({ "profiles": { "dev": profile } }).to_string(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn start_creates_and_records_the_profile_database() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    write_profile(&sandbox, serde_json::json!({ "database": "app" }));
+
+    let output = succeeded(
+        sandbox
+            .pgx()
+            .args(["-p", "dev", "start", "--daemon"])
+            .args(start_args())
+            .output()
+            .unwrap(),
+    );
+    let url = stdout(&output);
+    assert!(url.trim_end().ends_with("/app"), "{url}");
+    assert_eq!(sandbox.run(&["-p", "dev", "url"]), url);
+    assert_eq!(
+        sandbox
+            .run(&[
+                "-p",
+                "dev",
+                "psql",
+                "--",
+                "-XtAc",
+                "select current_database()"
+            ])
+            .trim(),
+        "app"
+    );
+
+    // The recorded database stays the default once the profile drops it.
+    write_profile(&sandbox, serde_json::json!({}));
+    assert_eq!(sandbox.run(&["-p", "dev", "url"]), url);
+}
+
+#[test]
+fn profiles_are_listed_and_forgotten_when_destroyed() {
+    let sandbox = Sandbox::without_server();
+    write_profile(&sandbox, serde_json::json!({ "port": 5433 }));
+    let data_dir = sandbox.path().join("home/profiles/dev");
+    fs::create_dir_all(&data_dir).unwrap();
+    fs::write(data_dir.join("PG_VERSION"), "17\n").unwrap();
+
+    let listed = sandbox.run(&["list"]);
+    assert!(listed.starts_with("dev "), "{listed}");
+    assert!(
+        listed.trim_end().ends_with(&data_dir.display().to_string()),
+        "{listed}"
+    );
+
+    sandbox.run(&["-p", "dev", "destroy", "--yes"]);
+    assert!(!data_dir.exists());
+    let index = fs::read_to_string(sandbox.path().join("home/profiles.json")).unwrap();
+    assert!(!index.contains("\"dev\""), "{index}");
+}
+
+#[test]
+fn profile_names_cannot_leave_the_profiles_directory() {
+    let sandbox = Sandbox::without_server();
+    let output = sandbox.output(&["-p", "../escape", "url"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("invalid profile name '../escape'"),
+        "{}",
+        stderr(&output)
+    );
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::{Sandbox, stderr};
+use std::fs;
+
+#[test]
+fn a_broken_pgx_toml_only_stops_the_commands_that_use_it() {
+    let sandbox = Sandbox::without_server();
+    fs::write(sandbox.path().join("pgx.toml"), "prot = 6000\n").unwrap();
+
+    let output = sandbox.output(&["list"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("ignoring pgx.toml"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = sandbox.output(&["start", "--port", "0"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("unknown field `prot`"),
+        "{}",
+        stderr(&output)
+    );
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{Sandbox, cluster_options};
+use pgx::{Cluster, ProvisionEvent, ProvisionLimits};
+use std::time::Duration;
+use tokio::sync::broadcast::error::TryRecvError;
+
+const CLUSTERS: usize = 32;
+const LIMIT: usize = 4;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_starts_queue_and_all_succeed() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let mut options = cluster_options();
+    options.provision = ProvisionLimits::new(LIMIT);
+    let mut events = options.provision.subscribe();
+    let clusters: Vec<Cluster> = (0..CLUSTERS)
+        .map(|index| {
+            Cluster::new(
+                sandbox.path().join(format!("data-{index}")),
+                options.clone(),
+            )
+        })
+        .collect();
+
+    let starts: Vec<_> = clusters
+        .iter()
+        .cloned()
+        .map(|cluster| tokio::spawn(async move { cluster.start().await }))
+        .collect();
+    let started = tokio::time::timeout(Duration::from_secs(600), async {
+        let mut results = Vec::new();
+        for start in starts {
+            results.push(start.await.unwrap());
+        }
+        results
+    })
+    .await;
+
+    for cluster in &clusters {
+        let _ = cluster.stop().await;
+    }
+    let results = started.expect("32 starts did not finish within 10 minutes");
+    for (cluster, result) in clusters.iter().zip(&results) {
+        if let Err(error) = result {
+            panic!("{} failed to start: {error}", cluster.data_dir().display());
+        }
+    }
+
+    let (mut in_progress, mut most_in_progress, mut deepest_queue) = (0, 0, 0);
+    let mut acquired = 0;
+    loop {
+        match events.try_recv() {
+            Ok(ProvisionEvent::Queued { queue_depth, .. }) => {
+                deepest_queue = deepest_queue.max(queue_depth);
+            }
+            Ok(ProvisionEvent::Acquired { .. }) => {
+                acquired += 1;
+                in_progress += 1;
+                most_in_progress = most_in_progress.max(in_progress);
+            }
+            Ok(ProvisionEvent::Released { .. }) => in_progress -= 1,
+            Err(TryRecvError::Empty) => break,
+            Err(error) => panic!("missed provisioning events: {error}"),
+        }
+    }
+    assert_eq!(acquired, CLUSTERS);
+    assert!(
+        most_in_progress <= LIMIT,
+        "{most_in_progress} provisioned at once"
+    );
+    assert!(
+        deepest_queue > LIMIT,
+        "the queue never backed up: {deepest_queue}"
+    );
+    assert_eq!(options.provision.queue_depth(), 0);
+    assert_eq!(options.provision.in_progress(), 0);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{Sandbox, stderr};
+use std::fs;
+
+#[test]
+fn reset_asks_for_confirmation_and_seed_scripts() {
+    let sandbox = Sandbox::without_server();
+    let data_dir = sandbox.data_dir();
+    let data_dir_arg = data_dir.to_string_lossy();
+    fs::create_dir_all(&data_dir).unwrap();
+    fs::write(data_dir.join("PG_VERSION"), "17\n").unwrap();
+    let state = serde_json::json!({
+        "schema_version": 4,
+        "port": 5432,
+        "host": "localhost",
+        "running": false,
+    });
+    fs::write(
+        sandbox.path().join("data.pgx-state.json"),
+        state.to_string(),
+    )
+    .unwrap();
+    fs::write(sandbox.path().join("data.pgx-password"), "secret").unwrap();
+
+    // Without a terminal to ask on, only --yes confirms.
+    let output = sandbox.output(&["reset", "--data-dir", &data_dir_arg]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("pass --yes to confirm"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = sandbox.output(&["reset", "--data-dir", &data_dir_arg, "--yes", "--seed"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("--seed needs init scripts"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(data_dir.join("PG_VERSION").exists());
+}
+
+#[test]
+fn reset_start_recreates_roles_and_reseeds() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let seed = sandbox.path().join("seed.sql");
+    fs::write(
+        &seed,
+        "CREATE TABLE seeded (id int); INSERT INTO seeded VALUES (1);",
+    )
+    .unwrap();
+    let seed_arg = seed.to_string_lossy().to_string();
+    let url = sandbox.start(&[
+        "--init-sql",
+        &seed_arg,
+        "--app-user",
+        "app",
+        "--role",
+        "reader:secret",
+    ]);
+    let app_url = sandbox.run(&[
+        "url",
+        "--data-dir",
+        &sandbox.data_dir().to_string_lossy(),
+        "--app",
+    ]);
+    sandbox.sql("INSERT INTO seeded VALUES (2)");
+
+    let data_dir = sandbox.data_dir().to_string_lossy().to_string();
+    let args = [
+        "reset",
+        "--data-dir",
+        &data_dir,
+        "--yes",
+        "--start",
+        "--seed",
+        "--init-sql",
+        &seed_arg,
+    ];
+    let mut command = sandbox.pgx();
+    // Without the leading `--port 0`, so the recorded port is kept.
+    command
+        .args(args)
+        .args(common::start_args().into_iter().skip(2));
+    let output = common::succeeded(command.output().unwrap());
+    assert_eq!(common::stdout(&output).trim(), url);
+
+    assert_eq!(sandbox.sql("SELECT count(*) FROM seeded"), "1");
+    assert_eq!(
+        sandbox.run(&["url", "--data-dir", &data_dir, "--app"]),
+        app_url
+    );
+    assert_eq!(
+        sandbox.sql("SELECT count(*) FROM pg_roles WHERE rolname IN ('app', 'reader')"),
+        "2"
+    );
+}
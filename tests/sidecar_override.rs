@@ -0,0 +1,98 @@
+mod common;
+
+use common::{Sandbox, stderr};
+use std::fs;
+
+#[test]
+fn clone_refuses_a_custom_state_or_password_file() {
+    let sandbox = Sandbox::without_server();
+    let state_file = sandbox.path().join("elsewhere/state.json");
+    let password_file = sandbox.path().join("elsewhere/password");
+    let source = sandbox.data_dir().to_string_lossy().to_string();
+    fs::create_dir_all(&source).unwrap();
+
+    for (name, path) in [
+        ("PGX_STATE_FILE", &state_file),
+        ("PGX_PASSWORD_FILE", &password_file),
+    ] {
+        let output = sandbox
+            .pgx()
+            .env(name, path)
+            .args(["clone", "--from", &source, "--to", "./copy"])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(
+            stderr(&output).contains("cannot be used with `pgx clone`"),
+            "{}",
+            stderr(&output)
+        );
+    }
+    assert!(!sandbox.path().join("elsewhere").exists());
+    assert!(!sandbox.path().join("copy").exists());
+}
+
+#[test]
+fn commands_on_several_clusters_refuse_a_custom_state_file() {
+    let sandbox = Sandbox::without_server();
+    let state_file = sandbox.path().join("state.json");
+    for (args, command) in [
+        (&["stop", "--all"][..], "stop --all"),
+        (
+            &["stop", "--data-dir", "a", "--data-dir", "b"][..],
+            "stop --all",
+        ),
+        (&["list"][..], "list"),
+        (&["prune", "--dry-run"][..], "prune"),
+        (&["snapshot", "list"][..], "snapshot"),
+    ] {
+        let output = sandbox
+            .pgx()
+            .arg("--state-file")
+            .arg(&state_file)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{args:?}");
+        assert!(
+            stderr(&output).contains(&format!("cannot be used with `pgx {command}`")),
+            "{}",
+            stderr(&output)
+        );
+    }
+}
+
+#[test]
+fn a_clone_of_a_cluster_with_custom_files_gets_its_own() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let state_file = sandbox.path().join("elsewhere/state.json");
+    let password_file = sandbox.path().join("elsewhere/password");
+    sandbox.start(&[
+        "--state-file",
+        &state_file.to_string_lossy(),
+        "--password-file",
+        &password_file.to_string_lossy(),
+    ]);
+    sandbox.stop();
+    let source_state = fs::read_to_string(&state_file).unwrap();
+    let source_password = fs::read_to_string(&password_file).unwrap();
+
+    let source = sandbox.data_dir().to_string_lossy().to_string();
+    let target = sandbox.path().join("copy");
+    sandbox.run(&[
+        "clone",
+        "--from",
+        &source,
+        "--to",
+        &target.to_string_lossy(),
+    ]);
+
+    // The source keeps its files; the clone has sidecars of its own.
+    assert_eq!(fs::read_to_string(&state_file).unwrap(), source_state);
+    assert_eq!(fs::read_to_string(&password_file).unwrap(), source_password);
+    assert!(sandbox.path().join("copy.pgx-state.json").exists());
+    let target_password = fs::read_to_string(sandbox.path().join("copy.pgx-password")).unwrap();
+    assert_ne!(target_password, source_password);
+}
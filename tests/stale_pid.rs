@@ -0,0 +1,80 @@
+mod common;
+
+use common::{Sandbox, stderr};
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Write a `postmaster.pid` naming a process that has exited and recording
+/// `port`, as a crash or power loss leaves behind.
+fn fabricate_stale_pid_file(data_dir: &Path, port: u16) -> u32 {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pgx"))
+        .arg("--version")
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.wait().unwrap();
+    let pid = child.id();
+    fs::write(
+        data_dir.join("postmaster.pid"),
+        format!(
+            "{pid}\n{}\n1700000000\n{port}\n\nlocalhost\n  5432001         0\nready   \n",
+            data_dir.display()
+        ),
+    )
+    .unwrap();
+    pid
+}
+
+fn pid_in_pid_file(data_dir: &Path) -> u32 {
+    let raw = fs::read_to_string(data_dir.join("postmaster.pid")).unwrap();
+    raw.lines().next().unwrap().trim().parse().unwrap()
+}
+
+#[test]
+fn start_replaces_a_stale_pid_file() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    sandbox.start(&[]);
+    sandbox.stop();
+    let free_port = TcpListener::bind("localhost:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let stale_pid = fabricate_stale_pid_file(&sandbox.data_dir(), free_port);
+
+    sandbox.start(&[]);
+    assert_eq!(sandbox.sql("SELECT 1"), "1");
+    assert_ne!(pid_in_pid_file(&sandbox.data_dir()), stale_pid);
+}
+
+#[test]
+fn start_keeps_a_stale_pid_file_whose_port_still_answers() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    sandbox.start(&[]);
+    sandbox.stop();
+    // Something else answers on the recorded port: perhaps a postmaster
+    // pgx cannot see, so the pid file must stay.
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let stale_pid = fabricate_stale_pid_file(&sandbox.data_dir(), port);
+
+    let output = sandbox
+        .start_command(&sandbox.data_dir(), &[])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let message = stderr(&output);
+    assert!(
+        message.contains(&format!(
+            "names a postmaster that is not running, but port {port} is accepting connections"
+        )),
+        "{message}"
+    );
+    assert_eq!(pid_in_pid_file(&sandbox.data_dir()), stale_pid);
+}
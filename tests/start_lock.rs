@@ -0,0 +1,39 @@
+mod common;
+
+use common::{Sandbox, stderr};
+use std::process::Stdio;
+
+#[test]
+fn concurrent_starts_of_one_data_dir_let_exactly_one_through() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    let data_dir = sandbox.data_dir();
+
+    let children = [(), ()].map(|()| {
+        sandbox
+            .start_command(&data_dir, &[])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap()
+    });
+    let outputs = children.map(|child| child.wait_with_output().unwrap());
+
+    let succeeded = outputs
+        .iter()
+        .filter(|output| output.status.success())
+        .count();
+    assert_eq!(succeeded, 1, "{outputs:#?}");
+    let refused = outputs
+        .iter()
+        .find(|output| !output.status.success())
+        .unwrap();
+    let message = stderr(refused);
+    assert!(
+        message.contains("another pgx operation is in progress")
+            || message.contains("already using"),
+        "{message}"
+    );
+    assert_eq!(sandbox.sql("SELECT 1"), "1");
+}
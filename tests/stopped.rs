@@ -0,0 +1,81 @@
+mod common;
+
+use common::{Sandbox, stderr};
+use std::fs;
+
+#[test]
+fn status_after_stop_reports_a_stopped_cluster() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    sandbox.start(&[]);
+    sandbox.stop();
+    let data_dir = sandbox.data_dir();
+    let data_dir_arg = data_dir.to_string_lossy();
+
+    let status = sandbox.run(&["status", "--data-dir", &data_dir_arg]);
+    assert_eq!(status.lines().next(), Some("stopped (cluster exists)"));
+
+    let status: serde_json::Value = serde_json::from_str(&sandbox.run(&[
+        "status",
+        "--data-dir",
+        &data_dir_arg,
+        "--format",
+        "json",
+    ]))
+    .unwrap();
+    assert_eq!(status["running"], false);
+    assert_eq!(status["cluster_exists"], true);
+    assert_eq!(status["pid"], serde_json::Value::Null);
+    assert_eq!(status["url"], serde_json::Value::Null);
+
+    // The password is needed again by the next start.
+    assert!(sandbox.path().join("data.pgx-password").exists());
+    let state: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(sandbox.path().join("data.pgx-state.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(state["running"], false);
+}
+
+#[test]
+fn url_after_stop_says_how_to_start_again() {
+    let Some(sandbox) = Sandbox::new() else {
+        return;
+    };
+    sandbox.start(&[]);
+    sandbox.stop();
+    let data_dir = sandbox.data_dir();
+
+    let output = sandbox.output(&["url", "--data-dir", &data_dir.to_string_lossy()]);
+    assert!(!output.status.success());
+    assert_eq!(
+        stderr(&output),
+        format!(
+            "error: stopped (cluster exists); start it with `pgx start --data-dir {}`\n",
+            fs::canonicalize(&data_dir).unwrap().display()
+        )
+    );
+}
+
+#[test]
+fn status_and_url_tell_a_never_started_data_dir_apart() {
+    let sandbox = Sandbox::without_server();
+    let data_dir = sandbox.data_dir();
+    fs::create_dir(&data_dir).unwrap();
+    let data_dir_arg = data_dir.to_string_lossy();
+
+    let status = sandbox.run(&["status", "--data-dir", &data_dir_arg]);
+    assert_eq!(
+        status.lines().next(),
+        Some("never started (no cluster or pgx state found)")
+    );
+
+    let output = sandbox.output(&["url", "--data-dir", &data_dir_arg]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("it has never been started with pgx"),
+        "{}",
+        stderr(&output)
+    );
+}